@@ -1,5 +1,7 @@
 use actix::{prelude::*, Actor, Addr};
 use futures::prelude::*;
+use pmindp_protocol::RD_PORT;
+use pmindp_sensor::SensorClass;
 use std::{collections::HashMap, net::SocketAddrV6};
 use thiserror::Error;
 use tokio::{
@@ -9,8 +11,10 @@ use tokio::{
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
 use crate::{
-    ClientId, ErrorState, EventRouter, EventRouterError, NodeEvent, NodeSensorReading, NodeStatus,
-    Registration,
+    Backoff, ClientId, ErrorState, EventRouter, EventRouterError, KeyStore, NetworkEvent,
+    NetworkStatus, NodeDiscovery, NodeEvent, NodeSensorReading, NodeStatus, OtCliClient, OtClient,
+    OtClientError, OtDbusClient, OtRestClient, OtSocketClient, ReceiveMode, Registration,
+    TransmissionParams,
 };
 
 #[derive(Error, Debug)]
@@ -19,6 +23,37 @@ pub enum BrokerError {
     Broker(#[from] EventRouterError),
     #[error("ActorError")]
     ActorError,
+    #[error("OT Client Error")]
+    OtClient(#[from] OtClientError),
+}
+
+/// [`OtClientBackend`] selects how the broker interfaces with the otbr-agent
+#[derive(Debug, Clone, Default)]
+pub enum OtClientBackend {
+    /// Spawn `ot-ctl` processes and parse their output
+    #[default]
+    Cli,
+    /// Send CLI commands over the otbr-agent CLI socket at the provided
+    /// path (e.g. [`crate::DEFAULT_OT_CLI_SOCKET`])
+    Socket(std::path::PathBuf),
+    /// Use the otbr-agent `io.openthread.BorderRouter` D-Bus interface
+    /// for the provided thread network interface (e.g. `wpan0`)
+    Dbus(String),
+    /// Query the otbr-agent REST server at the provided base url (e.g.
+    /// [`crate::DEFAULT_OT_REST_URL`])
+    Rest(String),
+}
+
+impl OtClientBackend {
+    /// Create the [`OtClient`] implementation for the backend
+    pub async fn client(self) -> Result<Box<dyn OtClient>, OtClientError> {
+        Ok(match self {
+            OtClientBackend::Cli => Box::new(OtCliClient),
+            OtClientBackend::Socket(path) => Box::new(OtSocketClient::new(path)),
+            OtClientBackend::Dbus(interface) => Box::new(OtDbusClient::new(&interface).await?),
+            OtClientBackend::Rest(url) => Box::new(OtRestClient::new(&url)),
+        })
+    }
 }
 
 pub struct Broker {
@@ -27,14 +62,64 @@ pub struct Broker {
     sender: UnboundedSender<BrokerEvent>,
     receiver: UnboundedReceiver<BrokerEvent>,
     _event_handler: tokio::task::JoinHandle<()>,
-    subscribers: HashMap<
-        ClientId,
-        (
-            UnboundedSender<NodeSensorReading>,
-            UnboundedSender<NodeStatus>,
-        ),
-    >,
+    subscribers: HashMap<ClientId, Subscriber>,
     subscription_receiver: UnboundedReceiver<ClientApi>,
+    /// Last network status published, for clients that subscribe later
+    network: Option<NetworkStatus>,
+}
+
+/// Queues of a subscribed client, and the sensor classes it wants
+type Subscriber = (
+    UnboundedSender<NodeSensorReading>,
+    UnboundedSender<NodeStatus>,
+    Vec<SensorClass>,
+    Option<UnboundedSender<NetworkStatus>>,
+);
+
+/// Node facing configuration of the [`Broker`]
+#[derive(Debug, Clone)]
+pub struct BrokerConfig {
+    /// How sensor data is received from the nodes
+    pub receive_mode: ReceiveMode,
+    /// Retransmission timeouts for confirmable CoAP messages to the nodes
+    pub transmission: TransmissionParams,
+    /// Keys of the nodes to accept, every node must then authenticate.
+    /// `None` accepts any node in the clear
+    pub keys: Option<KeyStore>,
+    /// How long a node may go without reporting before it is stale
+    pub node_timeout: Duration,
+    /// Delay between attempts to re-register a stale node
+    pub backoff: Backoff,
+    /// Port to serve the resource directory nodes register with on, `None`
+    /// to only find nodes by polling the mesh
+    pub resource_directory: Option<u16>,
+    /// Which devices on the mesh are sensor nodes
+    pub discovery: NodeDiscovery,
+    /// How often the link metrics of the nodes are published, `None` to
+    /// not collect them
+    pub link_metrics: Option<Duration>,
+    /// How many nodes are registered at once, the others wait their turn
+    pub max_registrations: usize,
+    /// How long one attempt to register a node may take before it is given
+    /// up on, and retried with backoff
+    pub registration_timeout: Duration,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        Self {
+            receive_mode: ReceiveMode::default(),
+            transmission: TransmissionParams::default(),
+            keys: None,
+            node_timeout: Duration::from_secs(crate::DEFAULT_TIMEOUT),
+            backoff: Backoff::default(),
+            resource_directory: Some(RD_PORT),
+            discovery: NodeDiscovery::default(),
+            link_metrics: Some(Duration::from_secs(crate::DEFAULT_LINK_METRICS_PERIOD)),
+            max_registrations: crate::DEFAULT_MAX_REGISTRATIONS,
+            registration_timeout: Duration::from_secs(crate::DEFAULT_REGISTRATION_TIMEOUT),
+        }
+    }
 }
 
 /// [`BrokerEvent`] enum is used by both the Broker and the [`EventRouter`] to
@@ -44,6 +129,11 @@ pub struct Broker {
 pub enum BrokerEvent {
     NodeRegistration(Registration),
     NodeTermination((SocketAddrV6, ErrorState)),
+    /// Lifecycle transitions and moves published by the
+    /// [`OtMonitor`](`crate::OtMonitor`)
+    NodeUpdate(NodeStatus),
+    /// Network changes published by the [`OtMonitor`](`crate::OtMonitor`)
+    NetworkUpdate(NetworkStatus),
     SensorReportHandleCreate(UnboundedReceiver<NodeEvent>),
 }
 
@@ -57,6 +147,8 @@ pub enum ClientApi {
         id: ClientId,
         sensor_readings: UnboundedSender<NodeSensorReading>,
         node_status: UnboundedSender<NodeStatus>,
+        classes: Vec<SensorClass>,
+        network_status: Option<UnboundedSender<NetworkStatus>>,
     },
     Unsubscribe {
         id: ClientId,
@@ -69,17 +161,75 @@ pub enum ClientApi {
 pub async fn broker(
     poll_interval: Duration,
     tick_rate_millis: u64,
+) -> Result<Addr<BrokerHandle>, BrokerError> {
+    broker_with_client(poll_interval, tick_rate_millis, Box::new(OtCliClient)).await
+}
+
+/// Same as [`broker`] but allows selecting the [`OtClientBackend`] used to
+/// interface with the otbr-agent
+pub async fn broker_with_backend(
+    poll_interval: Duration,
+    tick_rate_millis: u64,
+    backend: OtClientBackend,
+) -> Result<Addr<BrokerHandle>, BrokerError> {
+    broker_with_client(poll_interval, tick_rate_millis, backend.client().await?).await
+}
+
+/// Same as [`broker`] but uses the provided [`OtClient`] implementation
+/// (e.g. [`crate::OtDbusClient`] or [`crate::OtRestClient`]) to interface
+/// with the otbr-agent
+pub async fn broker_with_client(
+    poll_interval: Duration,
+    tick_rate_millis: u64,
+    ot_client: Box<dyn OtClient>,
+) -> Result<Addr<BrokerHandle>, BrokerError> {
+    broker_with_config(
+        poll_interval,
+        tick_rate_millis,
+        ot_client,
+        BrokerConfig::default(),
+    )
+    .await
+}
+
+/// Same as [`broker_with_client`] but configured by `config`, e.g. to
+/// receive from all nodes on one socket instead of reserving a port per
+/// node, to give lossy links more time to acknowledge, or to only accept
+/// nodes that authenticate
+pub async fn broker_with_config(
+    poll_interval: Duration,
+    tick_rate_millis: u64,
+    ot_client: Box<dyn OtClient>,
+    config: BrokerConfig,
 ) -> Result<Addr<BrokerHandle>, BrokerError> {
     let (stream_tx, stream_rx) = unbounded_channel();
     let (registration_tx, registration_rx) = unbounded_channel();
-
-    let mut event_router = EventRouter::new(stream_tx, registration_tx, poll_interval).await?;
+    let (update_tx, update_rx) = unbounded_channel();
+    let (network_tx, network_rx) = unbounded_channel();
+
+    let mut event_router = EventRouter::new(
+        ot_client,
+        stream_tx,
+        registration_tx,
+        update_tx,
+        network_tx,
+        poll_interval,
+        config,
+    )
+    .await?;
 
     tokio::spawn(async move {
         event_router.exec_monitor().await;
     });
 
-    let (mut broker, handle) = Broker::new(tick_rate_millis, stream_rx, registration_rx).await;
+    let (mut broker, handle) = Broker::new(
+        tick_rate_millis,
+        stream_rx,
+        registration_rx,
+        update_rx,
+        network_rx,
+    )
+    .await;
 
     tokio::spawn(async move {
         broker.event_loop().await;
@@ -95,6 +245,8 @@ impl Broker {
         tick_rate_millis: u64,
         node_data_rx: UnboundedReceiver<UnboundedReceiver<NodeEvent>>,
         node_reg_rx: UnboundedReceiver<Registration>,
+        node_update_rx: UnboundedReceiver<NodeStatus>,
+        network_rx: UnboundedReceiver<NetworkStatus>,
     ) -> (Self, BrokerHandle) {
         let tick_rate = Duration::from_millis(tick_rate_millis);
         let (sender, receiver) = unbounded_channel();
@@ -102,6 +254,8 @@ impl Broker {
 
         let mut node_event_stream = UnboundedReceiverStream::new(node_data_rx);
         let mut node_reg_stream = UnboundedReceiverStream::new(node_reg_rx);
+        let mut node_update_stream = UnboundedReceiverStream::new(node_update_rx);
+        let mut network_stream = UnboundedReceiverStream::new(network_rx);
 
         let (handle_sender, subscription_receiver) = unbounded_channel();
         let broker_handle = BrokerHandle(handle_sender);
@@ -113,6 +267,8 @@ impl Broker {
                 let tick_delay = tick.tick();
                 let node_event_stream = node_event_stream.next().fuse();
                 let node_reg_stream = node_reg_stream.next().fuse();
+                let node_update_stream = node_update_stream.next().fuse();
+                let network_stream = network_stream.next().fuse();
 
                 tokio::select! {
                   _ = _sender.closed() => {
@@ -128,6 +284,14 @@ impl Broker {
                     log::trace!("Node registration {reg:?}");
                     _sender.send(BrokerEvent::NodeRegistration(reg)).ok();
                   }
+                  Some(update) = node_update_stream => {
+                    log::trace!("Node update {update:?}");
+                    _sender.send(BrokerEvent::NodeUpdate(update)).ok();
+                  }
+                  Some(status) = network_stream => {
+                    log::trace!("Network update {status:?}");
+                    _sender.send(BrokerEvent::NetworkUpdate(status)).ok();
+                  }
                 };
             }
         });
@@ -143,6 +307,7 @@ impl Broker {
                 data_queue_rx,
                 subscribers: HashMap::new(),
                 subscription_receiver,
+                network: None,
             },
             broker_handle,
         )
@@ -174,13 +339,36 @@ impl Broker {
                             });
 
                         },
+                        BrokerEvent::NodeUpdate(update) => {
+                            self.subscribers.iter().for_each(|(key, val)|{
+                                val.1.send(update.clone()).map_err(|e|{
+                                        log::error!("Failure to send to client event \
+                                            receiver {e:} for client ID {key:}");
+                                    }
+                                ).ok();
+                            });
+                        }
+                        BrokerEvent::NetworkUpdate(status) => {
+                            self.network = Some(status);
+                            self.subscribers.iter().filter_map(|(key, val)| {
+                                val.3.as_ref().map(|network| (key, network))
+                            }).for_each(|(key, network)|{
+                                network.send(status).map_err(|e|{
+                                        log::error!("Failure to send to client network \
+                                            receiver {e:} for client ID {key:}");
+                                    }
+                                ).ok();
+                            });
+                        }
                         BrokerEvent::SensorReportHandleCreate(rcv) => {
                             self.handle_sensor_stream_task(rcv).await
                         }
                     };
                 }
                 Some(data) = self.data_queue_rx.recv() => {
-                    self.subscribers.iter().for_each(|(key, val)|{
+                    self.subscribers.iter()
+                        .filter(|(_, val)| val.2.contains(&data.class))
+                        .for_each(|(key, val)|{
                         val.0.send(data).map_err(|e|{
                             log::error!("Failure to send to client data \
                                 receiver {e:} for client ID {key:}");
@@ -189,8 +377,23 @@ impl Broker {
                 }
                 Some(msg) = self.subscription_receiver.recv() => {
                     match msg {
-                        ClientApi::Subscribe { id, sensor_readings, node_status } => {
-                            self.subscribers.insert(id, (sensor_readings, node_status));
+                        ClientApi::Subscribe {
+                            id, sensor_readings, node_status, classes, network_status
+                        } => {
+                            // The current state, as the changes so far were missed
+                            if let (Some(NetworkStatus { state, .. }), Some(network)) =
+                                (self.network, &network_status)
+                            {
+                                let event = match state {
+                                    Some(_) => NetworkEvent::AgentUp,
+                                    None => NetworkEvent::AgentDown,
+                                };
+                                network.send(NetworkStatus { state, event }).ok();
+                            }
+                            self.subscribers.insert(
+                                id,
+                                (sensor_readings, node_status, classes, network_status),
+                            );
                             log::debug!("Subscribed client ID {id:}");
                         }
                         ClientApi::Unsubscribe{ id } => {
@@ -261,6 +464,24 @@ impl Broker {
                         log::error!("Error sending to app {e:}");
                     }
                 }
+                NodeEvent::UnsupportedVersion(addr, version) => {
+                    log::warn!("Node at addr {:?} speaks protocol version {version}", addr);
+                    if let Err(e) = node_state_clone.send(BrokerEvent::NodeTermination((
+                        addr,
+                        ErrorState::UnsupportedVersion(version),
+                    ))) {
+                        log::error!("Error sending to app {e:}");
+                    }
+                }
+                NodeEvent::Unauthenticated(addr) => {
+                    log::warn!("Node at addr {:?} did not authenticate", addr);
+                    if let Err(e) = node_state_clone.send(BrokerEvent::NodeTermination((
+                        addr,
+                        ErrorState::Unauthenticated,
+                    ))) {
+                        log::error!("Error sending to app {e:}");
+                    }
+                }
                 NodeEvent::NodeTimeout(addr) => {
                     log::warn!(
                         "Node timeout for addr {:?}, \
@@ -289,6 +510,12 @@ pub struct ClientSubscribe {
     pub id: ClientId,
     pub sensor_readings: UnboundedSender<NodeSensorReading>,
     pub node_status: UnboundedSender<NodeStatus>,
+    /// Only readings reported for these classes are forwarded, see
+    /// [`SensorClass::ALL`]
+    pub classes: Vec<SensorClass>,
+    /// Changes of the Thread network, starting with its current state.
+    /// `None` for subscribers that do not follow the network
+    pub network_status: Option<UnboundedSender<NetworkStatus>>,
 }
 type ClientSubscribeResponse = Result<(), BrokerError>;
 
@@ -301,6 +528,8 @@ impl Handler<ClientSubscribe> for BrokerHandle {
                 id: msg.id,
                 sensor_readings: msg.sensor_readings,
                 node_status: msg.node_status,
+                classes: msg.classes,
+                network_status: msg.network_status,
             })
             .map_err(|e| {
                 log::error!("Error sending sub to actor {e:}");
@@ -1,30 +1,462 @@
 use ipnet::Ipv6Net;
-use std::{net::Ipv6Addr, process::Command};
+use pmindp_protocol::service::SERVICE_TYPE;
+use std::net::Ipv6Addr;
+use tokio::process::Command;
 
-use crate::{client::OtClient, OtClientError, Rloc};
+use crate::{
+    client::{DeviceRole, LinkMetrics, NetworkState, OperationalDataset, OtClient, PlantService},
+    commission::{CommissionerState, Eui64, Joiner, Pskd},
+    OtClientError, Rloc,
+};
 
 /// Lazy implementation of the [`crate::client::OtClient`] trait
 /// provides interface to the otbr-agent layer
-/// via the ot-ctl CLI process
+/// via the ot-ctl CLI process. The process is killed if the
+/// returned future is dropped, e.g. on timeout
 pub struct OtCliClient;
 
 impl OtCliClient {
-    pub fn get_children_from_cli(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
-        let child_resp = Command::new("ot-ctl").arg("childip").output()?;
+    pub async fn get_children_from_cli(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        let child_resp = Command::new("ot-ctl")
+            .arg("childip")
+            .kill_on_drop(true)
+            .output()
+            .await?;
 
         if child_resp.status.success() {
             Ok(OtCliClient::parse_childip_output(
                 std::str::from_utf8(&child_resp.stdout)?.to_string(),
             ))
         } else {
-            Err(OtClientError::from(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed CLI Command: exit status {:?}", child_resp.status),
+            Err(OtClientError::from(std::io::Error::other(format!(
+                "Failed CLI Command: exit status {:?}",
+                child_resp.status
+            ))))
+        }
+    }
+
+    /// Children of the border router from `childip`, plus the children of
+    /// every other router from the mesh diagnostics. The diagnostics need
+    /// otbr-agent built with `OT_MESH_DIAG`, without it (or when no other
+    /// router answers) only the border router's children are found
+    pub async fn get_mesh_nodes_from_cli(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        let mut nodes = self.get_children_from_cli().await?;
+
+        let routers = match OtCliClient::ot_ctl(&["meshdiag", "topology"]).await {
+            Ok(topology) => OtCliClient::parse_topology_output(&topology, false),
+            Err(e) => {
+                log::debug!("No mesh diagnostics, only finding children: {e:}");
+                return Ok(nodes);
+            }
+        };
+
+        for router in routers {
+            match OtCliClient::ot_ctl(&["meshdiag", "childip6", &format!("{router:#06x}")]).await {
+                Ok(res) => nodes.extend(OtCliClient::parse_childip6_output(&res)),
+                Err(e) => log::warn!("Unable to get children of router {router:#06x}: {e:}"),
+            }
+        }
+        Ok(nodes)
+    }
+
+    async fn ot_ctl(args: &[&str]) -> Result<String, OtClientError> {
+        let resp = Command::new("ot-ctl")
+            .args(args)
+            .kill_on_drop(true)
+            .output()
+            .await?;
+
+        let out = std::str::from_utf8(&resp.stdout)?;
+        if resp.status.success() && !out.contains("Error") {
+            Ok(out.to_string())
+        } else {
+            Err(OtClientError::OtClientErr(format!(
+                "Failed CLI Command {}: exit status {:?}",
+                args.join(" "),
+                resp.status
+            )))
+        }
+    }
+
+    /// Rlocs of the routers listed by `meshdiag topology`, the border
+    /// router itself (marked `me`) only `with_self`
+    pub(crate) fn parse_topology_output(res: &str, with_self: bool) -> Vec<Rloc> {
+        res.lines()
+            .filter(|l| l.starts_with("id:") && (with_self || !l.contains(" - me")))
+            .filter_map(|l| {
+                l.split_whitespace()
+                    .find_map(|f| f.strip_prefix("rloc16:0x"))
+                    .and_then(|rloc| u16::from_str_radix(rloc, 16).ok())
+            })
+            .collect()
+    }
+
+    /// Children and their addrs from `meshdiag childip6`, one
+    /// `child-rloc16:` line per child followed by its addrs
+    pub(crate) fn parse_childip6_output(res: &str) -> Vec<(Rloc, Ipv6Addr)> {
+        let mut child = None;
+        let mut res_ips = vec![];
+        for l in res.lines().map(str::trim) {
+            if let Some(rloc) = l.strip_prefix("child-rloc16:") {
+                child = u16::from_str_radix(rloc.trim().trim_start_matches("0x"), 16).ok();
+            } else if let (Some(rloc), Ok(ip)) = (child, l.parse::<Ipv6Addr>()) {
+                res_ips.push((rloc, ip));
+            }
+        }
+        res_ips
+    }
+
+    /// The `_plantminder._udp` services registered with the SRP server
+    pub async fn get_plant_services_from_cli(&self) -> Result<Vec<PlantService>, OtClientError> {
+        Ok(OtCliClient::parse_srp_service_output(
+            &OtCliClient::ot_ctl(&["srp", "server", "service"]).await?,
+        ))
+    }
+
+    /// Live `_plantminder._udp` services from `srp server service`, one
+    /// block per service: its full name, then indented `key: value` lines.
+    /// TXT entries are printed as `key=<value in hex>`
+    pub(crate) fn parse_srp_service_output(res: &str) -> Vec<PlantService> {
+        let mut services = vec![];
+        let mut current: Option<PlantService> = None;
+        for l in res.lines() {
+            if !l.starts_with(char::is_whitespace) {
+                services.extend(current.take());
+                let l = l.trim();
+                current = l
+                    .split_once(&format!(".{SERVICE_TYPE}."))
+                    .map(|(instance, _)| PlantService {
+                        instance: instance.to_string(),
+                        ..Default::default()
+                    });
+                continue;
+            }
+            let (Some(service), Some((key, value))) = (current.as_mut(), l.trim().split_once(':'))
+            else {
+                continue;
+            };
+            let value = value.trim();
+            let list = || {
+                value
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|e| !e.is_empty())
+            };
+            match key {
+                // Removed services are kept until their key lease expires
+                "deleted" if value == "true" => current = None,
+                "port" => service.port = value.parse().unwrap_or_default(),
+                "addresses" => service.addrs = list().filter_map(|a| a.parse().ok()).collect(),
+                "TXT" => {
+                    for (key, hex) in list().filter_map(|e| e.split_once('=')) {
+                        let bytes: Option<Vec<u8>> = (0..hex.len())
+                            .step_by(2)
+                            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                            .collect();
+                        service.txt(key, &bytes.unwrap_or_default());
+                    }
+                }
+                _ => {}
+            }
+        }
+        services.extend(current);
+        services
+    }
+
+    /// Link metrics of the children of every router from the mesh
+    /// diagnostics, as [`OtCliClient::get_mesh_nodes_from_cli`]. Without
+    /// them only the border router's own children are measured, from its
+    /// child and neighbor tables
+    pub async fn get_link_metrics_from_cli(&self) -> Result<Vec<LinkMetrics>, OtClientError> {
+        let routers = match OtCliClient::ot_ctl(&["meshdiag", "topology"]).await {
+            Ok(topology) => OtCliClient::parse_topology_output(&topology, true),
+            Err(e) => {
+                log::debug!("No mesh diagnostics, only measuring children: {e:}");
+                return Ok(OtCliClient::parse_child_link_metrics(
+                    &OtCliClient::ot_ctl(&["child", "table"]).await?,
+                    &OtCliClient::ot_ctl(&["neighbor", "linkquality"]).await?,
+                ));
+            }
+        };
+
+        let mut metrics = vec![];
+        for router in routers {
+            match OtCliClient::ot_ctl(&["meshdiag", "childtable", &format!("{router:#06x}")]).await
+            {
+                Ok(res) => metrics.extend(OtCliClient::parse_childtable_output(&res)),
+                Err(e) => log::warn!("Unable to get child table of router {router:#06x}: {e:}"),
+            }
+        }
+        Ok(metrics)
+    }
+
+    /// Role, leader data and active dataset of the border router. A detached
+    /// border router has no leader data, nor one not commissioned a dataset
+    pub async fn get_network_state_from_cli(&self) -> Result<NetworkState, OtClientError> {
+        let state = OtCliClient::ot_ctl(&["state"]).await?;
+        let leader = OtCliClient::ot_ctl(&["leaderdata"])
+            .await
+            .unwrap_or_default();
+        let dataset = OtCliClient::ot_ctl(&["dataset", "active"])
+            .await
+            .unwrap_or_default();
+        OtCliClient::parse_network_state(&state, &leader, &dataset)
+    }
+
+    /// Network state from the output of `state`, `leaderdata` and
+    /// `dataset active`, the latter two as `Key: value` lines
+    pub(crate) fn parse_network_state(
+        state: &str,
+        leader: &str,
+        dataset: &str,
+    ) -> Result<NetworkState, OtClientError> {
+        let role =
+            state
+                .lines()
+                .find_map(DeviceRole::from_name)
+                .ok_or(OtClientError::OtClientErr(format!(
+                    "Unknown device role {}",
+                    state.trim()
+                )))?;
+        Ok(NetworkState {
+            role,
+            partition_id: OtCliClient::field(leader, "Partition ID").and_then(|v| v.parse().ok()),
+            leader_router_id: OtCliClient::field(leader, "Leader Router ID")
+                .and_then(|v| v.parse().ok()),
+            active_timestamp: OtCliClient::parse_dataset_output(dataset).active_timestamp,
+        })
+    }
+
+    /// Value of the `key: value` line of `res` for `key`
+    fn field(res: &str, key: &str) -> Option<String> {
+        res.lines()
+            .filter_map(|l| l.split_once(':'))
+            .find(|(k, _)| k.trim() == key)
+            .map(|(_, v)| v.trim().to_string())
+    }
+
+    /// The active dataset from `dataset active`
+    pub async fn get_active_dataset_from_cli(&self) -> Result<OperationalDataset, OtClientError> {
+        Ok(OtCliClient::parse_dataset_output(
+            &OtCliClient::ot_ctl(&["dataset", "active"]).await?,
+        ))
+    }
+
+    /// Generate a dataset, commit it as the active one and start Thread on
+    /// it. Returns the new dataset
+    pub async fn form_network_from_cli(&self) -> Result<OperationalDataset, OtClientError> {
+        for cmd in OtCliClient::FORM_NETWORK {
+            OtCliClient::ot_ctl(&cmd.split(' ').collect::<Vec<_>>()).await?;
+        }
+        self.get_active_dataset_from_cli().await
+    }
+
+    /// Commands that generate a dataset and start Thread on it
+    pub(crate) const FORM_NETWORK: [&'static str; 4] = [
+        "dataset init new",
+        "dataset commit active",
+        "ifconfig up",
+        "thread start",
+    ];
+
+    /// Dataset from the `Key: value` lines of `dataset active`
+    pub(crate) fn parse_dataset_output(res: &str) -> OperationalDataset {
+        let field = |key: &str| OtCliClient::field(res, key);
+        OperationalDataset {
+            active_timestamp: field("Active Timestamp").and_then(|v| v.parse().ok()),
+            network_name: field("Network Name"),
+            pan_id: field("PAN ID")
+                .and_then(|v| u16::from_str_radix(v.trim_start_matches("0x"), 16).ok()),
+            extended_pan_id: field("Ext PAN ID").and_then(|v| OperationalDataset::parse_hex(&v)),
+            channel: field("Channel").and_then(|v| v.parse().ok()),
+            mesh_local_prefix: field("Mesh Local Prefix").and_then(|v| v.parse().ok()),
+            network_key: field("Network Key").and_then(|v| OperationalDataset::parse_hex(&v)),
+        }
+    }
+
+    /// Petition to become the commissioner, unless the border router is
+    /// already petitioning or active
+    pub async fn start_commissioner_from_cli(&self) -> Result<CommissionerState, OtClientError> {
+        let state = OtCliClient::parse_commissioner_state(
+            &OtCliClient::ot_ctl(&["commissioner", "state"]).await?,
+        )?;
+        if state != CommissionerState::Disabled {
+            return Ok(state);
+        }
+        OtCliClient::ot_ctl(&["commissioner", "start"]).await?;
+        Ok(CommissionerState::Petition)
+    }
+
+    pub(crate) fn parse_commissioner_state(res: &str) -> Result<CommissionerState, OtClientError> {
+        res.lines()
+            .find_map(CommissionerState::from_name)
+            .ok_or(OtClientError::OtClientErr(format!(
+                "Unknown commissioner state {}",
+                res.trim()
             )))
+    }
+
+    /// `commissioner joiner add` arguments for `joiner`, the timeout in
+    /// seconds
+    pub(crate) fn joiner_add_args(joiner: &Joiner) -> [String; 3] {
+        [
+            joiner.eui64.to_string(),
+            joiner.pskd.as_str().to_string(),
+            joiner.timeout.as_secs().max(1).to_string(),
+        ]
+    }
+
+    pub async fn add_joiner_from_cli(&self, joiner: &Joiner) -> Result<(), OtClientError> {
+        let [eui64, pskd, timeout] = OtCliClient::joiner_add_args(joiner);
+        OtCliClient::ot_ctl(&["commissioner", "joiner", "add", &eui64, &pskd, &timeout]).await?;
+        Ok(())
+    }
+
+    pub async fn remove_joiner_from_cli(&self, eui64: &Eui64) -> Result<(), OtClientError> {
+        OtCliClient::ot_ctl(&["commissioner", "joiner", "remove", &eui64.to_string()]).await?;
+        Ok(())
+    }
+
+    pub async fn get_joiners_from_cli(&self) -> Result<Vec<Joiner>, OtClientError> {
+        Ok(OtCliClient::parse_joiner_table(
+            &OtCliClient::ot_ctl(&["commissioner", "joiner", "table"]).await?,
+        ))
+    }
+
+    /// Joiners from `commissioner joiner table`, with the ms left until
+    /// each expires. Joiners added by discerner or for any EUI-64 (`*`)
+    /// are skipped, only the ones added by EUI-64 are tracked
+    pub(crate) fn parse_joiner_table(res: &str) -> Vec<Joiner> {
+        OtCliClient::parse_table(res)
+            .iter()
+            .filter_map(|row| {
+                let field = |name: &str| {
+                    row.iter()
+                        .find(|(column, _)| column == name)
+                        .map(|(_, value)| value.as_str())
+                };
+                Some(Joiner {
+                    eui64: field("ID")?.parse().ok()?,
+                    pskd: Pskd::new(field("PSKd")?).ok()?,
+                    timeout: tokio::time::Duration::from_millis(field("Expiration")?.parse().ok()?),
+                })
+            })
+            .collect()
+    }
+
+    /// Children and their link metrics from `meshdiag childtable`, one
+    /// `rloc16:` line per child followed by its indented attributes
+    pub(crate) fn parse_childtable_output(res: &str) -> Vec<LinkMetrics> {
+        let mut metrics: Vec<LinkMetrics> = vec![];
+        for l in res.lines().map(str::trim) {
+            let mut fields = l.split_whitespace();
+            if let Some(rloc) = fields.next().and_then(|f| f.strip_prefix("rloc16:0x")) {
+                if let Ok(rloc) = u16::from_str_radix(rloc, 16) {
+                    metrics.push(LinkMetrics {
+                        rloc,
+                        parent: rloc & 0xfc00,
+                        ..Default::default()
+                    });
+                }
+                continue;
+            }
+            let Some(entry) = metrics.last_mut() else {
+                continue;
+            };
+            for (key, value) in l.split_whitespace().filter_map(|f| f.split_once(':')) {
+                match key {
+                    "ave" => entry.avg_rssi = value.parse().ok(),
+                    "last" => entry.last_rssi = value.parse().ok(),
+                    "margin" => entry.link_margin = value.parse().ok(),
+                    "frame" if l.starts_with("err-rate") => {
+                        entry.frame_error_rate = OtCliClient::parse_percent(value)
+                    }
+                    "msg" if l.starts_with("err-rate") => {
+                        entry.message_error_rate = OtCliClient::parse_percent(value)
+                    }
+                    _ => {}
+                }
+            }
         }
+        metrics
+            .into_iter()
+            .map(LinkMetrics::derive_link_quality)
+            .collect()
     }
 
-    fn parse_childip_output(res: String) -> Vec<(Rloc, Ipv6Addr)> {
+    /// Link metrics of the border router's children from `child table`
+    /// (link quality) and `neighbor linkquality` (RSSI and error rates)
+    pub(crate) fn parse_child_link_metrics(
+        child_table: &str,
+        linkquality: &str,
+    ) -> Vec<LinkMetrics> {
+        let neighbors = OtCliClient::parse_table(linkquality);
+        OtCliClient::parse_table(child_table)
+            .iter()
+            .filter_map(|child| {
+                let rloc = OtCliClient::table_rloc(child)?;
+                let neighbor = neighbors
+                    .iter()
+                    .find(|n| OtCliClient::table_rloc(n) == Some(rloc));
+                let field = |row: Option<&Vec<(String, String)>>, name: &str| {
+                    row?.iter()
+                        .find(|(column, _)| column == name)
+                        .map(|(_, value)| value.clone())
+                };
+                Some(LinkMetrics {
+                    rloc,
+                    parent: rloc & 0xfc00,
+                    avg_rssi: field(neighbor, "Avg RSS").and_then(|v| v.parse().ok()),
+                    last_rssi: field(neighbor, "Last RSS").and_then(|v| v.parse().ok()),
+                    link_quality: field(Some(child), "LQ In").and_then(|v| v.parse().ok()),
+                    link_margin: None,
+                    frame_error_rate: field(neighbor, "Frame Error")
+                        .and_then(|v| OtCliClient::parse_percent(&v)),
+                    message_error_rate: field(neighbor, "Msg Error")
+                        .and_then(|v| OtCliClient::parse_percent(&v)),
+                })
+            })
+            .collect()
+    }
+
+    /// Rows of a `| col | col |` table as (column, value) pairs
+    fn parse_table(res: &str) -> Vec<Vec<(String, String)>> {
+        let cells = |l: &str| -> Vec<String> {
+            l.trim()
+                .trim_matches('|')
+                .split('|')
+                .map(|c| c.trim().to_string())
+                .collect()
+        };
+        let mut lines = res.lines().filter(|l| l.trim_start().starts_with('|'));
+        let Some(header) = lines.next().map(cells) else {
+            return vec![];
+        };
+        lines
+            .map(|l| header.iter().cloned().zip(cells(l)).collect())
+            .collect()
+    }
+
+    fn table_rloc(row: &[(String, String)]) -> Option<Rloc> {
+        row.iter()
+            .find(|(column, _)| column == "RLOC16")
+            .and_then(|(_, rloc)| u16::from_str_radix(rloc.trim_start_matches("0x"), 16).ok())
+    }
+
+    /// `11.51%` or `11.51 %` as a share
+    fn parse_percent(value: &str) -> Option<f32> {
+        value
+            .trim()
+            .trim_end_matches('%')
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .map(|p| p / 100.0)
+    }
+
+    pub(crate) fn parse_childip_output(res: String) -> Vec<(Rloc, Ipv6Addr)> {
         let res = res.trim_end_matches("Done");
         let lines = res.split('\n').collect::<Vec<_>>();
 
@@ -50,8 +482,12 @@ impl OtCliClient {
         res
     }
 
-    fn get_omr_prefix_from_cli(&self) -> Result<Ipv6Net, OtClientError> {
-        let resp = Command::new("ot-ctl").arg("prefix").output()?;
+    async fn get_omr_prefix_from_cli(&self) -> Result<Ipv6Net, OtClientError> {
+        let resp = Command::new("ot-ctl")
+            .arg("prefix")
+            .kill_on_drop(true)
+            .output()
+            .await?;
 
         if resp.status.success() {
             OtCliClient::parse_prefix_output(std::str::from_utf8(&resp.stdout)?.to_string())
@@ -63,7 +499,7 @@ impl OtCliClient {
         }
     }
 
-    fn parse_prefix_output(res: String) -> Result<Ipv6Net, OtClientError> {
+    pub(crate) fn parse_prefix_output(res: String) -> Result<Ipv6Net, OtClientError> {
         let res = res.trim_end_matches("Done");
         let elems = res.split(' ').collect::<Vec<_>>();
         if elems.is_empty() {
@@ -75,25 +511,17 @@ impl OtCliClient {
         }
     }
 
-    fn get_ip_addrs_from_cli(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
-        let resp = Command::new("ot-ctl").arg("ipaddr").output()?;
+    async fn get_ip_addrs_from_cli(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+        let resp = Command::new("ot-ctl")
+            .arg("ipaddr")
+            .kill_on_drop(true)
+            .output()
+            .await?;
 
         if resp.status.success() {
-            let res = std::str::from_utf8(&resp.stdout)?.to_string();
-            let res = res.trim_end_matches("Done");
-            let elems = res.split('\n').collect::<Vec<_>>();
-            let ips = elems
-                .iter()
-                .map(|i| {
-                    let i = i.trim();
-                    i.parse::<std::net::Ipv6Addr>()
-                })
-                .collect::<Vec<_>>()
-                .iter()
-                .flatten()
-                .cloned()
-                .collect();
-            Ok(ips)
+            Ok(OtCliClient::parse_ipaddr_output(
+                std::str::from_utf8(&resp.stdout)?.to_string(),
+            ))
         } else {
             Err(OtClientError::OtClientErr(format!(
                 "Failed CLI Command: exit status {:?}",
@@ -102,10 +530,26 @@ impl OtCliClient {
         }
     }
 
-    pub fn get_omr_ip_addr_from_cli(&self) -> Result<Ipv6Addr, OtClientError> {
-        let prefix = self.get_omr_prefix_from_cli()?;
+    pub(crate) fn parse_ipaddr_output(res: String) -> Vec<Ipv6Addr> {
+        let res = res.trim_end_matches("Done");
+        let elems = res.split('\n').collect::<Vec<_>>();
+        elems
+            .iter()
+            .map(|i| {
+                let i = i.trim();
+                i.parse::<std::net::Ipv6Addr>()
+            })
+            .collect::<Vec<_>>()
+            .iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    pub async fn get_omr_ip_addr_from_cli(&self) -> Result<Ipv6Addr, OtClientError> {
+        let prefix = self.get_omr_prefix_from_cli().await?;
         let prefix_addr = prefix.addr();
-        let ips = self.get_ip_addrs_from_cli()?;
+        let ips = self.get_ip_addrs_from_cli().await?;
         if let Some(ip) = ips
             .iter()
             .find(|i| i.segments()[0] == prefix_addr.segments()[0])
@@ -119,21 +563,62 @@ impl OtCliClient {
     }
 }
 
+#[async_trait::async_trait]
 impl OtClient for OtCliClient {
-    fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
-        self.get_children_from_cli()
+    async fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        self.get_children_from_cli().await
+    }
+
+    async fn get_mesh_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        self.get_mesh_nodes_from_cli().await
+    }
+
+    async fn get_plant_services(&self) -> Result<Vec<PlantService>, OtClientError> {
+        self.get_plant_services_from_cli().await
+    }
+
+    async fn get_link_metrics(&self) -> Result<Vec<LinkMetrics>, OtClientError> {
+        self.get_link_metrics_from_cli().await
+    }
+
+    async fn get_network_state(&self) -> Result<NetworkState, OtClientError> {
+        self.get_network_state_from_cli().await
+    }
+
+    async fn get_active_dataset(&self) -> Result<OperationalDataset, OtClientError> {
+        self.get_active_dataset_from_cli().await
     }
 
-    fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError> {
-        self.get_omr_prefix_from_cli()
+    async fn form_network(&self) -> Result<OperationalDataset, OtClientError> {
+        self.form_network_from_cli().await
     }
 
-    fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError> {
-        self.get_omr_ip_addr_from_cli()
+    async fn start_commissioner(&self) -> Result<CommissionerState, OtClientError> {
+        self.start_commissioner_from_cli().await
     }
 
-    fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
-        self.get_ip_addrs_from_cli()
+    async fn add_joiner(&self, joiner: &Joiner) -> Result<(), OtClientError> {
+        self.add_joiner_from_cli(joiner).await
+    }
+
+    async fn remove_joiner(&self, eui64: &Eui64) -> Result<(), OtClientError> {
+        self.remove_joiner_from_cli(eui64).await
+    }
+
+    async fn get_joiners(&self) -> Result<Vec<Joiner>, OtClientError> {
+        self.get_joiners_from_cli().await
+    }
+
+    async fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError> {
+        self.get_omr_prefix_from_cli().await
+    }
+
+    async fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError> {
+        self.get_omr_ip_addr_from_cli().await
+    }
+
+    async fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+        self.get_ip_addrs_from_cli().await
     }
 }
 
@@ -142,7 +627,7 @@ mod tests {
     use ipnet::Ipv6Net;
     use std::net::Ipv6Addr;
 
-    use crate::OtCliClient;
+    use crate::{client::DeviceRole, OtCliClient};
 
     #[tokio::test]
     async fn check_cli_parse_child_ips() {
@@ -157,6 +642,158 @@ mod tests {
         );
     }
 
+    #[test]
+    fn check_cli_parse_mesh_diag() {
+        let topology = "id:02 rloc16:0x0800 ext-addr:8aa57d2c603fe16c ver:4 - me - leader\r\n   \
+            3-links:{ 46 }\r\nid:46 rloc16:0xb800 ext-addr:fe109d277e0175cc ver:4\r\n   \
+            3-links:{ 02 }\r\nDone\r\n";
+        assert_eq!(
+            OtCliClient::parse_topology_output(topology, false),
+            [0xb800]
+        );
+        assert_eq!(
+            OtCliClient::parse_topology_output(topology, true),
+            [0x0800, 0xb800]
+        );
+
+        let childip6 = "child-rloc16: 0xb801\r\n    fdde:ad00:beef:0:ded8:cd58:b73:2c21\r\n    \
+            fdc9:fdb2:9fe8:1:c34:6e6e:1c3a:2a5e\r\nchild-rloc16: 0xb802\r\n    \
+            fdc9:fdb2:9fe8:1:8ff8:a188:7436:6720\r\nDone\r\n";
+        let ret = OtCliClient::parse_childip6_output(childip6);
+        assert_eq!(ret.len(), 3);
+        assert_eq!(
+            ret[2],
+            (
+                0xb802,
+                Ipv6Addr::from([0xfdc9, 0xfdb2, 0x9fe8, 0x1, 0x8ff8, 0xa188, 0x7436, 0x6720])
+            )
+        );
+    }
+
+    #[test]
+    fn check_cli_parse_srp_services() {
+        let res = "6055f9f70778._plantminder._udp.default.service.arpa.\r\n    \
+            deleted: false\r\n    subtypes: (null)\r\n    port: 1212\r\n    priority: 0\r\n    \
+            weight: 0\r\n    ttl: 7200\r\n    lease: 7200\r\n    key-lease: 1209600\r\n    \
+            TXT: [name=426173696c, species=4f63696d756d, fw=302e312e30]\r\n    \
+            host: pmind-6055f9f70778.default.service.arpa.\r\n    \
+            addresses: [fdc9:fdb2:9fe8:1:c34:6e6e:1c3a:2a5e]\r\n\
+            printer._ipps._tcp.default.service.arpa.\r\n    deleted: false\r\n    \
+            port: 631\r\n    addresses: [fdc9:fdb2:9fe8:1::99]\r\n\
+            6055f9f70779._plantminder._udp.default.service.arpa.\r\n    deleted: true\r\n\
+            Done\r\n";
+        let ret = OtCliClient::parse_srp_service_output(res);
+        assert_eq!(ret.len(), 1);
+        assert_eq!(ret[0].instance, "6055f9f70778");
+        assert_eq!(ret[0].port, 1212);
+        assert_eq!(
+            ret[0].addrs,
+            [Ipv6Addr::from([
+                0xfdc9, 0xfdb2, 0x9fe8, 0x1, 0xc34, 0x6e6e, 0x1c3a, 0x2a5e
+            ])]
+        );
+        assert_eq!(
+            (ret[0].name.as_deref(), ret[0].species.as_deref()),
+            (Some("Basil"), Some("Ocimum"))
+        );
+        assert_eq!(ret[0].firmware.as_deref(), Some("0.1.0"));
+    }
+
+    #[test]
+    fn check_cli_parse_link_metrics() {
+        let childtable = "rloc16:0xb801 ext-addr:8e6f4d323bbed1fe ver:4\r\n    \
+            timeout:120 age:36 supvn:129 q-msg:0\r\n    rx-on:no type:mtd full-net:no\r\n    \
+            rss - ave:-72 last:-75 margin:28\r\n    err-rate - frame:11.51% msg:0.76%\r\n    \
+            conn-time:00:11:07\r\n    csl - sync:no period:0 timeout:0 channel:0\r\nDone\r\n";
+        let ret = OtCliClient::parse_childtable_output(childtable);
+        assert_eq!(ret.len(), 1);
+        assert_eq!((ret[0].rloc, ret[0].parent), (0xb801, 0xb800));
+        assert_eq!((ret[0].avg_rssi, ret[0].last_rssi), (Some(-72), Some(-75)));
+        assert_eq!(
+            (ret[0].link_margin, ret[0].link_quality),
+            (Some(28), Some(3))
+        );
+        assert!((ret[0].frame_error_rate.unwrap() - 0.1151).abs() < 1e-6);
+
+        let child_table = "| ID  | RLOC16 | Timeout    | Age        | LQ In | C_VN |R|D|N|Ver|CSL|QMsgCnt|Suprvsn| Extended MAC     |\r\n\
+            +-----+--------+------------+------------+-------+------+-+-+-+---+---+-------+-------+------------------+\r\n\
+            |   1 | 0xc001 |        240 |         24 |     2 |  131 |0|0|0|  4| 0 |     0 |   129 | 4ecede68435358ac |\r\nDone\r\n";
+        let linkquality = "| RLOC16 | Extended MAC     | Frame Error | Msg Error | Avg RSS | Last RSS | Age   |\r\n\
+            +--------+------------------+-------------+-----------+---------+----------+-------+\r\n\
+            | 0xe800 | 9e2fa4e1b84f92db |      0.00 % |    0.00 % |     -46 |      -48 |     1 |\r\n\
+            | 0xc001 | 4ecede68435358ac |      2.50 % |    0.00 % |     -68 |      -72 |    18 |\r\nDone\r\n";
+        let ret = OtCliClient::parse_child_link_metrics(child_table, linkquality);
+        assert_eq!(ret.len(), 1);
+        assert_eq!((ret[0].rloc, ret[0].parent), (0xc001, 0xc000));
+        assert_eq!((ret[0].avg_rssi, ret[0].link_quality), (Some(-68), Some(2)));
+        assert_eq!(ret[0].message_error_rate, Some(0.0));
+    }
+
+    #[tokio::test]
+    async fn check_cli_parse_network_state() {
+        let leader = "Partition ID: 1077744240\r\nWeighting: 64\r\nData Version: 109\r\n\
+            Stable Data Version: 211\r\nLeader Router ID: 60\r\nDone\r\n";
+        let dataset = "Active Timestamp: 2\r\nChannel: 15\r\nChannel Mask: 0x07fff800\r\n\
+            Ext PAN ID: 39758ec8144b07fb\r\nNetwork Name: OpenThread-5938\r\nDone\r\n";
+        let ret = OtCliClient::parse_network_state("router\r\nDone\r\n", leader, dataset)
+            .expect("Unable to parse network state");
+        assert_eq!(ret.role, DeviceRole::Router);
+        assert_eq!(
+            (ret.partition_id, ret.leader_router_id, ret.active_timestamp),
+            (Some(1077744240), Some(60), Some(2))
+        );
+
+        // Detached, the leader data query failed
+        let ret = OtCliClient::parse_network_state("detached\r\nDone\r\n", "", dataset)
+            .expect("Unable to parse network state");
+        assert_eq!((ret.role, ret.partition_id), (DeviceRole::Detached, None));
+        assert!(OtCliClient::parse_network_state("Done\r\n", "", "").is_err());
+    }
+
+    #[test]
+    fn check_cli_parse_dataset() {
+        let res = "Active Timestamp: 1\r\nChannel: 15\r\nChannel Mask: 0x07fff800\r\n\
+            Ext PAN ID: 39758ec8144b07fb\r\nMesh Local Prefix: fdf1:f1ad:d079:7dc0::/64\r\n\
+            Network Key: f366cec7a446bab978d90d27abe38f23\r\nNetwork Name: OpenThread-5938\r\n\
+            PAN ID: 0x5938\r\nPSKc: 3ca67c969efb0d0c74a4d8ee923b576c\r\n\
+            Security Policy: 672 onrc 0\r\nDone\r\n";
+        let ret = OtCliClient::parse_dataset_output(res);
+        assert_eq!(
+            (ret.active_timestamp, ret.channel, ret.pan_id),
+            (Some(1), Some(15), Some(0x5938))
+        );
+        assert_eq!(ret.network_name.as_deref(), Some("OpenThread-5938"));
+        assert_eq!(
+            ret.extended_pan_id,
+            Some([0x39, 0x75, 0x8e, 0xc8, 0x14, 0x4b, 0x07, 0xfb])
+        );
+        assert_eq!(
+            ret.mesh_local_prefix,
+            Some("fdf1:f1ad:d079:7dc0::/64".parse().unwrap())
+        );
+        assert_eq!(ret.network_key.map(|k| k[0]), Some(0xf3));
+    }
+
+    #[test]
+    fn check_cli_parse_joiner_table() {
+        let res = "| ID                    | PSKd                             | Expiration |\r\n\
+            +-----------------------+----------------------------------+------------+\r\n\
+            |                   *   |                           J01NME |      81015 |\r\n\
+            | d45e64fa83f81cf7      |                           J01NME |     101204 |\r\n\
+            | 0x0000000000000abc/12 |                           J01NME |     114360 |\r\n\
+            Done\r\n";
+        let ret = OtCliClient::parse_joiner_table(res);
+        assert_eq!(ret.len(), 1);
+        assert_eq!(ret[0].eui64.to_string(), "d45e64fa83f81cf7");
+        assert_eq!(ret[0].pskd.as_str(), "J01NME");
+        assert_eq!(ret[0].timeout, tokio::time::Duration::from_millis(101204));
+
+        assert_eq!(
+            OtCliClient::parse_commissioner_state("active\r\nDone\r\n").ok(),
+            Some(crate::CommissionerState::Active)
+        );
+    }
+
     #[tokio::test]
     async fn check_cli_parse_prefix() {
         let res = "fdc9:fdb2:9fe8:1::/64 paos low 4400\r\nDone".to_string();
@@ -0,0 +1,497 @@
+use ipnet::Ipv6Net;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv6Addr;
+use zbus::{
+    proxy,
+    zvariant::{OwnedValue, Type, Value},
+    Connection,
+};
+
+use crate::{
+    client::{DeviceRole, LinkMetrics, NetworkState, OperationalDataset, OtClient},
+    OtClientError, Rloc,
+};
+
+/// Default thread network interface that otbr-agent is bound to
+pub const DEFAULT_OT_INTERFACE: &str = "wpan0";
+
+/// Path to the kernel's table of per-interface IPv6 addresses
+const IF_INET6_PATH: &str = "/proc/net/if_inet6";
+
+/// Entry of the otbr-agent `ChildTable` property, signature `(tuuqqyyyyqqbbbb)`.
+/// D-Bus has no signed byte type, so the RSSI fields are carried as `u8`
+#[derive(
+    Serialize, Deserialize, Type, Value, OwnedValue, Debug, Clone, Copy, Default, PartialEq,
+)]
+pub struct ChildInfo {
+    pub ext_address: u64,
+    pub timeout: u32,
+    pub age: u32,
+    pub rloc16: u16,
+    pub child_id: u16,
+    pub network_data_version: u8,
+    pub link_quality_in: u8,
+    pub average_rssi: u8,
+    pub last_rssi: u8,
+    pub frame_error_rate: u16,
+    pub message_error_rate: u16,
+    pub rx_on_when_idle: bool,
+    pub full_thread_device: bool,
+    pub full_network_data: bool,
+    pub is_state_restoring: bool,
+}
+
+/// IPv6 prefix as encoded by otbr-agent, signature `(ayy)`
+#[derive(Serialize, Deserialize, Type, Value, OwnedValue, Debug, Clone, Default, PartialEq)]
+pub struct Ip6Prefix {
+    pub prefix: Vec<u8>,
+    pub length: u8,
+}
+
+/// The otbr-agent `LeaderData` property, signature `(uyyyy)`
+#[derive(Serialize, Deserialize, Type, Value, OwnedValue, Debug, Clone, Default, PartialEq)]
+pub struct LeaderData {
+    pub partition_id: u32,
+    pub weighting: u8,
+    pub data_version: u8,
+    pub stable_data_version: u8,
+    pub leader_router_id: u8,
+}
+
+/// Entry of the otbr-agent `OnMeshPrefixes` property,
+/// signature `((ayy)qybbbbbbbbb)`
+#[derive(Serialize, Deserialize, Type, Value, OwnedValue, Debug, Clone, Default, PartialEq)]
+pub struct OnMeshPrefix {
+    pub prefix: Ip6Prefix,
+    pub rloc16: u16,
+    pub preference: u8,
+    pub preferred: bool,
+    pub slaac: bool,
+    pub dhcp: bool,
+    pub configure: bool,
+    pub default_route: bool,
+    pub on_mesh: bool,
+    pub stable: bool,
+    pub nd_dns: bool,
+    pub dp: bool,
+}
+
+#[proxy(
+    interface = "io.openthread.BorderRouter",
+    default_service = "io.openthread.BorderRouter.wpan0",
+    default_path = "/io/openthread/BorderRouter/wpan0",
+    gen_blocking = false
+)]
+trait BorderRouter {
+    #[zbus(property)]
+    fn child_table(&self) -> zbus::Result<Vec<ChildInfo>>;
+
+    #[zbus(property)]
+    fn on_mesh_prefixes(&self) -> zbus::Result<Vec<OnMeshPrefix>>;
+
+    #[zbus(property)]
+    fn mesh_local_prefix(&self) -> zbus::Result<Vec<u8>>;
+
+    #[zbus(property)]
+    fn rloc16(&self) -> zbus::Result<u16>;
+
+    #[zbus(property)]
+    fn device_role(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn leader_data(&self) -> zbus::Result<LeaderData>;
+
+    #[zbus(property)]
+    fn active_dataset_tlvs(&self) -> zbus::Result<Vec<u8>>;
+}
+
+/// Implementation of the [`crate::client::OtClient`] trait that talks
+/// to the otbr-agent over its `io.openthread.BorderRouter` D-Bus interface
+/// instead of spawning `ot-ctl` processes.
+///
+/// The D-Bus interface does not publish the unicast addresses registered
+/// by children, so child addresses are reported as the child's mesh-local
+/// RLOC address (`<mesh local prefix>::ff:fe00:<rloc16>`). Nor does it
+/// offer mesh diagnostics, so only the border router's own children are
+/// found. The addresses of the border router itself are read from the
+/// kernel's view of the thread interface
+pub struct OtDbusClient {
+    proxy: BorderRouterProxy<'static>,
+    interface: String,
+}
+
+impl OtDbusClient {
+    /// Connect to the otbr-agent instance bound to `interface` (e.g. `wpan0`)
+    /// via the system bus
+    pub async fn new(interface: &str) -> Result<Self, OtClientError> {
+        Self::with_connection(Connection::system().await?, interface).await
+    }
+
+    /// Use an existing connection, such as a private bus or peer to peer
+    /// connection to a mock service
+    pub async fn with_connection(conn: Connection, interface: &str) -> Result<Self, OtClientError> {
+        let proxy = BorderRouterProxy::builder(&conn)
+            .destination(format!("io.openthread.BorderRouter.{interface}"))?
+            .path(format!("/io/openthread/BorderRouter/{interface}"))?
+            .build()
+            .await?;
+
+        Ok(Self {
+            proxy,
+            interface: interface.to_string(),
+        })
+    }
+
+    pub async fn get_children_from_dbus(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        let mesh_local = self.proxy.mesh_local_prefix().await?;
+        let children = self.proxy.child_table().await?;
+        OtDbusClient::child_rloc_addrs(&mesh_local, &children)
+    }
+
+    /// The child table carries the link metrics of each child, the RSSI
+    /// reinterpreted as signed and the error rates scaled to `0xffff`
+    pub async fn get_link_metrics_from_dbus(&self) -> Result<Vec<LinkMetrics>, OtClientError> {
+        let parent = self.proxy.rloc16().await?;
+        Ok(self
+            .proxy
+            .child_table()
+            .await?
+            .iter()
+            .map(|c| LinkMetrics {
+                rloc: c.rloc16,
+                parent,
+                avg_rssi: Some(c.average_rssi as i8),
+                last_rssi: Some(c.last_rssi as i8),
+                link_quality: Some(c.link_quality_in),
+                link_margin: None,
+                frame_error_rate: Some(c.frame_error_rate as f32 / u16::MAX as f32),
+                message_error_rate: Some(c.message_error_rate as f32 / u16::MAX as f32),
+            })
+            .collect())
+    }
+
+    /// A detached border router has no leader data, nor one not
+    /// commissioned a dataset
+    pub async fn get_network_state_from_dbus(&self) -> Result<NetworkState, OtClientError> {
+        let role = self.proxy.device_role().await?;
+        let role = DeviceRole::from_name(&role).ok_or(OtClientError::OtClientErr(format!(
+            "Unknown device role {role}"
+        )))?;
+        let leader = match role.is_attached() {
+            true => self.proxy.leader_data().await.ok(),
+            false => None,
+        };
+        let dataset = self.proxy.active_dataset_tlvs().await.unwrap_or_default();
+
+        Ok(NetworkState {
+            role,
+            partition_id: leader.as_ref().map(|l| l.partition_id),
+            leader_router_id: leader.as_ref().map(|l| l.leader_router_id),
+            active_timestamp: OperationalDataset::from_tlvs(&dataset).active_timestamp,
+        })
+    }
+
+    /// The active dataset from its TLVs. The D-Bus API has no commissioner,
+    /// joiners are added through the CLI clients
+    pub async fn get_active_dataset_from_dbus(&self) -> Result<OperationalDataset, OtClientError> {
+        Ok(OperationalDataset::from_tlvs(
+            &self.proxy.active_dataset_tlvs().await?,
+        ))
+    }
+
+    fn child_rloc_addrs(
+        mesh_local: &[u8],
+        children: &[ChildInfo],
+    ) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        if mesh_local.len() < 8 {
+            return Err(OtClientError::OtClientErr(
+                "Invalid mesh local prefix".to_string(),
+            ));
+        }
+
+        Ok(children
+            .iter()
+            .map(|c| {
+                let mut octets = [0u8; 16];
+                octets[..8].copy_from_slice(&mesh_local[..8]);
+                octets[8..14].copy_from_slice(&[0x00, 0x00, 0x00, 0xff, 0xfe, 0x00]);
+                octets[14..].copy_from_slice(&c.rloc16.to_be_bytes());
+                (c.rloc16, Ipv6Addr::from(octets))
+            })
+            .collect())
+    }
+
+    async fn get_omr_prefix_from_dbus(&self) -> Result<Ipv6Net, OtClientError> {
+        OtDbusClient::select_omr_prefix(&self.proxy.on_mesh_prefixes().await?)
+    }
+
+    fn select_omr_prefix(prefixes: &[OnMeshPrefix]) -> Result<Ipv6Net, OtClientError> {
+        // Match the `paos` flags that `ot-ctl prefix` reports for the OMR prefix
+        let omr = prefixes
+            .iter()
+            .find(|p| p.preferred && p.slaac && p.on_mesh && p.stable)
+            .ok_or(OtClientError::OtClientErr(
+                "No prefix is currently set".to_string(),
+            ))?;
+
+        let mut octets = [0u8; 16];
+        let len = omr.prefix.prefix.len().min(16);
+        octets[..len].copy_from_slice(&omr.prefix.prefix[..len]);
+
+        Ipv6Net::new(Ipv6Addr::from(octets), omr.prefix.length)
+            .map_err(|e| OtClientError::OtClientErr(format!("Invalid prefix length {e:}")))
+    }
+
+    async fn get_ip_addrs_from_kernel(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+        let table = tokio::fs::read_to_string(IF_INET6_PATH).await?;
+        Ok(OtDbusClient::parse_if_inet6(&table, &self.interface))
+    }
+
+    fn parse_if_inet6(table: &str, interface: &str) -> Vec<Ipv6Addr> {
+        table
+            .lines()
+            .filter_map(|l| {
+                let elems = l.split_whitespace().collect::<Vec<_>>();
+                if elems.len() == 6 && elems[5] == interface {
+                    u128::from_str_radix(elems[0], 16).ok().map(Ipv6Addr::from)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub async fn get_omr_ip_addr_from_dbus(&self) -> Result<Ipv6Addr, OtClientError> {
+        let prefix = self.get_omr_prefix_from_dbus().await?;
+        let ips = self.get_ip_addrs_from_kernel().await?;
+        if let Some(ip) = ips.iter().find(|i| prefix.contains(*i)) {
+            Ok(*ip)
+        } else {
+            Err(OtClientError::OtClientErr(
+                "No matching prefix found".to_string(),
+            ))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl OtClient for OtDbusClient {
+    async fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        self.get_children_from_dbus().await
+    }
+
+    async fn get_link_metrics(&self) -> Result<Vec<LinkMetrics>, OtClientError> {
+        self.get_link_metrics_from_dbus().await
+    }
+
+    async fn get_network_state(&self) -> Result<NetworkState, OtClientError> {
+        self.get_network_state_from_dbus().await
+    }
+
+    async fn get_active_dataset(&self) -> Result<OperationalDataset, OtClientError> {
+        self.get_active_dataset_from_dbus().await
+    }
+
+    async fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError> {
+        self.get_omr_prefix_from_dbus().await
+    }
+
+    async fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError> {
+        self.get_omr_ip_addr_from_dbus().await
+    }
+
+    async fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+        self.get_ip_addrs_from_kernel().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ipnet::Ipv6Net;
+    use std::{net::Ipv6Addr, os::unix::net::UnixStream};
+    use zbus::{connection::Builder, Connection};
+
+    use super::{ChildInfo, Ip6Prefix, LeaderData, OnMeshPrefix, OtDbusClient};
+    use crate::{client::DeviceRole, OtClient};
+
+    struct MockBorderRouter {
+        children: Vec<ChildInfo>,
+        prefixes: Vec<OnMeshPrefix>,
+    }
+
+    #[zbus::interface(name = "io.openthread.BorderRouter")]
+    impl MockBorderRouter {
+        #[zbus(property)]
+        fn child_table(&self) -> Vec<ChildInfo> {
+            self.children.clone()
+        }
+
+        #[zbus(property)]
+        fn on_mesh_prefixes(&self) -> Vec<OnMeshPrefix> {
+            self.prefixes.clone()
+        }
+
+        #[zbus(property)]
+        fn mesh_local_prefix(&self) -> Vec<u8> {
+            vec![0xfd, 0xde, 0xad, 0x00, 0xbe, 0xef, 0x00, 0x00]
+        }
+
+        #[zbus(property)]
+        fn rloc16(&self) -> u16 {
+            0xc000
+        }
+
+        #[zbus(property)]
+        fn device_role(&self) -> String {
+            "leader".to_string()
+        }
+
+        #[zbus(property)]
+        fn leader_data(&self) -> LeaderData {
+            LeaderData {
+                partition_id: 0x4d3c2b1a,
+                leader_router_id: 48,
+                ..Default::default()
+            }
+        }
+
+        #[zbus(property)]
+        fn active_dataset_tlvs(&self) -> Vec<u8> {
+            // Channel, an Active Timestamp of 3 seconds, then the PAN ID
+            vec![
+                0, 3, 0, 0, 15, 14, 8, 0, 0, 0, 0, 0, 3, 0, 0, 1, 2, 0x58, 0xd1,
+            ]
+        }
+    }
+
+    /// Serve the mock interface on one end of a private peer to peer bus
+    /// and hand back a client connected to the other end
+    async fn mock_client(mock: MockBorderRouter) -> (OtDbusClient, Connection) {
+        let (server_sock, client_sock) = UnixStream::pair().expect("Unable to create socket pair");
+        let guid = zbus::Guid::generate();
+
+        let server = async {
+            Builder::async_io_unix_stream(server_sock)
+                .server(guid)?
+                .p2p()
+                .serve_at("/io/openthread/BorderRouter/wpan0", mock)?
+                .build()
+                .await
+        };
+        let client = Builder::async_io_unix_stream(client_sock).p2p().build();
+
+        // Both ends of the handshake must be driven at once
+        let (server, conn) = futures::join!(server, client);
+        let server = server.expect("Unable to start mock service");
+        let conn = conn.expect("Unable to connect to mock service");
+
+        (
+            OtDbusClient::with_connection(conn, "wpan0")
+                .await
+                .expect("Unable to build client"),
+            server,
+        )
+    }
+
+    #[tokio::test]
+    async fn check_dbus_child_ips() {
+        let (client, _server) = mock_client(MockBorderRouter {
+            children: vec![
+                ChildInfo {
+                    rloc16: 0xc04f,
+                    ..Default::default()
+                },
+                ChildInfo {
+                    rloc16: 0xc050,
+                    link_quality_in: 2,
+                    average_rssi: -70i8 as u8,
+                    frame_error_rate: 0x8000,
+                    ..Default::default()
+                },
+            ],
+            prefixes: vec![],
+        })
+        .await;
+
+        let ret = client
+            .get_child_ips()
+            .await
+            .expect("Unable to get child ips");
+        assert_eq!(
+            ret[1],
+            (
+                0xc050,
+                Ipv6Addr::from([0xfdde, 0xad00, 0xbeef, 0x0, 0x0, 0xff, 0xfe00, 0xc050])
+            )
+        );
+
+        let metrics = client
+            .get_link_metrics()
+            .await
+            .expect("Unable to get link metrics");
+        assert_eq!((metrics[1].rloc, metrics[1].parent), (0xc050, 0xc000));
+        assert_eq!(
+            (metrics[1].avg_rssi, metrics[1].link_quality),
+            (Some(-70), Some(2))
+        );
+        assert!((metrics[1].frame_error_rate.unwrap() - 0.5).abs() < 0.01);
+    }
+
+    #[tokio::test]
+    async fn check_dbus_omr_prefix() {
+        let (client, _server) = mock_client(MockBorderRouter {
+            children: vec![],
+            prefixes: vec![OnMeshPrefix {
+                prefix: Ip6Prefix {
+                    prefix: vec![0xfd, 0xc9, 0xfd, 0xb2, 0x9f, 0xe8, 0x00, 0x01],
+                    length: 64,
+                },
+                preferred: true,
+                slaac: true,
+                on_mesh: true,
+                stable: true,
+                ..Default::default()
+            }],
+        })
+        .await;
+
+        let ret: Ipv6Net = client
+            .get_omr_prefix()
+            .await
+            .expect("Unable to get Ipv6Net");
+        assert_eq!(ret, "fdc9:fdb2:9fe8:1::/64".parse().unwrap());
+
+        let state = client
+            .get_network_state()
+            .await
+            .expect("Unable to get network state");
+        assert_eq!(state.role, DeviceRole::Leader);
+        assert_eq!(
+            (state.partition_id, state.leader_router_id),
+            (Some(0x4d3c2b1a), Some(48))
+        );
+        assert_eq!(state.active_timestamp, Some(3));
+
+        let dataset = client
+            .get_active_dataset()
+            .await
+            .expect("Unable to get active dataset");
+        assert_eq!(
+            (dataset.channel, dataset.pan_id, dataset.active_timestamp),
+            (Some(15), Some(0x58d1), Some(3))
+        );
+    }
+
+    #[tokio::test]
+    async fn check_parse_if_inet6() {
+        let table = "fdc9fdb29fe800016e0a4c3b2a1d9e01 03 40 00 80    wpan0\n\
+            fe80000000000000a0b1c2d3e4f50617 03 40 20 80    wpan0\n\
+            fdc9fdb29fe80001000000000000beef 02 40 00 80     eth0\n";
+        let ret = OtDbusClient::parse_if_inet6(table, "wpan0");
+        assert_eq!(ret.len(), 2);
+        assert_eq!(
+            ret[0],
+            Ipv6Addr::from([0xfdc9, 0xfdb2, 0x9fe8, 0x1, 0x6e0a, 0x4c3b, 0x2a1d, 0x9e01])
+        );
+    }
+}
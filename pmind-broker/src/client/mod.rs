@@ -1,12 +1,23 @@
 //! Mod for different impls for interfacing with otbr-agent
-//! currently only ot-ctl is implemented, soon to implement
-//! something safer like DBus
+//! currently supports the ot-ctl CLI process, the otbr-agent
+//! CLI socket, the otbr-agent DBus interface and the otbr-agent
+//! REST server
 
 mod cli;
-use crate::Rloc;
+mod dbus;
+mod rest;
+mod socket;
+use crate::{
+    commission::{CommissionerState, Eui64, Joiner},
+    Rloc,
+};
 pub use cli::OtCliClient;
+pub use dbus::{OtDbusClient, DEFAULT_OT_INTERFACE};
+pub use rest::{OtRestClient, DEFAULT_OT_REST_URL};
+pub use socket::{OtSocketClient, DEFAULT_OT_CLI_SOCKET};
 
 use ipnet::Ipv6Net;
+use pmindp_protocol::service::{TXT_FIRMWARE, TXT_NAME, TXT_SPECIES};
 use std::net::Ipv6Addr;
 use thiserror::Error;
 
@@ -18,16 +29,295 @@ pub enum OtClientError {
     StrParse(#[from] std::str::Utf8Error),
     #[error("AddrParse error")]
     AddrParse(#[from] ipnet::AddrParseError),
+    #[error("DBus Error")]
+    Dbus(#[from] zbus::Error),
+    #[error("HTTP Error")]
+    Http(#[from] Box<ureq::Error>),
+    #[error("Json parse Error")]
+    Json(#[from] serde_json::Error),
+    #[error("Blocking task Error")]
+    Join(#[from] tokio::task::JoinError),
     #[error("OT Client Error {0}")]
     OtClientErr(String),
+    #[error("{0} not supported by this client")]
+    Unsupported(&'static str),
+}
+
+/// A `_plantminder._udp` service registered with the border router's SRP
+/// server, see [`pmindp_protocol::service`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlantService {
+    /// Service instance name, the node's EUI in hex
+    pub instance: String,
+    /// Addrs the node registered for its host
+    pub addrs: Vec<Ipv6Addr>,
+    /// Port the node serves CoAP on
+    pub port: u16,
+    /// Plant name from the TXT record
+    pub name: Option<String>,
+    /// Plant species from the TXT record
+    pub species: Option<String>,
+    /// Firmware version from the TXT record
+    pub firmware: Option<String>,
+}
+
+impl PlantService {
+    /// Set the field of the TXT `key`, unknown keys are ignored
+    pub(crate) fn txt(&mut self, key: &str, value: &[u8]) {
+        let value = Some(String::from_utf8_lossy(value).into_owned());
+        match key {
+            TXT_NAME => self.name = value,
+            TXT_SPECIES => self.species = value,
+            TXT_FIRMWARE => self.firmware = value,
+            _ => {}
+        }
+    }
+}
+
+/// Quality of a node's link to its parent, as the parent measures it from
+/// its neighbor (child) table. Clients fill in what the otbr-agent reports
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkMetrics {
+    /// [`Rloc`] of the node
+    pub rloc: Rloc,
+    /// [`Rloc`] of the router the node is attached to
+    pub parent: Rloc,
+    /// Average RSSI (dBm) of the frames the parent received from the node
+    pub avg_rssi: Option<i8>,
+    /// RSSI (dBm) of the last frame the parent received from the node
+    pub last_rssi: Option<i8>,
+    /// Link quality indicator (0 to 3) of the frames from the node
+    pub link_quality: Option<u8>,
+    /// Link margin (dB) of the frames from the node over the noise floor
+    pub link_margin: Option<u8>,
+    /// Share (0 to 1) of the frames to the node that were not acknowledged
+    pub frame_error_rate: Option<f32>,
+    /// Share (0 to 1) of the messages to the node that failed
+    pub message_error_rate: Option<f32>,
+}
+
+impl LinkMetrics {
+    /// Fill in the link quality from the link margin, as Thread derives it
+    pub(crate) fn derive_link_quality(mut self) -> Self {
+        if let (None, Some(margin)) = (self.link_quality, self.link_margin) {
+            self.link_quality = Some(match margin {
+                21.. => 3,
+                11..=20 => 2,
+                3..=10 => 1,
+                _ => 0,
+            });
+        }
+        self
+    }
+}
+
+/// Role of the border router on the Thread network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeviceRole {
+    #[default]
+    Disabled,
+    Detached,
+    Child,
+    Router,
+    Leader,
+}
+
+impl DeviceRole {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceRole::Disabled => "disabled",
+            DeviceRole::Detached => "detached",
+            DeviceRole::Child => "child",
+            DeviceRole::Router => "router",
+            DeviceRole::Leader => "leader",
+        }
+    }
+
+    /// The role named as otbr-agent reports it, e.g. `leader`
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            DeviceRole::Disabled,
+            DeviceRole::Detached,
+            DeviceRole::Child,
+            DeviceRole::Router,
+            DeviceRole::Leader,
+        ]
+        .into_iter()
+        .find(|r| r.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// Whether the border router is part of a partition, and can reach
+    /// the nodes
+    pub fn is_attached(&self) -> bool {
+        matches!(
+            self,
+            DeviceRole::Child | DeviceRole::Router | DeviceRole::Leader
+        )
+    }
+}
+
+/// State of the Thread network as the border router sees it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetworkState {
+    pub role: DeviceRole,
+    /// Partition the border router is attached to, `None` while detached
+    pub partition_id: Option<u32>,
+    /// Router ID of the partition's leader, `None` while detached
+    pub leader_router_id: Option<u8>,
+    /// Seconds of the active operational dataset's timestamp, which every
+    /// change to the dataset advances. `None` without a dataset
+    pub active_timestamp: Option<u64>,
+}
+
+/// Operational dataset of the Thread network, the parameters and
+/// credentials every device on it shares
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperationalDataset {
+    /// Seconds of the dataset's timestamp
+    pub active_timestamp: Option<u64>,
+    pub network_name: Option<String>,
+    pub pan_id: Option<u16>,
+    pub extended_pan_id: Option<[u8; 8]>,
+    pub channel: Option<u16>,
+    pub mesh_local_prefix: Option<Ipv6Net>,
+    /// Key every frame on the network is secured with, what joiners are
+    /// commissioned for
+    pub network_key: Option<[u8; 16]>,
+}
+
+impl OperationalDataset {
+    const CHANNEL_TLV: u8 = 0;
+    const PAN_ID_TLV: u8 = 1;
+    const EXTENDED_PAN_ID_TLV: u8 = 2;
+    const NETWORK_NAME_TLV: u8 = 3;
+    const NETWORK_KEY_TLV: u8 = 5;
+    const MESH_LOCAL_PREFIX_TLV: u8 = 7;
+    const ACTIVE_TIMESTAMP_TLV: u8 = 14;
+
+    /// Dataset from its TLVs, as the otbr-agent stores it. Unknown and
+    /// malformed TLVs are skipped
+    pub(crate) fn from_tlvs(mut tlvs: &[u8]) -> Self {
+        let mut dataset = OperationalDataset::default();
+        while let [tlv, len, rest @ ..] = tlvs {
+            let Some(value) = rest.get(..*len as usize) else {
+                break;
+            };
+            match *tlv {
+                // Channel page, then the channel
+                OperationalDataset::CHANNEL_TLV => {
+                    dataset.channel = value.get(1..3).map(|c| u16::from_be_bytes([c[0], c[1]]))
+                }
+                OperationalDataset::PAN_ID_TLV => {
+                    dataset.pan_id = value.try_into().ok().map(u16::from_be_bytes)
+                }
+                OperationalDataset::EXTENDED_PAN_ID_TLV => {
+                    dataset.extended_pan_id = value.try_into().ok()
+                }
+                OperationalDataset::NETWORK_NAME_TLV => {
+                    dataset.network_name = std::str::from_utf8(value).ok().map(str::to_string)
+                }
+                OperationalDataset::NETWORK_KEY_TLV => dataset.network_key = value.try_into().ok(),
+                OperationalDataset::MESH_LOCAL_PREFIX_TLV => {
+                    dataset.mesh_local_prefix = <[u8; 8]>::try_from(value).ok().map(|prefix| {
+                        let mut octets = [0u8; 16];
+                        octets[..8].copy_from_slice(&prefix);
+                        Ipv6Net::new(octets.into(), 64).unwrap_or_default()
+                    })
+                }
+                // Seconds in the upper 48 bits, then ticks and the
+                // authoritative bit
+                OperationalDataset::ACTIVE_TIMESTAMP_TLV => {
+                    dataset.active_timestamp =
+                        value.try_into().ok().map(|ts| u64::from_be_bytes(ts) >> 16)
+                }
+                _ => {}
+            }
+            tlvs = &rest[*len as usize..];
+        }
+        dataset
+    }
+
+    /// `N` bytes of hex, e.g. a network key as `dataset active` prints it
+    pub(crate) fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+        let hex = hex.trim().trim_start_matches("0x");
+        if hex.len() != 2 * N {
+            return None;
+        }
+        let mut bytes = [0u8; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+        }
+        Some(bytes)
+    }
 }
 
 /// Trait to allow different implementations for interfacing with the
-/// otbr-agent
+/// otbr-agent. The broker accepts any implementation via
+/// [`crate::broker_with_client`]. Methods are async so that a slow or
+/// hung otbr-agent never blocks the actix arbiter; implementations that
+/// can only make blocking calls must move them onto a blocking pool
+/// (e.g. [`tokio::task::spawn_blocking`])
+#[async_trait::async_trait]
 pub trait OtClient: Send + Sync {
-    fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError>;
-    fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError>;
-    fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError>;
+    /// Get the [`Rloc`] and IPv6 addr(s) of the children on the mesh
+    async fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError>;
+    /// Get the [`Rloc`] and IPv6 addr(s) of the children of every router on
+    /// the mesh, not only the border router's own. Defaults to
+    /// [`OtClient::get_child_ips`] for clients that can only see those
+    async fn get_mesh_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        self.get_child_ips().await
+    }
+    /// Get the `_plantminder._udp` services registered with the border
+    /// router's SRP server. Clients that cannot browse the SRP server fail,
+    /// see [`crate::NodeDiscovery::Service`]
+    async fn get_plant_services(&self) -> Result<Vec<PlantService>, OtClientError> {
+        Err(OtClientError::Unsupported("SRP server services"))
+    }
+    /// Get the [`LinkMetrics`] of the nodes attached to any router on the
+    /// mesh, or only to the border router for clients without mesh
+    /// diagnostics
+    async fn get_link_metrics(&self) -> Result<Vec<LinkMetrics>, OtClientError> {
+        Err(OtClientError::Unsupported("Link metrics"))
+    }
+    /// Get the role of the border router, the partition and leader it is
+    /// attached to and the version of the active dataset. Fails while the
+    /// otbr-agent is down; clients that cannot tell fail with
+    /// [`OtClientError::Unsupported`], the network is then taken to be up
+    async fn get_network_state(&self) -> Result<NetworkState, OtClientError> {
+        Err(OtClientError::Unsupported("Network state"))
+    }
+    /// Get the active operational dataset of the network
+    async fn get_active_dataset(&self) -> Result<OperationalDataset, OtClientError> {
+        Err(OtClientError::Unsupported("Active dataset"))
+    }
+    /// Form a new network: generate a dataset with random credentials,
+    /// make it active and bring Thread up. Returns the new dataset
+    async fn form_network(&self) -> Result<OperationalDataset, OtClientError> {
+        Err(OtClientError::Unsupported("Forming a network"))
+    }
+    /// Petition to become the network's commissioner, unless the border
+    /// router is already. Returns the state of the commissioner, which
+    /// only becomes active once the leader accepts the petition
+    async fn start_commissioner(&self) -> Result<CommissionerState, OtClientError> {
+        Err(OtClientError::Unsupported("Commissioning"))
+    }
+    /// Let `joiner` join the network, the commissioner must be active
+    async fn add_joiner(&self, _joiner: &Joiner) -> Result<(), OtClientError> {
+        Err(OtClientError::Unsupported("Commissioning"))
+    }
+    /// Stop the joiner `eui64` from joining
+    async fn remove_joiner(&self, _eui64: &Eui64) -> Result<(), OtClientError> {
+        Err(OtClientError::Unsupported("Commissioning"))
+    }
+    /// Get the joiners the commissioner still lets join
+    async fn get_joiners(&self) -> Result<Vec<Joiner>, OtClientError> {
+        Err(OtClientError::Unsupported("Commissioning"))
+    }
+    /// Get the currently set OMR prefix
+    async fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError>;
+    /// Get the border router's addr on the OMR prefix
+    async fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError>;
+    /// Get all of the border router's thread interface addrs
     #[allow(unused)]
-    fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError>;
+    async fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError>;
 }
@@ -0,0 +1,656 @@
+use ipnet::Ipv6Net;
+use serde::Deserialize;
+use std::{net::Ipv6Addr, time::Duration};
+
+use crate::{
+    client::{DeviceRole, LinkMetrics, NetworkState, OperationalDataset, OtClient},
+    OtClientError, Rloc,
+};
+
+/// Default address of the otbr-agent REST server
+pub const DEFAULT_OT_REST_URL: &str = "http://localhost:8081";
+
+/// Subset of the otbr-agent `GET /node` response that the client needs
+#[derive(Deserialize, Debug, Clone)]
+pub struct NodeInfo {
+    #[serde(rename = "Rloc16")]
+    pub rloc16: serde_json::Value,
+    #[serde(rename = "RlocAddress")]
+    pub rloc_address: Ipv6Addr,
+    #[serde(rename = "ExtAddress", default)]
+    pub ext_address: String,
+    #[serde(rename = "NetworkName", default)]
+    pub network_name: String,
+    #[serde(rename = "State", default)]
+    pub state: serde_json::Value,
+    #[serde(rename = "LeaderData", default)]
+    pub leader_data: Option<RestLeaderData>,
+}
+
+/// Leader data in the otbr-agent `GET /node` response
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct RestLeaderData {
+    #[serde(rename = "PartitionId")]
+    pub partition_id: u32,
+    #[serde(rename = "LeaderRouterId")]
+    pub leader_router_id: u8,
+}
+
+/// Subset of the otbr-agent `GET /node/dataset/active` response
+#[derive(Deserialize, Debug, Clone)]
+struct ActiveDataset {
+    #[serde(rename = "ActiveTimestamp")]
+    active_timestamp: Option<ActiveTimestamp>,
+    #[serde(rename = "NetworkName")]
+    network_name: Option<String>,
+    #[serde(rename = "PanId")]
+    pan_id: Option<u16>,
+    #[serde(rename = "ExtPanId")]
+    extended_pan_id: Option<String>,
+    #[serde(rename = "Channel")]
+    channel: Option<u16>,
+    #[serde(rename = "MeshLocalPrefix")]
+    mesh_local_prefix: Option<String>,
+    #[serde(rename = "NetworkKey")]
+    network_key: Option<String>,
+}
+
+impl From<ActiveDataset> for OperationalDataset {
+    fn from(dataset: ActiveDataset) -> Self {
+        OperationalDataset {
+            active_timestamp: dataset.active_timestamp.map(|ts| ts.seconds),
+            network_name: dataset.network_name,
+            pan_id: dataset.pan_id,
+            extended_pan_id: dataset
+                .extended_pan_id
+                .and_then(|id| OperationalDataset::parse_hex(&id)),
+            channel: dataset.channel,
+            mesh_local_prefix: dataset.mesh_local_prefix.and_then(|p| p.parse().ok()),
+            network_key: dataset
+                .network_key
+                .and_then(|key| OperationalDataset::parse_hex(&key)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+struct ActiveTimestamp {
+    #[serde(rename = "Seconds")]
+    seconds: u64,
+}
+
+/// Child entry of the `ChildTable` network diagnostic TLV
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct DiagChild {
+    #[serde(rename = "ChildId")]
+    pub child_id: u16,
+    #[serde(rename = "Timeout", default)]
+    pub timeout: u32,
+    #[serde(rename = "LinkQuality", default)]
+    pub link_quality: Option<u8>,
+}
+
+/// Subset of a single node's entry in the otbr-agent `GET /diagnostics`
+/// response that the client needs
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Diagnostic {
+    #[serde(rename = "Rloc16")]
+    pub rloc16: serde_json::Value,
+    #[serde(rename = "ExtAddress", default)]
+    pub ext_address: String,
+    #[serde(rename = "IP6AddressList", default)]
+    pub ip6_address_list: Vec<Ipv6Addr>,
+    #[serde(rename = "ChildTable", default)]
+    pub child_table: Vec<DiagChild>,
+    /// Raw network data TLVs, in hex
+    #[serde(rename = "NetworkData", default)]
+    pub network_data: Option<String>,
+}
+
+// Network data TLV types (Thread 1.3 §5.18), the type is the upper 7 bits
+// of the first byte and the lowest is the stable flag
+const NETDATA_PREFIX_TLV: u8 = 1;
+const NETDATA_BORDER_ROUTER_TLV: u8 = 2;
+const NETDATA_STABLE: u8 = 0x01;
+
+// Flags of a Border Router sub-TLV entry
+const BR_PREFERENCE: u16 = 0xc000;
+const BR_SLAAC: u16 = 0x1000;
+const BR_ON_MESH: u16 = 0x0100;
+const BR_DP: u16 = 0x0040;
+
+/// An on-mesh prefix published in the network data by a border router
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct OnMeshPrefix {
+    prefix: Ipv6Net,
+    flags: u16,
+    stable: bool,
+}
+
+impl OnMeshPrefix {
+    /// Same rules as OpenThread's `IsValidOmrPrefix`: a stable /64 on-mesh
+    /// prefix that addrs are configured on with SLAAC, and not a domain
+    /// prefix
+    fn is_omr(&self) -> bool {
+        self.stable
+            && self.prefix.prefix_len() == 64
+            && !self.prefix.addr().is_unicast_link_local()
+            && !self.prefix.addr().is_multicast()
+            && self.flags & (BR_ON_MESH | BR_SLAAC) == (BR_ON_MESH | BR_SLAAC)
+            && self.flags & BR_DP == 0
+    }
+
+    /// Two bit signed preference, high (1), medium (0) or low (-1)
+    fn preference(&self) -> i8 {
+        (((self.flags & BR_PREFERENCE) >> 8) as u8 as i8) >> 6
+    }
+
+    /// Parse the on-mesh prefixes out of the network data TLVs, one per
+    /// Border Router sub-TLV entry
+    fn from_network_data(data: &[u8]) -> Vec<OnMeshPrefix> {
+        let mut prefixes = Vec::new();
+        for (tlv, stable, value) in OnMeshPrefix::tlvs(data) {
+            if tlv != NETDATA_PREFIX_TLV || value.len() < 2 {
+                continue;
+            }
+            let len = value[1];
+            let bytes = usize::from(len).div_ceil(8);
+            if len > 128 || value.len() < 2 + bytes {
+                continue;
+            }
+            let mut addr = [0u8; 16];
+            addr[..bytes].copy_from_slice(&value[2..2 + bytes]);
+            let Ok(prefix) = Ipv6Net::new(Ipv6Addr::from(addr), len) else {
+                continue;
+            };
+            for (sub, sub_stable, entries) in OnMeshPrefix::tlvs(&value[2 + bytes..]) {
+                if sub != NETDATA_BORDER_ROUTER_TLV {
+                    continue;
+                }
+                prefixes.extend(entries.chunks_exact(4).map(|entry| OnMeshPrefix {
+                    prefix: prefix.trunc(),
+                    flags: u16::from_be_bytes([entry[2], entry[3]]),
+                    stable: stable && sub_stable,
+                }));
+            }
+        }
+        prefixes
+    }
+
+    /// Iterate `(type, stable, value)` of the TLVs in `data`, up to the
+    /// first truncated one
+    fn tlvs(mut data: &[u8]) -> impl Iterator<Item = (u8, bool, &[u8])> {
+        std::iter::from_fn(move || {
+            let [kind, len, rest @ ..] = data else {
+                return None;
+            };
+            let value = rest.get(..usize::from(*len))?;
+            data = &rest[usize::from(*len)..];
+            Some((kind >> 1, kind & NETDATA_STABLE != 0, value))
+        })
+    }
+}
+
+/// Implementation of the [`crate::client::OtClient`] trait that talks to the
+/// otbr-agent REST server, for hosts that run otbr-agent without `ot-ctl`.
+///
+/// Node info comes from `GET /node` and everything else from the network
+/// diagnostics reported by `GET /diagnostics`. ureq is blocking, so each
+/// request runs on the tokio blocking pool. As with the D-Bus client,
+/// the diagnostic child table does not carry the unicast addresses that
+/// children register, so children are reported with their mesh-local
+/// RLOC address
+pub struct OtRestClient {
+    agent: ureq::Agent,
+    base_url: String,
+}
+
+impl OtRestClient {
+    /// Create a client for the REST server at `base_url`, e.g.
+    /// [`DEFAULT_OT_REST_URL`]
+    pub fn new(base_url: &str) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(5))
+            .build();
+
+        Self {
+            agent,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    async fn get(&self, path: &str) -> Result<String, OtClientError> {
+        let agent = self.agent.clone();
+        let url = format!("{}{}", self.base_url, path);
+        tokio::task::spawn_blocking(move || {
+            let resp = agent
+                .get(&url)
+                .set("Accept", "application/json")
+                .call()
+                .map_err(Box::new)?;
+            Ok(resp.into_string()?)
+        })
+        .await?
+    }
+
+    pub async fn get_node_info(&self) -> Result<NodeInfo, OtClientError> {
+        Ok(serde_json::from_str(&self.get("/node").await?)?)
+    }
+
+    pub async fn get_diagnostics(&self) -> Result<Vec<Diagnostic>, OtClientError> {
+        Ok(serde_json::from_str(&self.get("/diagnostics").await?)?)
+    }
+
+    /// Get the diagnostics entry reported by the border router itself
+    async fn get_own_diagnostic(&self) -> Result<(NodeInfo, Diagnostic), OtClientError> {
+        let node = self.get_node_info().await?;
+        let rloc = OtRestClient::parse_rloc16(&node.rloc16)?;
+        let diag = self
+            .get_diagnostics()
+            .await?
+            .into_iter()
+            .find(|d| OtRestClient::parse_rloc16(&d.rloc16).ok() == Some(rloc))
+            .ok_or(OtClientError::OtClientErr(format!(
+                "No diagnostics reported for rloc {rloc:#06x}"
+            )))?;
+        Ok((node, diag))
+    }
+
+    /// otbr-agent versions differ in reporting the RLOC16 as a
+    /// number or as a hex string
+    fn parse_rloc16(value: &serde_json::Value) -> Result<Rloc, OtClientError> {
+        let rloc = match value {
+            serde_json::Value::Number(n) => n.as_u64().and_then(|n| u16::try_from(n).ok()),
+            serde_json::Value::String(s) => {
+                u16::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+            }
+            _ => None,
+        };
+        rloc.ok_or(OtClientError::OtClientErr(format!(
+            "Invalid Rloc16 {value:}"
+        )))
+    }
+
+    /// otbr-agent versions differ in reporting the role by name or as the
+    /// `otDeviceRole` number
+    fn parse_role(value: &serde_json::Value) -> Result<DeviceRole, OtClientError> {
+        let role = match value {
+            serde_json::Value::Number(n) => match n.as_u64() {
+                Some(0) => Some(DeviceRole::Disabled),
+                Some(1) => Some(DeviceRole::Detached),
+                Some(2) => Some(DeviceRole::Child),
+                Some(3) => Some(DeviceRole::Router),
+                Some(4) => Some(DeviceRole::Leader),
+                _ => None,
+            },
+            serde_json::Value::String(s) => DeviceRole::from_name(s),
+            _ => None,
+        };
+        role.ok_or(OtClientError::OtClientErr(format!(
+            "Unknown device role {value:}"
+        )))
+    }
+
+    /// Role and leader data from `GET /node`, the dataset from
+    /// `GET /node/dataset/active` which has no content without one
+    pub async fn get_network_state_from_rest(&self) -> Result<NetworkState, OtClientError> {
+        let node = self.get_node_info().await?;
+        let role = OtRestClient::parse_role(&node.state)?;
+        let leader = node.leader_data.filter(|_| role.is_attached());
+        let active_timestamp = match self.get("/node/dataset/active").await {
+            Ok(body) => serde_json::from_str::<ActiveDataset>(&body)
+                .ok()
+                .and_then(|d| d.active_timestamp)
+                .map(|ts| ts.seconds),
+            Err(_) => None,
+        };
+
+        Ok(NetworkState {
+            role,
+            partition_id: leader.map(|l| l.partition_id),
+            leader_router_id: leader.map(|l| l.leader_router_id),
+            active_timestamp,
+        })
+    }
+
+    /// The active dataset from `GET /node/dataset/active`. The REST API
+    /// has no commissioner, joiners are added through the CLI clients
+    pub async fn get_active_dataset_from_rest(&self) -> Result<OperationalDataset, OtClientError> {
+        let body = self.get("/node/dataset/active").await?;
+        Ok(serde_json::from_str::<ActiveDataset>(&body)?.into())
+    }
+
+    fn mesh_local_prefix(node: &NodeInfo) -> Ipv6Net {
+        Ipv6Net::new(node.rloc_address, 64)
+            .unwrap_or_default()
+            .trunc()
+    }
+
+    pub async fn get_children_from_rest(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        let (node, diag) = self.get_own_diagnostic().await?;
+        let rloc = OtRestClient::parse_rloc16(&node.rloc16)?;
+        Ok(OtRestClient::child_rloc_addrs(&node, rloc, &diag))
+    }
+
+    /// The diagnostics hold an entry for every router on the mesh, so
+    /// the children of all of them are found
+    pub async fn get_mesh_nodes_from_rest(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        let node = self.get_node_info().await?;
+        Ok(self
+            .get_diagnostics()
+            .await?
+            .iter()
+            .flat_map(|diag| {
+                OtRestClient::parse_rloc16(&diag.rloc16)
+                    .inspect_err(|e| log::warn!("Skipping diagnostics entry: {e:}"))
+                    .map(|rloc| OtRestClient::child_rloc_addrs(&node, rloc, diag))
+                    .unwrap_or_default()
+            })
+            .collect())
+    }
+
+    /// The diagnostic child table only carries the link quality of each
+    /// child, for every router on the mesh
+    pub async fn get_link_metrics_from_rest(&self) -> Result<Vec<LinkMetrics>, OtClientError> {
+        Ok(self
+            .get_diagnostics()
+            .await?
+            .iter()
+            .filter_map(|diag| {
+                OtRestClient::parse_rloc16(&diag.rloc16)
+                    .ok()
+                    .map(|r| (r, diag))
+            })
+            .flat_map(|(parent, diag)| {
+                diag.child_table.iter().map(move |c| LinkMetrics {
+                    rloc: (parent & 0xfc00) | (c.child_id & 0x01ff),
+                    parent,
+                    link_quality: c.link_quality,
+                    ..Default::default()
+                })
+            })
+            .collect())
+    }
+
+    fn child_rloc_addrs(node: &NodeInfo, rloc: Rloc, diag: &Diagnostic) -> Vec<(Rloc, Ipv6Addr)> {
+        let mesh_local = OtRestClient::mesh_local_prefix(node).addr().segments();
+
+        diag.child_table
+            .iter()
+            .map(|c| {
+                // Child RLOC16 is the parent router id with the child id in the low bits
+                let child_rloc = (rloc & 0xfc00) | (c.child_id & 0x01ff);
+                let ip = Ipv6Addr::new(
+                    mesh_local[0],
+                    mesh_local[1],
+                    mesh_local[2],
+                    mesh_local[3],
+                    0x0,
+                    0xff,
+                    0xfe00,
+                    child_rloc,
+                );
+                (child_rloc, ip)
+            })
+            .collect()
+    }
+
+    async fn get_omr_prefix_from_rest(&self) -> Result<Ipv6Net, OtClientError> {
+        let (_, diag) = self.get_own_diagnostic().await?;
+        OtRestClient::select_omr_prefix(&diag)
+    }
+
+    /// The OMR prefix is read from the network data the border router
+    /// reports in its diagnostics. Of several, the one OpenThread favors
+    /// is taken: the highest preference, then the numerically smallest.
+    /// otbr-agent versions that do not report the network data are not
+    /// supported, the prefix can not be told from the addrs alone
+    fn select_omr_prefix(diag: &Diagnostic) -> Result<Ipv6Net, OtClientError> {
+        let data = diag
+            .network_data
+            .as_deref()
+            .ok_or(OtClientError::Unsupported(
+                "OMR prefix without network data",
+            ))?;
+        let data = (0..data.len())
+            .step_by(2)
+            .map(|i| {
+                data.get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(OtClientError::OtClientErr(format!(
+                "Invalid network data {data:}"
+            )))?;
+        OnMeshPrefix::from_network_data(&data)
+            .into_iter()
+            .filter(OnMeshPrefix::is_omr)
+            .min_by(|a, b| {
+                b.preference()
+                    .cmp(&a.preference())
+                    .then(a.prefix.addr().cmp(&b.prefix.addr()))
+            })
+            .map(|p| p.prefix)
+            .ok_or(OtClientError::OtClientErr(
+                "No prefix is currently set".to_string(),
+            ))
+    }
+
+    pub async fn get_omr_ip_addr_from_rest(&self) -> Result<Ipv6Addr, OtClientError> {
+        let (_, diag) = self.get_own_diagnostic().await?;
+        let prefix = OtRestClient::select_omr_prefix(&diag)?;
+        if let Some(ip) = diag.ip6_address_list.iter().find(|i| prefix.contains(*i)) {
+            Ok(*ip)
+        } else {
+            Err(OtClientError::OtClientErr(
+                "No matching prefix found".to_string(),
+            ))
+        }
+    }
+
+    async fn get_ip_addrs_from_rest(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+        Ok(self.get_own_diagnostic().await?.1.ip6_address_list)
+    }
+}
+
+#[async_trait::async_trait]
+impl OtClient for OtRestClient {
+    async fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        self.get_children_from_rest().await
+    }
+
+    async fn get_mesh_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        self.get_mesh_nodes_from_rest().await
+    }
+
+    async fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError> {
+        self.get_omr_prefix_from_rest().await
+    }
+
+    async fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError> {
+        self.get_omr_ip_addr_from_rest().await
+    }
+
+    async fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+        self.get_ip_addrs_from_rest().await
+    }
+
+    async fn get_link_metrics(&self) -> Result<Vec<LinkMetrics>, OtClientError> {
+        self.get_link_metrics_from_rest().await
+    }
+
+    async fn get_network_state(&self) -> Result<NetworkState, OtClientError> {
+        self.get_network_state_from_rest().await
+    }
+
+    async fn get_active_dataset(&self) -> Result<OperationalDataset, OtClientError> {
+        self.get_active_dataset_from_rest().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ipnet::Ipv6Net;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{Ipv6Addr, TcpListener},
+    };
+
+    use super::{Diagnostic, OtRestClient};
+    use crate::{client::DeviceRole, OtClient, OtClientError};
+
+    const NODE: &str = r#"{"BaId":"","State":"leader","NumOfRouter":1,
+        "RlocAddress":"fdde:ad00:beef:0:0:ff:fe00:c000","ExtAddress":"4a2cf3b1d3f09e1c",
+        "NetworkName":"OpenThread-58d1","Rloc16":49152,"ExtPanId":"3a90e3a319a90494",
+        "LeaderData":{"PartitionId":1077744240,"Weighting":64,"DataVersion":109,
+        "StableDataVersion":211,"LeaderRouterId":48}}"#;
+
+    const ACTIVE_DATASET: &str = r#"{"ActiveTimestamp":{"Seconds":7,"Ticks":0,
+        "Authoritative":false},"NetworkName":"OpenThread-58d1","Channel":15,"PanId":22737,
+        "ExtPanId":"3a90e3a319a90494","MeshLocalPrefix":"fd4e:8b55:dc1d:1d2b::/64"}"#;
+
+    // Network data with, in order, an on-mesh prefix served over DHCP, the
+    // OMR prefix (with a 6LoWPAN context), a service and a low preference
+    // OMR prefix
+    const DIAGNOSTICS: &str = r#"[{"ExtAddress":"4a2cf3b1d3f09e1c","Rloc16":"0xc000",
+        "IP6AddressList":["fdde:ad00:beef:0:0:ff:fe00:fc00","fdde:ad00:beef:0:0:ff:fe00:c000",
+        "fdde:ad00:beef:0:8a4b:2b5c:1c9e:3a41","2001:db8:0:1::4","fd00:aaaa:bbbb:cccc::4",
+        "fd10::4","fdc9:fdb2:9fe8:1:766d:d75b:52f7:c71f","fe80:0:0:0:482c:f3b1:d3f0:9e1c"],
+        "NetworkData":"03100040fd00aaaabbbbcccc0504c000090003140040fdc9fdb29fe80001070211400504c00031000a0300010203100040fd100000000000000504c000f100",
+        "ChildTable":[{"ChildId":1,"Timeout":240,"Mode":{"RxOnWhenIdle":0,"DeviceType":0,"NetworkData":0}},
+        {"ChildId":2,"Timeout":240,"Mode":{"RxOnWhenIdle":0,"DeviceType":0,"NetworkData":0}}]},
+        {"ExtAddress":"fe109d277e0175cc","Rloc16":12288,
+        "IP6AddressList":["fdde:ad00:beef:0:0:ff:fe00:3000"],
+        "ChildTable":[{"ChildId":5,"Timeout":240}]}]"#;
+
+    /// Minimal stand-in for the otbr-agent REST server that serves
+    /// canned responses, returns the base url to use
+    fn rest_stand_in() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to bind listener");
+        let addr = listener.local_addr().expect("No local addr");
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                if reader.read_line(&mut request).is_err() {
+                    continue;
+                }
+                // drain the headers
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok() && line != "\r\n" {
+                    line.clear();
+                }
+
+                let body = if request.starts_with("GET /node ") {
+                    NODE
+                } else if request.starts_with("GET /diagnostics ") {
+                    DIAGNOSTICS
+                } else if request.starts_with("GET /node/dataset/active ") {
+                    ACTIVE_DATASET
+                } else {
+                    ""
+                };
+                let status = if body.is_empty() {
+                    "404 Not Found"
+                } else {
+                    "200 OK"
+                };
+                let mut stream = &stream;
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .ok();
+            }
+        });
+
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn check_rest_child_ips() {
+        let client = OtRestClient::new(&rest_stand_in());
+        let ret = client
+            .get_child_ips()
+            .await
+            .expect("Unable to get child ips");
+        assert_eq!(
+            ret[1],
+            (
+                0xc002,
+                Ipv6Addr::from([0xfdde, 0xad00, 0xbeef, 0x0, 0x0, 0xff, 0xfe00, 0xc002])
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn check_rest_mesh_ips() {
+        let client = OtRestClient::new(&rest_stand_in());
+        let ret = client.get_mesh_ips().await.expect("Unable to get mesh ips");
+        assert_eq!(ret.len(), 3);
+        assert_eq!(
+            ret[2],
+            (
+                0x3005,
+                Ipv6Addr::from([0xfdde, 0xad00, 0xbeef, 0x0, 0x0, 0xff, 0xfe00, 0x3005])
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn check_rest_network_state() {
+        let client = OtRestClient::new(&rest_stand_in());
+        let state = client
+            .get_network_state()
+            .await
+            .expect("Unable to get network state");
+        assert_eq!(state.role, DeviceRole::Leader);
+        assert_eq!(
+            (state.partition_id, state.leader_router_id),
+            (Some(1077744240), Some(48))
+        );
+        assert_eq!(state.active_timestamp, Some(7));
+
+        let dataset = client
+            .get_active_dataset()
+            .await
+            .expect("Unable to get active dataset");
+        assert_eq!(
+            (dataset.pan_id, dataset.channel, dataset.network_key),
+            (Some(0x58d1), Some(15), None)
+        );
+        assert_eq!(
+            dataset.extended_pan_id,
+            Some([0x3a, 0x90, 0xe3, 0xa3, 0x19, 0xa9, 0x04, 0x94])
+        );
+    }
+
+    #[tokio::test]
+    async fn check_rest_omr_prefix_and_ip() {
+        let client = OtRestClient::new(&rest_stand_in());
+        let ret: Ipv6Net = client
+            .get_omr_prefix()
+            .await
+            .expect("Unable to get Ipv6Net");
+        assert_eq!(ret, "fdc9:fdb2:9fe8:1::/64".parse().unwrap());
+
+        let ip = client.get_omr_ip().await.expect("Unable to get OMR ip");
+        assert_eq!(
+            ip,
+            Ipv6Addr::from([0xfdc9, 0xfdb2, 0x9fe8, 0x1, 0x766d, 0xd75b, 0x52f7, 0xc71f])
+        );
+
+        // Without the network data the prefix is not guessed from the addrs
+        let diag = Diagnostic {
+            ip6_address_list: vec!["fdc9:fdb2:9fe8:1::4".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            OtRestClient::select_omr_prefix(&diag),
+            Err(OtClientError::Unsupported(_))
+        ));
+    }
+}
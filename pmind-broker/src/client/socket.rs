@@ -0,0 +1,444 @@
+use ipnet::Ipv6Net;
+use std::{
+    net::Ipv6Addr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+    sync::Mutex,
+};
+
+use crate::{
+    client::{LinkMetrics, NetworkState, OperationalDataset, OtClient, PlantService},
+    commission::{CommissionerState, Eui64, Joiner},
+    OtCliClient, OtClientError, Rloc,
+};
+
+/// Default path of the CLI socket that otbr-agent opens for `wpan0`
+pub const DEFAULT_OT_CLI_SOCKET: &str = "/run/openthread-wpan0.sock";
+
+/// How long to wait on the otbr-agent before giving up on a response
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Implementation of the [`crate::client::OtClient`] trait that talks to
+/// the otbr-agent CLI socket directly (the same socket `ot-ctl` uses),
+/// so no process is spawned per command. The connection is kept open
+/// between calls and re-established if it errors out. Commands are
+/// pipelined: all are written before any response is read, and
+/// responses are matched to commands in order
+pub struct OtSocketClient {
+    path: PathBuf,
+    conn: Mutex<Option<BufReader<UnixStream>>>,
+}
+
+impl OtSocketClient {
+    /// Create a client for the CLI socket at `path`, e.g.
+    /// [`DEFAULT_OT_CLI_SOCKET`]. The socket is connected lazily
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            conn: Mutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> Result<BufReader<UnixStream>, OtClientError> {
+        let stream = UnixStream::connect(&self.path).await.inspect_err(|e| {
+            log::error!("Unable to connect to CLI socket {:?}: {e:}", self.path);
+        })?;
+        Ok(BufReader::new(stream))
+    }
+
+    /// Send all `cmds` and return the output of each, in order, with the
+    /// trailing `Done` left in place so the `ot-ctl` parsers can be reused
+    pub async fn pipeline(&self, cmds: &[&str]) -> Result<Vec<String>, OtClientError> {
+        let mut conn = self.conn.lock().await;
+
+        if conn.is_none() {
+            *conn = Some(self.connect().await?);
+        }
+
+        let res = match conn.as_mut() {
+            Some(c) => tokio::time::timeout(SOCKET_TIMEOUT, OtSocketClient::exec(c, cmds))
+                .await
+                .unwrap_or(Err(OtClientError::OtClientErr(
+                    "Timed out waiting on CLI socket".to_string(),
+                ))),
+            None => Err(OtClientError::OtClientErr(
+                "CLI socket not connected".to_string(),
+            )),
+        };
+
+        // Any unread output would desync later responses, so start over
+        if res.is_err() {
+            *conn = None;
+        }
+        res
+    }
+
+    async fn exec(
+        conn: &mut BufReader<UnixStream>,
+        cmds: &[&str],
+    ) -> Result<Vec<String>, OtClientError> {
+        let mut req = String::new();
+        for cmd in cmds {
+            req.push_str(cmd);
+            req.push('\n');
+        }
+        conn.get_mut().write_all(req.as_bytes()).await?;
+
+        let mut resps = Vec::with_capacity(cmds.len());
+        for cmd in cmds {
+            resps.push(OtSocketClient::read_response(conn, cmd).await?);
+        }
+        Ok(resps)
+    }
+
+    async fn read_response(
+        conn: &mut BufReader<UnixStream>,
+        cmd: &str,
+    ) -> Result<String, OtClientError> {
+        let mut resp = String::new();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if conn.read_line(&mut line).await? == 0 {
+                return Err(OtClientError::from(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "CLI socket closed",
+                )));
+            }
+
+            let l = line.trim_start_matches("> ").trim_end();
+            if l == "Done" {
+                resp.push_str("Done");
+                return Ok(resp);
+            } else if l.starts_with("Error") {
+                return Err(OtClientError::OtClientErr(format!(
+                    "Failed CLI Command {cmd}: {l}"
+                )));
+            } else if l.is_empty() || l == cmd {
+                // Skip the prompt and the echoed command
+                continue;
+            }
+            resp.push_str(l);
+            resp.push_str("\r\n");
+        }
+    }
+
+    async fn command(&self, cmd: &str) -> Result<String, OtClientError> {
+        self.pipeline(&[cmd])
+            .await?
+            .pop()
+            .ok_or(OtClientError::OtClientErr(format!("No output for {cmd}")))
+    }
+
+    pub async fn get_children_from_socket(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        Ok(OtCliClient::parse_childip_output(
+            self.command("childip").await?,
+        ))
+    }
+
+    /// Children of the border router plus those of every other router from
+    /// the mesh diagnostics, as [`OtCliClient::get_mesh_nodes_from_cli`]
+    pub async fn get_mesh_nodes_from_socket(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        let mut nodes = self.get_children_from_socket().await?;
+
+        let routers = match self.command("meshdiag topology").await {
+            Ok(topology) => OtCliClient::parse_topology_output(&topology, false),
+            Err(e) => {
+                log::debug!("No mesh diagnostics, only finding children: {e:}");
+                return Ok(nodes);
+            }
+        };
+
+        // One at a time, so a router that does not answer only loses its own children
+        for router in routers {
+            match self
+                .command(&format!("meshdiag childip6 {router:#06x}"))
+                .await
+            {
+                Ok(res) => nodes.extend(OtCliClient::parse_childip6_output(&res)),
+                Err(e) => log::warn!("Unable to get children of router {router:#06x}: {e:}"),
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Link metrics of the children of every router, as
+    /// [`OtCliClient::get_link_metrics_from_cli`]
+    pub async fn get_link_metrics_from_socket(&self) -> Result<Vec<LinkMetrics>, OtClientError> {
+        let routers = match self.command("meshdiag topology").await {
+            Ok(topology) => OtCliClient::parse_topology_output(&topology, true),
+            Err(e) => {
+                log::debug!("No mesh diagnostics, only measuring children: {e:}");
+                let mut resp = self
+                    .pipeline(&["child table", "neighbor linkquality"])
+                    .await?
+                    .into_iter();
+                return Ok(OtCliClient::parse_child_link_metrics(
+                    &resp.next().unwrap_or_default(),
+                    &resp.next().unwrap_or_default(),
+                ));
+            }
+        };
+
+        let mut metrics = vec![];
+        for router in routers {
+            match self
+                .command(&format!("meshdiag childtable {router:#06x}"))
+                .await
+            {
+                Ok(res) => metrics.extend(OtCliClient::parse_childtable_output(&res)),
+                Err(e) => log::warn!("Unable to get child table of router {router:#06x}: {e:}"),
+            }
+        }
+        Ok(metrics)
+    }
+
+    /// Network state as [`OtCliClient::get_network_state_from_cli`]
+    pub async fn get_network_state_from_socket(&self) -> Result<NetworkState, OtClientError> {
+        let state = self.command("state").await?;
+        let leader = self.command("leaderdata").await.unwrap_or_default();
+        let dataset = self.command("dataset active").await.unwrap_or_default();
+        OtCliClient::parse_network_state(&state, &leader, &dataset)
+    }
+
+    pub async fn get_active_dataset_from_socket(
+        &self,
+    ) -> Result<OperationalDataset, OtClientError> {
+        Ok(OtCliClient::parse_dataset_output(
+            &self.command("dataset active").await?,
+        ))
+    }
+
+    /// Generate a dataset, commit it as the active one and start Thread on
+    /// it, in one pipeline. Returns the new dataset
+    pub async fn form_network_from_socket(&self) -> Result<OperationalDataset, OtClientError> {
+        let mut cmds = OtCliClient::FORM_NETWORK.to_vec();
+        cmds.push("dataset active");
+        let resps = self.pipeline(&cmds).await?;
+        Ok(OtCliClient::parse_dataset_output(
+            resps.last().map(String::as_str).unwrap_or_default(),
+        ))
+    }
+
+    pub async fn start_commissioner_from_socket(&self) -> Result<CommissionerState, OtClientError> {
+        let state =
+            OtCliClient::parse_commissioner_state(&self.command("commissioner state").await?)?;
+        if state != CommissionerState::Disabled {
+            return Ok(state);
+        }
+        self.command("commissioner start").await?;
+        Ok(CommissionerState::Petition)
+    }
+
+    pub async fn add_joiner_from_socket(&self, joiner: &Joiner) -> Result<(), OtClientError> {
+        let [eui64, pskd, timeout] = OtCliClient::joiner_add_args(joiner);
+        self.command(&format!("commissioner joiner add {eui64} {pskd} {timeout}"))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_joiner_from_socket(&self, eui64: &Eui64) -> Result<(), OtClientError> {
+        self.command(&format!("commissioner joiner remove {eui64}"))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_joiners_from_socket(&self) -> Result<Vec<Joiner>, OtClientError> {
+        Ok(OtCliClient::parse_joiner_table(
+            &self.command("commissioner joiner table").await?,
+        ))
+    }
+
+    pub async fn get_omr_ip_addr_from_socket(&self) -> Result<Ipv6Addr, OtClientError> {
+        let mut resp = self.pipeline(&["prefix", "ipaddr"]).await?.into_iter();
+        let prefix = OtCliClient::parse_prefix_output(resp.next().unwrap_or_default())?;
+        let ips = OtCliClient::parse_ipaddr_output(resp.next().unwrap_or_default());
+        if let Some(ip) = ips.iter().find(|i| prefix.contains(*i)) {
+            Ok(*ip)
+        } else {
+            Err(OtClientError::OtClientErr(
+                "No matching prefix found".to_string(),
+            ))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl OtClient for OtSocketClient {
+    async fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        self.get_children_from_socket().await
+    }
+
+    async fn get_mesh_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        self.get_mesh_nodes_from_socket().await
+    }
+
+    async fn get_link_metrics(&self) -> Result<Vec<LinkMetrics>, OtClientError> {
+        self.get_link_metrics_from_socket().await
+    }
+
+    async fn get_network_state(&self) -> Result<NetworkState, OtClientError> {
+        self.get_network_state_from_socket().await
+    }
+
+    async fn get_plant_services(&self) -> Result<Vec<PlantService>, OtClientError> {
+        Ok(OtCliClient::parse_srp_service_output(
+            &self.command("srp server service").await?,
+        ))
+    }
+
+    async fn get_active_dataset(&self) -> Result<OperationalDataset, OtClientError> {
+        self.get_active_dataset_from_socket().await
+    }
+
+    async fn form_network(&self) -> Result<OperationalDataset, OtClientError> {
+        self.form_network_from_socket().await
+    }
+
+    async fn start_commissioner(&self) -> Result<CommissionerState, OtClientError> {
+        self.start_commissioner_from_socket().await
+    }
+
+    async fn add_joiner(&self, joiner: &Joiner) -> Result<(), OtClientError> {
+        self.add_joiner_from_socket(joiner).await
+    }
+
+    async fn remove_joiner(&self, eui64: &Eui64) -> Result<(), OtClientError> {
+        self.remove_joiner_from_socket(eui64).await
+    }
+
+    async fn get_joiners(&self) -> Result<Vec<Joiner>, OtClientError> {
+        self.get_joiners_from_socket().await
+    }
+
+    async fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError> {
+        OtCliClient::parse_prefix_output(self.command("prefix").await?)
+    }
+
+    async fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError> {
+        self.get_omr_ip_addr_from_socket().await
+    }
+
+    async fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+        Ok(OtCliClient::parse_ipaddr_output(
+            self.command("ipaddr").await?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::Ipv6Addr,
+        os::unix::net::UnixListener,
+        path::PathBuf,
+    };
+
+    use super::OtSocketClient;
+    use crate::OtClient;
+
+    /// Output recorded from `ot-ctl` on the RPi
+    fn recorded(cmd: &str) -> &'static str {
+        match cmd {
+            "childip" => {
+                "c04f: fd1f:a298:dbd1:e329:1c45:9c98:b941:1a5a\r\n\
+                c04f: fdc9:fdb2:9fe8:1:9b57:cf1a:c2d3:49d5\r\nDone\r\n"
+            }
+            "meshdiag topology" => {
+                "id:48 rloc16:0xc000 ext-addr:4a2cf3b1d3f09e1c ver:4 - me - leader\r\n   \
+                3-links:{ 12 }\r\nid:12 rloc16:0x3000 ext-addr:fe109d277e0175cc ver:4\r\n   \
+                3-links:{ 48 }\r\nDone\r\n"
+            }
+            "meshdiag childip6 0x3000" => {
+                "child-rloc16: 0x3001\r\n    fdde:ad00:beef:0:ded8:cd58:b73:2c21\r\n    \
+                fdc9:fdb2:9fe8:1:c34:6e6e:1c3a:2a5e\r\nDone\r\n"
+            }
+            "prefix" => "fdc9:fdb2:9fe8:1::/64 paos low 4400\r\nDone\r\n",
+            "ipaddr" => {
+                "fdde:ad00:beef:0:0:ff:fe00:fc00\r\n\
+                fdc9:fdb2:9fe8:1:766d:d75b:52f7:c71f\r\n\
+                fdde:ad00:beef:0:8a4b:2b5c:1c9e:3a41\r\n\
+                fe80:0:0:0:482c:f3b1:d3f0:9e1c\r\nDone\r\n"
+            }
+            _ => "Error 35: InvalidCommand\r\n",
+        }
+    }
+
+    /// Stand-in for the otbr-agent CLI socket that echoes each command
+    /// and replays recorded output, returns the socket path
+    fn socket_stand_in(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("pmind-{}-{name}.sock", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let listener = UnixListener::bind(&path).expect("Unable to bind stand-in socket");
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).map(|l| l > 0).unwrap_or(false) {
+                    let cmd = line.trim();
+                    let mut writer = &stream;
+                    write!(writer, "> {cmd}\r\n{}", recorded(cmd)).ok();
+                    line.clear();
+                }
+            }
+        });
+
+        path
+    }
+
+    #[tokio::test]
+    async fn check_socket_child_ips() {
+        let client = OtSocketClient::new(socket_stand_in("childip"));
+        let ret = client
+            .get_child_ips()
+            .await
+            .expect("Unable to get child ips");
+        assert_eq!(
+            ret[1],
+            (
+                0xc04f,
+                Ipv6Addr::from([0xfdc9, 0xfdb2, 0x9fe8, 0x1, 0x9b57, 0xcf1a, 0xc2d3, 0x49d5])
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn check_socket_mesh_ips() {
+        let client = OtSocketClient::new(socket_stand_in("meshdiag"));
+        let ret = client.get_mesh_ips().await.expect("Unable to get mesh ips");
+        assert_eq!(ret.len(), 4);
+        assert_eq!(
+            ret[3],
+            (
+                0x3001,
+                Ipv6Addr::from([0xfdc9, 0xfdb2, 0x9fe8, 0x1, 0xc34, 0x6e6e, 0x1c3a, 0x2a5e])
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn check_socket_pipelined_omr_ip() {
+        let client = OtSocketClient::new(socket_stand_in("omr"));
+        let ip = client.get_omr_ip().await.expect("Unable to get OMR ip");
+        assert_eq!(
+            ip,
+            Ipv6Addr::from([0xfdc9, 0xfdb2, 0x9fe8, 0x1, 0x766d, 0xd75b, 0x52f7, 0xc71f])
+        );
+
+        // Errors should not desync the connection for later commands
+        assert!(client.pipeline(&["bogus", "prefix"]).await.is_err());
+        assert_eq!(
+            client
+                .get_omr_prefix()
+                .await
+                .expect("Unable to get Ipv6Net"),
+            "fdc9:fdb2:9fe8:1::/64".parse().unwrap()
+        );
+    }
+}
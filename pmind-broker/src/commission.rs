@@ -0,0 +1,389 @@
+//! Host-driven commissioning of new nodes onto the Thread network.
+//!
+//! Nodes do not ship with the network's credentials, they join with the
+//! standard Thread joiner flow: the border router petitions to become the
+//! network's commissioner, a node is added as a joiner by its factory
+//! EUI-64 and the PSKd (joining device credential) it was built with, and
+//! the commissioner hands the node the active dataset once it proves it
+//! has the PSKd. The [`Commissioner`] drives this through any
+//! [`OtClient`], and tracks each joiner until it joins or its window
+//! expires
+use pmindp_sensor::{is_valid_pskd, PSKD_ALPHABET};
+use std::{collections::HashMap, fmt, str::FromStr};
+use thiserror::Error;
+use tokio::time::{Duration, Instant};
+
+use crate::{client::OperationalDataset, OtClient, OtClientError};
+
+/// How long a joiner may take to join unless told otherwise, as the
+/// otbr-agent defaults it
+pub const DEFAULT_JOINER_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long the border router may take to become the commissioner
+const PETITION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Length of the PSKds [`Pskd::generate`] draws
+const PSKD_GENERATED_LEN: usize = 8;
+
+#[derive(Error, Debug)]
+pub enum CommissionError {
+    #[error("EUI-64 {0} is not 16 hex digits")]
+    Eui64(String),
+    #[error("PSKd must be 6 to 32 of 0-9 and A-Y but I, O and Q")]
+    Pskd,
+    #[error("Border router already has an active dataset")]
+    Commissioned,
+    #[error("Border router did not become the commissioner")]
+    Petition,
+    #[error("OT Client Error")]
+    OtClient(#[from] OtClientError),
+    #[error("Random source Error")]
+    Random(#[from] getrandom::Error),
+}
+
+/// Factory-assigned IEEE EUI-64 a node joins with, its joiner ID. Not the
+/// [`Eui`](`crate::Eui`) the node reports once on the mesh
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Eui64(pub [u8; 8]);
+
+impl FromStr for Eui64 {
+    type Err = CommissionError;
+
+    /// 16 hex digits, e.g. `d45e64fa83f81cf7`
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        let invalid = || CommissionError::Eui64(hex.to_string());
+        let mut eui64 = [0u8; 8];
+        if hex.len() != 2 * eui64.len() {
+            return Err(invalid());
+        }
+        for (i, byte) in eui64.iter_mut().enumerate() {
+            *byte = hex
+                .get(2 * i..2 * i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(invalid)?;
+        }
+        Ok(Eui64(eui64))
+    }
+}
+
+impl fmt::Display for Eui64 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+/// Pre-shared key for device, the credential a joiner proves it has
+#[derive(Clone, PartialEq, Eq)]
+pub struct Pskd(String);
+
+impl fmt::Debug for Pskd {
+    /// PSKds stay out of the logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Pskd(..)")
+    }
+}
+
+impl Pskd {
+    /// Check `pskd` is a valid PSKd, see [`is_valid_pskd`]
+    pub fn new(pskd: &str) -> Result<Self, CommissionError> {
+        match is_valid_pskd(pskd) {
+            true => Ok(Pskd(pskd.to_string())),
+            false => Err(CommissionError::Pskd),
+        }
+    }
+
+    /// Draw a random PSKd, to build a node with
+    pub fn generate() -> Result<Self, CommissionError> {
+        let mut bytes = [0u8; PSKD_GENERATED_LEN];
+        getrandom::fill(&mut bytes)?;
+        // The alphabet is 32 long, so every character is as likely
+        Ok(Pskd(
+            bytes
+                .iter()
+                .map(|b| PSKD_ALPHABET[*b as usize % PSKD_ALPHABET.len()] as char)
+                .collect(),
+        ))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A node the commissioner lets join
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Joiner {
+    pub eui64: Eui64,
+    pub pskd: Pskd,
+    /// Left of the window the node may join in
+    pub timeout: Duration,
+}
+
+/// State of the border router's commissioner
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommissionerState {
+    #[default]
+    Disabled,
+    /// Petitioning the leader to become the commissioner
+    Petition,
+    Active,
+}
+
+impl CommissionerState {
+    /// The state named as otbr-agent reports it, e.g. `active`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "disabled" => Some(CommissionerState::Disabled),
+            "petition" | "petitioning" => Some(CommissionerState::Petition),
+            "active" => Some(CommissionerState::Active),
+            _ => None,
+        }
+    }
+}
+
+/// Where a joiner added through the [`Commissioner`] is at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinState {
+    /// Waiting for the node to join
+    Pending,
+    /// The node was given the dataset
+    Joined,
+    /// The node did not join in time
+    Expired,
+}
+
+/// A joiner's state, and when its window ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinerStatus {
+    pub state: JoinState,
+    pub expires: Instant,
+}
+
+/// Commissioning workflow of the border router behind an [`OtClient`]
+pub struct Commissioner {
+    ot_client: Box<dyn OtClient>,
+    joiners: HashMap<Eui64, JoinerStatus>,
+}
+
+impl Commissioner {
+    pub fn new(ot_client: Box<dyn OtClient>) -> Self {
+        Self {
+            ot_client,
+            joiners: HashMap::new(),
+        }
+    }
+
+    /// The network's active dataset
+    pub async fn active_dataset(&self) -> Result<OperationalDataset, CommissionError> {
+        Ok(self.ot_client.get_active_dataset().await?)
+    }
+
+    /// Form a new network with generated credentials, for a border router
+    /// that has no active dataset yet
+    pub async fn form_network(&self) -> Result<OperationalDataset, CommissionError> {
+        match self.ot_client.get_active_dataset().await {
+            Ok(dataset) if dataset.active_timestamp.is_some() => {
+                return Err(CommissionError::Commissioned)
+            }
+            Ok(_) => {}
+            // otbr-agent fails to read a dataset it does not have
+            Err(OtClientError::OtClientErr(e)) => log::debug!("No active dataset: {e:}"),
+            Err(e) => return Err(e.into()),
+        }
+        let dataset = self.ot_client.form_network().await?;
+        log::info!(
+            "Formed network {:?} on channel {:?}",
+            dataset.network_name,
+            dataset.channel
+        );
+        Ok(dataset)
+    }
+
+    /// Let the node `eui64` join with `pskd` for `timeout`, petitioning to
+    /// become the commissioner first if the border router is not yet
+    pub async fn add_joiner(
+        &mut self,
+        eui64: Eui64,
+        pskd: Pskd,
+        timeout: Duration,
+    ) -> Result<(), CommissionError> {
+        let deadline = Instant::now() + PETITION_TIMEOUT;
+        while self.ot_client.start_commissioner().await? != CommissionerState::Active {
+            if Instant::now() >= deadline {
+                return Err(CommissionError::Petition);
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+
+        self.ot_client
+            .add_joiner(&Joiner {
+                eui64,
+                pskd,
+                timeout,
+            })
+            .await?;
+        log::info!("Added joiner {eui64} for {timeout:?}");
+        self.joiners.insert(
+            eui64,
+            JoinerStatus {
+                state: JoinState::Pending,
+                expires: Instant::now() + timeout,
+            },
+        );
+        Ok(())
+    }
+
+    /// Stop the node `eui64` from joining, and stop tracking it
+    pub async fn remove_joiner(&mut self, eui64: &Eui64) -> Result<(), CommissionError> {
+        self.joiners.remove(eui64);
+        Ok(self.ot_client.remove_joiner(eui64).await?)
+    }
+
+    /// Every joiner added, and its state as of the last [`Commissioner::poll`]
+    pub fn joiners(&self) -> &HashMap<Eui64, JoinerStatus> {
+        &self.joiners
+    }
+
+    /// Update the state of the pending joiners from the commissioner's
+    /// joiner table, returns the joiners whose state changed. The
+    /// commissioner drops a joiner from its table shortly after giving it
+    /// the dataset, so one that leaves the table before its window ends
+    /// has joined
+    pub async fn poll(&mut self) -> Result<Vec<(Eui64, JoinState)>, CommissionError> {
+        let table = self.ot_client.get_joiners().await?;
+        let now = Instant::now();
+        let mut changes = vec![];
+        for (eui64, status) in self
+            .joiners
+            .iter_mut()
+            .filter(|(_, s)| s.state == JoinState::Pending)
+        {
+            let state = match (
+                table.iter().any(|j| j.eui64 == *eui64),
+                now < status.expires,
+            ) {
+                (_, false) => JoinState::Expired,
+                (false, true) => JoinState::Joined,
+                (true, true) => continue,
+            };
+            log::info!("Joiner {eui64} {state:?}");
+            status.state = state;
+            changes.push((*eui64, state));
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv6Addr, sync::Mutex};
+    use tokio::time::Duration;
+
+    use super::{CommissionError, Commissioner, CommissionerState, Eui64, JoinState, Joiner, Pskd};
+    use crate::{client::OperationalDataset, OtClient, OtClientError, Rloc};
+
+    /// Border router that becomes the commissioner on its second petition,
+    /// and whose joiner table the test edits
+    #[derive(Default)]
+    struct MockClient {
+        petitions: Mutex<u32>,
+        table: Mutex<Vec<Joiner>>,
+    }
+
+    #[async_trait::async_trait]
+    impl OtClient for &'static MockClient {
+        async fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+            Ok(vec![])
+        }
+        async fn get_active_dataset(&self) -> Result<OperationalDataset, OtClientError> {
+            Ok(OperationalDataset {
+                active_timestamp: Some(1),
+                network_name: Some("pmind-test".to_string()),
+                ..Default::default()
+            })
+        }
+        async fn start_commissioner(&self) -> Result<CommissionerState, OtClientError> {
+            let mut petitions = self.petitions.lock().unwrap();
+            *petitions += 1;
+            Ok(match *petitions {
+                1 => CommissionerState::Petition,
+                _ => CommissionerState::Active,
+            })
+        }
+        async fn add_joiner(&self, joiner: &Joiner) -> Result<(), OtClientError> {
+            self.table.lock().unwrap().push(joiner.clone());
+            Ok(())
+        }
+        async fn remove_joiner(&self, eui64: &Eui64) -> Result<(), OtClientError> {
+            self.table.lock().unwrap().retain(|j| j.eui64 != *eui64);
+            Ok(())
+        }
+        async fn get_joiners(&self) -> Result<Vec<Joiner>, OtClientError> {
+            Ok(self.table.lock().unwrap().clone())
+        }
+        async fn get_omr_prefix(&self) -> Result<ipnet::Ipv6Net, OtClientError> {
+            Err(OtClientError::Unsupported("OMR prefix"))
+        }
+        async fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError> {
+            Err(OtClientError::Unsupported("OMR addr"))
+        }
+        async fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn check_pskd() {
+        assert!(Pskd::new("J01NME").is_ok());
+        for invalid in ["J01NM", "J01NMO", "j01nme", &"A".repeat(33)] {
+            assert!(matches!(Pskd::new(invalid), Err(CommissionError::Pskd)));
+        }
+        let pskd = Pskd::generate().expect("No random source");
+        assert_eq!(Pskd::new(pskd.as_str()).ok(), Some(pskd));
+
+        let eui64: Eui64 = "d45e64fa83f81cf7".parse().expect("Invalid EUI-64");
+        assert_eq!(eui64.to_string(), "d45e64fa83f81cf7");
+        assert!("d45e64fa83f81c".parse::<Eui64>().is_err());
+    }
+
+    #[tokio::test]
+    async fn check_commissioner_join_status() {
+        let client: &'static MockClient = Box::leak(Box::default());
+        let mut commissioner = Commissioner::new(Box::new(client));
+        assert!(matches!(
+            commissioner.form_network().await,
+            Err(CommissionError::Commissioned)
+        ));
+
+        let (joins, expires) = (Eui64([1; 8]), Eui64([2; 8]));
+        for (eui64, timeout) in [(joins, 60), (expires, 1)] {
+            commissioner
+                .add_joiner(
+                    eui64,
+                    Pskd::new("J01NME").unwrap(),
+                    Duration::from_secs(timeout),
+                )
+                .await
+                .expect("Unable to add joiner");
+        }
+        // The first petition is polled until the border router is active
+        assert_eq!(*client.petitions.lock().unwrap(), 3);
+        assert_eq!(client.table.lock().unwrap().len(), 2);
+        assert!(commissioner.poll().await.unwrap().is_empty());
+
+        // The commissioner drops a joiner that joined from its table
+        client.table.lock().unwrap().retain(|j| j.eui64 != joins);
+        assert_eq!(
+            commissioner.poll().await.unwrap(),
+            [(joins, JoinState::Joined)]
+        );
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(
+            commissioner.poll().await.unwrap(),
+            [(expires, JoinState::Expired)]
+        );
+        assert!(commissioner.poll().await.unwrap().is_empty());
+        assert_eq!(commissioner.joiners()[&joins].state, JoinState::Joined);
+    }
+}
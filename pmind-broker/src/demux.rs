@@ -0,0 +1,280 @@
+//! Sockets that node Observe traffic is received on.
+//!
+//! In [`ReceiveMode::PortPerNode`] each node is registered from, and
+//! notifies, a socket bound to its own port out of the monitor's pool. In
+//! [`ReceiveMode::Shared`] every node is registered from one socket and a
+//! single task demultiplexes the notifications by Observe token (which is
+//! random per node, see [`pmindp_protocol::ObserveClient`]) to per-node
+//! channels, so there is no port bookkeeping and no cap on the number of
+//! nodes. Either way the [`NodeEventHandler`](`crate::node::NodeEventHandler`)
+//! sees a [`NodeSocket`] and fans readings out to the same per-node
+//! [`NodeEvent`](`crate::NodeEvent`) streams
+use coap_lite::{MessageClass, Packet};
+use pmindp_protocol::{ObserveClient, Token};
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, SocketAddrV6},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+
+/// Port the shared receive socket binds by default
+pub const DEFAULT_SHARED_RCV_PORT: u16 = 1213;
+
+/// How the broker receives sensor data from nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReceiveMode {
+    /// Each node reports to its own port, reserved from a pool of 100
+    #[default]
+    PortPerNode,
+    /// All nodes report to one socket bound to this port
+    Shared(u16),
+}
+
+type Routes = Arc<Mutex<HashMap<Token, UnboundedSender<(SocketAddr, Packet)>>>>;
+
+/// Single socket shared by all nodes, demultiplexed by Observe token
+pub(crate) struct SharedSocket {
+    socket: Arc<UdpSocket>,
+    routes: Routes,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl SharedSocket {
+    pub async fn bind(addr: SocketAddrV6) -> std::io::Result<Self> {
+        let socket = Arc::new(UdpSocket::bind(addr).await.inspect_err(|_| {
+            log::error!("Unable to bind shared receive socket at addr {:?}", addr);
+        })?);
+        let routes: Routes = Arc::default();
+        let task = tokio::spawn(SharedSocket::demux(socket.clone(), routes.clone()));
+        log::info!("Receiving node data on shared socket {addr:}");
+
+        Ok(Self {
+            socket,
+            routes,
+            task,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Route packets carrying `token` to the returned [`NodeSocket`] until
+    /// it is dropped, see [`NodeSocket::route`] for more tokens
+    pub fn node_socket(&self, token: Token) -> NodeSocket {
+        let (tx, rx) = unbounded_channel();
+        SharedSocket::lock(&self.routes).insert(token, tx);
+        NodeSocket::Shared {
+            socket: self.socket.clone(),
+            routes: self.routes.clone(),
+            tokens: vec![token],
+            rx,
+        }
+    }
+
+    fn lock(
+        routes: &Routes,
+    ) -> std::sync::MutexGuard<'_, HashMap<Token, UnboundedSender<(SocketAddr, Packet)>>> {
+        routes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn demux(socket: Arc<UdpSocket>, routes: Routes) {
+        let mut buffer = [0u8; 512];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buffer).await {
+                Ok(res) => res,
+                Err(e) => {
+                    log::error!("Shared receive socket error {e:}");
+                    break;
+                }
+            };
+            let Ok(packet) = Packet::from_bytes(&buffer[..len]) else {
+                log::error!("Non CoAP packet from {from:} len {:?}", len);
+                continue;
+            };
+
+            let route = Token::try_from(packet.get_token())
+                .ok()
+                .and_then(|token| SharedSocket::lock(&routes).get(&token).cloned());
+            match route {
+                Some(route) => {
+                    route.send((from, packet)).ok();
+                }
+                // Empty ACK / RST carry no token, nothing to reject
+                None if packet.header.code == MessageClass::Empty => {}
+                None => {
+                    log::warn!("Rejecting packet with unknown token from {from:}");
+                    if let Ok(reset) = ObserveClient::reset(&packet).to_bytes() {
+                        socket.send_to(&reset, from).await.ok();
+                    }
+                }
+            }
+        }
+        // Dropping the routes closes every node channel, which the node
+        // handlers report as a socket error
+        SharedSocket::lock(&routes).clear();
+    }
+}
+
+impl Drop for SharedSocket {
+    fn drop(&mut self) {
+        self.task.abort();
+        SharedSocket::lock(&self.routes).clear();
+    }
+}
+
+/// Socket a single node's Observe traffic is sent and received on
+pub(crate) enum NodeSocket {
+    Dedicated(UdpSocket),
+    Shared {
+        socket: Arc<UdpSocket>,
+        routes: Routes,
+        tokens: Vec<Token>,
+        rx: UnboundedReceiver<(SocketAddr, Packet)>,
+    },
+}
+
+impl NodeSocket {
+    /// Also receive packets carrying `token`, for another observation of
+    /// the same node. A dedicated socket receives everything already
+    pub fn route(&mut self, token: Token) {
+        if let NodeSocket::Shared { routes, tokens, .. } = self {
+            if tokens.contains(&token) {
+                return;
+            }
+            // Share the channel of the first token; the socket holds no
+            // sender itself so the channel still closes with the routes
+            let mut routes = SharedSocket::lock(routes);
+            if let Some(tx) = tokens.first().and_then(|t| routes.get(t)).cloned() {
+                routes.insert(token, tx);
+                tokens.push(token);
+            }
+        }
+    }
+
+    pub async fn send_to(&self, packet: &[u8], addr: SocketAddrV6) -> std::io::Result<usize> {
+        match self {
+            NodeSocket::Dedicated(socket) => socket.send_to(packet, addr).await,
+            NodeSocket::Shared { socket, .. } => socket.send_to(packet, addr).await,
+        }
+    }
+
+    /// Next packet from the node, `None` for anything that is not CoAP
+    pub async fn recv_from(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> std::io::Result<(SocketAddr, Option<Packet>)> {
+        match self {
+            NodeSocket::Dedicated(socket) => {
+                let (len, from) = socket.recv_from(buffer).await?;
+                Ok((from, Packet::from_bytes(&buffer[..len]).ok()))
+            }
+            NodeSocket::Shared { rx, .. } => {
+                let (from, packet) = rx.recv().await.ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::BrokenPipe,
+                        "Shared receive socket closed",
+                    )
+                })?;
+                Ok((from, Some(packet)))
+            }
+        }
+    }
+}
+
+impl Drop for NodeSocket {
+    fn drop(&mut self) {
+        if let NodeSocket::Shared { routes, tokens, .. } = self {
+            let mut routes = SharedSocket::lock(routes);
+            for token in tokens.iter() {
+                routes.remove(token);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coap_lite::{ContentFormat, Packet};
+    use pmindp_protocol::{Notification, ObserveClient, ObserverRegistry};
+    use pmindp_sensor::SensorClass;
+    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV6};
+    use tokio::net::UdpSocket;
+
+    use super::SharedSocket;
+
+    #[tokio::test]
+    async fn check_shared_socket_demux_by_token() {
+        let ip = |host| Ipv4Addr::new(127, 30, 0, host).to_ipv6_mapped();
+        let shared = SharedSocket::bind(SocketAddrV6::new(ip(1), 1213, 0, 0))
+            .await
+            .expect("Unable to bind shared socket");
+        let shared_addr = shared.local_addr().expect("No local addr");
+
+        let node = UdpSocket::bind(SocketAddr::new(ip(2).into(), 1212))
+            .await
+            .expect("Unable to bind node");
+        let mut observers = ObserverRegistry::new(SensorClass::Soil.name(), 60);
+        let mut buffer = [0u8; 512];
+
+        // Two observations of the same node, told apart by token
+        let node_addr = SocketAddrV6::new(ip(2), 1212, 0, 0);
+        let mut clients = vec![];
+        let mut sockets = vec![];
+        for (message_id, token) in [(1, [0x1; 8]), (2, [0x2; 8])] {
+            let mut client = ObserveClient::new(token, message_id, SensorClass::Soil.name());
+            let socket = shared.node_socket(token);
+            let request = client.register().to_bytes().expect("Bad request");
+            socket
+                .send_to(&request, node_addr)
+                .await
+                .expect("Unable to send");
+            let (len, from) = node.recv_from(&mut buffer).await.expect("No request");
+            assert_eq!(from, shared_addr);
+            let request = Packet::from_bytes(&buffer[..len]).expect("Bad request");
+            observers.handle_request(&request, from, b"", None, 0);
+            sockets.push(socket);
+            clients.push(client);
+        }
+
+        for (addr, packet) in observers.notify(
+            SensorClass::Soil.name(),
+            b"{}",
+            ContentFormat::ApplicationJSON,
+            0,
+        ) {
+            node.send_to(&packet.to_bytes().expect("Bad packet"), addr)
+                .await
+                .ok();
+        }
+        for (socket, client) in sockets.iter_mut().zip(clients.iter_mut()) {
+            let (from, packet) = socket.recv_from(&mut buffer).await.expect("No packet");
+            assert_eq!(from.port(), 1212);
+            let packet = packet.expect("Not CoAP");
+            assert_eq!(
+                client.handle_response(&packet, 0),
+                Notification::Fresh(b"{}")
+            );
+        }
+
+        // Once the node socket is dropped its token is rejected
+        drop(sockets);
+        for (addr, packet) in observers.notify(
+            SensorClass::Soil.name(),
+            b"{}",
+            ContentFormat::ApplicationJSON,
+            0,
+        ) {
+            node.send_to(&packet.to_bytes().expect("Bad packet"), addr)
+                .await
+                .ok();
+        }
+        let (len, _) = node.recv_from(&mut buffer).await.expect("No reset");
+        let reset = Packet::from_bytes(&buffer[..len]).expect("Bad reset");
+        assert_eq!(reset.header.get_type(), coap_lite::MessageType::Reset);
+    }
+}
@@ -12,28 +12,39 @@
 //!    thread mesh for new / reset sensor nodes via [`OtMonitor`],
 //!    an [`actix::Actor`] oject. For each active node on the mesh, this
 //!    actor does the following:
-//!    a. Register and maintain active CoAP subscription (as an observer client)
-//!       to request nodes to start serving sensor data.
-//!    b. The actor spawns a dedicated task for each node to open and manage a
-//!       socket to receive sensor data, in a 1:1 mapping where each active node
-//!       gets it's own port.
+//!    a. Discover the resources and sensors of each node from its
+//!    `/.well-known/core` ([`NodeCapabilities`]), then register and
+//!    maintain active CoAP subscription (as an RFC 7641 observer client,
+//!    see `pmindp-protocol`) to request nodes to start serving sensor data.
+//!    With a [`KeyStore`] configured, every exchange after discovery is
+//!    authenticated and encrypted with the node's pre-shared key.
+//!    b. The actor spawns a dedicated task for each node to manage receiving
+//!    its sensor data, either on a socket in a 1:1 mapping where each active
+//!    node gets it's own port, or on one socket shared by all nodes (see
+//!    [`ReceiveMode`]).
 //!    b. The actor also tracks available ports to use as new nodes come online or
-//!       existing nodes have a reset event, freeing up ports when not in use/when
-//!       a node resets, and generally tracks when nodes fall off the network & logs
-//!       appropriately / generates an event
+//!    existing nodes have a reset event, freeing up ports when not in use/when
+//!    a node resets, and tracks the [`Lifecycle`] of every node, publishing
+//!    each transition and re-registering nodes that stop reporting. Nodes
+//!    are tracked by EUI, a node that re-parents or changes addr keeps its
+//!    observation and stream. Nodes that register with the broker's
+//!    resource directory as they join are registered right away, the
+//!    others on the next poll of the mesh. With [`NodeDiscovery::Service`]
+//!    only devices that advertise a plant-minder service through the
+//!    border router's SRP server are taken for nodes
 //! 2. Route received sensor data and node events so that it is available to any
 //!    subscribing clients. The [`EventRouter`] actor performs the set up and
 //!    coordination between the , including the [`OtMonitor`] object, to enable this.
 //!    a. Using [`tokio_stream::wrappers::UnboundedReceiverStream`] objects, the dedicated
-//!       task set up for each node streams sensor data to a queue that is then available
-//!       for subscribing clients to consume
+//!    task set up for each node streams sensor data to a queue that is then available
+//!    for subscribing clients to consume
 //!
 //! The [`Broker`] object exposes a client subscription API to enable subscribers to
 //! receive and process sensor data as it is received from each discovered node on
 //! the mesh. See the below example
 //!
 //! # Examples
-//! ```rust
+//! ```rust,no_run
 //! #[actix::main]
 //! async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!    let broker_handle = pmind_broker::broker(tokio::time::Duration::from_secs(15), 500)
@@ -45,6 +56,7 @@
 //!
 //!     let (sensor_stream_tx, sensor_stream_rx) = tokio::sync::mpsc::unbounded_channel();
 //!     let (node_state_tx, node_state_rx) = tokio::sync::mpsc::unbounded_channel();
+//!     let (network_tx, network_rx) = tokio::sync::mpsc::unbounded_channel();
 //!
 //!     // The provided client ID must be unique for each subscriber
 //!     broker_handle
@@ -52,26 +64,59 @@
 //!             id: 0,
 //!             sensor_readings: sensor_stream_tx,
 //!             node_status: node_state_tx,
+//!             classes: pmind_broker::SensorClass::ALL.to_vec(),
+//!             network_status: Some(network_tx),
 //!         })
 //!         .await
 //!         .map_err(|e| {
 //!            log::error!("Error sending client subscribe request {e:}");
 //!             e
 //!         })??;
+//!
+//!     Ok(())
+//! }
 //! ```
 
 mod broker;
 mod client;
+mod commission;
+mod demux;
+mod lifecycle;
 mod monitor;
 mod node;
+mod rd;
 mod router;
+mod security;
+#[cfg(feature = "sim")]
+mod sim;
 
-pub(crate) use client::{OtCliClient, OtClient, OtClientError};
 pub(crate) use monitor::{OtMonitor, OtMonitorError};
 pub(crate) use router::{EventRouter, EventRouterError};
 
-pub use broker::{broker, Broker, BrokerError, ClientSubscribe, ClientUnsubscribe};
-pub use node::{ErrorState, NodeEvent, NodeSensorReading, NodeState, NodeStatus};
+pub use broker::{
+    broker, broker_with_backend, broker_with_client, broker_with_config, Broker, BrokerConfig,
+    BrokerError, ClientSubscribe, ClientUnsubscribe, OtClientBackend,
+};
+pub use client::{
+    DeviceRole, LinkMetrics, NetworkState, OperationalDataset, OtCliClient, OtClient,
+    OtClientError, OtDbusClient, OtRestClient, OtSocketClient, PlantService, DEFAULT_OT_CLI_SOCKET,
+    DEFAULT_OT_INTERFACE, DEFAULT_OT_REST_URL,
+};
+pub use commission::{
+    CommissionError, Commissioner, CommissionerState, Eui64, JoinState, Joiner, JoinerStatus, Pskd,
+    DEFAULT_JOINER_TIMEOUT,
+};
+pub use demux::{ReceiveMode, DEFAULT_SHARED_RCV_PORT};
+pub use lifecycle::{Backoff, Lifecycle, NodeMove, NodeTransition};
+pub use monitor::{NetworkChange, NetworkEvent, NetworkStatus, NodeDiscovery, NodeLinkMetrics};
+pub use node::{ErrorState, NodeCapabilities, NodeEvent, NodeSensorReading, NodeState, NodeStatus};
+pub use pmindp_protocol::{Psk, TransmissionParams, RD_PORT};
+pub use pmindp_sensor::{GrowthStage, SensorClass, SensorType};
+pub use security::{KeyStore, KeyStoreError};
+#[cfg(feature = "sim")]
+pub use sim::{
+    SimError, SimMesh, SimOtClient, SimStep, VirtualNode, SIM_BORDER_ROUTER, SIM_NETWORK,
+};
 
 /// [`Eui`] is the Extended Unique Identifier: each node should have a
 /// unique EUI that persists across node cpu resets / power events
@@ -83,12 +128,71 @@ pub type Rloc = u16;
 /// [`ClientId`] is used with subscribing to broker events
 pub type ClientId = u32;
 
-/// Node [`Registration`] information for client subscribers
-pub type Registration = (Eui, std::net::Ipv6Addr, String);
+/// Node [`Registration`] information for client subscribers: the node, the
+/// plant it was configured for and what it can report. Fields the node's
+/// protocol version does not send (see [`pmindp_sensor::protocol`]) are
+/// left at their defaults
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registration {
+    pub eui: Eui,
+    pub addr: std::net::Ipv6Addr,
+    pub name: String,
+    pub pot_num: u32,
+    pub species: String,
+    pub growth_stage: GrowthStage,
+    /// Version of the firmware the node runs
+    pub firmware: String,
+    /// Resources the node serves and the sensors it was built with
+    pub capabilities: NodeCapabilities,
+}
+
+impl Registration {
+    pub(crate) fn new(
+        identity: pmindp_sensor::NodeIdentity,
+        addr: std::net::Ipv6Addr,
+        capabilities: NodeCapabilities,
+    ) -> Self {
+        Registration {
+            eui: identity.eui,
+            addr,
+            name: identity.name,
+            pot_num: identity.pot_num,
+            species: identity.species,
+            growth_stage: identity.growth_stage,
+            firmware: identity.firmware,
+            capabilities,
+        }
+    }
+
+    /// Fill in what the node's identity left out from the TXT record of
+    /// its service, e.g. the firmware version of nodes that predate it
+    pub(crate) fn describe(&mut self, service: &PlantService) {
+        for (field, txt) in [
+            (&mut self.name, &service.name),
+            (&mut self.species, &service.species),
+            (&mut self.firmware, &service.firmware),
+        ] {
+            if let (true, Some(txt)) = (field.is_empty(), txt) {
+                field.clone_from(txt);
+            }
+        }
+    }
+}
 
 // Used to limit rendered plant names
 const MAX_PLANT_NAME_SIZE: usize = 20;
 
-// Define the number of seconds before a node is considered "Timed out"
-// a.k.a. dropped off the network
+// Define the number of seconds without readings before a node is
+// considered "Timed out", see `BrokerConfig::node_timeout`
 const DEFAULT_TIMEOUT: u64 = 100;
+
+// Seconds between link metrics of the nodes being published, see
+// `BrokerConfig::link_metrics`
+const DEFAULT_LINK_METRICS_PERIOD: u64 = 60;
+
+// Number of nodes registered at once, see `BrokerConfig::max_registrations`
+const DEFAULT_MAX_REGISTRATIONS: usize = 4;
+
+// Seconds one attempt to register a node may take, see
+// `BrokerConfig::registration_timeout`
+const DEFAULT_REGISTRATION_TIMEOUT: u64 = 30;
@@ -0,0 +1,114 @@
+//! Lifecycle of the nodes on the mesh, as tracked by the
+//! [`OtMonitor`](`crate::OtMonitor`).
+//!
+//! A node found in the child table of any router on the mesh is [`Lifecycle::Discovered`], then
+//! [`Lifecycle::Registering`] while the broker discovers and observes it,
+//! and [`Lifecycle::Online`] once it does. A node that stops reporting, or
+//! whose registration fails, is [`Lifecycle::Stale`] and re-registered
+//! with exponential [`Backoff`]. A node that drops out of the child table
+//! is [`Lifecycle::Offline`] and [`Lifecycle::Rejoined`] when it is back.
+//! Every transition is published to subscribers as a [`NodeTransition`].
+//!
+//! Registered nodes are tracked by EUI, so a node that re-parents (new
+//! RLOC16) or gets a new addr keeps its observation and stream; the move
+//! is published as a [`NodeMove`]
+use std::net::Ipv6Addr;
+use tokio::time::Duration;
+
+use crate::{Eui, Rloc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifecycle {
+    /// Found on the mesh, not contacted yet
+    Discovered,
+    /// Being discovered and observed
+    Registering,
+    /// Observed and reporting
+    Online,
+    /// Still on the mesh but not reporting, or registration failed; the
+    /// node is re-registered with backoff
+    Stale,
+    /// Dropped off the mesh
+    Offline,
+    /// Back on the mesh after being offline, registered again next
+    Rejoined,
+}
+
+impl Lifecycle {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Discovered => "discovered",
+            Self::Registering => "registering",
+            Self::Online => "online",
+            Self::Stale => "stale",
+            Self::Offline => "offline",
+            Self::Rejoined => "rejoined",
+        }
+    }
+}
+
+/// A change of one node's [`Lifecycle`], see
+/// [`NodeStatus::Lifecycle`](`crate::NodeStatus`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeTransition {
+    pub rloc: Rloc,
+    pub addr: Ipv6Addr,
+    /// Known once the node has registered
+    pub eui: Option<Eui>,
+    /// `None` when the node was just discovered
+    pub from: Option<Lifecycle>,
+    pub to: Lifecycle,
+}
+
+/// A registered node showed up under another parent or at another addr,
+/// see [`NodeStatus::Moved`](`crate::NodeStatus`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeMove {
+    pub eui: Eui,
+    pub from: (Rloc, Ipv6Addr),
+    pub to: (Rloc, Ipv6Addr),
+}
+
+/// Delay before re-registering a stale node, doubled after every failed
+/// attempt up to `max`
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(5),
+            max: Duration::from_secs(300),
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the attempt following `failures` failed ones
+    pub fn delay(&self, failures: u32) -> Duration {
+        self.initial
+            .saturating_mul(1 << failures.min(16))
+            .min(self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Duration;
+
+    use super::Backoff;
+
+    #[test]
+    fn check_backoff_doubles_up_to_max() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(2),
+            max: Duration::from_secs(30),
+        };
+        let delays: Vec<_> = (0..6).map(|n| backoff.delay(n).as_secs()).collect();
+        assert_eq!(delays, [2, 4, 8, 16, 30, 30]);
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(30));
+    }
+}
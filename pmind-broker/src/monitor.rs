@@ -1,11 +1,22 @@
 use actix::prelude::*;
+use ipnet::Ipv6Net;
 use std::{
     collections::{HashMap, HashSet},
+    future::Future,
     net::Ipv6Addr,
+    sync::Arc,
 };
 use thiserror::Error;
+use tokio::{
+    sync::mpsc::UnboundedSender,
+    time::{Duration, Instant},
+};
 
-use crate::{Eui, OtClient, OtClientError, Rloc};
+use crate::{
+    client::{DeviceRole, LinkMetrics, NetworkState, PlantService},
+    node::NodeLink,
+    Backoff, Eui, Lifecycle, NodeMove, NodeStatus, NodeTransition, OtClient, OtClientError, Rloc,
+};
 
 #[derive(Error, Debug)]
 pub enum OtMonitorError {
@@ -15,8 +26,128 @@ pub enum OtMonitorError {
     OtClientError(#[from] OtClientError),
     #[error("Port Error {0}")]
     PortError(String),
+    #[error("OT Client timed out")]
+    Timeout,
+    #[error("No OMR prefix on the mesh")]
+    NoOmrPrefix,
+}
+
+/// The OMR prefix changed and the broker has a new addr on it, see
+/// [`NodeStatus::NetworkChange`]. Registered nodes are registered again
+/// from the new addr, keeping their streams
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkChange {
+    pub prefix: Ipv6Net,
+    /// `None` if the mesh had no OMR prefix so far
+    pub from: Option<Ipv6Addr>,
+    pub to: Ipv6Addr,
+}
+
+/// What changed on the Thread network, see [`NetworkStatus`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// The otbr-agent answers, for the first time or since it was down
+    AgentUp,
+    /// The otbr-agent stopped answering
+    AgentDown,
+    Role {
+        from: DeviceRole,
+        to: DeviceRole,
+    },
+    Partition {
+        from: Option<u32>,
+        to: Option<u32>,
+    },
+    /// Router ID of the leader
+    Leader {
+        from: Option<u8>,
+        to: Option<u8>,
+    },
+    /// Timestamp of the active dataset, e.g. as the channel or network key
+    /// changed
+    Dataset {
+        from: Option<u64>,
+        to: Option<u64>,
+    },
+}
+
+/// A change of the Thread network, published on
+/// [`ClientSubscribe::network_status`](`crate::ClientSubscribe`).
+/// Subscribers get the current state as they subscribe, as an
+/// [`NetworkEvent::AgentUp`] or [`NetworkEvent::AgentDown`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkStatus {
+    /// State of the network, `None` while the otbr-agent is down
+    pub state: Option<NetworkState>,
+    pub event: NetworkEvent,
 }
 
+impl NetworkStatus {
+    /// Whether the border router can reach the nodes: the otbr-agent is up
+    /// and attached to a partition. While it is not, nodes are neither
+    /// registered nor marked offline
+    pub fn is_mesh_up(&self) -> bool {
+        self.state.is_some_and(|s| s.role.is_attached())
+    }
+
+    /// The changes from `from` to `to`
+    fn events(from: NetworkState, to: NetworkState) -> Vec<NetworkEvent> {
+        let mut events = vec![];
+        if from.role != to.role {
+            events.push(NetworkEvent::Role {
+                from: from.role,
+                to: to.role,
+            });
+        }
+        if from.partition_id != to.partition_id {
+            events.push(NetworkEvent::Partition {
+                from: from.partition_id,
+                to: to.partition_id,
+            });
+        }
+        if from.leader_router_id != to.leader_router_id {
+            events.push(NetworkEvent::Leader {
+                from: from.leader_router_id,
+                to: to.leader_router_id,
+            });
+        }
+        if from.active_timestamp != to.active_timestamp {
+            events.push(NetworkEvent::Dataset {
+                from: from.active_timestamp,
+                to: to.active_timestamp,
+            });
+        }
+        events
+    }
+}
+
+/// Link to its parent of a registered node, published every
+/// [`BrokerConfig::link_metrics`](`crate::BrokerConfig`), see
+/// [`NodeStatus::LinkMetrics`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeLinkMetrics {
+    pub eui: Eui,
+    pub addr: Ipv6Addr,
+    pub metrics: LinkMetrics,
+}
+
+/// Which devices on the mesh the [`OtMonitor`] takes for sensor nodes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NodeDiscovery {
+    /// Every device attached to a router on the mesh
+    #[default]
+    Mesh,
+    /// Only devices that registered a `_plantminder._udp` service with the
+    /// border router's SRP server, which are then contacted on the port of
+    /// the service. Needs an [`OtClient`] that can browse the SRP server,
+    /// see [`OtClient::get_plant_services`]
+    Service,
+}
+
+/// Upper bound on a single otbr-agent query, so that a hung agent
+/// surfaces as an error instead of stalling the monitor loop
+const OT_CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
 type NodeRcvPort = u16;
 
 struct Ports {
@@ -79,27 +210,75 @@ impl Ports {
     }
 }
 
+/// Where a node is on the mesh: the RLOC16 of its link and its addr
+type Location = (Rloc, Ipv6Addr);
+
+/// How the [`OtMonitor`] knows a node: by EUI once it has registered, by
+/// its location until then
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum NodeId {
+    Eui(Eui),
+    Unidentified(Location),
+}
+
+/// What the [`OtMonitor`] knows about a node it has seen on the mesh
+struct TrackedNode {
+    /// Where the node was last seen
+    location: Location,
+    state: Lifecycle,
+    /// Set once the node has registered
+    registration: Option<InternalRegistration>,
+    /// Registration attempts failed since the node was last online
+    failures: u32,
+    /// When a stale node is due to be registered again
+    retry_at: Instant,
+    /// Registered from an OMR addr the broker no longer has, it is
+    /// registered again from the new one
+    rebind: bool,
+}
+
 pub struct OtMonitor {
-    /// Track nodes that are currently registered
-    nodes: HashMap<NodeRcvPort, InternalRegistration>,
+    /// Every node seen on the mesh
+    nodes: HashMap<NodeId, TrackedNode>,
     /// Pool of free ports to grab from
     ports: Ports,
-    addr: Ipv6Addr,
+    /// OMR addr the broker binds its sockets to, `None` while the mesh has
+    /// no OMR prefix; nodes are only registered once it has one
+    addr: Option<Ipv6Addr>,
     /// Dynamic trait object that implements the needed traits to
-    /// interface with the otbr-agent layer
-    ot_client: Box<dyn OtClient>,
+    /// interface with the otbr-agent layer. Shared so that queries
+    /// can run as futures outside of the actor's handlers
+    ot_client: Arc<dyn OtClient>,
+    /// Where lifecycle transitions and moves are published
+    updates: UnboundedSender<NodeStatus>,
+    backoff: Backoff,
+    /// Addrs of nodes that registered with the resource directory since the
+    /// last [`CheckNewNode`]
+    announced: HashSet<Ipv6Addr>,
+    discovery: NodeDiscovery,
+    /// Services by node addr as of the last [`CheckNewNode`], with
+    /// [`NodeDiscovery::Service`]
+    services: HashMap<Ipv6Addr, PlantService>,
+    /// Where changes of the network are published
+    network_updates: UnboundedSender<NetworkStatus>,
+    /// State of the network as of the last [`CheckNetworkState`], `None`
+    /// until the otbr-agent answers and while it is down
+    network: Option<NetworkState>,
+    /// The otbr-agent did not answer the last [`CheckNetworkState`]
+    agent_down: bool,
 }
 
 impl OtMonitor {
-    pub fn new(ot_client: Box<dyn OtClient>) -> Self {
-        let addr = {
-            if let Ok(addr) = ot_client.get_omr_ip() {
-                addr
-            } else {
-                // This will trigger logic to update later if possible
-                Ipv6Addr::from([0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x1])
-            }
-        };
+    pub async fn new(
+        ot_client: Box<dyn OtClient>,
+        updates: UnboundedSender<NodeStatus>,
+        network_updates: UnboundedSender<NetworkStatus>,
+        backoff: Backoff,
+        discovery: NodeDiscovery,
+    ) -> Self {
+        let ot_client: Arc<dyn OtClient> = Arc::from(ot_client);
+        // Picked up by `MonitorNetworkStatus` once there is a prefix
+        let addr = OtMonitor::get_omr_ip(ot_client.clone()).await.ok();
 
         // 100 ports should be more than enough
         let ports = Ports::new(1213, 100).unwrap_or_default();
@@ -109,34 +288,123 @@ impl OtMonitor {
             addr,
             ot_client,
             ports,
+            updates,
+            backoff,
+            announced: HashSet::new(),
+            discovery,
+            services: HashMap::new(),
+            network_updates,
+            network: None,
+            agent_down: false,
         }
     }
 
-    pub fn get_omr_ip(&self) -> Result<Ipv6Addr, OtMonitorError> {
-        self.ot_client.get_omr_ip().map_err(|e| {
-            log::error!("Ot Client unable to get OMR ip {e:}");
-            OtMonitorError::from(e)
-        })
+    /// Bound an otbr-agent query by [`OT_CLIENT_TIMEOUT`]
+    async fn query<T>(
+        fut: impl Future<Output = Result<T, OtClientError>>,
+    ) -> Result<T, OtMonitorError> {
+        tokio::time::timeout(OT_CLIENT_TIMEOUT, fut)
+            .await
+            .map_err(|_| {
+                log::error!("Ot Client timed out after {OT_CLIENT_TIMEOUT:?}");
+                OtMonitorError::Timeout
+            })?
+            .map_err(OtMonitorError::from)
     }
 
-    pub fn check_addr_update_needed(&mut self) -> Result<bool, OtMonitorError> {
-        Ok(self.addr.is_loopback()
-            || self.addr.segments()[0] != self.ot_client.get_omr_prefix()?.addr().segments()[0])
+    pub async fn get_omr_ip(ot_client: Arc<dyn OtClient>) -> Result<Ipv6Addr, OtMonitorError> {
+        OtMonitor::query(ot_client.get_omr_ip())
+            .await
+            .inspect_err(|e| {
+                log::error!("Ot Client unable to get OMR ip {e:}");
+            })
     }
 
-    pub fn update_addr(&mut self) -> Result<(), OtMonitorError> {
-        self.addr = self.get_omr_ip()?;
-        Ok(())
+    /// The current OMR prefix and the broker's addr on it, if `addr` is not
+    /// on that prefix (or there is no `addr` yet)
+    pub async fn check_omr_change(
+        ot_client: Arc<dyn OtClient>,
+        addr: Option<Ipv6Addr>,
+    ) -> Result<Option<(Ipv6Net, Ipv6Addr)>, OtMonitorError> {
+        let prefix = OtMonitor::query(ot_client.get_omr_prefix()).await?;
+        if addr.is_some_and(|addr| prefix.contains(&addr)) {
+            return Ok(None);
+        }
+        Ok(Some((prefix, OtMonitor::get_omr_ip(ot_client).await?)))
+    }
+
+    /// The broker has `addr` on the new OMR `prefix`. Nodes registered from
+    /// the old addr are registered again, see [`NetworkChange`]
+    fn renumber(&mut self, prefix: Ipv6Net, addr: Ipv6Addr) {
+        if self.addr == Some(addr) {
+            return;
+        }
+        let from = self.addr.replace(addr);
+        log::info!("OMR addr changed from {from:?} to {addr} on {prefix}");
+        for node in self.nodes.values_mut() {
+            // Other nodes are registered from the new addr anyway
+            node.rebind = matches!(node.state, Lifecycle::Online | Lifecycle::Registering);
+        }
+        self.updates
+            .send(NodeStatus::NetworkChange(NetworkChange {
+                prefix,
+                from,
+                to: addr,
+            }))
+            .ok();
+    }
+
+    /// Publish the changes from the last network state to `state`. Returns
+    /// whether the nodes can be reached, which they are taken to be with
+    /// clients that cannot report the state
+    fn track_network(&mut self, state: Result<NetworkState, OtMonitorError>) -> bool {
+        let state = match state {
+            Ok(state) => state,
+            Err(OtMonitorError::OtClientError(OtClientError::Unsupported(_))) => return true,
+            Err(e) => {
+                self.network = None;
+                if !std::mem::replace(&mut self.agent_down, true) {
+                    log::error!("otbr-agent down: {e:}");
+                    self.publish_network(NetworkEvent::AgentDown);
+                }
+                return false;
+            }
+        };
+        self.agent_down = false;
+
+        let events = match self.network.replace(state) {
+            Some(from) => NetworkStatus::events(from, state),
+            None => vec![NetworkEvent::AgentUp],
+        };
+        for event in events {
+            log::info!("Network change {event:?}");
+            self.publish_network(event);
+        }
+        state.role.is_attached()
+    }
+
+    fn publish_network(&self, event: NetworkEvent) {
+        self.network_updates
+            .send(NetworkStatus {
+                state: self.network,
+                event,
+            })
+            .ok();
     }
 
-    pub fn get_nodes(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtMonitorError> {
-        Ok(self
-            .ot_client
-            .get_child_ips()?
+    /// The nodes attached to any router on the mesh, see
+    /// [`OtClient::get_mesh_ips`]
+    pub async fn get_nodes(
+        ot_client: Arc<dyn OtClient>,
+        addr: Ipv6Addr,
+    ) -> Result<Vec<(Rloc, Ipv6Addr)>, OtMonitorError> {
+        Ok(OtMonitor::query(ot_client.get_mesh_ips())
+            .await?
             .iter()
             .filter_map(|(rloc, ip)| {
-                if ip.segments()[0] == self.addr.segments()[0] {
-                    // Only push addrs that match the addr scope (OMR)
+                if ip.segments()[0] == addr.segments()[0] || is_rloc_addr(ip, *rloc) {
+                    // Only push addrs that match the addr scope (OMR), or the
+                    // RLOC addr when that is all the client can report
                     Some((rloc, ip))
                 } else {
                     None
@@ -146,21 +414,253 @@ impl OtMonitor {
             .collect())
     }
 
-    pub fn register_node(&mut self, node: InternalRegistration) -> Result<(), OtMonitorError> {
-        log::debug!("Registering node rloc {} : port {}", node.rloc, node.port);
-        let port = node.port;
+    /// The `_plantminder._udp` services on the mesh, `None` unless nodes
+    /// are discovered by [`NodeDiscovery::Service`]. A failed query finds
+    /// no services, so that no other device is taken for a node
+    pub async fn get_services(
+        ot_client: Arc<dyn OtClient>,
+        discovery: NodeDiscovery,
+    ) -> Option<Vec<PlantService>> {
+        if discovery != NodeDiscovery::Service {
+            return None;
+        }
+        Some(
+            OtMonitor::query(ot_client.get_plant_services())
+                .await
+                .inspect_err(|e| log::warn!("Unable to browse SRP services {e:}"))
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Keep the nodes on the mesh that advertise a service in `services`,
+    /// which are kept to look up the port and metadata of each node by
+    fn advertised(&mut self, nodes: &mut Vec<Location>, services: Vec<PlantService>) {
+        self.services.clear();
+        for service in services {
+            for ip in &service.addrs {
+                self.services.insert(*ip, service.clone());
+            }
+        }
+        nodes.retain(|(_, ip)| self.services.contains_key(ip));
+    }
+
+    /// Record the registration of a node. Returns the handler to relocate
+    /// the node to when its previous registration's handler is still
+    /// running, see [`NodeLink`]
+    pub fn register_node(
+        &mut self,
+        node: InternalRegistration,
+    ) -> Result<Option<UnboundedSender<NodeLink>>, OtMonitorError> {
+        log::debug!("Registering node rloc {} : port {:?}", node.rloc, node.port);
 
+        let location = (node.rloc, node.ip);
+        let id = NodeId::Eui(node.eui);
+        // The attempt was tracked by location until the node told its EUI
+        let attempt = self.nodes.remove(&NodeId::Unidentified(location));
+        match self.nodes.get_mut(&id) {
+            Some(tracked) => {
+                let from = std::mem::replace(&mut tracked.location, location);
+                if from != location {
+                    self.moved(node.eui, from, location);
+                }
+            }
+            None => {
+                self.nodes.insert(
+                    id,
+                    attempt.unwrap_or(TrackedNode {
+                        location,
+                        state: Lifecycle::Registering,
+                        registration: None,
+                        failures: 0,
+                        retry_at: Instant::now(),
+                        rebind: false,
+                    }),
+                );
+            }
+        }
+        Ok(self.registered(id, node))
+    }
+
+    /// The node `id` registered with `node`, which moves into the running
+    /// handler of its previous registration if there is one
+    fn registered(
+        &mut self,
+        id: NodeId,
+        node: InternalRegistration,
+    ) -> Option<UnboundedSender<NodeLink>> {
+        let tracked = self.nodes.get_mut(&id)?;
+        tracked.failures = 0;
+        let handler = match tracked.registration.as_mut() {
+            // The handler carries on, with its session, on the new port
+            Some(current) if !current.relocate.is_closed() => {
+                let port = std::mem::replace(&mut current.port, node.port);
+                current.rloc = node.rloc;
+                current.ip = node.ip;
+                Some((current.relocate.clone(), port))
+            }
+            // A port still held by an earlier registration of the node was
+            // released when its handler gave up, or the node went offline
+            _ => {
+                tracked.registration = Some(node);
+                None
+            }
+        };
+        self.transition(id, Lifecycle::Online);
+        let (handler, port) = handler?;
+        if let Some(port) = port {
+            self.ports.mark_port_free_to_use(port);
+        }
+        Some(handler)
+    }
+
+    /// Publish that the node `eui` moved
+    fn moved(&mut self, eui: Eui, from: Location, to: Location) {
+        log::info!("Node {eui:02x?} moved from {from:?} to {to:?}");
+        self.updates
+            .send(NodeStatus::Moved(NodeMove { eui, from, to }))
+            .ok();
+    }
+
+    /// Move the node to `to`, publishing the transition if it is one. A node
+    /// not tracked yet is added at the location it is identified by
+    fn transition(&mut self, id: NodeId, to: Lifecycle) {
+        let (from, location) = match (self.nodes.get_mut(&id), id) {
+            (Some(node), _) if node.state == to => return,
+            (Some(node), _) => (Some(std::mem::replace(&mut node.state, to)), node.location),
+            (None, NodeId::Unidentified(location)) => {
+                self.nodes.insert(
+                    id,
+                    TrackedNode {
+                        location,
+                        state: to,
+                        registration: None,
+                        failures: 0,
+                        retry_at: Instant::now(),
+                        rebind: false,
+                    },
+                );
+                (None, location)
+            }
+            (None, NodeId::Eui(_)) => return,
+        };
+        let eui = match id {
+            NodeId::Eui(eui) => Some(eui),
+            NodeId::Unidentified(_) => None,
+        };
+        log::info!("Node {:#06x} {} {from:?} -> {to:?}", location.0, location.1);
+        self.updates
+            .send(NodeStatus::Lifecycle(NodeTransition {
+                rloc: location.0,
+                addr: location.1,
+                eui,
+                from,
+                to,
+            }))
+            .ok();
+    }
+
+    /// The node last seen at `location`, if any
+    fn find(&self, location: Location) -> Option<NodeId> {
         self.nodes
-            .entry(port)
-            .and_modify(|a| *a = node.clone())
-            .or_insert(node);
+            .iter()
+            .find(|(_, node)| node.location == location)
+            .map(|(id, _)| *id)
+    }
+
+    /// The node is stale, schedule its next registration attempt
+    fn schedule_retry(&mut self, id: NodeId) {
+        let Some(node) = self.nodes.get_mut(&id) else {
+            return;
+        };
+        node.retry_at = Instant::now() + self.backoff.delay(node.failures);
+        node.failures = node.failures.saturating_add(1);
+        if let Some(port) = node.registration.as_mut().and_then(|r| r.port.take()) {
+            self.ports.mark_port_free_to_use(port);
+        }
+        self.transition(id, Lifecycle::Stale);
+    }
 
-        Ok(())
+    /// The node dropped off the mesh. Registered nodes are kept to tell when
+    /// they rejoin, nodes that never registered are forgotten
+    fn evict_node(&mut self, id: NodeId) {
+        let Some(node) = self.nodes.get_mut(&id) else {
+            return;
+        };
+        node.failures = 0;
+        if let Some(port) = node.registration.as_mut().and_then(|r| r.port.take()) {
+            self.ports.mark_port_free_to_use(port);
+        }
+        self.transition(id, Lifecycle::Offline);
+        if let NodeId::Unidentified(_) = id {
+            self.nodes.remove(&id);
+        }
     }
 
-    pub fn evict_node(&mut self, key: &NodeRcvPort) {
-        self.nodes.remove(key);
-        self.ports.mark_port_free_to_use(*key);
+    /// The node the mesh reports at `location`, `None` to skip it. A
+    /// registered node seen at its addr under another RLOC has re-parented,
+    /// and another addr of a child tracked at one still on the mesh is not
+    /// another node. Any other new location is tracked as unidentified until
+    /// the node registers, which tells if it is a node that changed addr
+    fn locate(&mut self, location: Location, active_nodes: &[Location]) -> Option<NodeId> {
+        if let Some(id) = self.find(location) {
+            return Some(id);
+        }
+        let (rloc, ip) = location;
+        let reparented = self
+            .nodes
+            .iter_mut()
+            .find(|(id, node)| matches!(id, NodeId::Eui(_)) && node.location.1 == ip);
+        if let Some((&id, node)) = reparented {
+            let from = std::mem::replace(&mut node.location, location);
+            if let Some(registration) = node.registration.as_mut() {
+                registration.rloc = rloc;
+            }
+            if let NodeId::Eui(eui) = id {
+                self.moved(eui, from, location);
+            }
+            return Some(id);
+        }
+        let other_addr = self.nodes.values().any(|node| {
+            node.location.0 == rloc
+                && node.state != Lifecycle::Offline
+                && active_nodes.contains(&node.location)
+        });
+        if other_addr {
+            return None;
+        }
+        let id = NodeId::Unidentified(location);
+        self.transition(id, Lifecycle::Discovered);
+        Some(id)
+    }
+
+    /// Nodes on the mesh that are due to be registered, moved to
+    /// [`Lifecycle::Registering`]: new and rejoined nodes, stale nodes whose
+    /// backoff has passed and nodes to register from a new OMR addr. Nodes
+    /// that registered with the resource directory have booted, online
+    /// and stale ones are registered again right away
+    fn due_for_registration(&mut self, active_nodes: Vec<Location>) -> Vec<Location> {
+        let now = Instant::now();
+        let announced = std::mem::take(&mut self.announced);
+        let mut due = Vec::new();
+        for &location in &active_nodes {
+            let Some(id) = self.locate(location, &active_nodes) else {
+                continue;
+            };
+            let booted = announced.contains(&location.1);
+            match self.nodes.get(&id).map(|n| (n.state, n.retry_at, n.rebind)) {
+                Some((Lifecycle::Discovered, _, _)) | Some((Lifecycle::Online, _, true)) => {}
+                Some((Lifecycle::Online, _, _)) if booted => {}
+                Some((Lifecycle::Offline, _, _)) => self.transition(id, Lifecycle::Rejoined),
+                Some((Lifecycle::Stale, retry_at, _)) if retry_at <= now || booted => {}
+                _ => continue,
+            }
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.rebind = false;
+            }
+            self.transition(id, Lifecycle::Registering);
+            due.push(location);
+        }
+        due
     }
 
     pub fn get_free_port(&mut self) -> Result<NodeRcvPort, OtMonitorError> {
@@ -172,6 +672,12 @@ impl OtMonitor {
     }
 }
 
+/// Check if the addr is the mesh-local RLOC addr (`<prefix>::ff:fe00:<rloc16>`)
+/// for the provided [`Rloc`]
+fn is_rloc_addr(ip: &Ipv6Addr, rloc: Rloc) -> bool {
+    ip.segments()[4..] == [0x0, 0xff, 0xfe00, rloc]
+}
+
 impl Actor for OtMonitor {
     type Context = Context<Self>;
 }
@@ -184,57 +690,70 @@ pub(crate) struct GetNodeStatus;
 type NodeStatusResponse = Result<Vec<(u16, Ipv6Addr)>, OtMonitorError>;
 
 impl Handler<GetNodeStatus> for OtMonitor {
-    type Result = NodeStatusResponse;
+    type Result = ResponseActFuture<Self, NodeStatusResponse>;
 
     fn handle(&mut self, _msg: GetNodeStatus, _ctx: &mut Self::Context) -> Self::Result {
-        let active_nodes = self.get_nodes()?;
+        // Without an OMR prefix no node is reachable, nor was registered
+        let Some(addr) = self.addr else {
+            return Box::pin(fut::ready(Ok(vec![])));
+        };
+        let active_nodes = OtMonitor::get_nodes(self.ot_client.clone(), addr);
 
-        // Iterate through the hashmap of currently registered nodes;
-        // if any are not in the active node list then
-        // they are missing
-        let missing_nodes = self
-            .nodes
-            .iter()
-            .filter_map(|(key, node)| {
-                if let Some(_found) = active_nodes
-                    .iter()
-                    .find(|(r, i)| *r == node.rloc && *i == node.ip)
-                {
-                    None
-                } else {
-                    Some((key, (node.rloc, node.ip)))
-                }
-            })
-            .map(|(key, (rloc, ip))| (*key, (rloc, ip)))
-            .collect::<Vec<_>>();
+        Box::pin(
+            active_nodes
+                .into_actor(self)
+                .map(|active_nodes, act, _ctx| {
+                    let active_nodes = active_nodes?;
 
-        // clean up internal info based on results
-        for (key, _) in &missing_nodes {
-            self.evict_node(key);
-        }
+                    // Iterate through the hashmap of nodes still on the mesh;
+                    // if any are not in the active node list then
+                    // they are missing. Nodes being registered are left to
+                    // their attempt, which fails if the node is gone, so
+                    // that it cannot bring an evicted node back online
+                    let missing_nodes = act
+                        .nodes
+                        .iter()
+                        .filter(|(_, node)| {
+                            !matches!(node.state, Lifecycle::Offline | Lifecycle::Registering)
+                                && !active_nodes.contains(&node.location)
+                        })
+                        .map(|(id, node)| (*id, node.location))
+                        .collect::<Vec<_>>();
 
-        // Filter out the uneeded info for broker layer and return vec of missing
-        let missing = missing_nodes
-            .iter()
-            .map(|(_, (rloc, ip))| (*rloc, *ip))
-            .collect::<Vec<_>>();
+                    // clean up internal info based on results
+                    for (id, _) in &missing_nodes {
+                        act.evict_node(*id);
+                    }
 
-        Ok(missing)
+                    Ok(missing_nodes
+                        .into_iter()
+                        .map(|(_, location)| location)
+                        .collect())
+                }),
+        )
     }
 }
 
 /// The [`OtMonitor`] actor and other external event handlers may use this type
-#[derive(Message, Clone)]
+#[derive(Message)]
 #[rtype(result = "NodeRegResponse")]
 pub(crate) struct InternalRegistration {
     pub rloc: Rloc,
     pub ip: Ipv6Addr,
-    pub port: NodeRcvPort,
-    #[allow(unused)]
+    /// Dedicated receive port, `None` when the node reports to the shared
+    /// receive socket
+    pub port: Option<NodeRcvPort>,
     pub eui: Eui,
+    /// Identifies this registration among the node's, see [`NodeLost`]
+    pub session: u64,
+    /// Where the handler of this registration takes the node's later
+    /// registrations, when it moves
+    pub relocate: UnboundedSender<NodeLink>,
 }
 
-type NodeRegResponse = Result<(), OtMonitorError>;
+/// The handler of the node's previous registration if it is still running,
+/// the node is moved there instead of handled anew
+type NodeRegResponse = Result<Option<UnboundedSender<NodeLink>>, OtMonitorError>;
 impl Handler<InternalRegistration> for OtMonitor {
     type Result = NodeRegResponse;
 
@@ -244,35 +763,178 @@ impl Handler<InternalRegistration> for OtMonitor {
     }
 }
 
-/// Check for new nodes
+/// Registration of the node failed, it is retried with backoff
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct RegistrationFailed {
+    pub rloc: Rloc,
+    pub ip: Ipv6Addr,
+}
+
+impl Handler<RegistrationFailed> for OtMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegistrationFailed, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(id) = self.find((msg.rloc, msg.ip)) {
+            self.schedule_retry(id);
+        }
+    }
+}
+
+/// The handler of registration `session` gave up on the node (timeout or
+/// socket error), it is re-registered with backoff. Ignored if the node
+/// has registered again since
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct NodeLost {
+    pub eui: Eui,
+    pub session: u64,
+}
+
+impl Handler<NodeLost> for OtMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: NodeLost, _ctx: &mut Self::Context) -> Self::Result {
+        let id = NodeId::Eui(msg.eui);
+        let current = self.nodes.get(&id).is_some_and(|n| {
+            n.state == Lifecycle::Online
+                && n.registration.as_ref().map(|r| r.session) == Some(msg.session)
+        });
+        if current {
+            self.schedule_retry(id);
+        }
+    }
+}
+
+/// Check for nodes due to be registered: new, rejoined, or stale
 #[derive(Message)]
 #[rtype(result = "NewNodeResponse")]
 pub(crate) struct CheckNewNode;
 type NewNodeResponse = Result<Vec<(u16, Ipv6Addr)>, OtMonitorError>;
 
 impl Handler<CheckNewNode> for OtMonitor {
-    type Result = NewNodeResponse;
+    type Result = ResponseActFuture<Self, NewNodeResponse>;
 
     fn handle(&mut self, _msg: CheckNewNode, _ctx: &mut Self::Context) -> Self::Result {
-        let active_nodes = self.get_nodes()?;
+        let Some(addr) = self.addr else {
+            log::warn!("No OMR prefix on the mesh, not registering nodes");
+            return Box::pin(fut::ready(Ok(vec![])));
+        };
+        let active_nodes = OtMonitor::get_nodes(self.ot_client.clone(), addr);
+        let services = OtMonitor::get_services(self.ot_client.clone(), self.discovery);
+
+        Box::pin(
+            async move { (active_nodes.await, services.await) }
+                .into_actor(self)
+                .map(|(active_nodes, services), act, _ctx| {
+                    let mut active_nodes = active_nodes?;
+                    if let Some(services) = services {
+                        act.advertised(&mut active_nodes, services);
+                    }
+                    Ok(act.due_for_registration(active_nodes))
+                }),
+        )
+    }
+}
+
+/// When the next stale node is due to be registered again, `None` if no
+/// node is stale or the mesh has no OMR prefix to register from
+#[derive(Message)]
+#[rtype(result = "Option<Instant>")]
+pub(crate) struct NextRetry;
+
+impl Handler<NextRetry> for OtMonitor {
+    type Result = Option<Instant>;
+
+    fn handle(&mut self, _msg: NextRetry, _ctx: &mut Self::Context) -> Self::Result {
+        self.addr?;
+        self.nodes
+            .values()
+            .filter(|node| node.state == Lifecycle::Stale)
+            .map(|node| node.retry_at)
+            .min()
+    }
+}
+
+/// Stale nodes whose backoff has passed, moved to
+/// [`Lifecycle::Registering`]. They are registered again where they were
+/// last seen, without checking the mesh; the attempt of a node that left
+/// fails and the next [`GetNodeStatus`] evicts it
+#[derive(Message)]
+#[rtype(result = "Vec<(u16, Ipv6Addr)>")]
+pub(crate) struct DueRetries;
+
+impl Handler<DueRetries> for OtMonitor {
+    type Result = Vec<(u16, Ipv6Addr)>;
 
-        let new_nodes = active_nodes
+    fn handle(&mut self, _msg: DueRetries, _ctx: &mut Self::Context) -> Self::Result {
+        if self.addr.is_none() {
+            return vec![];
+        }
+        let now = Instant::now();
+        let due = self
+            .nodes
             .iter()
-            .filter_map(|(rloc, ip)| {
-                if let Some(_found) = self
-                    .nodes
-                    .iter()
-                    .find(|&(_r, i)| i.rloc == *rloc && i.ip == *ip)
-                {
-                    None
-                } else {
-                    Some((rloc, ip))
-                }
+            .filter(|(_, node)| node.state == Lifecycle::Stale && node.retry_at <= now)
+            .map(|(id, node)| (*id, node.location))
+            .collect::<Vec<_>>();
+        due.into_iter()
+            .map(|(id, location)| {
+                self.transition(id, Lifecycle::Registering);
+                location
             })
-            .map(|p| (*p.0, *p.1))
-            .collect();
+            .collect()
+    }
+}
+
+/// Get the service the node at the addr advertised, as of the last
+/// [`CheckNewNode`]. `None` unless nodes are discovered by
+/// [`NodeDiscovery::Service`]
+#[derive(Message)]
+#[rtype(result = "Option<PlantService>")]
+pub(crate) struct GetService(pub Ipv6Addr);
+
+impl Handler<GetService> for OtMonitor {
+    type Result = Option<PlantService>;
+
+    fn handle(&mut self, msg: GetService, _ctx: &mut Self::Context) -> Self::Result {
+        self.services.get(&msg.0).cloned()
+    }
+}
+
+/// The node at the addr registered with the resource directory, it is
+/// registered by the next [`CheckNewNode`]
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Announce(pub Ipv6Addr);
+
+impl Handler<Announce> for OtMonitor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Announce, _ctx: &mut Self::Context) -> Self::Result {
+        self.announced.insert(msg.0);
+    }
+}
 
-        Ok(new_nodes)
+/// Check the role of the border router, its partition and leader and the
+/// active dataset, publishing what changed as a [`NetworkStatus`]. Returns
+/// whether the mesh is up, see [`NetworkStatus::is_mesh_up`]
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub(crate) struct CheckNetworkState;
+
+impl Handler<CheckNetworkState> for OtMonitor {
+    type Result = ResponseActFuture<Self, bool>;
+
+    fn handle(&mut self, _msg: CheckNetworkState, _ctx: &mut Self::Context) -> Self::Result {
+        let ot_client = self.ot_client.clone();
+        let state = async move { OtMonitor::query(ot_client.get_network_state()).await };
+
+        Box::pin(
+            state
+                .into_actor(self)
+                .map(|state, act, _ctx| act.track_network(state)),
+        )
     }
 }
 
@@ -283,17 +945,64 @@ pub(crate) struct MonitorNetworkStatus;
 type MonitorNetworkResponse = Result<(), OtMonitorError>;
 
 impl Handler<MonitorNetworkStatus> for OtMonitor {
-    type Result = MonitorNetworkResponse;
+    type Result = ResponseActFuture<Self, MonitorNetworkResponse>;
 
     fn handle(&mut self, _msg: MonitorNetworkStatus, _ctx: &mut Self::Context) -> Self::Result {
-        if self.check_addr_update_needed()? {
-            self.update_addr()?;
-        }
-        Ok(())
+        let ot_client = self.ot_client.clone();
+        let addr = self.addr;
+
+        let change = OtMonitor::check_omr_change(ot_client, addr);
+
+        Box::pin(change.into_actor(self).map(|change, act, _ctx| {
+            if let Some((prefix, addr)) = change? {
+                act.renumber(prefix, addr);
+            }
+            Ok(())
+        }))
+    }
+}
+
+/// Publish the link metrics of every online node, nodes the client has no
+/// metrics for are skipped
+#[derive(Message)]
+#[rtype(result = "LinkMetricsResponse")]
+pub(crate) struct PublishLinkMetrics;
+type LinkMetricsResponse = Result<usize, OtMonitorError>;
+
+impl Handler<PublishLinkMetrics> for OtMonitor {
+    type Result = ResponseActFuture<Self, LinkMetricsResponse>;
+
+    fn handle(&mut self, _msg: PublishLinkMetrics, _ctx: &mut Self::Context) -> Self::Result {
+        let ot_client = self.ot_client.clone();
+        let metrics = async move { OtMonitor::query(ot_client.get_link_metrics()).await };
+
+        Box::pin(metrics.into_actor(self).map(|metrics, act, _ctx| {
+            let metrics: HashMap<Rloc, LinkMetrics> =
+                metrics?.into_iter().map(|m| (m.rloc, m)).collect();
+            let mut published = 0;
+            for (id, node) in &act.nodes {
+                let (NodeId::Eui(eui), Lifecycle::Online) = (id, node.state) else {
+                    continue;
+                };
+                let Some(metrics) = metrics.get(&node.location.0) else {
+                    continue;
+                };
+                act.updates
+                    .send(NodeStatus::LinkMetrics(NodeLinkMetrics {
+                        eui: *eui,
+                        addr: node.location.1,
+                        metrics: *metrics,
+                    }))
+                    .ok();
+                published += 1;
+            }
+            Ok(published)
+        }))
     }
 }
 
-/// Get the OMR addr
+/// Get the OMR addr nodes are registered from, as of the last
+/// [`MonitorNetworkStatus`]
 #[derive(Message)]
 #[rtype(result = "OmrResponse")]
 pub(crate) struct OmrIp;
@@ -303,7 +1012,7 @@ impl Handler<OmrIp> for OtMonitor {
     type Result = OmrResponse;
 
     fn handle(&mut self, _msg: OmrIp, _ctx: &mut Self::Context) -> Self::Result {
-        self.get_omr_ip()
+        self.addr.ok_or(OtMonitorError::NoOmrPrefix)
     }
 }
 
@@ -334,3 +1043,265 @@ impl Handler<ReturnFreePort> for OtMonitor {
         self.return_port(msg.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use actix::Actor;
+    use ipnet::Ipv6Net;
+    use std::{
+        net::Ipv6Addr,
+        sync::{Arc, Mutex},
+    };
+    use tokio::sync::{mpsc::unbounded_channel, Notify};
+
+    use super::{
+        CheckNetworkState, CheckNewNode, MonitorNetworkStatus, NetworkEvent, NodeDiscovery, OmrIp,
+        OtMonitor, ReserveFreePort,
+    };
+    use crate::{
+        client::{DeviceRole, NetworkState},
+        NodeStatus, OtClient, OtClientError, Rloc,
+    };
+
+    const OMR_IP: Ipv6Addr = Ipv6Addr::new(0xfdc9, 0xfdb2, 0x9fe8, 0x1, 0x0, 0x0, 0x0, 0x1);
+    const CHILD_IP: Ipv6Addr = Ipv6Addr::new(0xfdc9, 0xfdb2, 0x9fe8, 0x1, 0x0, 0x0, 0x0, 0x2);
+
+    /// Client whose child table query hangs until released
+    struct StalledClient(Arc<Notify>);
+
+    #[async_trait::async_trait]
+    impl OtClient for StalledClient {
+        async fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+            self.0.notified().await;
+            Ok(vec![(0xc001, CHILD_IP)])
+        }
+
+        async fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError> {
+            Ok("fdc9:fdb2:9fe8:1::/64".parse()?)
+        }
+
+        async fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError> {
+            Ok(OMR_IP)
+        }
+
+        async fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+            Ok(vec![OMR_IP])
+        }
+    }
+
+    #[actix::test]
+    async fn check_monitor_not_stalled_by_client() {
+        let release = Arc::new(Notify::new());
+        let (transitions, _) = unbounded_channel();
+        let (network, _) = unbounded_channel();
+        let mon = OtMonitor::new(
+            Box::new(StalledClient(release.clone())),
+            transitions,
+            network,
+            Default::default(),
+            NodeDiscovery::Mesh,
+        )
+        .await
+        .start();
+
+        let pending = mon.send(CheckNewNode);
+
+        // The actor must keep serving other messages while the query hangs
+        let port = mon
+            .send(ReserveFreePort)
+            .await
+            .expect("Mailbox error")
+            .expect("No free port");
+        assert!((1213..1313).contains(&port));
+
+        release.notify_one();
+        let nodes = pending
+            .await
+            .expect("Mailbox error")
+            .expect("Unable to get new nodes");
+        assert_eq!(nodes, vec![(0xc001, CHILD_IP)]);
+    }
+
+    /// Client of a mesh without an OMR prefix until one is set
+    struct PrefixClient(Arc<Mutex<Option<Ipv6Net>>>);
+
+    impl PrefixClient {
+        fn prefix(&self) -> Result<Ipv6Net, OtClientError> {
+            self.0
+                .lock()
+                .unwrap()
+                .ok_or_else(|| OtClientError::OtClientErr("No OMR prefix".to_string()))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl OtClient for PrefixClient {
+        async fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+            Ok(vec![(0xc001, CHILD_IP)])
+        }
+
+        async fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError> {
+            self.prefix()
+        }
+
+        async fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError> {
+            self.prefix().map(|_| OMR_IP)
+        }
+
+        async fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+            Ok(self.prefix().map(|_| vec![OMR_IP]).unwrap_or_default())
+        }
+    }
+
+    #[actix::test]
+    async fn check_monitor_waits_for_omr_prefix() {
+        let prefix = Arc::new(Mutex::new(None));
+        let (updates, mut updates_rx) = unbounded_channel();
+        let (network, _) = unbounded_channel();
+        let mon = OtMonitor::new(
+            Box::new(PrefixClient(prefix.clone())),
+            updates,
+            network,
+            Default::default(),
+            NodeDiscovery::Mesh,
+        )
+        .await
+        .start();
+
+        // Nodes are not reachable, nor registered, without a prefix
+        assert!(mon
+            .send(MonitorNetworkStatus)
+            .await
+            .expect("Mailbox error")
+            .is_err());
+        assert!(mon.send(OmrIp).await.expect("Mailbox error").is_err());
+        let nodes = mon
+            .send(CheckNewNode)
+            .await
+            .expect("Mailbox error")
+            .expect("Unable to get new nodes");
+        assert!(nodes.is_empty());
+
+        *prefix.lock().unwrap() = Some("fdc9:fdb2:9fe8:1::/64".parse().unwrap());
+        mon.send(MonitorNetworkStatus)
+            .await
+            .expect("Mailbox error")
+            .expect("Unable to check network status");
+        let Ok(NodeStatus::NetworkChange(change)) = updates_rx.try_recv() else {
+            panic!("No network change published");
+        };
+        assert_eq!((change.from, change.to), (None, OMR_IP));
+        let addr = mon.send(OmrIp).await.expect("Mailbox error");
+        assert_eq!(addr.expect("No OMR addr"), OMR_IP);
+        let nodes = mon
+            .send(CheckNewNode)
+            .await
+            .expect("Mailbox error")
+            .expect("Unable to get new nodes");
+        assert_eq!(nodes, vec![(0xc001, CHILD_IP)]);
+    }
+
+    /// Client of a border router whose network state is set by the test,
+    /// `None` while its otbr-agent is down
+    struct NetworkClient(Arc<Mutex<Option<NetworkState>>>);
+
+    #[async_trait::async_trait]
+    impl OtClient for NetworkClient {
+        async fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+            Ok(vec![])
+        }
+
+        async fn get_network_state(&self) -> Result<NetworkState, OtClientError> {
+            self.0
+                .lock()
+                .unwrap()
+                .ok_or_else(|| OtClientError::OtClientErr("No otbr-agent".to_string()))
+        }
+
+        async fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError> {
+            Ok("fdc9:fdb2:9fe8:1::/64".parse()?)
+        }
+
+        async fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError> {
+            Ok(OMR_IP)
+        }
+
+        async fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+            Ok(vec![OMR_IP])
+        }
+    }
+
+    #[actix::test]
+    async fn check_monitor_network_state() {
+        let leader = NetworkState {
+            role: DeviceRole::Leader,
+            partition_id: Some(1),
+            leader_router_id: Some(48),
+            active_timestamp: Some(1),
+        };
+        let state = Arc::new(Mutex::new(Some(leader)));
+        let (updates, _) = unbounded_channel();
+        let (network, mut network_rx) = unbounded_channel();
+        let mon = OtMonitor::new(
+            Box::new(NetworkClient(state.clone())),
+            updates,
+            network,
+            Default::default(),
+            NodeDiscovery::Mesh,
+        )
+        .await
+        .start();
+
+        assert!(mon.send(CheckNetworkState).await.expect("Mailbox error"));
+        let status = network_rx.try_recv().expect("No network status");
+        assert_eq!(
+            (status.event, status.state),
+            (NetworkEvent::AgentUp, Some(leader))
+        );
+        assert!(status.is_mesh_up());
+
+        // Merged into another partition, as a router
+        let router = NetworkState {
+            role: DeviceRole::Router,
+            partition_id: Some(2),
+            ..leader
+        };
+        *state.lock().unwrap() = Some(router);
+        assert!(mon.send(CheckNetworkState).await.expect("Mailbox error"));
+        let events: Vec<_> = std::iter::from_fn(|| network_rx.try_recv().ok())
+            .map(|s| s.event)
+            .collect();
+        assert_eq!(
+            events,
+            [
+                NetworkEvent::Role {
+                    from: DeviceRole::Leader,
+                    to: DeviceRole::Router
+                },
+                NetworkEvent::Partition {
+                    from: Some(1),
+                    to: Some(2)
+                }
+            ]
+        );
+
+        // Down is published once, and up again with the state
+        *state.lock().unwrap() = None;
+        assert!(!mon.send(CheckNetworkState).await.expect("Mailbox error"));
+        assert!(!mon.send(CheckNetworkState).await.expect("Mailbox error"));
+        let status = network_rx.try_recv().expect("No network status");
+        assert_eq!(
+            (status.event, status.state),
+            (NetworkEvent::AgentDown, None)
+        );
+        assert!(network_rx.try_recv().is_err());
+
+        *state.lock().unwrap() = Some(router);
+        assert!(mon.send(CheckNetworkState).await.expect("Mailbox error"));
+        let status = network_rx.try_recv().expect("No network status");
+        assert_eq!(
+            (status.event, status.state),
+            (NetworkEvent::AgentUp, Some(router))
+        );
+    }
+}
@@ -1,15 +1,31 @@
 use chrono::Local;
-use pmindp_sensor::SensorReading;
-use std::net::{SocketAddr, SocketAddrV6};
-use tokio::{net::UdpSocket, sync::mpsc};
+use coap_lite::{MessageClass, MessageType, Packet};
+use pmindp_protocol::{
+    observe::MAX_AGE_GRACE_SECS, Link, Notification, ObserveClient, Outbox, SecurityContext,
+};
+use pmindp_sensor::{ProtocolHeader, SensorClass, SensorReading, SensorType, WireFormat};
+use std::net::{IpAddr, SocketAddrV6};
+use tokio::{
+    sync::mpsc,
+    time::{Duration, Instant},
+};
 
-use crate::Registration;
+use crate::{
+    demux::NodeSocket, BrokerConfig, Lifecycle, NetworkChange, NodeLinkMetrics, NodeMove,
+    NodeTransition, Registration,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub enum NodeEvent {
     NodeTimeout(SocketAddrV6),
     SocketError(SocketAddrV6),
     SetupError,
+    /// The node answered registration with a protocol version the broker
+    /// does not speak
+    UnsupportedVersion(SocketAddrV6, u8),
+    /// The broker has keys but the node has none it knows, or the node
+    /// failed to authenticate
+    Unauthenticated(SocketAddrV6),
     // TODO someday make this a dynamic trait object SensorReading
     // sp this can support different sensors
     SensorReading(NodeSensorReading),
@@ -25,6 +41,19 @@ pub enum NodeState {
     Online,
 }
 
+impl From<Lifecycle> for NodeState {
+    fn from(lifecycle: Lifecycle) -> Self {
+        match lifecycle {
+            Lifecycle::Online => NodeState::Online,
+            Lifecycle::Stale => NodeState::Offline(ErrorState::Timeout),
+            Lifecycle::Offline => NodeState::Offline(ErrorState::Other),
+            Lifecycle::Discovered | Lifecycle::Registering | Lifecycle::Rejoined => {
+                NodeState::Unknown
+            }
+        }
+    }
+}
+
 /// [`NodeStatus`] is used to separate out data receipt events
 /// from registration or node fall-off when routing to
 /// client subscribers
@@ -32,6 +61,16 @@ pub enum NodeState {
 pub enum NodeStatus {
     Registration(Registration),
     Termination((SocketAddrV6, ErrorState)),
+    /// The node moved to another stage of its
+    /// [`Lifecycle`](`crate::Lifecycle`)
+    Lifecycle(NodeTransition),
+    /// The node re-parented or changed addr, its readings carry the new addr
+    /// from now on
+    Moved(NodeMove),
+    /// The broker moved to a new OMR prefix, every node is registered again
+    NetworkChange(NetworkChange),
+    /// Periodic measurement of a node's link to its parent
+    LinkMetrics(NodeLinkMetrics),
 }
 
 /// [`ErrorState`] is reported to client subscribers via
@@ -43,15 +82,97 @@ pub enum ErrorState {
     Timeout,
     SocketError,
     SetupError,
+    /// The node speaks this protocol version, which the broker does not.
+    /// Reported on every registration attempt until it is updated
+    UnsupportedVersion(u8),
+    /// The node did not authenticate with a key of the broker's
+    /// [`KeyStore`](`crate::KeyStore`). Reported on every registration
+    /// attempt until it is provisioned
+    Unauthenticated,
     Other,
 }
 
+/// [`NodeCapabilities`] are what a node listed in its `/.well-known/core`
+/// when it registered, empty if it does not support resource discovery
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeCapabilities {
+    /// Paths of the resources the node serves
+    pub resources: Vec<String>,
+    /// Sensors the node was built with, from the resource types
+    pub sensors: Vec<SensorType>,
+}
+
+impl NodeCapabilities {
+    pub(crate) fn from_links(links: &[Link]) -> Self {
+        let mut capabilities = NodeCapabilities::default();
+        for link in links {
+            capabilities.resources.push(link.path.clone());
+            for sensor in link
+                .resource_types
+                .iter()
+                .filter_map(|rt| SensorType::from_name(rt))
+            {
+                if !capabilities.sensors.contains(&sensor) {
+                    capabilities.sensors.push(sensor);
+                }
+            }
+        }
+        capabilities
+    }
+
+    pub fn has_sensor(&self, sensor: SensorType) -> bool {
+        self.sensors.contains(&sensor)
+    }
+
+    /// Sensor classes the node serves a resource for. Nodes that predate
+    /// discovery are assumed to have only the soil sensor every node has
+    pub fn classes(&self) -> Vec<SensorClass> {
+        let classes: Vec<_> = SensorClass::ALL
+            .into_iter()
+            .filter(|c| self.resources.iter().any(|r| r == c.name()))
+            .collect();
+        if classes.is_empty() {
+            return vec![SensorClass::Soil];
+        }
+        classes
+    }
+}
+
+/// [`NodeSensorReading`] is sent every time one of the node's sensor
+/// classes reports. `data` merges the latest report of each class, `class`
+/// is the one that just reported
 #[derive(Debug, Clone, Copy)]
 pub struct NodeSensorReading {
     pub addr: SocketAddrV6,
+    pub class: SensorClass,
     pub data: SensorReading,
 }
 
+/// Seconds since the epoch, the clock the Observe freshness and Max-Age
+/// checks run on
+pub(crate) fn now() -> u64 {
+    Local::now().timestamp() as u64
+}
+
+/// Milliseconds since the epoch, the clock CoAP retransmissions run on
+pub(crate) fn now_ms() -> u64 {
+    Local::now().timestamp_millis() as u64
+}
+
+/// What a registration leaves the broker with to exchange with a node
+pub(crate) struct NodeLink {
+    /// The socket the Observe registrations were sent from, the node sends
+    /// its notifications there
+    pub socket: NodeSocket,
+    pub node_addr: SocketAddrV6,
+    /// One observation per sensor class resource
+    pub observations: Vec<(SensorClass, ObserveClient)>,
+    /// Protocol version negotiated at registration
+    pub header: ProtocolHeader,
+    /// Session every message is protected with, if the node authenticated
+    pub security: Option<SecurityContext>,
+}
+
 /// [`NodeEventHandler`] handles all events pertaining to child nodes on the
 /// Thread mesh that support reporting sensor data. All such node events are
 /// condensed into a single enum, [`NodeEvent`], which is split out into
@@ -60,86 +181,156 @@ pub struct NodeSensorReading {
 /// via the [`EventRouter`](`crate::router::EventRouter`).
 ///
 /// [`NodeEventHandler`] has the following responsibilities:
-/// 1. Open new socket to start receiving sensor data (using the port sent in
-/// the CoAP registration)
-/// 2. Track state of socket and time since last socket activity, in order to
-/// notify [`Broker`](`crate::broker::Broker`) when node stops sending data, and
-/// indicate the reason (e.g. due to timeout or socket error) as [`ErrorState`]
-/// 3. Stream sensor data to node event stream as it is received on the socket
-/// which gets routed via the [`EventRouter`](`crate::router::EventRouter`) to the
-/// event queue exposed to client subscribers by the
-/// [`Broker`](`crate::broker::Broker`)
+/// 1. Receive Observe notifications on the [`NodeSocket`] the CoAP
+///    registrations were sent from, either a dedicated port or the shared
+///    receive socket, acknowledging confirmable ones and rejecting (RST)
+///    those with a token that is not one of the node's observations (one
+///    per [`SensorClass`] resource)
+/// 2. Drop duplicate (retransmitted) and stale (reordered) notifications,
+///    and re-register when the Max-Age of the last notification lapses or
+///    the node ends the observation, retransmitting the confirmable
+///    re-registration until the node acknowledges it
+/// 3. Track state of socket and time since last socket activity, in order to
+///    notify [`Broker`](`crate::broker::Broker`) when node stops sending data, and
+///    indicate the reason (e.g. due to timeout or socket error) as [`ErrorState`],
+///    deregistering from the node on the way out
+/// 4. Merge the readings of each class and stream them to the node event
+///    stream as they are received on the socket, which gets routed via the
+///    [`EventRouter`](`crate::router::EventRouter`) to the
+///    event queue exposed to client subscribers by the
+///    [`Broker`](`crate::broker::Broker`)
+/// 5. Take over the [`NodeLink`] of the node's registration at a new addr,
+///    so that a node that moved keeps its stream
 pub struct NodeEventHandler {
     _handler: tokio::task::JoinHandle<()>,
 }
 
+/// Per node state the handler task works on
+struct NodeExchange {
+    socket: NodeSocket,
+    node_addr: SocketAddrV6,
+    /// One observation per sensor class resource
+    observations: Vec<(SensorClass, ObserveClient)>,
+    /// Confirmable requests (re-registrations) awaiting an ACK
+    outbox: Outbox<SocketAddrV6>,
+    /// Latest report of every class
+    reading: SensorReading,
+    /// Protocol version negotiated at registration
+    header: ProtocolHeader,
+    /// Session every message is protected with, if the broker has keys
+    security: Option<SecurityContext>,
+    /// When the node last reported a reading
+    last_reading: Instant,
+}
+
 impl NodeEventHandler {
-    async fn new(addr: SocketAddrV6, sender: mpsc::UnboundedSender<NodeEvent>) -> Self {
+    async fn new(
+        link: NodeLink,
+        config: &BrokerConfig,
+        mut relocations: mpsc::UnboundedReceiver<NodeLink>,
+        sender: mpsc::UnboundedSender<NodeEvent>,
+    ) -> Self {
         let _sender = sender.clone();
+        let timeout = config.node_timeout;
+        let seed = link
+            .observations
+            .first()
+            .map(|(_, c)| {
+                c.token()
+                    .iter()
+                    .take(4)
+                    .fold(0, |acc, b| (acc << 8) | *b as u32)
+            })
+            .unwrap_or_default();
+        let mut node = NodeExchange {
+            socket: link.socket,
+            node_addr: link.node_addr,
+            observations: link.observations,
+            outbox: Outbox::new(config.transmission, seed),
+            reading: SensorReading::default(),
+            header: link.header,
+            security: link.security,
+            last_reading: Instant::now(),
+        };
         let _handler = tokio::spawn(async move {
-            let timeout = std::time::Duration::from_secs(crate::DEFAULT_TIMEOUT);
-
-            let sensor_read_socket = {
-                if let Ok(sensor_read_socket) = UdpSocket::bind(addr).await {
-                    sensor_read_socket
-                } else {
-                    log::error!("Unable to bind to socket {addr:}");
-                    _sender.send(NodeEvent::SetupError).ok();
-                    return;
-                }
-            };
-
+            let mut reregister = tokio::time::interval(Duration::from_secs(MAX_AGE_GRACE_SECS));
             let mut buffer = [0u8; 512];
-            // Update this in the socket poll loop to make sure that when we report
-            // a node event (error) with an address, we return the actual node ip not the
-            // addr we used to open a socket
-            let mut node_addr = addr;
+            let mut relocatable = true;
+
             loop {
-                let node_timeout = tokio::time::sleep(timeout);
+                let node_addr = node.node_addr;
+                // Only readings count, a node that still acknowledges
+                // re-registrations but stopped reporting has timed out
+                let node_timeout = tokio::time::sleep_until(node.last_reading + timeout);
+                let retransmit = node.outbox.next_deadline().map(|deadline| {
+                    tokio::time::Instant::now()
+                        + Duration::from_millis(deadline.saturating_sub(now_ms()))
+                });
                 tokio::select! {
                   _ = _sender.closed() => {
                     log::error!("Sender is closed");
+                    node.deregister().await;
                     break;
                   }
                   _ = node_timeout => {
                     log::error!("Node timed out! No longer receiving data?");
+                    node.deregister().await;
                     _sender.send(NodeEvent::NodeTimeout(node_addr)).ok();
-                    drop(sensor_read_socket);
                     break;
                   }
-                  res = sensor_read_socket.recv_from(&mut buffer) => {
+                  _ = reregister.tick() => {
+                    for idx in 0..node.observations.len() {
+                        let (class, client) = &node.observations[idx];
+                        if client.reregistration_due(now())
+                            && !node.outbox.is_pending(&node_addr, client.token())
+                        {
+                            log::info!("Max-Age lapsed for {node_addr:} {class:?}, re-registering");
+                            node.register(idx).await;
+                        }
+                    }
+                  }
+                  _ = tokio::time::sleep_until(retransmit.unwrap_or_else(tokio::time::Instant::now)),
+                    if retransmit.is_some() => {
+                    let polled = node.outbox.poll(now_ms());
+                    for (_, request) in polled.resend {
+                        log::debug!("Retransmitting to {node_addr:}");
+                        node.send(&request).await;
+                    }
+                    if !polled.failed.is_empty() {
+                        log::warn!("{node_addr:} did not acknowledge re-registration");
+                    }
+                  }
+                  link = relocations.recv(), if relocatable => match link {
+                    Some(link) => node.relocate(link).await,
+                    // The monitor has a newer registration of the node
+                    None => relocatable = false,
+                  },
+                  res = node.socket.recv_from(&mut buffer) => {
                         match res {
-                            Ok((len, from)) => {
-                                // This should always be true unless we get some bad actor sending
-                                // us non-ipv6 traffic at this port
-                                if let SocketAddr::V6(a) = from {
-                                    node_addr = a;
-                                } else {
-                                    log::warn!("Non-ipv6 address sent {from:}");
+                            Ok((from, packet)) => {
+                                if from.ip() != IpAddr::V6(*node_addr.ip())
+                                    || from.port() != node_addr.port()
+                                {
+                                    log::warn!("Dropping packet from {from:}, expected {node_addr:}");
+                                    continue;
                                 }
-
-                                if let Ok(mut data) =
-                                    serde_json::from_slice::<SensorReading>(
-                                        &buffer[..len]
-                                    ).map_err(|e|{
-                                        log::error!("Deserde error {e:} len {:?}", len);
-                                    }) {
-                                        log::trace!("got data from node {:?}", data);
-                                        data.ts = Local::now().timestamp();
-                                        _sender.send(
-                                            NodeEvent::SensorReading(
-                                                NodeSensorReading {
-                                                    addr: node_addr,
-                                                    data,
-                                                }
-                                            )
-                                        ).ok();
+                                let Some(packet) = packet else {
+                                    log::error!("Non CoAP packet from {from:}");
+                                    continue;
+                                };
+                                node.handle_packet(&packet, &_sender).await;
+                            }
+                            // The socket of a node that moved may close first,
+                            // take the pending relocation rather than polling
+                            // the failed socket again
+                            Err(_) if !relocations.is_empty() => {
+                                if let Some(link) = relocations.recv().await {
+                                    node.relocate(link).await;
                                 }
                             }
                             _ => {
                                 log::error!("Socket error");
                                 _sender.send(NodeEvent::SocketError(node_addr)).ok();
-                                drop(sensor_read_socket);
                                 break;
                             }
                         }
@@ -152,14 +343,183 @@ impl NodeEventHandler {
     }
 }
 
+impl NodeExchange {
+    async fn handle_packet(&mut self, packet: &Packet, sender: &mpsc::UnboundedSender<NodeEvent>) {
+        let node_addr = self.node_addr;
+        if matches!(
+            packet.header.get_type(),
+            MessageType::Acknowledgement | MessageType::Reset
+        ) {
+            self.outbox
+                .acknowledge(&node_addr, packet.header.message_id);
+        }
+
+        if packet.header.code == MessageClass::Empty {
+            return;
+        }
+        let Some(idx) = self
+            .observations
+            .iter()
+            .position(|(_, c)| c.token().as_slice() == packet.get_token())
+        else {
+            log::warn!("Rejecting notification with unknown token from {node_addr:}");
+            self.send(&ObserveClient::reset(packet)).await;
+            return;
+        };
+        let unprotected;
+        let packet = match self.security.as_mut() {
+            Some(security) => match security.unprotect(packet) {
+                Ok(packet) => {
+                    unprotected = packet;
+                    &unprotected
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Dropping notification from {node_addr:} that does not verify: {e:?}"
+                    );
+                    return;
+                }
+            },
+            None => packet,
+        };
+        let (class, client) = &mut self.observations[idx];
+        let class = *class;
+
+        match client.handle_response(packet, now()) {
+            Notification::Fresh(payload) => {
+                if let Some(ack) = ObserveClient::ack(packet) {
+                    self.send(&ack).await;
+                }
+                // Piggybacked responses to (re-)registration carry the node
+                // identity, only notifications carry readings
+                if packet.header.get_type() == MessageType::Acknowledgement {
+                    return;
+                }
+                // Nodes that predate the binary format send untagged JSON
+                let format = match packet.get_content_format() {
+                    None => WireFormat::Json,
+                    Some(cf) => match WireFormat::from_content_format(usize::from(cf) as u16) {
+                        Some(format) => format,
+                        None => {
+                            log::error!("Unsupported content format {cf:?} from {node_addr:}");
+                            return;
+                        }
+                    },
+                };
+                if let Ok(()) = self
+                    .reading
+                    .merge_class(class, self.header, format, payload)
+                    .map_err(|e| {
+                        log::error!("Deserde error {e:} len {:?}", payload.len());
+                    })
+                {
+                    log::trace!("got {class:?} data from node {:?}", self.reading);
+                    self.last_reading = Instant::now();
+                    self.reading.ts = Local::now().timestamp();
+                    sender
+                        .send(NodeEvent::SensorReading(NodeSensorReading {
+                            addr: node_addr,
+                            class,
+                            data: self.reading,
+                        }))
+                        .ok();
+                }
+            }
+            Notification::Stale | Notification::Duplicate => {
+                log::debug!("Dropping stale or duplicate notification from {node_addr:}");
+                if let Some(ack) = ObserveClient::ack(packet) {
+                    self.send(&ack).await;
+                }
+            }
+            Notification::Ended => {
+                log::warn!("Node {node_addr:} ended the {class:?} observation, re-registering");
+                self.register(idx).await;
+            }
+            // Both filtered out above
+            Notification::Empty | Notification::Unknown => {}
+        }
+    }
+
+    /// The node moved and was registered at its new addr: carry on with the
+    /// exchange that registration set up, ending the observations made from
+    /// the old socket (the node keeps notifying it otherwise)
+    async fn relocate(&mut self, link: NodeLink) {
+        log::info!("{} moved to {}", self.node_addr, link.node_addr);
+        self.outbox.cancel(&self.node_addr);
+        self.node_addr = link.node_addr;
+        self.deregister().await;
+        self.socket = link.socket;
+        self.observations = link.observations;
+        self.header = link.header;
+        self.security = link.security;
+    }
+
+    /// Send a confirmable (re-)registration of observation `idx`,
+    /// retransmitted until the node acknowledges it
+    async fn register(&mut self, idx: usize) {
+        let request = self.observations[idx].1.register();
+        let Some(request) = self.protect(request) else {
+            return;
+        };
+        // Supersedes an earlier registration still pending
+        self.outbox.send(self.node_addr, request.clone(), now_ms());
+        self.send(&request).await;
+    }
+
+    /// Best effort, the handler is going away so there is no retransmission;
+    /// the node drops the observers anyway once notifications go unanswered
+    async fn deregister(&mut self) {
+        for idx in 0..self.observations.len() {
+            let request = self.observations[idx].1.deregister();
+            if let Some(request) = self.protect(request) {
+                self.send(&request).await;
+            }
+        }
+    }
+
+    /// `request` as it is sent to the node: protected when there is a
+    /// session, `None` if that fails
+    fn protect(&mut self, request: Packet) -> Option<Packet> {
+        match self.security.as_mut() {
+            Some(security) => security
+                .protect(&request)
+                .map_err(|e| log::error!("Unable to protect request to {}: {e:?}", self.node_addr))
+                .ok(),
+            None => Some(request),
+        }
+    }
+
+    async fn send(&self, packet: &Packet) {
+        let node_addr = self.node_addr;
+        match packet.to_bytes() {
+            Ok(bytes) => {
+                self.socket
+                    .send_to(&bytes, node_addr)
+                    .await
+                    .map_err(|e| log::error!("Error sending to {node_addr:}: {e:}"))
+                    .ok();
+            }
+            Err(e) => log::error!("Unable to encode CoAP packet {e:?}"),
+        }
+    }
+}
+
 pub struct NodeHandler {
     _handler: NodeEventHandler,
 }
 
 impl NodeHandler {
-    pub async fn new(addr: SocketAddrV6, sender: mpsc::UnboundedSender<NodeEvent>) -> Self {
+    /// Exchange with the node over `link`, or the links of its later
+    /// registrations sent on `relocations` when it moves. The node times
+    /// out after `config.node_timeout` without a reading
+    pub(crate) async fn new(
+        link: NodeLink,
+        config: &BrokerConfig,
+        relocations: mpsc::UnboundedReceiver<NodeLink>,
+        sender: mpsc::UnboundedSender<NodeEvent>,
+    ) -> Self {
         Self {
-            _handler: NodeEventHandler::new(addr, sender).await,
+            _handler: NodeEventHandler::new(link, config, relocations, sender).await,
         }
     }
 }
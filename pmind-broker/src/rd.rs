@@ -0,0 +1,286 @@
+//! Resource directory that nodes register with as they join.
+//!
+//! The broker serves the RFC 9176 registration interface (see
+//! [`pmindp_protocol::directory`]) on [`RD_PORT`](`pmindp_protocol::RD_PORT`)
+//! of its OMR addr. A node that registers is announced to the monitor
+//! loop, which registers it right away instead of on its next poll of the
+//! mesh. Nodes only register with the directory when they boot, so one
+//! that was observed already is registered again. Polling stays as the
+//! fallback for nodes that do not know the directory
+use coap_lite::{CoapOption, MessageClass, MessageType, Packet, RequestType, ResponseType};
+use pmindp_protocol::{directory::RD_PATH, Deduplicator, Endpoint, TransmissionParams};
+use std::{
+    collections::HashMap,
+    net::{Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::Arc,
+};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::UnboundedSender,
+    time::{Duration, Instant},
+};
+
+use crate::node::now_ms;
+
+/// A node's registration with the directory
+struct Entry {
+    endpoint: Endpoint,
+    expires: Instant,
+}
+
+/// The directory's socket and registrations, served by a background task
+/// until dropped
+pub(crate) struct ResourceDirectory {
+    socket: Arc<UdpSocket>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ResourceDirectory {
+    /// Serve the directory at `addr`, the addrs of nodes that register are
+    /// sent to `announce`
+    pub async fn bind(
+        addr: SocketAddrV6,
+        announce: UnboundedSender<Ipv6Addr>,
+    ) -> std::io::Result<Self> {
+        let socket = Arc::new(UdpSocket::bind(addr).await.inspect_err(|_| {
+            log::error!("Unable to bind resource directory at addr {:?}", addr);
+        })?);
+        let task = tokio::spawn(ResourceDirectory::serve(socket.clone(), announce));
+        log::info!("Serving resource directory on {addr:}");
+
+        Ok(Self { socket, task })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    async fn serve(socket: Arc<UdpSocket>, announce: UnboundedSender<Ipv6Addr>) {
+        let mut buffer = [0u8; 1024];
+        let mut entries: HashMap<u32, Entry> = HashMap::new();
+        let mut next_id = 0u32;
+        // Retransmitted registrations get the same response, and are not
+        // announced twice
+        let mut dedup = Deduplicator::new(&TransmissionParams::default());
+
+        loop {
+            let (len, from) = match socket.recv_from(&mut buffer).await {
+                Ok(res) => res,
+                Err(e) => {
+                    log::error!("Resource directory socket error {e:}");
+                    break;
+                }
+            };
+            let Ok(request) = Packet::from_bytes(&buffer[..len]) else {
+                log::error!("Non CoAP packet from {from:} len {:?}", len);
+                continue;
+            };
+            if !matches!(request.header.code, MessageClass::Request(_)) {
+                continue;
+            }
+            let confirmable = request.header.get_type() == MessageType::Confirmable;
+            if confirmable {
+                if let Some(response) = dedup.duplicate(&from, request.header.message_id, now_ms())
+                {
+                    if let Some(Ok(response)) = response.map(Packet::to_bytes) {
+                        socket.send_to(&response, from).await.ok();
+                    }
+                    continue;
+                }
+            }
+
+            let now = Instant::now();
+            entries.retain(|_, e| e.expires > now);
+            let (code, location) =
+                ResourceDirectory::handle(&request, from, &mut entries, &mut next_id, &announce);
+
+            let mut response = Packet::new();
+            response.header.set_type(if confirmable {
+                MessageType::Acknowledgement
+            } else {
+                MessageType::NonConfirmable
+            });
+            response.header.code = MessageClass::Response(code);
+            response.header.message_id = request.header.message_id;
+            response.set_token(request.get_token().to_vec());
+            if let Some(id) = location {
+                response.add_option(CoapOption::LocationPath, RD_PATH.as_bytes().to_vec());
+                response.add_option(CoapOption::LocationPath, id.to_string().into_bytes());
+            }
+            if let Ok(bytes) = response.to_bytes() {
+                socket.send_to(&bytes, from).await.ok();
+            }
+            if confirmable {
+                dedup.record(from, request.header.message_id, now_ms(), Some(response));
+            }
+        }
+    }
+
+    /// Apply `request` to the registrations, returns the response code and
+    /// the id of the registration created, if any
+    fn handle(
+        request: &Packet,
+        from: SocketAddr,
+        entries: &mut HashMap<u32, Entry>,
+        next_id: &mut u32,
+        announce: &UnboundedSender<Ipv6Addr>,
+    ) -> (ResponseType, Option<u32>) {
+        let path: Vec<_> = request
+            .get_option(CoapOption::UriPath)
+            .into_iter()
+            .flatten()
+            .map(|seg| String::from_utf8_lossy(seg).into_owned())
+            .collect();
+        let method = match request.header.code {
+            MessageClass::Request(method) => method,
+            _ => return (ResponseType::BadRequest, None),
+        };
+
+        match (method, path.as_slice()) {
+            (RequestType::Post, [rd]) if rd == RD_PATH => {
+                let endpoint = match Endpoint::from_request(request) {
+                    Ok(endpoint) => endpoint,
+                    Err(e) => {
+                        log::warn!("Invalid registration from {from:}: {e:?}");
+                        return (ResponseType::BadRequest, None);
+                    }
+                };
+                log::info!(
+                    "Node {} registered at {from:} with {} resources",
+                    endpoint.name,
+                    endpoint.links.len()
+                );
+                // A node registering again replaces its registration
+                let id = entries
+                    .iter()
+                    .find(|(_, e)| e.endpoint.name == endpoint.name)
+                    .map(|(id, _)| *id)
+                    .unwrap_or_else(|| {
+                        *next_id = next_id.wrapping_add(1);
+                        *next_id
+                    });
+                let expires = Instant::now() + Duration::from_secs(endpoint.lifetime.into());
+                entries.insert(id, Entry { endpoint, expires });
+                announce.send(ResourceDirectory::ip(from)).ok();
+                (ResponseType::Created, Some(id))
+            }
+            // Registration update, only refreshes the lifetime
+            (RequestType::Post, [rd, id]) if rd == RD_PATH => {
+                match id.parse().ok().and_then(|id: u32| entries.get_mut(&id)) {
+                    Some(entry) => {
+                        let lifetime = request
+                            .get_option(CoapOption::UriQuery)
+                            .into_iter()
+                            .flatten()
+                            .find_map(|q| q.strip_prefix(b"lt="))
+                            .and_then(|lt| std::str::from_utf8(lt).ok()?.parse().ok())
+                            .unwrap_or(entry.endpoint.lifetime);
+                        entry.endpoint.lifetime = lifetime;
+                        entry.expires = Instant::now() + Duration::from_secs(lifetime.into());
+                        (ResponseType::Changed, None)
+                    }
+                    None => (ResponseType::NotFound, None),
+                }
+            }
+            (RequestType::Delete, [rd, id]) if rd == RD_PATH => {
+                match id.parse().ok().and_then(|id: u32| entries.remove(&id)) {
+                    Some(entry) => {
+                        log::info!("Node {} removed its registration", entry.endpoint.name);
+                        (ResponseType::Deleted, None)
+                    }
+                    None => (ResponseType::NotFound, None),
+                }
+            }
+            (_, [rd, ..]) if rd == RD_PATH => (ResponseType::MethodNotAllowed, None),
+            _ => (ResponseType::NotFound, None),
+        }
+    }
+
+    fn ip(addr: SocketAddr) -> Ipv6Addr {
+        match addr {
+            SocketAddr::V6(addr) => *addr.ip(),
+            SocketAddr::V4(addr) => addr.ip().to_ipv6_mapped(),
+        }
+    }
+}
+
+impl Drop for ResourceDirectory {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coap_lite::{CoapOption, MessageClass, Packet, RequestType, ResponseType};
+    use pmindp_protocol::{directory, Endpoint, Link};
+    use std::net::{Ipv4Addr, SocketAddrV6};
+    use tokio::{net::UdpSocket, sync::mpsc::unbounded_channel};
+
+    use super::ResourceDirectory;
+
+    async fn exchange(socket: &UdpSocket, request: &Packet, to: std::net::SocketAddr) -> Packet {
+        let mut buffer = [0u8; 512];
+        socket
+            .send_to(&request.to_bytes().unwrap(), to)
+            .await
+            .unwrap();
+        let (len, _) = socket.recv_from(&mut buffer).await.unwrap();
+        Packet::from_bytes(&buffer[..len]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn check_rd_registration() {
+        let loopback = Ipv4Addr::LOCALHOST.to_ipv6_mapped();
+        let (announce, mut announced) = unbounded_channel();
+        let rd = ResourceDirectory::bind(SocketAddrV6::new(loopback, 0, 0, 0), announce)
+            .await
+            .expect("Unable to bind directory");
+        let rd_addr = rd.local_addr().unwrap();
+        let node = UdpSocket::bind(SocketAddrV6::new(loopback, 0, 0, 0))
+            .await
+            .unwrap();
+
+        let endpoint = Endpoint::new(&[0x60, 0x55, 0xf9, 0x01, 0x02, 0x03])
+            .links(&[Link::new("soil").observable()]);
+        let register = endpoint.register(1, &[0xaa]);
+        let response = exchange(&node, &register, rd_addr).await;
+        let location = directory::location(&response).expect("Not created");
+        assert_eq!(response.get_token(), [0xaa]);
+        assert_eq!(announced.recv().await, Some(loopback));
+
+        // A retransmission is answered the same, but not announced again
+        let again = exchange(&node, &register, rd_addr).await;
+        assert_eq!(directory::location(&again), Some(location.clone()));
+        assert!(announced.try_recv().is_err());
+
+        let mut update = register.clone();
+        update.header.message_id = 2;
+        update.clear_option(CoapOption::UriQuery);
+        update.clear_option(CoapOption::UriPath);
+        for seg in location.split('/').filter(|s| !s.is_empty()) {
+            update.add_option(CoapOption::UriPath, seg.as_bytes().to_vec());
+        }
+        update.payload.clear();
+        let response = exchange(&node, &update, rd_addr).await;
+        assert_eq!(
+            response.header.code,
+            MessageClass::Response(ResponseType::Changed)
+        );
+
+        let mut delete = update.clone();
+        delete.header.message_id = 3;
+        delete.header.code = MessageClass::Request(RequestType::Delete);
+        let response = exchange(&node, &delete, rd_addr).await;
+        assert_eq!(
+            response.header.code,
+            MessageClass::Response(ResponseType::Deleted)
+        );
+        delete.header.message_id = 4;
+        let response = exchange(&node, &delete, rd_addr).await;
+        assert_eq!(
+            response.header.code,
+            MessageClass::Response(ResponseType::NotFound)
+        );
+    }
+}
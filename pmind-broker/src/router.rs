@@ -1,26 +1,50 @@
 use actix::{Actor, Addr, MailboxError};
-use coap_lite::{CoapRequest, ObserveOption, Packet, RequestType};
-use futures::prelude::*;
+use coap_lite::{ContentFormat, MessageClass, MessageType, Packet, ResponseType};
+use pmindp_protocol::{
+    discovery,
+    oscore::{self, BROKER_ID, SESSION_NONCE_LEN},
+    Notification, ObserveClient, Outbox, SecurityContext, Token, TransmissionParams,
+    NODE_COAP_PORT, TOKEN_LEN,
+};
+use pmindp_sensor::{NodeIdentity, SensorClass, WireError, PROTOCOL_VERSION};
 use std::{
     boxed::Box,
-    net::{Ipv6Addr, SocketAddr, SocketAddrV6},
+    net::{Ipv6Addr, SocketAddrV6},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 use thiserror::Error;
 use tokio::{
     net::UdpSocket,
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-    time::Duration,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Semaphore,
+    },
+    time::{Duration, Instant},
 };
 
 use crate::{
+    demux::{NodeSocket, ReceiveMode, SharedSocket},
     monitor::{
-        CheckNewNode, GetNodeStatus, InternalRegistration, MonitorNetworkStatus, OmrIp,
-        ReserveFreePort, ReturnFreePort,
+        Announce, CheckNetworkState, CheckNewNode, DueRetries, GetNodeStatus, GetService,
+        InternalRegistration, MonitorNetworkStatus, NextRetry, NodeLost, OmrIp, PublishLinkMetrics,
+        RegistrationFailed, ReserveFreePort, ReturnFreePort,
     },
-    node::{NodeEvent, NodeHandler},
-    Eui, OtCliClient, OtMonitor, OtMonitorError,
+    node::{now_ms, NodeCapabilities, NodeEvent, NodeHandler, NodeLink},
+    rd::ResourceDirectory,
+    BrokerConfig, Eui, KeyStore, NetworkStatus, NodeStatus, OtClient, OtMonitor, OtMonitorError,
+    Registration, Rloc,
 };
 
+/// Busy ports to skip when reserving a dedicated receive port, see
+/// [`EventRouter::node_socket`]
+const MAX_BIND_ATTEMPTS: usize = 4;
+
+/// Source of [`InternalRegistration::session`]
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Error, Debug)]
 pub enum EventRouterError {
     #[error("I/O Error")]
@@ -33,6 +57,12 @@ pub enum EventRouterError {
     CoAPMsgError(#[from] coap_lite::error::MessageError),
     #[error("AddrParse error")]
     AddrParse(#[from] std::net::AddrParseError),
+    #[error("Unable to generate Observe token")]
+    Token(#[from] getrandom::Error),
+    #[error("Unsupported protocol version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Node did not authenticate")]
+    Unauthenticated,
 }
 pub struct EventRouter {
     monitor_handle: Option<tokio::task::JoinHandle<Result<(), EventRouterError>>>,
@@ -40,19 +70,36 @@ pub struct EventRouter {
 
 impl EventRouter {
     pub async fn new(
+        ot_client: Box<dyn OtClient>,
         stream_tx: UnboundedSender<UnboundedReceiver<NodeEvent>>,
-        registration_tx: UnboundedSender<(Eui, Ipv6Addr, String)>,
+        registration_tx: UnboundedSender<Registration>,
+        update_tx: UnboundedSender<NodeStatus>,
+        network_tx: UnboundedSender<NetworkStatus>,
         poll_interval: Duration,
+        config: BrokerConfig,
     ) -> Result<Self, EventRouterError> {
         let mut broker = Self {
             monitor_handle: None,
         };
 
-        let ot_mon = OtMonitor::new(Box::new(OtCliClient));
+        let ot_mon = OtMonitor::new(
+            ot_client,
+            update_tx,
+            network_tx,
+            config.backoff,
+            config.discovery,
+        )
+        .await;
         let ot_mon_handle = ot_mon.start();
 
         broker
-            .spawn_child_mon_task(poll_interval, ot_mon_handle, stream_tx, registration_tx)
+            .spawn_child_mon_task(
+                poll_interval,
+                ot_mon_handle,
+                stream_tx,
+                registration_tx,
+                config,
+            )
             .await;
 
         Ok(broker)
@@ -62,40 +109,100 @@ impl EventRouter {
         self.monitor_handle.take().unwrap().await.ok();
     }
 
-    async fn coap_observer_register(
+    /// Observation state with a fresh random token, which must not be
+    /// guessable (RFC 7641) and identifies the node on the shared socket
+    fn observe_client(class: SensorClass) -> Result<ObserveClient, EventRouterError> {
+        let mut seed = [0u8; TOKEN_LEN + 2];
+        getrandom::fill(&mut seed)?;
+        let mut token: Token = [0u8; TOKEN_LEN];
+        token.copy_from_slice(&seed[..TOKEN_LEN]);
+        let message_id = u16::from_be_bytes([seed[TOKEN_LEN], seed[TOKEN_LEN + 1]]);
+        Ok(ObserveClient::new(token, message_id, class.name()).version(PROTOCOL_VERSION))
+    }
+
+    /// Socket to register `client` from and receive its notifications on:
+    /// either a dedicated socket on a port reserved from the monitor pool,
+    /// or a route on the shared socket. Returns the reserved port, if any
+    async fn node_socket(
+        ot_mon: &Addr<OtMonitor>,
+        shared: Option<&SharedSocket>,
         omr_addr: Ipv6Addr,
-        ip_addr: Ipv6Addr,
-        port: u16,
-    ) -> Result<Option<(SocketAddrV6, Eui, Vec<u8>)>, EventRouterError> {
-        log::info!("Starting CoAP Registration for {ip_addr:} on port {port:}");
-        let mut request: CoapRequest<SocketAddr> = CoapRequest::new();
+        client: &ObserveClient,
+    ) -> Result<(NodeSocket, Option<u16>), EventRouterError> {
+        if let Some(shared) = shared {
+            return Ok((shared.node_socket(*client.token()), None));
+        }
+
+        // Get a free port from the monitor pool. The port of a lost node is
+        // back in the pool before its handler times out and closes the
+        // socket, so hold on to busy ports while trying the next one
+        let mut busy = Vec::new();
+        let res = loop {
+            let port = match ot_mon.send(ReserveFreePort).await? {
+                Ok(port) => port,
+                Err(e) => break Err(e.into()),
+            };
+            let addr = SocketAddrV6::new(omr_addr, port, 0, 0);
+            match UdpSocket::bind(addr).await {
+                Ok(socket) => break Ok((NodeSocket::Dedicated(socket), Some(port))),
+                Err(e) => {
+                    log::error!("Unable to bind to socket at addr {:?}", addr);
+                    busy.push(port);
+                    if e.kind() != std::io::ErrorKind::AddrInUse || busy.len() >= MAX_BIND_ATTEMPTS
+                    {
+                        break Err(e.into());
+                    }
+                }
+            }
+        };
+        for port in busy {
+            ot_mon.send(ReturnFreePort(port)).await.ok();
+        }
+        res
+    }
+
+    /// Send the confirmable `request` to the node at `send_addr` from
+    /// `socket` and wait for its response, piggybacked on the ACK or
+    /// separate. The request is
+    /// retransmitted per `params` until acknowledged. `None` if the node
+    /// rejects the request (RST) or never answers. With `security` the
+    /// exchange is protected, and fails with
+    /// [`EventRouterError::Unauthenticated`] if the node refuses it
+    async fn coap_exchange(
+        socket: &mut NodeSocket,
+        request: Packet,
+        send_addr: SocketAddrV6,
+        params: TransmissionParams,
+        mut security: Option<&mut SecurityContext>,
+    ) -> Result<Option<Packet>, EventRouterError> {
         let mut buffer = [0u8; 512];
-        // following https://datatracker.ietf.org/doc/html/rfc7641
-        // observing resources in CoAP (loosely! needs work)
-        request.set_method(RequestType::Get);
-        request.set_path("/soilmoisture");
-        request.message.set_token(vec![0xfa, 0xce, 0xbe, 0xef]);
-
-        // TODO! Here we are using the message_id field to
-        // tell the node what port we want to receive sensor data on
-        request.message.header.message_id = port;
-        request.set_observe_flag(ObserveOption::Register);
-        let packet = request.message.to_bytes()?;
-
-        let ip_w_port = format!("[{}]:1212", ip_addr);
-        // fix this later
-        let send_addr: SocketAddrV6 = ip_w_port.parse()?;
-
-        let addr = format!("[{}]:{}", omr_addr, port);
-        let addr: SocketAddrV6 = addr.parse()?;
-
-        let send_socket = UdpSocket::bind(addr).await.map_err(|e| {
-            log::error!("Unable to bind to socket at addr {:?}", addr);
-            e
-        })?;
-
-        // Allow this to fail, there will be retries
-        send_socket
+        let ip_addr = *send_addr.ip();
+        let request = match security.as_mut() {
+            Some(security) => security.protect(&request).map_err(|e| {
+                log::error!("Unable to protect request to {ip_addr:}: {e:?}");
+                EventRouterError::Unauthenticated
+            })?,
+            None => request,
+        };
+        let packet = request.to_bytes()?;
+        let message_id = request.header.message_id;
+        let token = request.get_token().to_vec();
+
+        let mut outbox = Outbox::new(
+            params,
+            token
+                .iter()
+                .take(4)
+                .fold(0, |acc, b| (acc << 8) | *b as u32),
+        );
+        outbox.send(send_addr, request, now_ms());
+        // Once the request is acknowledged without a response (a separate
+        // response is coming), give the node the exchange lifetime to send it
+        let give_up =
+            tokio::time::Instant::now() + Duration::from_millis(params.exchange_lifetime_ms());
+
+        // Allow this to fail, it will be retransmitted
+        socket
             .send_to(&packet[..], send_addr)
             .await
             .map_err(|e| {
@@ -103,39 +210,469 @@ impl EventRouter {
             })
             .ok();
 
-        // allow retries in case the radio is currently idle
-        // not currently enabling rx_on_when_idle, should only
-        // be a couple seconds
-        tokio::select! {
-            Ok((mut len, mut from)) = send_socket.recv_from(&mut buffer) => {
-                while len == 0 {
-                    // sleep a lil
-                    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-                    send_socket.send_to(&packet[..], send_addr).await.map_err(|e|{
-                        log::error!("Error sending: {e:}");
-                    }).ok();
-
-                    (len, from) = send_socket.recv_from(&mut buffer).await.map_err(|e|{
+        loop {
+            // Retransmissions cover the radio being idle, we are not
+            // currently enabling rx_on_when_idle on the nodes
+            let wake = outbox
+                .next_deadline()
+                .map(|deadline| {
+                    tokio::time::Instant::now()
+                        + Duration::from_millis(deadline.saturating_sub(now_ms()))
+                })
+                .unwrap_or(give_up);
+
+            tokio::select! {
+                res = socket.recv_from(&mut buffer) => {
+                    let (from, response) = res.map_err(|e| {
                         log::error!("Error receiving from socket: {e:}");
                         e
                     })?;
+                    let Some(response) = response else {
+                        continue;
+                    };
+                    log::debug!("Got a response from {from:}, expected {send_addr:}");
+
+                    let message_type = response.header.get_type();
+                    if matches!(
+                        message_type,
+                        MessageType::Acknowledgement | MessageType::Reset
+                    ) && response.header.message_id == message_id
+                    {
+                        outbox.acknowledge(&send_addr, message_id);
+                        if message_type == MessageType::Reset {
+                            log::warn!("{ip_addr:} rejected the request");
+                            return Ok(None);
+                        }
+                    }
+                    // Empty ACKs, and piggybacked responses to an earlier
+                    // exchange that were retransmitted
+                    if response.header.code == MessageClass::Empty
+                        || response.get_token() != token
+                        || (message_type == MessageType::Acknowledgement
+                            && response.header.message_id != message_id)
+                    {
+                        continue;
+                    }
+                    if let Some(ack) = ObserveClient::ack(&response) {
+                        socket.send_to(&ack.to_bytes()?, send_addr).await.ok();
+                    }
+                    let Some(security) = security.as_mut() else {
+                        return Ok(Some(response));
+                    };
+                    // Nodes answer requests they cannot verify in the clear
+                    if response.header.code
+                        == MessageClass::Response(ResponseType::Unauthorized)
+                    {
+                        log::warn!("{ip_addr:} refused the protected request");
+                        return Err(EventRouterError::Unauthenticated);
+                    }
+                    match security.unprotect(&response) {
+                        Ok(response) => return Ok(Some(response)),
+                        Err(e) => log::warn!("Dropping response from {from:} that does not verify: {e:?}"),
+                    }
                 }
-                log::debug!("Got a response from {from:}, expected {send_addr:}");
-
-                let mut eui: Eui = [0u8; 6];
-                let mut name = vec![];
-                if let Ok(packet) = Packet::from_bytes(&buffer[..len]) {
-                    let resp = CoapRequest::from_packet(packet, from);
-                    if resp.message.payload.len() >= 6 {
-                        eui.copy_from_slice(&resp.message.payload[..6]);
+                _ = tokio::time::sleep_until(wake) => {
+                    if outbox.is_empty() {
+                        log::warn!("No response from {ip_addr:} after it acknowledged");
+                        return Ok(None);
+                    }
+                    let polled = outbox.poll(now_ms());
+                    if !polled.failed.is_empty() {
+                        log::warn!("{ip_addr:} did not acknowledge the request");
+                        return Ok(None);
+                    }
+                    for (to, request) in polled.resend {
+                        log::debug!("Retransmitting request to {to:}");
+                        socket.send_to(&request.to_bytes()?, to).await.ok();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Query the node's `/.well-known/core` for the resources and sensors it
+    /// has. Nodes that predate discovery answer 4.04 and get empty
+    /// capabilities; `None` if the node does not answer at all. With `keys`
+    /// this also opens the session every later exchange is protected with,
+    /// see [`EventRouter::security_context`]
+    async fn coap_discover(
+        socket: &mut NodeSocket,
+        client: &mut ObserveClient,
+        node_addr: SocketAddrV6,
+        params: TransmissionParams,
+        keys: Option<&KeyStore>,
+    ) -> Result<Option<(NodeCapabilities, Option<SecurityContext>)>, EventRouterError> {
+        let ip_addr = *node_addr.ip();
+        log::info!("Discovering resources of {ip_addr:}");
+        let Some(response) =
+            EventRouter::coap_exchange(socket, client.discover(), node_addr, params, None).await?
+        else {
+            return Ok(None);
+        };
+        let security = keys
+            .map(|keys| EventRouter::security_context(keys, &response, ip_addr))
+            .transpose()?;
+        let capabilities = match discovery::links(&response) {
+            Ok(links) => NodeCapabilities::from_links(&links),
+            Err(e) => {
+                log::warn!("{ip_addr:} did not list its resources: {e:?}");
+                NodeCapabilities::default()
+            }
+        };
+        Ok(Some((capabilities, security)))
+    }
+
+    /// Session with the node that answered discovery with `response`, under
+    /// the key stored for the EUI it advertised and a fresh nonce
+    fn security_context(
+        keys: &KeyStore,
+        response: &Packet,
+        ip_addr: Ipv6Addr,
+    ) -> Result<SecurityContext, EventRouterError> {
+        let Some((kid, node_nonce)) = oscore::advertised(response) else {
+            log::warn!("{ip_addr:} does not support authentication");
+            return Err(EventRouterError::Unauthenticated);
+        };
+        let Some(psk) = Eui::try_from(kid.as_slice())
+            .ok()
+            .and_then(|eui| keys.get(&eui))
+        else {
+            log::warn!("No key for {ip_addr:} (EUI {kid:02x?})");
+            return Err(EventRouterError::Unauthenticated);
+        };
+        let mut id_context = vec![0u8; SESSION_NONCE_LEN];
+        getrandom::fill(&mut id_context)?;
+        id_context.extend_from_slice(&node_nonce);
+        SecurityContext::new(psk, &id_context, BROKER_ID, &kid)
+            .map_err(|_| EventRouterError::Unauthenticated)
+    }
+
+    /// Register as an observer of the node's sensor resource from `socket`,
+    /// returning the node's identity once the node accepts. Fails with
+    /// [`EventRouterError::UnsupportedVersion`] if the node answers in a
+    /// protocol version the broker does not speak, and with
+    /// [`EventRouterError::Unauthenticated`] if it does not authenticate
+    /// as the EUI of the `security` session
+    async fn coap_observer_register(
+        socket: &mut NodeSocket,
+        client: &mut ObserveClient,
+        node_addr: SocketAddrV6,
+        params: TransmissionParams,
+        mut security: Option<&mut SecurityContext>,
+    ) -> Result<Option<NodeIdentity>, EventRouterError> {
+        let ip_addr = *node_addr.ip();
+        log::info!("Starting CoAP Registration for {ip_addr:}");
+        let Some(response) = EventRouter::coap_exchange(
+            socket,
+            client.register(),
+            node_addr,
+            params,
+            security.as_deref_mut(),
+        )
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        match client.handle_response(&response, crate::node::now()) {
+            // Nodes that predate versioning answer untagged
+            Notification::Fresh(payload) => match response.get_content_format() {
+                None => Ok(Some(NodeIdentity::from_legacy(payload))),
+                Some(ContentFormat::ApplicationCBOR) => match NodeIdentity::decode(payload) {
+                    Ok(identity)
+                        if security
+                            .is_some_and(|s| s.recipient_id() != identity.eui.as_slice()) =>
+                    {
+                        log::warn!("{ip_addr:} registered as another node's EUI");
+                        Err(EventRouterError::Unauthenticated)
+                    }
+                    Ok(identity) => Ok(Some(identity)),
+                    Err(WireError::UnsupportedVersion(version)) => {
+                        Err(EventRouterError::UnsupportedVersion(version))
                     }
-                    name.clone_from(&resp.message.payload[6..].to_vec());
+                    Err(e) => {
+                        log::warn!("{ip_addr:} sent a malformed identity: {e:}");
+                        Ok(None)
+                    }
+                },
+                Some(cf) => {
+                    log::warn!("{ip_addr:} sent its identity as {cf:?}");
+                    Ok(None)
                 }
-                Ok(Some((addr, eui, name)))
+            },
+            _ => {
+                log::warn!("{ip_addr:} refused the Observe registration");
+                Ok(None)
             }
-            _ = tokio::time::sleep(tokio::time::Duration::from_secs(30)) => {
+        }
+    }
+
+    /// Forward the events of the handler of registration `session` to the
+    /// broker, and tell the monitor when the handler gives up on the node so
+    /// that it is re-registered
+    fn watch_node(
+        ot_mon: Addr<OtMonitor>,
+        eui: Eui,
+        session: u64,
+        mut events: UnboundedReceiver<NodeEvent>,
+    ) -> UnboundedReceiver<NodeEvent> {
+        let (sender, receiver) = unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                if let NodeEvent::NodeTimeout(_) | NodeEvent::SocketError(_) = event {
+                    ot_mon.send(NodeLost { eui, session }).await.ok();
+                }
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    /// Keep the shared receive socket bound to the current OMR addr, it is
+    /// rebound if the addr changed. Nodes registered on the old socket are
+    /// registered again from the new one, the old socket must stay open
+    /// until their handlers have moved over
+    async fn shared_socket(
+        current: Option<Arc<SharedSocket>>,
+        omr_addr: Ipv6Addr,
+        port: u16,
+    ) -> Option<Arc<SharedSocket>> {
+        match &current {
+            Some(s) if s.local_addr().is_ok_and(|a| a.ip() == omr_addr) => current,
+            _ => SharedSocket::bind(SocketAddrV6::new(omr_addr, port, 0, 0))
+                .await
+                .map(Arc::new)
+                .ok()
+                .or(current),
+        }
+    }
+
+    /// Keep the resource directory bound to the current OMR addr, as
+    /// [`EventRouter::shared_socket`]. Registrations made with the old
+    /// directory are dropped with it
+    async fn resource_directory(
+        current: Option<ResourceDirectory>,
+        omr_addr: Ipv6Addr,
+        port: u16,
+        announce: &UnboundedSender<Ipv6Addr>,
+    ) -> Option<ResourceDirectory> {
+        match &current {
+            Some(rd) if rd.local_addr().is_ok_and(|a| a.ip() == omr_addr) => current,
+            _ => ResourceDirectory::bind(SocketAddrV6::new(omr_addr, port, 0, 0), announce.clone())
+                .await
+                .ok()
+                .or(current),
+        }
+    }
+
+    /// Discover the node at `ip`, observe every class it serves and hand
+    /// its stream and registration to the broker. Discovery and the
+    /// observe registrations must be done within
+    /// [`BrokerConfig::registration_timeout`]; a failed attempt is reported
+    /// to the monitor, which retries it with backoff
+    async fn register(
+        ot_mon: Addr<OtMonitor>,
+        shared: Option<Arc<SharedSocket>>,
+        omr_addr: Ipv6Addr,
+        (rloc, ip): (Rloc, Ipv6Addr),
+        config: BrokerConfig,
+        stream_sender: UnboundedSender<UnboundedReceiver<NodeEvent>>,
+        registration_sender: UnboundedSender<Registration>,
+    ) {
+        // Nodes that advertise a service are
+        // contacted on its port
+        let service = ot_mon.send(GetService(ip)).await.ok().flatten();
+        let node_addr = SocketAddrV6::new(
+            ip,
+            service.as_ref().map_or(NODE_COAP_PORT, |s| s.port),
+            0,
+            0,
+        );
+        let res = async {
+            let mut client = EventRouter::observe_client(SensorClass::Soil)?;
+            let (mut socket, port) =
+                EventRouter::node_socket(&ot_mon, shared.as_deref(), omr_addr, &client).await?;
+            // The socket is set up first, so that its port goes back to
+            // the pool however the attempt ends
+            let reg = tokio::time::timeout(config.registration_timeout, async {
+                let Some((capabilities, mut security)) = EventRouter::coap_discover(
+                    &mut socket,
+                    &mut client,
+                    node_addr,
+                    config.transmission,
+                    config.keys.as_ref(),
+                )
+                .await?
+                else {
+                    return Ok(None);
+                };
+
+                // Observe every class the node serves, each
+                // with its own token on the same socket
+                let mut identity = None;
+                let mut observations = Vec::new();
+                let mut soil = Some(client);
+                for class in capabilities.classes() {
+                    let mut client = match soil.take() {
+                        Some(c) if class == SensorClass::Soil => c,
+                        other => {
+                            soil = other;
+                            EventRouter::observe_client(class)?
+                        }
+                    };
+                    socket.route(*client.token());
+                    match EventRouter::coap_observer_register(
+                        &mut socket,
+                        &mut client,
+                        node_addr,
+                        config.transmission,
+                        security.as_mut(),
+                    )
+                    .await?
+                    {
+                        Some(id) => {
+                            identity.get_or_insert(id);
+                            observations.push((class, client));
+                        }
+                        None => log::warn!("Failed to observe /{} on {ip:}", class.name()),
+                    }
+                }
+                Ok::<_, EventRouterError>(
+                    identity.map(|identity| (identity, capabilities, observations, security)),
+                )
+            })
+            .await
+            .unwrap_or_else(|_| {
+                log::warn!("Registration of {ip:} timed out");
                 Ok(None)
+            });
+            Ok::<_, EventRouterError>((socket, port, reg))
+        }
+        .await
+        .map_err(|e| {
+            log::error!("Unable to set up the registration of {ip:} {e:}");
+        });
+
+        let Ok((socket, port, reg)) = res else {
+            EventRouter::registration_failed(&ot_mon, (rloc, ip)).await;
+            return;
+        };
+        let (identity, capabilities, observations, security) = match reg {
+            Ok(Some(reg)) => reg,
+            Err(
+                e @ (EventRouterError::UnsupportedVersion(_) | EventRouterError::Unauthenticated),
+            ) => {
+                // Report the node rather than retrying quietly, it
+                // needs a firmware update or a key to be monitored
+                let (sender, receiver) = unbounded_channel();
+                sender
+                    .send(match e {
+                        EventRouterError::UnsupportedVersion(v) => {
+                            NodeEvent::UnsupportedVersion(node_addr, v)
+                        }
+                        _ => NodeEvent::Unauthenticated(node_addr),
+                    })
+                    .ok();
+                if let Err(e) = stream_sender.send(receiver) {
+                    log::error!(
+                        "Unable to report {ip:} to the broker, its stream task is gone {e:}"
+                    );
+                }
+                if let Some(port) = port {
+                    ot_mon.send(ReturnFreePort(port)).await.ok();
+                }
+                EventRouter::registration_failed(&ot_mon, (rloc, ip)).await;
+                return;
+            }
+            reg => {
+                if let Err(e) = reg {
+                    log::warn!("Unable to register {ip:} {e:}");
+                }
+                if let Some(port) = port {
+                    ot_mon.send(ReturnFreePort(port)).await.ok();
+                }
+                EventRouter::registration_failed(&ot_mon, (rloc, ip)).await;
+                return;
             }
+        };
+        let eui = identity.eui;
+        let session = NEXT_SESSION.fetch_add(1, Ordering::Relaxed);
+        let link = NodeLink {
+            socket,
+            node_addr,
+            observations,
+            header: identity.header,
+            security,
+        };
+        let (relocate, relocations) = unbounded_channel();
+
+        // Update monitor registration record after successful CoAP reg
+        let handler = ot_mon
+            .send(InternalRegistration {
+                rloc,
+                ip,
+                eui,
+                port,
+                session,
+                relocate,
+            })
+            .await
+            .map_err(|e| log::error!("Failure to reg node {e:}"))
+            .ok()
+            .and_then(Result::ok)
+            .flatten();
+
+        if let Some(handler) = handler {
+            // The node moved, its handler takes over the
+            // new observations and keeps its stream
+            if handler.send(link).is_err() {
+                log::warn!("Handler of {ip:} gone");
+                EventRouter::registration_failed(&ot_mon, (rloc, ip)).await;
+                return;
+            }
+        } else {
+            let (sender, receiver) = unbounded_channel();
+
+            // This object will spawn tasks that will
+            // not close unless there are appropriate
+            // node events to trigger shutdown, such
+            // as node timeout, socket error, or
+            // other lost node event
+            let _new_node = NodeHandler::new(link, &config, relocations, sender).await;
+            let receiver = EventRouter::watch_node(ot_mon, eui, session, receiver);
+
+            // Send the sensor data source to the task
+            // managing those streams. Only fails once the broker is
+            // shutting down, the handler then ends with the stream
+            if let Err(e) = stream_sender.send(receiver) {
+                log::error!("Unable to hand the stream of {ip:} to the broker {e:}");
+            }
+        }
+
+        let mut registration = Registration::new(identity, ip, capabilities);
+        if let Some(service) = &service {
+            registration.describe(service);
+        }
+        // Shorten name (but this should be handled by
+        // calling subscribers, so TODO move this)
+        while registration.name.len() > crate::MAX_PLANT_NAME_SIZE {
+            registration.name.pop();
+        }
+
+        // Publish the registration to subscribers, only fails once the
+        // broker is shutting down
+        if let Err(e) = registration_sender.send(registration) {
+            log::error!("Unable to publish the registration of {ip:} {e:}");
+        }
+    }
+
+    /// Have the monitor retry the node with backoff
+    async fn registration_failed(ot_mon: &Addr<OtMonitor>, (rloc, ip): (Rloc, Ipv6Addr)) {
+        log::warn!("Registration of {ip:} failed, retrying with backoff");
+        if let Err(e) = ot_mon.send(RegistrationFailed { rloc, ip }).await {
+            log::error!("Unable to report the failed registration of {ip:} to the monitor {e:}");
         }
     }
 
@@ -144,15 +681,76 @@ impl EventRouter {
         poll: Duration,
         ot_mon: Addr<OtMonitor>,
         stream_sender: UnboundedSender<UnboundedReceiver<NodeEvent>>,
-        registration_sender: UnboundedSender<(Eui, Ipv6Addr, String)>,
+        registration_sender: UnboundedSender<Registration>,
+        config: BrokerConfig,
     ) {
         let handle = tokio::spawn(async move {
+            let mut shared: Option<Arc<SharedSocket>> = None;
+            let mut rd: Option<ResourceDirectory> = None;
+            let (announce, mut announcements) = unbounded_channel();
+            let (finished, mut finished_rx) = unbounded_channel();
+            let registrar = Registrar {
+                ot_mon: ot_mon.clone(),
+                permits: Arc::new(Semaphore::new(config.max_registrations.max(1))),
+                config: config.clone(),
+                stream_sender,
+                registration_sender,
+                finished,
+            };
+            // First published a period in, once the nodes had time to register
+            let mut link_metrics_at = config.link_metrics.map(|period| Instant::now() + period);
+            // The first poll is right away
+            let mut polled = Instant::now();
+            let mut mesh_up = false;
             log::info!(
                 "Setting up node / network monitor task to check every {:?} seconds",
                 poll
             );
 
             loop {
+                // Between polls, nodes that register with the directory are
+                // registered right away and failed nodes when their backoff
+                // ends. Neither moves the next poll, the retry wait is
+                // recomputed as registrations finish
+                let retry_at = match mesh_up {
+                    true => ot_mon.send(NextRetry).await?,
+                    false => None,
+                };
+                tokio::select! {
+                    _ = tokio::time::sleep_until(polled) => {}
+                    _ = tokio::time::sleep_until(retry_at.unwrap_or(polled)), if retry_at.is_some() => {
+                        if let Ok(omr_addr) = ot_mon.send(OmrIp).await? {
+                            let nodes = ot_mon.send(DueRetries).await?;
+                            registrar.spawn(nodes, &shared, &None, omr_addr);
+                        }
+                        continue;
+                    }
+                    Some(ip) = announcements.recv() => {
+                        ot_mon.send(Announce(ip)).await?;
+                        while let Ok(ip) = announcements.try_recv() {
+                            ot_mon.send(Announce(ip)).await?;
+                        }
+                        if mesh_up {
+                            if let (Ok(nodes), Ok(omr_addr)) =
+                                (ot_mon.send(CheckNewNode).await?, ot_mon.send(OmrIp).await?)
+                            {
+                                registrar.spawn(nodes, &shared, &None, omr_addr);
+                            }
+                        }
+                        continue;
+                    }
+                    Some(()) = finished_rx.recv() => continue,
+                }
+                polled = Instant::now() + poll;
+
+                // Nodes are neither registered nor marked offline while the
+                // mesh is down, subscribers get its network status instead
+                mesh_up = ot_mon.send(CheckNetworkState).await?;
+                if !mesh_up {
+                    log::warn!("Mesh down, not checking nodes");
+                    continue;
+                }
+
                 log::info!(
                     "Monitor task: Polling for network change, new nodes, and missing nodes"
                 );
@@ -166,89 +764,20 @@ impl EventRouter {
                     })
                     .ok();
 
-                // TODO need serious refactor here
                 if let Ok(nodes) = ot_mon.send(CheckNewNode).await? {
                     if let Ok(omr_addr) = ot_mon.send(OmrIp).await? {
-                        futures::stream::iter(nodes)
-                            .enumerate()
-                            .for_each(|(i, (rloc, ip))| {
-                                let ot_mon_clone = ot_mon.clone();
-                                let mut _stream_sender = stream_sender.clone();
-                                let mut _registration_sender = registration_sender.clone();
-
-                                async move {
-                                    // Get a free port from the monitor pool
-                                    let free_port: u16 = {
-                                        if let Ok(Ok(free_port)) =
-                                            ot_mon_clone.clone().send(ReserveFreePort).await
-                                        {
-                                            free_port
-                                        } else {
-                                            // TODO pick some random number ?
-                                            1213 + i as u16
-                                        }
-                                    };
-                                    let res = EventRouter::coap_observer_register(
-                                        omr_addr, ip, free_port,
-                                    )
-                                    .await
-                                    .map_err(|e| {
-                                        log::error!("failure to register coap observer {e:}");
-                                    });
-
-                                    if let Ok(Some((addr, eui, mut name))) = res {
-                                        // Update monitor registration record after successful CoAP reg
-                                        ot_mon_clone
-                                            .send(InternalRegistration {
-                                                rloc,
-                                                ip,
-                                                eui,
-                                                port: free_port,
-                                            })
-                                            .await
-                                            .map_err(|e| log::error!("Failure to reg node {e:}"))
-                                            .ok();
-
-                                        let (sender, receiver) = unbounded_channel();
-
-                                        // This object will spawn tasks that will
-                                        // not close unless there are appropriate
-                                        // node events to trigger shutdown, such
-                                        // as node timeout, socket error, or
-                                        // other lost node event
-                                        let _new_node = NodeHandler::new(addr, sender).await;
-
-                                        // Send the sensor data source to the task
-                                        // managing those streams
-                                        if let Err(e) = _stream_sender.send(receiver) {
-                                            // TODO
-                                            log::error!("failure to send sensor stream {e:}");
-                                        }
-
-                                        // Shorten name (but this should be handled by
-                                        // calling subscribers, so TODO move this)
-                                        if name.len() > crate::MAX_PLANT_NAME_SIZE {
-                                            name.drain(crate::MAX_PLANT_NAME_SIZE..);
-                                        }
-
-                                        // TODO: handle non utf8 input or in the case that
-                                        // we have drained the vec to a size that is not a
-                                        // valid codepoint?? can panic if we parse
-                                        let name = String::from_utf8(name).unwrap_or_default();
-
-                                        // Send the sensor data source to the task managing
-                                        // those streams
-                                        if let Err(e) = _registration_sender.send((eui, ip, name)) {
-                                            // TODO
-                                            log::error!("failure to send sensor stream {e:}");
-                                        }
-                                    } else {
-                                        log::warn!("Registration failed, need to retry");
-                                        ot_mon_clone.send(ReturnFreePort(free_port)).await.ok();
-                                    }
-                                }
-                            })
-                            .await;
+                        let retired = shared.clone();
+                        if let ReceiveMode::Shared(port) = config.receive_mode {
+                            shared = EventRouter::shared_socket(shared, omr_addr, port).await;
+                        }
+                        if let Some(port) = config.resource_directory {
+                            rd = EventRouter::resource_directory(rd, omr_addr, port, &announce)
+                                .await;
+                        }
+                        // Handlers of nodes registered again move to the new
+                        // shared socket, the old one is kept open until they
+                        // have
+                        registrar.spawn(nodes, &shared, &retired, omr_addr);
                     } else {
                         log::warn!("actor returned err on getting OmrIp");
                         // break;
@@ -259,8 +788,9 @@ impl EventRouter {
                     break;
                 }
 
+                // Lost nodes are offline, their transitions are published by
+                // the monitor and they are registered again when they rejoin
                 if let Ok(lost_nodes) = ot_mon.send(GetNodeStatus).await? {
-                    // TODO need to handle this
                     if !lost_nodes.is_empty() {
                         log::warn!("Lost nodes {:?}", lost_nodes);
                     }
@@ -268,7 +798,15 @@ impl EventRouter {
                     log::warn!("actor returned err on GetNodeStatus");
                     // break;
                 }
-                tokio::time::sleep(poll).await;
+
+                if let (Some(period), Some(at)) = (config.link_metrics, link_metrics_at.as_mut()) {
+                    if Instant::now() >= *at {
+                        *at = Instant::now() + period;
+                        if let Err(e) = ot_mon.send(PublishLinkMetrics).await? {
+                            log::warn!("Unable to collect link metrics {e:}");
+                        }
+                    }
+                }
             }
 
             log::warn!("Node / network monitor task exiting");
@@ -279,6 +817,62 @@ impl EventRouter {
     }
 }
 
+/// What the registration tasks of the monitor task share
+struct Registrar {
+    ot_mon: Addr<OtMonitor>,
+    config: BrokerConfig,
+    stream_sender: UnboundedSender<UnboundedReceiver<NodeEvent>>,
+    registration_sender: UnboundedSender<Registration>,
+    /// Bounds the registrations running at once to
+    /// [`BrokerConfig::max_registrations`]
+    permits: Arc<Semaphore>,
+    /// Signalled as each registration ends, the node may now wait out a
+    /// backoff
+    finished: UnboundedSender<()>,
+}
+
+impl Registrar {
+    /// Register each node in its own task, up to `max_registrations` at
+    /// once, so that nodes that do not answer hold up neither the others
+    /// nor the poll. `retired` is kept open until the tasks are done
+    fn spawn(
+        &self,
+        nodes: Vec<(Rloc, Ipv6Addr)>,
+        shared: &Option<Arc<SharedSocket>>,
+        retired: &Option<Arc<SharedSocket>>,
+        omr_addr: Ipv6Addr,
+    ) {
+        for node in nodes {
+            let ot_mon = self.ot_mon.clone();
+            let shared = shared.clone();
+            let stream_sender = self.stream_sender.clone();
+            let registration_sender = self.registration_sender.clone();
+            let config = self.config.clone();
+            let permits = self.permits.clone();
+            let finished = self.finished.clone();
+            let retired = retired.clone();
+
+            tokio::spawn(async move {
+                let Ok(_permit) = permits.acquire_owned().await else {
+                    return;
+                };
+                EventRouter::register(
+                    ot_mon,
+                    shared,
+                    omr_addr,
+                    node,
+                    config,
+                    stream_sender,
+                    registration_sender,
+                )
+                .await;
+                drop(retired);
+                finished.send(()).ok();
+            });
+        }
+    }
+}
+
 impl Drop for EventRouter {
     fn drop(&mut self) {
         if let Some(mon) = &self.monitor_handle {
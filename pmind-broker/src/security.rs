@@ -0,0 +1,104 @@
+//! Pre-shared keys the broker authenticates nodes with.
+//!
+//! With a [`KeyStore`] in the [`BrokerConfig`](`crate::BrokerConfig`) every
+//! exchange with a node is protected end to end (see
+//! [`pmindp_protocol::oscore`]) under the key stored for the node's EUI.
+//! Nodes the store has no key for, or that do not answer protected, are
+//! reported with [`ErrorState::Unauthenticated`](`crate::ErrorState`) and
+//! nothing they send is accepted
+use pmindp_protocol::{oscore::parse_psk, Psk};
+use std::{collections::HashMap, fmt, path::Path};
+use thiserror::Error;
+
+use crate::Eui;
+
+#[derive(Error, Debug)]
+pub enum KeyStoreError {
+    #[error("I/O Error")]
+    Io(#[from] std::io::Error),
+    #[error("Key file line {0} is not `<eui> <psk>` in hex")]
+    Parse(usize),
+}
+
+/// Pre-shared key of every node the broker accepts, by EUI
+#[derive(Clone, Default)]
+pub struct KeyStore {
+    keys: HashMap<Eui, Psk>,
+}
+
+impl fmt::Debug for KeyStore {
+    /// Lists the EUIs only, keys stay out of the logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.keys.keys()).finish()
+    }
+}
+
+impl KeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also accept the node `eui`, provisioned with `psk`
+    pub fn with(mut self, eui: Eui, psk: Psk) -> Self {
+        self.insert(eui, psk);
+        self
+    }
+
+    /// Returns the key `eui` was provisioned with before, if any
+    pub fn insert(&mut self, eui: Eui, psk: Psk) -> Option<Psk> {
+        self.keys.insert(eui, psk)
+    }
+
+    pub fn remove(&mut self, eui: &Eui) -> Option<Psk> {
+        self.keys.remove(eui)
+    }
+
+    pub fn get(&self, eui: &Eui) -> Option<&Psk> {
+        self.keys.get(eui)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Load a key file of one `<eui> <psk>` pair per line, both in hex
+    /// (e.g. `6055f9f70778 000102030405060708090a0b0c0d0e0f`). Blank lines
+    /// and lines starting with `#` are skipped
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KeyStoreError> {
+        KeyStore::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn parse(contents: &str) -> Result<Self, KeyStoreError> {
+        let mut store = KeyStore::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(eui), Some(psk), None) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(KeyStoreError::Parse(i + 1));
+            };
+            let (Some(eui), Some(psk)) = (parse_eui(eui), parse_psk(psk)) else {
+                return Err(KeyStoreError::Parse(i + 1));
+            };
+            store.insert(eui, psk);
+        }
+        Ok(store)
+    }
+}
+
+fn parse_eui(hex: &str) -> Option<Eui> {
+    let mut eui = Eui::default();
+    if hex.len() != 2 * eui.len() {
+        return None;
+    }
+    for (i, byte) in eui.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(eui)
+}
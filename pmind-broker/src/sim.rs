@@ -0,0 +1,1511 @@
+//! Simulated Thread mesh for running the broker without an otbr-agent,
+//! RCP dongle or ESP32 nodes, enabled via the `sim` feature.
+//!
+//! [`SimMesh`] tracks a set of in-process [`VirtualNode`]s and hands out
+//! a [`SimOtClient`] that reports them as children of the mesh. Nodes with
+//! an rloc under [`SIM_BORDER_ROUTER`] are the border router's children,
+//! the others are attached to other routers and only found mesh wide. Each
+//! virtual node serves the same `/.well-known/core` and per
+//! [`SensorClass`] CoAP Observe resources as the `pmindp-esp32-thread`
+//! firmware and then notifies observers of each class with its part of a
+//! `SensorReading` as JSON. Nodes given a pre-shared key protect every
+//! exchange after discovery, as provisioned firmware does. Nodes given
+//! the addr of the broker's resource directory register with it as they
+//! join, and nodes given a service port advertise a plant-minder service
+//! on it.
+//!
+//! `::1` is the only IPv6 loopback addr and every node must serve
+//! [`pmindp_protocol::NODE_COAP_PORT`], so the mesh hands out IPv4-mapped loopback
+//! addrs (`::ffff:127.<net>.x.y`) instead. These are bound through
+//! ordinary IPv6 sockets on Linux, no interface setup is needed
+use coap_lite::{ContentFormat, MessageClass, Packet};
+use ipnet::Ipv6Net;
+use pmindp_protocol::{
+    directory, oscore::SESSION_NONCE_LEN, requested_version, service, Endpoint, Link,
+    ObserverRegistry, OscoreServer, Outbox, Psk, TransmissionParams, DEFAULT_MAX_AGE,
+    NODE_COAP_PORT,
+};
+use pmindp_sensor::{
+    Gas, GrowthStage, Light, NodeIdentity, ProtocolHeader, SensorClass, SensorReading, SensorType,
+    Soil, WireFormat,
+};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use thiserror::Error;
+use tokio::{net::UdpSocket, time::Duration};
+
+use crate::{
+    client::{DeviceRole, LinkMetrics, NetworkState, OtClient, PlantService},
+    node::now_ms,
+    Eui, OtClientError, Rloc,
+};
+
+#[derive(Error, Debug)]
+pub enum SimError {
+    #[error("I/O Error")]
+    Io(#[from] std::io::Error),
+    #[error("No virtual node with rloc {0:#06x}")]
+    UnknownNode(Rloc),
+    #[error("Virtual node with rloc {0:#06x} already joined")]
+    DuplicateNode(Rloc),
+}
+
+/// Configuration of a single virtual sensor node
+#[derive(Debug, Clone)]
+pub struct VirtualNode {
+    pub rloc: Rloc,
+    pub eui: Eui,
+    pub name: String,
+    pub pot_num: u32,
+    pub species: String,
+    pub growth_stage: GrowthStage,
+    pub ip: Ipv6Addr,
+    /// How often each observed class is reported
+    pub interval: Duration,
+    /// Sensors listed in the node's `/.well-known/core`
+    pub sensors: Vec<SensorType>,
+    /// Encoding of the readings the node sends
+    pub format: WireFormat,
+    /// Protocol version the node answers with whatever it is asked for,
+    /// to stand in for nodes built from an older or newer tree. `None`
+    /// negotiates like current nodes
+    pub version: Option<u8>,
+    /// Key the node was provisioned with, `None` for nodes that predate
+    /// authentication
+    pub psk: Option<Psk>,
+    /// Resource directory the node registers with when it joins, `None`
+    /// to wait for the broker to poll the mesh
+    pub rd: Option<SocketAddr>,
+    /// Port of the `_plantminder._udp` service the node registers with the
+    /// border router's SRP server, and serves CoAP on. `None` serves
+    /// [`NODE_COAP_PORT`] without advertising a service, as other Thread
+    /// devices do
+    pub service: Option<u16>,
+    /// Average RSSI (dBm) its parent hears the node at
+    pub rssi: i8,
+}
+
+/// A single step of a scripted scenario, see [`SimMesh::run`]
+#[derive(Debug, Clone)]
+pub enum SimStep {
+    /// Node joins the mesh and starts serving the CoAP handshake
+    Join(VirtualNode),
+    /// Node drops out of the child table and stops responding
+    Leave(Rloc),
+    /// Node re-attaches with a new addr, it must be observed again
+    ChangeAddr(Rloc, Ipv6Addr),
+    /// Node attaches to another parent and gets a new rloc, it keeps its
+    /// addr and observers
+    Reparent(Rloc, Rloc),
+    /// The border router moves to a new OMR addr, e.g. as the prefix
+    /// changed; nodes keep theirs
+    ChangeOmrIp(Ipv6Addr),
+    /// The border router reports another network state, or its otbr-agent
+    /// goes down (`None`)
+    Network(Option<NetworkState>),
+    /// Node stays in the child table but stops sending readings
+    Silence(Rloc),
+    /// Node stays in the child table but stops answering requests and
+    /// sending readings, as a node stuck in its firmware
+    Hang(Rloc),
+    Wait(Duration),
+}
+
+struct SimNodeHandle {
+    node: VirtualNode,
+    silent: Arc<AtomicBool>,
+    hung: Arc<AtomicBool>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for SimNodeHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Rloc of the simulated border router
+pub const SIM_BORDER_ROUTER: Rloc = 0xc000;
+
+/// Network the simulated border router leads until told otherwise, see
+/// [`SimMesh::set_network`]
+pub const SIM_NETWORK: NetworkState = NetworkState {
+    role: DeviceRole::Leader,
+    partition_id: Some(0x5eed),
+    leader_router_id: Some((SIM_BORDER_ROUTER >> 10) as u8),
+    active_timestamp: Some(1),
+};
+
+struct SimMeshState {
+    omr_ip: Ipv6Addr,
+    nodes: HashMap<Rloc, SimNodeHandle>,
+    /// `None` while the otbr-agent is down
+    network: Option<NetworkState>,
+}
+
+/// Handle to a simulated mesh; virtual nodes are stopped when it is dropped
+pub struct SimMesh {
+    net: u8,
+    state: Arc<Mutex<SimMeshState>>,
+}
+
+impl SimMesh {
+    /// Create an empty mesh on `::ffff:127.<net>.0.0/112`. Meshes that run
+    /// at the same time (e.g. in parallel tests) need distinct `net` values
+    /// so the broker's receive ports do not collide
+    pub fn new(net: u8) -> Self {
+        let state = SimMeshState {
+            omr_ip: Ipv4Addr::new(127, net, 0, 1).to_ipv6_mapped(),
+            nodes: HashMap::new(),
+            network: Some(SIM_NETWORK),
+        };
+
+        Self {
+            net,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// The border router's addr, which the broker binds its sockets to
+    pub fn omr_ip(&self) -> Ipv6Addr {
+        self.lock().omr_ip
+    }
+
+    /// Addr on the mesh prefix for host number `host` (host 1 is the
+    /// border router)
+    pub fn node_ip(&self, host: u16) -> Ipv6Addr {
+        let [hi, lo] = host.to_be_bytes();
+        Ipv4Addr::new(127, self.net, hi, lo).to_ipv6_mapped()
+    }
+
+    /// Build a [`VirtualNode`] with defaults derived from `rloc`
+    pub fn virtual_node(&self, rloc: Rloc, name: &str) -> VirtualNode {
+        let [hi, lo] = rloc.to_be_bytes();
+        VirtualNode {
+            rloc,
+            eui: [0x60, 0x55, 0xf9, self.net, hi, lo],
+            name: name.to_string(),
+            pot_num: rloc as u32,
+            species: String::from("Jade"),
+            growth_stage: GrowthStage::Vegetative,
+            ip: self.node_ip((rloc & 0x01ff) | 0x0100),
+            interval: Duration::from_secs(1),
+            sensors: vec![SensorType::Atsamd10, SensorType::Tsl2591],
+            format: WireFormat::Cbor,
+            version: None,
+            psk: None,
+            rd: None,
+            service: None,
+            rssi: -60,
+        }
+    }
+
+    /// The broker's resource directory on `port` (e.g.
+    /// [`RD_PORT`](`pmindp_protocol::RD_PORT`)), for [`VirtualNode::rd`]
+    pub fn rd_addr(&self, port: u16) -> SocketAddr {
+        SocketAddr::V6(SocketAddrV6::new(self.omr_ip(), port, 0, 0))
+    }
+
+    /// [`OtClient`] that reports this mesh, for use with
+    /// [`crate::broker_with_client`]
+    pub fn client(&self) -> SimOtClient {
+        SimOtClient {
+            state: self.state.clone(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SimMeshState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub async fn join(&self, node: VirtualNode) -> Result<(), SimError> {
+        if self.lock().nodes.contains_key(&node.rloc) {
+            return Err(SimError::DuplicateNode(node.rloc));
+        }
+        let handle = SimMesh::spawn_node(node).await?;
+        log::info!(
+            "Sim node {:#06x} joined at {}",
+            handle.node.rloc,
+            handle.node.ip
+        );
+        self.lock().nodes.insert(handle.node.rloc, handle);
+        Ok(())
+    }
+
+    pub fn leave(&self, rloc: Rloc) -> Result<(), SimError> {
+        self.lock()
+            .nodes
+            .remove(&rloc)
+            .ok_or(SimError::UnknownNode(rloc))?;
+        log::info!("Sim node {rloc:#06x} left");
+        Ok(())
+    }
+
+    pub async fn change_addr(&self, rloc: Rloc, ip: Ipv6Addr) -> Result<(), SimError> {
+        let mut node = self
+            .lock()
+            .nodes
+            .remove(&rloc)
+            .ok_or(SimError::UnknownNode(rloc))?
+            .node
+            .clone();
+        node.ip = ip;
+        self.join(node).await
+    }
+
+    pub fn reparent(&self, rloc: Rloc, new_rloc: Rloc) -> Result<(), SimError> {
+        let mut state = self.lock();
+        if state.nodes.contains_key(&new_rloc) {
+            return Err(SimError::DuplicateNode(new_rloc));
+        }
+        let mut handle = state
+            .nodes
+            .remove(&rloc)
+            .ok_or(SimError::UnknownNode(rloc))?;
+        handle.node.rloc = new_rloc;
+        state.nodes.insert(new_rloc, handle);
+        log::info!("Sim node {rloc:#06x} re-parented as {new_rloc:#06x}");
+        Ok(())
+    }
+
+    /// Move the border router to `ip`, reported on the `/112` it is on
+    pub fn change_omr_ip(&self, ip: Ipv6Addr) {
+        self.lock().omr_ip = ip;
+        log::info!("Sim border router moved to {ip}");
+    }
+
+    /// The border router reports `network` from now on, `None` stops its
+    /// otbr-agent, so that no query of the [`SimOtClient`] is answered.
+    /// Nodes keep streaming either way
+    pub fn set_network(&self, network: Option<NetworkState>) {
+        self.lock().network = network;
+        log::info!("Sim network now {network:?}");
+    }
+
+    pub fn silence(&self, rloc: Rloc) -> Result<(), SimError> {
+        self.lock()
+            .nodes
+            .get(&rloc)
+            .ok_or(SimError::UnknownNode(rloc))?
+            .silent
+            .store(true, Ordering::Relaxed);
+        log::info!("Sim node {rloc:#06x} went silent");
+        Ok(())
+    }
+
+    /// The node's socket stays bound, so requests to it are neither
+    /// answered nor refused and only time out
+    pub fn hang(&self, rloc: Rloc) -> Result<(), SimError> {
+        let state = self.lock();
+        let handle = state.nodes.get(&rloc).ok_or(SimError::UnknownNode(rloc))?;
+        handle.silent.store(true, Ordering::Relaxed);
+        handle.hung.store(true, Ordering::Relaxed);
+        log::info!("Sim node {rloc:#06x} hung");
+        Ok(())
+    }
+
+    /// Run a scripted scenario, steps are applied in order
+    pub async fn run(&self, scenario: &[SimStep]) -> Result<(), SimError> {
+        for step in scenario {
+            match step {
+                SimStep::Join(node) => self.join(node.clone()).await?,
+                SimStep::Leave(rloc) => self.leave(*rloc)?,
+                SimStep::ChangeAddr(rloc, ip) => self.change_addr(*rloc, *ip).await?,
+                SimStep::Reparent(rloc, new_rloc) => self.reparent(*rloc, *new_rloc)?,
+                SimStep::ChangeOmrIp(ip) => self.change_omr_ip(*ip),
+                SimStep::Network(network) => self.set_network(*network),
+                SimStep::Silence(rloc) => self.silence(*rloc)?,
+                SimStep::Hang(rloc) => self.hang(*rloc)?,
+                SimStep::Wait(d) => tokio::time::sleep(*d).await,
+            }
+        }
+        Ok(())
+    }
+
+    /// Bind the node's CoAP socket up front so that bind errors are
+    /// reported to the caller, then serve it in the background
+    async fn spawn_node(node: VirtualNode) -> Result<SimNodeHandle, SimError> {
+        let port = node.service.unwrap_or(NODE_COAP_PORT);
+        let socket = UdpSocket::bind(SocketAddrV6::new(node.ip, port, 0, 0)).await?;
+        let silent = Arc::new(AtomicBool::new(false));
+        let hung = Arc::new(AtomicBool::new(false));
+        let task = tokio::spawn(SimMesh::node_loop(
+            node.clone(),
+            socket,
+            silent.clone(),
+            hung.clone(),
+        ));
+
+        Ok(SimNodeHandle {
+            node,
+            silent,
+            hung,
+            task,
+        })
+    }
+
+    async fn node_loop(
+        node: VirtualNode,
+        socket: UdpSocket,
+        silent: Arc<AtomicBool>,
+        hung: Arc<AtomicBool>,
+    ) {
+        let mut buffer = [0u8; 512];
+        let content_format = match node.format {
+            WireFormat::Json => ContentFormat::ApplicationJSON,
+            WireFormat::Cbor => ContentFormat::ApplicationCBOR,
+        };
+        let classes: Vec<_> = SensorClass::ALL
+            .into_iter()
+            .filter(|c| node.sensors.iter().any(|s| s.class() == *c))
+            .collect();
+        let links: Vec<_> = classes
+            .iter()
+            .map(|class| {
+                Link::new(class.name())
+                    .resource_types(
+                        node.sensors
+                            .iter()
+                            .filter(|s| s.class() == *class)
+                            .map(|s| s.name()),
+                    )
+                    .content_format(content_format)
+                    .observable()
+            })
+            .collect();
+        let mut observers = classes
+            .iter()
+            .fold(
+                ObserverRegistry::new(SensorClass::Soil.name(), DEFAULT_MAX_AGE),
+                |registry, class| registry.resource(class.name(), DEFAULT_MAX_AGE),
+            )
+            .confirmable(TransmissionParams::default(), node.rloc as u32)
+            .discoverable(&links);
+        let mut security = node.psk.map(|psk| {
+            let mut nonce = [0u8; SESSION_NONCE_LEN];
+            getrandom::fill(&mut nonce).ok();
+            OscoreServer::new(psk, &node.eui, nonce, &TransmissionParams::default())
+        });
+        let mut tick = tokio::time::interval(node.interval);
+        let mut count = 0u16;
+
+        // Register with the directory as the firmware does on joining,
+        // retransmitted until the broker acknowledges
+        let mut registration = Outbox::new(TransmissionParams::default(), node.rloc as u32);
+        if let Some(rd) = node.rd {
+            // Random like on a booting node, so the broker does not take a
+            // registration after a reboot for a retransmission
+            let mut seed = [0u8; 4];
+            getrandom::fill(&mut seed).ok();
+            let request = Endpoint::new(&node.eui)
+                .links(&links)
+                .register(u16::from_be_bytes([seed[0], seed[1]]), &seed[2..]);
+            if let Ok(packet) = request.to_bytes() {
+                socket.send_to(&packet, rd).await.ok();
+            }
+            registration.send(rd, request, now_ms());
+        }
+
+        loop {
+            let retransmit = [observers.next_deadline(), registration.next_deadline()]
+                .into_iter()
+                .flatten()
+                .min()
+                .map(|deadline| {
+                    tokio::time::Instant::now()
+                        + Duration::from_millis(deadline.saturating_sub(now_ms()))
+                });
+            tokio::select! {
+                res = socket.recv_from(&mut buffer) => {
+                    let Ok((len, from)) = res else {
+                        log::error!("Sim node {:#06x} socket error", node.rloc);
+                        break;
+                    };
+                    if hung.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    let Ok(packet) = Packet::from_bytes(&buffer[..len]) else {
+                        continue;
+                    };
+                    if Some(from) == node.rd && matches!(packet.header.code, MessageClass::Response(_)) {
+                        registration.acknowledge(&from, packet.header.message_id);
+                        log::debug!(
+                            "Sim node {:#06x} registered with the directory as {:?}",
+                            node.rloc,
+                            directory::location(&packet)
+                        );
+                        continue;
+                    }
+                    let mut serve = |packet: &Packet| {
+                        let header = node.version.map_or_else(
+                            || ProtocolHeader::negotiate(requested_version(packet)),
+                            |version| ProtocolHeader { version },
+                        );
+                        let (record, record_format) = NodeIdentity {
+                            header,
+                            eui: node.eui,
+                            name: node.name.clone(),
+                            pot_num: node.pot_num,
+                            species: node.species.clone(),
+                            growth_stage: node.growth_stage,
+                            firmware: env!("CARGO_PKG_VERSION").to_string(),
+                        }
+                        .reply();
+                        observers.handle_request(
+                            packet,
+                            from,
+                            &record,
+                            record_format.and_then(|f| {
+                                ContentFormat::try_from(f.content_format() as usize).ok()
+                            }),
+                            now_ms(),
+                        )
+                    };
+                    let (outcome, response) = match security.as_mut() {
+                        Some(security) => security.handle(&packet, from, now_ms(), serve),
+                        None => {
+                            let (outcome, response) = serve(&packet);
+                            (Some(outcome), response)
+                        }
+                    };
+                    if let Some(Ok(response)) = response.map(|r| r.to_bytes()) {
+                        socket.send_to(&response, from).await.ok();
+                    }
+                    log::debug!("Sim node {:#06x} request from {from}: {outcome:?}", node.rloc);
+                }
+                _ = tick.tick() => {
+                    if silent.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    let reading = SimMesh::reading(count);
+                    for class in &classes {
+                        let Some(Ok(data)) = reading.encode_class(*class, node.format) else {
+                            continue;
+                        };
+                        for (dest, notification) in observers.notify(
+                            class.name(),
+                            &data,
+                            content_format,
+                            now_ms(),
+                        ) {
+                            SimMesh::send(&socket, &mut security, &notification, dest).await;
+                        }
+                    }
+                    count = count.wrapping_add(1);
+                }
+                _ = tokio::time::sleep_until(retransmit.unwrap_or_else(tokio::time::Instant::now)),
+                    if retransmit.is_some() => {
+                    for (dest, notification) in observers.poll(now_ms()) {
+                        SimMesh::send(&socket, &mut security, &notification, dest).await;
+                    }
+                    for (rd, request) in registration.poll(now_ms()).resend {
+                        if let Ok(packet) = request.to_bytes() {
+                            socket.send_to(&packet, rd).await.ok();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send a notification, protected if the node has a key. Observers
+    /// from a session that was since replaced get nothing
+    async fn send(
+        socket: &UdpSocket,
+        security: &mut Option<OscoreServer<SocketAddr>>,
+        notification: &Packet,
+        dest: SocketAddr,
+    ) {
+        let notification = match security.as_mut() {
+            Some(security) => security.protect(notification),
+            None => Some(notification.clone()),
+        };
+        if let Some(Ok(packet)) = notification.map(|n| n.to_bytes()) {
+            socket.send_to(&packet, dest).await.ok();
+        }
+    }
+
+    /// Plausible, slowly drifting sensor values
+    fn reading(count: u16) -> SensorReading {
+        let drift = (count % 64) as f32;
+        SensorReading {
+            soil: Soil {
+                moisture: 400 + (count % 64) * 8,
+                temp: 21.0 + drift / 16.0,
+            },
+            light: Some(Light {
+                fs: 3500 + (count % 64) * 4,
+                lux: 80.0 + drift,
+            }),
+            gas: Some(Gas {
+                temp: 21.5 + drift / 16.0,
+                p: 1013.0,
+                h: 45.0 + drift / 4.0,
+                gas: 50_000,
+            }),
+            ts: 0,
+        }
+    }
+}
+
+impl Drop for SimMesh {
+    fn drop(&mut self) {
+        // The client may outlive the mesh, so stop the nodes explicitly
+        self.lock().nodes.clear();
+    }
+}
+
+/// Implementation of the [`crate::client::OtClient`] trait backed by a
+/// [`SimMesh`]
+pub struct SimOtClient {
+    state: Arc<Mutex<SimMeshState>>,
+}
+
+impl SimOtClient {
+    fn lock(&self) -> std::sync::MutexGuard<'_, SimMeshState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The mesh, unless its otbr-agent is down
+    fn agent(&self) -> Result<std::sync::MutexGuard<'_, SimMeshState>, OtClientError> {
+        let state = self.lock();
+        match state.network {
+            Some(_) => Ok(state),
+            None => Err(OtClientError::OtClientErr(
+                "Sim otbr-agent down".to_string(),
+            )),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl OtClient for SimOtClient {
+    async fn get_child_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        Ok(self
+            .agent()?
+            .nodes
+            .values()
+            .filter(|h| h.node.rloc & 0xfc00 == SIM_BORDER_ROUTER)
+            .map(|h| (h.node.rloc, h.node.ip))
+            .collect())
+    }
+
+    async fn get_mesh_ips(&self) -> Result<Vec<(Rloc, Ipv6Addr)>, OtClientError> {
+        Ok(self
+            .agent()?
+            .nodes
+            .values()
+            .map(|h| (h.node.rloc, h.node.ip))
+            .collect())
+    }
+
+    async fn get_plant_services(&self) -> Result<Vec<PlantService>, OtClientError> {
+        Ok(self
+            .agent()?
+            .nodes
+            .values()
+            .filter_map(|h| {
+                Some(PlantService {
+                    instance: service::instance_name(&h.node.eui),
+                    addrs: vec![h.node.ip],
+                    port: h.node.service?,
+                    name: Some(h.node.name.clone()),
+                    species: Some(h.node.species.clone()),
+                    firmware: Some(env!("CARGO_PKG_VERSION").to_string()),
+                })
+            })
+            .collect())
+    }
+
+    /// Link margin is taken over a -100 dBm noise floor
+    async fn get_link_metrics(&self) -> Result<Vec<LinkMetrics>, OtClientError> {
+        Ok(self
+            .agent()?
+            .nodes
+            .values()
+            .map(|h| {
+                LinkMetrics {
+                    rloc: h.node.rloc,
+                    parent: h.node.rloc & 0xfc00,
+                    avg_rssi: Some(h.node.rssi),
+                    last_rssi: Some(h.node.rssi),
+                    link_margin: Some((h.node.rssi as i16 + 100).clamp(0, u8::MAX as i16) as u8),
+                    frame_error_rate: Some(0.0),
+                    message_error_rate: Some(0.0),
+                    ..Default::default()
+                }
+                .derive_link_quality()
+            })
+            .collect())
+    }
+
+    async fn get_network_state(&self) -> Result<NetworkState, OtClientError> {
+        Ok(self.agent()?.network.unwrap_or_default())
+    }
+
+    async fn get_omr_prefix(&self) -> Result<Ipv6Net, OtClientError> {
+        Ok(Ipv6Net::new(self.agent()?.omr_ip, 112)
+            .map_err(|e| OtClientError::OtClientErr(format!("Invalid prefix length {e:}")))?
+            .trunc())
+    }
+
+    async fn get_omr_ip(&self) -> Result<Ipv6Addr, OtClientError> {
+        Ok(self.agent()?.omr_ip)
+    }
+
+    async fn get_ip_addrs(&self) -> Result<Vec<Ipv6Addr>, OtClientError> {
+        Ok(vec![self.agent()?.omr_ip])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix::Addr;
+    use pmindp_protocol::{Notification, ObserveClient, NODE_COAP_PORT, RD_PORT};
+    use pmindp_sensor::{GrowthStage, SensorClass, PROTOCOL_VERSION};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use tokio::{
+        sync::mpsc::{unbounded_channel, UnboundedReceiver},
+        time::Duration,
+    };
+
+    use super::{SimMesh, SimStep, SIM_NETWORK};
+    use crate::{
+        broker::BrokerHandle,
+        client::{DeviceRole, NetworkState, OtClient},
+        BrokerConfig, ErrorState, Lifecycle, NetworkEvent, NetworkStatus, NodeMove,
+        NodeSensorReading, NodeStatus, NodeTransition,
+    };
+
+    /// A broker on the mesh, and the channels of a client subscribed to it
+    struct SimBroker {
+        _handle: Addr<BrokerHandle>,
+        readings: UnboundedReceiver<NodeSensorReading>,
+        status: UnboundedReceiver<NodeStatus>,
+        network: UnboundedReceiver<NetworkStatus>,
+    }
+
+    impl SimBroker {
+        /// Start a broker polling `mesh` every `poll`, with a client
+        /// subscribed to every class
+        async fn start(mesh: &SimMesh, poll: Duration, config: BrokerConfig) -> SimBroker {
+            SimBroker::subscribe(start_broker(mesh, poll, config).await, &SensorClass::ALL).await
+        }
+
+        async fn subscribe(handle: Addr<BrokerHandle>, classes: &[SensorClass]) -> SimBroker {
+            let (sensor_tx, readings) = unbounded_channel();
+            let (status_tx, status) = unbounded_channel();
+            let (network_tx, network) = unbounded_channel();
+            handle
+                .send(crate::ClientSubscribe {
+                    id: 0,
+                    sensor_readings: sensor_tx,
+                    node_status: status_tx,
+                    classes: classes.to_vec(),
+                    network_status: Some(network_tx),
+                })
+                .await
+                .expect("Mailbox error")
+                .expect("Unable to subscribe");
+            SimBroker {
+                _handle: handle,
+                readings,
+                status,
+                network,
+            }
+        }
+    }
+
+    async fn start_broker(
+        mesh: &SimMesh,
+        poll: Duration,
+        config: BrokerConfig,
+    ) -> Addr<BrokerHandle> {
+        crate::broker_with_config(poll, 100, Box::new(mesh.client()), config)
+            .await
+            .expect("Unable to start broker")
+    }
+
+    async fn next_registration(rx: &mut UnboundedReceiver<NodeStatus>) -> crate::Registration {
+        loop {
+            let status = tokio::time::timeout(Duration::from_secs(20), rx.recv())
+                .await
+                .expect("Timed out waiting for registration")
+                .expect("Status channel closed");
+            if let NodeStatus::Registration(reg) = status {
+                return reg;
+            }
+        }
+    }
+
+    async fn next_network(rx: &mut UnboundedReceiver<NetworkStatus>) -> NetworkStatus {
+        tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .expect("Timed out waiting for network status")
+            .expect("Network channel closed")
+    }
+
+    async fn next_reading_from(
+        rx: &mut UnboundedReceiver<NodeSensorReading>,
+        from: std::net::Ipv6Addr,
+    ) -> NodeSensorReading {
+        loop {
+            let reading = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+                .await
+                .expect("Timed out waiting for reading")
+                .expect("Reading channel closed");
+            if *reading.addr.ip() == from {
+                return reading;
+            }
+        }
+    }
+
+    #[actix::test]
+    async fn check_sim_broker_to_subscriber() {
+        let mesh = SimMesh::new(10);
+        let node = mesh.virtual_node(0xc001, "SimJade");
+        let moved = mesh.node_ip(0x0200);
+        let config = crate::BrokerConfig {
+            node_timeout: Duration::from_secs(2),
+            ..Default::default()
+        };
+        let mut broker = SimBroker::start(&mesh, Duration::from_millis(500), config).await;
+
+        // Join: the node is registered and starts streaming
+        mesh.run(&[SimStep::Join(node.clone())])
+            .await
+            .expect("Unable to join node");
+        let reg = next_registration(&mut broker.status).await;
+        assert_eq!(
+            (reg.eui, reg.addr, reg.name.as_str()),
+            (node.eui, node.ip, "SimJade")
+        );
+        assert_eq!(
+            (reg.pot_num, reg.species.as_str(), reg.growth_stage),
+            (node.pot_num, "Jade", GrowthStage::Vegetative)
+        );
+        assert_eq!(reg.firmware, env!("CARGO_PKG_VERSION"));
+        let capabilities = reg.capabilities;
+        assert_eq!(capabilities.resources, vec!["soil", "light"]);
+        assert_eq!(capabilities.sensors, node.sensors);
+        assert_eq!(
+            capabilities.classes(),
+            [SensorClass::Soil, SensorClass::Light]
+        );
+        let reading = next_reading_from(&mut broker.readings, node.ip).await;
+        assert!(reading.data.soil.moisture >= 400);
+        // Both classes are observed and merged into the node's readings
+        let mut reading = next_reading_from(&mut broker.readings, node.ip).await;
+        while reading.class != SensorClass::Light {
+            reading = next_reading_from(&mut broker.readings, node.ip).await;
+        }
+        assert!(reading.data.light.is_some_and(|l| l.fs >= 3500));
+
+        // Change addr: the node is registered again at its new addr
+        mesh.run(&[SimStep::ChangeAddr(node.rloc, moved)])
+            .await
+            .expect("Unable to move node");
+        assert_eq!(next_registration(&mut broker.status).await.addr, moved);
+        next_reading_from(&mut broker.readings, moved).await;
+
+        // Silence: readings stop arriving, the subscriber is told the node
+        // timed out and it goes stale
+        mesh.run(&[SimStep::Silence(node.rloc)])
+            .await
+            .expect("Unable to silence node");
+        let (mut timed_out, mut stale) = (false, false);
+        while !(timed_out && stale) {
+            match tokio::time::timeout(Duration::from_secs(20), broker.status.recv())
+                .await
+                .expect("Timed out waiting for node status")
+                .expect("Status channel closed")
+            {
+                NodeStatus::Termination((addr, ErrorState::Timeout)) if *addr.ip() == moved => {
+                    timed_out = true
+                }
+                NodeStatus::Lifecycle(t) if t.addr == moved && t.to == Lifecycle::Stale => {
+                    stale = true
+                }
+                _ => {}
+            }
+        }
+        while broker.readings.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(broker.readings.try_recv().is_err());
+
+        // Leave: the node goes offline, still known by its EUI
+        mesh.leave(node.rloc).expect("Unable to remove node");
+        assert!(mesh.leave(node.rloc).is_err());
+        let mut transition = next_transition(&mut broker.status, moved).await;
+        while transition.to != Lifecycle::Offline {
+            transition = next_transition(&mut broker.status, moved).await;
+        }
+        assert_eq!(transition.eui, Some(node.eui));
+    }
+
+    #[actix::test]
+    async fn check_sim_shared_receive_socket() {
+        let mesh = SimMesh::new(12);
+        let config = crate::BrokerConfig {
+            receive_mode: crate::ReceiveMode::Shared(crate::DEFAULT_SHARED_RCV_PORT),
+            ..Default::default()
+        };
+        let handle = start_broker(&mesh, Duration::from_millis(500), config).await;
+        let mut broker = SimBroker::subscribe(handle, &[SensorClass::Soil]).await;
+
+        // Both nodes report to the same broker port, readings are still
+        // attributed to the node that sent them, whichever format they use
+        let mut json_node = mesh.virtual_node(0xc002, "SimMoss");
+        json_node.format = pmindp_sensor::WireFormat::Json;
+        let nodes = [mesh.virtual_node(0xc001, "SimIvy"), json_node];
+        for node in &nodes {
+            mesh.join(node.clone()).await.expect("Unable to join node");
+        }
+        for _ in &nodes {
+            next_registration(&mut broker.status).await;
+        }
+        // Only the subscribed class is forwarded
+        for node in &nodes {
+            let reading = next_reading_from(&mut broker.readings, node.ip).await;
+            assert_eq!(reading.class, SensorClass::Soil);
+            assert!(reading.data.soil.moisture >= 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn check_sim_node_handshake() {
+        let mesh = SimMesh::new(11);
+        let node = mesh.virtual_node(0xc002, "SimFern");
+        mesh.join(node.clone()).await.expect("Unable to join node");
+
+        let observer = tokio::net::UdpSocket::bind(SocketAddr::new(mesh.omr_ip().into(), 1250))
+            .await
+            .expect("Unable to bind observer");
+        let mut client = ObserveClient::new([0xa5; 8], 1, SensorClass::Soil.name());
+        observer
+            .send_to(
+                &client.register().to_bytes().expect("Bad request"),
+                SocketAddr::new(node.ip.into(), NODE_COAP_PORT),
+            )
+            .await
+            .expect("Unable to send request");
+
+        let mut buffer = [0u8; 512];
+        let (len, _) = observer
+            .recv_from(&mut buffer)
+            .await
+            .expect("No handshake response");
+        let packet = coap_lite::Packet::from_bytes(&buffer[..len]).expect("Bad response");
+        let Notification::Fresh(record) = client.handle_response(&packet, 0) else {
+            panic!("Registration was not accepted");
+        };
+        // Brokers that do not ask for a protocol version get the legacy record
+        assert_eq!(packet.get_content_format(), None);
+        assert_eq!(&record[..6], &node.eui);
+        assert_eq!(&record[6..], b"SimFern");
+
+        let (len, _) = observer.recv_from(&mut buffer).await.expect("No reading");
+        let packet = coap_lite::Packet::from_bytes(&buffer[..len]).expect("Bad notification");
+        let Notification::Fresh(data) = client.handle_response(&packet, 1) else {
+            panic!("Notification was not fresh");
+        };
+        pmindp_sensor::wire::from_cbor::<pmindp_sensor::Soil>(data)
+            .expect("Reading is not Soil CBOR");
+    }
+
+    #[actix::test]
+    async fn check_sim_protocol_versions() {
+        let mesh = SimMesh::new(13);
+        let mut broker =
+            SimBroker::start(&mesh, Duration::from_millis(500), Default::default()).await;
+
+        // Nodes that predate versioning are still monitored, nodes on a
+        // version the broker does not know are reported
+        let mut legacy = mesh.virtual_node(0xc001, "SimOld");
+        legacy.version = Some(1);
+        let mut future = mesh.virtual_node(0xc002, "SimNew");
+        future.version = Some(PROTOCOL_VERSION + 1);
+        mesh.join(legacy.clone())
+            .await
+            .expect("Unable to join node");
+        mesh.join(future.clone())
+            .await
+            .expect("Unable to join node");
+
+        let (mut registered, mut rejected) = (None, None);
+        while registered.is_none() || rejected.is_none() {
+            match tokio::time::timeout(Duration::from_secs(20), broker.status.recv())
+                .await
+                .expect("Timed out waiting for node status")
+                .expect("Status channel closed")
+            {
+                NodeStatus::Registration(reg) => {
+                    // Legacy nodes do not send the rest of their plant config
+                    assert!(reg.species.is_empty() && reg.firmware.is_empty());
+                    registered = Some((reg.eui, reg.addr, reg.name))
+                }
+                NodeStatus::Termination((addr, ErrorState::UnsupportedVersion(v))) => {
+                    rejected = Some((*addr.ip(), v))
+                }
+                NodeStatus::Lifecycle(_) => {}
+                other => panic!("Unexpected status {other:?}"),
+            }
+        }
+        assert_eq!(
+            registered,
+            Some((legacy.eui, legacy.ip, String::from("SimOld")))
+        );
+        assert_eq!(rejected, Some((future.ip, PROTOCOL_VERSION + 1)));
+        next_reading_from(&mut broker.readings, legacy.ip).await;
+    }
+
+    #[actix::test]
+    async fn check_sim_authentication() {
+        let mesh = SimMesh::new(14);
+        let mut trusted = mesh.virtual_node(0xc001, "SimTrusted");
+        trusted.psk = Some([0x11; 16]);
+        let mut forger = mesh.virtual_node(0xc002, "SimForger");
+        forger.psk = Some([0x22; 16]);
+        let legacy = mesh.virtual_node(0xc003, "SimLegacy");
+        let mut stranger = mesh.virtual_node(0xc004, "SimStranger");
+        stranger.psk = Some([0x44; 16]);
+        let keys = crate::KeyStore::new()
+            .with(trusted.eui, [0x11; 16])
+            .with(forger.eui, [0x33; 16])
+            .with(legacy.eui, [0x55; 16]);
+        let config = crate::BrokerConfig {
+            keys: Some(keys),
+            ..Default::default()
+        };
+        let mut broker = SimBroker::start(&mesh, Duration::from_millis(500), config).await;
+
+        // Only the node holding the key stored for its EUI is registered,
+        // a wrong key, no key or an unknown EUI are all reported
+        for node in [&trusted, &forger, &legacy, &stranger] {
+            mesh.join(node.clone()).await.expect("Unable to join node");
+        }
+        let (mut registered, mut rejected) = (None, std::collections::BTreeSet::new());
+        while registered.is_none() || rejected.len() < 3 {
+            match tokio::time::timeout(Duration::from_secs(20), broker.status.recv())
+                .await
+                .expect("Timed out waiting for node status")
+                .expect("Status channel closed")
+            {
+                NodeStatus::Registration(reg) => registered = Some((reg.eui, reg.addr)),
+                NodeStatus::Termination((addr, ErrorState::Unauthenticated)) => {
+                    rejected.insert(*addr.ip());
+                }
+                NodeStatus::Lifecycle(_) => {}
+                other => panic!("Unexpected status {other:?}"),
+            }
+        }
+        assert_eq!(registered, Some((trusted.eui, trusted.ip)));
+        assert_eq!(
+            rejected,
+            [forger.ip, legacy.ip, stranger.ip].into_iter().collect()
+        );
+
+        // Readings of the trusted node come through, nobody else's do
+        next_reading_from(&mut broker.readings, trusted.ip).await;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        while let Ok(reading) = broker.readings.try_recv() {
+            assert_eq!(*reading.addr.ip(), trusted.ip);
+        }
+    }
+
+    /// Next lifecycle transition of the node at `addr`
+    async fn next_transition(
+        rx: &mut UnboundedReceiver<NodeStatus>,
+        addr: std::net::Ipv6Addr,
+    ) -> NodeTransition {
+        loop {
+            let status = tokio::time::timeout(Duration::from_secs(20), rx.recv())
+                .await
+                .expect("Timed out waiting for transition")
+                .expect("Status channel closed");
+            if let NodeStatus::Lifecycle(transition) = status {
+                if transition.addr == addr {
+                    return transition;
+                }
+            }
+        }
+    }
+
+    /// Expect the next transitions of the node at `addr` to be `steps`
+    async fn expect_transitions(
+        rx: &mut UnboundedReceiver<NodeStatus>,
+        addr: std::net::Ipv6Addr,
+        steps: &[(Option<Lifecycle>, Lifecycle)],
+    ) {
+        for &(from, to) in steps {
+            let transition = next_transition(rx, addr).await;
+            assert_eq!((transition.from, transition.to), (from, to));
+        }
+    }
+
+    #[actix::test]
+    async fn check_sim_node_lifecycle() {
+        let mesh = SimMesh::new(15);
+        let node = mesh.virtual_node(0xc001, "SimCactus");
+        let config = crate::BrokerConfig {
+            node_timeout: Duration::from_secs(2),
+            backoff: crate::Backoff {
+                initial: Duration::from_secs(1),
+                max: Duration::from_secs(4),
+            },
+            ..Default::default()
+        };
+        let mut broker = SimBroker::start(&mesh, Duration::from_millis(500), config).await;
+
+        mesh.join(node.clone()).await.expect("Unable to join node");
+        expect_transitions(
+            &mut broker.status,
+            node.ip,
+            &[
+                (None, Lifecycle::Discovered),
+                (Some(Lifecycle::Discovered), Lifecycle::Registering),
+                (Some(Lifecycle::Registering), Lifecycle::Online),
+            ],
+        )
+        .await;
+
+        // A node that stops reporting is stale, and registered again; the
+        // silent node still accepts, and goes stale again
+        mesh.silence(node.rloc).expect("Unable to silence node");
+        expect_transitions(
+            &mut broker.status,
+            node.ip,
+            &[
+                (Some(Lifecycle::Online), Lifecycle::Stale),
+                (Some(Lifecycle::Stale), Lifecycle::Registering),
+                (Some(Lifecycle::Registering), Lifecycle::Online),
+            ],
+        )
+        .await;
+
+        // A node that leaves is offline, and rejoined when it is back
+        mesh.leave(node.rloc).expect("Unable to remove node");
+        let mut transition = next_transition(&mut broker.status, node.ip).await;
+        while transition.to != Lifecycle::Offline {
+            transition = next_transition(&mut broker.status, node.ip).await;
+        }
+        assert_eq!(transition.eui, Some(node.eui));
+        mesh.join(node.clone()).await.expect("Unable to join node");
+        expect_transitions(
+            &mut broker.status,
+            node.ip,
+            &[
+                (Some(Lifecycle::Offline), Lifecycle::Rejoined),
+                (Some(Lifecycle::Rejoined), Lifecycle::Registering),
+                (Some(Lifecycle::Registering), Lifecycle::Online),
+            ],
+        )
+        .await;
+    }
+
+    #[actix::test]
+    async fn check_sim_rd_registration() {
+        let mesh = SimMesh::new(19);
+        let mut node = mesh.virtual_node(0xc001, "SimAloe");
+        node.rd = Some(mesh.rd_addr(RD_PORT));
+        // Polling alone would not find the node within the test
+        let mut broker =
+            SimBroker::start(&mesh, Duration::from_secs(600), Default::default()).await;
+        // Let the first poll of the empty mesh pass
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        mesh.join(node.clone()).await.expect("Unable to join node");
+        let reg = tokio::time::timeout(
+            Duration::from_secs(10),
+            next_registration(&mut broker.status),
+        )
+        .await
+        .expect("Node not registered on joining");
+        assert_eq!(reg.eui, node.eui);
+        next_reading_from(&mut broker.readings, node.ip).await;
+
+        // A rebooted node lost its observers, it is observed again as soon
+        // as it registers with the directory
+        mesh.run(&[
+            SimStep::Leave(node.rloc),
+            SimStep::Wait(Duration::from_millis(200)),
+            SimStep::Join(node.clone()),
+        ])
+        .await
+        .expect("Unable to reboot node");
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            next_registration(&mut broker.status),
+        )
+        .await
+        .expect("Node not registered again on rebooting");
+        while broker.readings.try_recv().is_ok() {}
+        next_reading_from(&mut broker.readings, node.ip).await;
+    }
+
+    #[actix::test]
+    async fn check_sim_node_behind_router() {
+        let mesh = SimMesh::new(18);
+        let node = mesh.virtual_node(0xc801, "SimFicus");
+        let mut broker =
+            SimBroker::start(&mesh, Duration::from_millis(500), Default::default()).await;
+
+        // Not a child of the border router, only found mesh wide
+        mesh.join(node.clone()).await.expect("Unable to join node");
+        let client = mesh.client();
+        assert!(client.get_child_ips().await.unwrap().is_empty());
+        assert_eq!(client.get_mesh_ips().await.unwrap(), [(node.rloc, node.ip)]);
+
+        assert_eq!(next_registration(&mut broker.status).await.eui, node.eui);
+        next_reading_from(&mut broker.readings, node.ip).await;
+    }
+
+    #[actix::test]
+    async fn check_sim_link_metrics() {
+        let mesh = SimMesh::new(21);
+        let node = super::VirtualNode {
+            rssi: -75,
+            ..mesh.virtual_node(0xc801, "SimFern")
+        };
+        let config = crate::BrokerConfig {
+            link_metrics: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let mut broker = SimBroker::start(&mesh, Duration::from_millis(500), config).await;
+
+        mesh.join(node.clone()).await.expect("Unable to join node");
+        assert_eq!(next_registration(&mut broker.status).await.eui, node.eui);
+
+        // Published for registered nodes only, once they are online
+        let link = loop {
+            let status = tokio::time::timeout(Duration::from_secs(10), broker.status.recv())
+                .await
+                .expect("Timed out waiting for link metrics")
+                .expect("Status channel closed");
+            if let NodeStatus::LinkMetrics(link) = status {
+                break link;
+            }
+        };
+        assert_eq!((link.eui, link.addr), (node.eui, node.ip));
+        assert_eq!((link.metrics.rloc, link.metrics.parent), (0xc801, 0xc800));
+        assert_eq!(
+            (link.metrics.avg_rssi, link.metrics.link_margin),
+            (Some(-75), Some(25))
+        );
+        assert_eq!(link.metrics.link_quality, Some(3));
+    }
+
+    #[actix::test]
+    async fn check_sim_mesh_down() {
+        let mesh = SimMesh::new(22);
+        let node = mesh.virtual_node(0xc001, "SimMoss");
+        let late = mesh.virtual_node(0xc002, "SimIvy");
+        let handle = start_broker(&mesh, Duration::from_millis(500), Default::default()).await;
+        mesh.join(node.clone()).await.expect("Unable to join node");
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+
+        // Subscribing late, the current state comes first
+        let mut broker = SimBroker::subscribe(handle, &SensorClass::ALL).await;
+        let status = next_network(&mut broker.network).await;
+        assert_eq!(
+            (status.event, status.state),
+            (NetworkEvent::AgentUp, Some(SIM_NETWORK))
+        );
+        next_reading_from(&mut broker.readings, node.ip).await;
+
+        // While the otbr-agent is down nodes are not marked offline, and
+        // the monitor keeps polling for it to come back
+        mesh.run(&[SimStep::Network(None), SimStep::Join(late.clone())])
+            .await
+            .expect("Unable to run scenario");
+        let status = next_network(&mut broker.network).await;
+        assert_eq!(status.event, NetworkEvent::AgentDown);
+        assert!(!status.is_mesh_up());
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        while let Ok(status) = broker.status.try_recv() {
+            if let NodeStatus::Lifecycle(NodeTransition { to, .. }) = status {
+                assert_ne!(to, Lifecycle::Offline, "Node marked offline while down");
+            }
+        }
+
+        let detached = NetworkState {
+            role: DeviceRole::Detached,
+            partition_id: None,
+            leader_router_id: None,
+            ..SIM_NETWORK
+        };
+        mesh.set_network(Some(detached));
+        assert_eq!(
+            next_network(&mut broker.network).await.event,
+            NetworkEvent::AgentUp
+        );
+        mesh.set_network(Some(SIM_NETWORK));
+        let status = next_network(&mut broker.network).await;
+        assert_eq!(
+            status.event,
+            NetworkEvent::Role {
+                from: DeviceRole::Detached,
+                to: DeviceRole::Leader
+            }
+        );
+        assert!(status.is_mesh_up());
+        assert_eq!(next_registration(&mut broker.status).await.eui, late.eui);
+    }
+
+    #[actix::test]
+    async fn check_sim_service_discovery() {
+        let mesh = SimMesh::new(20);
+        let node = super::VirtualNode {
+            service: Some(5700),
+            ..mesh.virtual_node(0xc001, "SimBasil")
+        };
+        let other = mesh.virtual_node(0xc002, "NotAPlant");
+        let config = crate::BrokerConfig {
+            discovery: crate::NodeDiscovery::Service,
+            ..Default::default()
+        };
+        let mut broker = SimBroker::start(&mesh, Duration::from_millis(500), config).await;
+
+        mesh.join(other.clone()).await.expect("Unable to join node");
+        mesh.join(node.clone()).await.expect("Unable to join node");
+        let services = mesh.client().get_plant_services().await.unwrap();
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].port, 5700);
+
+        // Only the node advertising the service is contacted, on its port
+        let reg = next_registration(&mut broker.status).await;
+        assert_eq!((reg.eui, reg.addr), (node.eui, node.ip));
+        next_reading_from(&mut broker.readings, node.ip).await;
+
+        // The other device is never tracked
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        while let Ok(status) = broker.status.try_recv() {
+            if let NodeStatus::Lifecycle(NodeTransition { addr, .. }) = status {
+                assert_ne!(addr, other.ip, "Device without a service was tracked");
+            }
+        }
+    }
+
+    #[actix::test]
+    async fn check_sim_node_moves() {
+        let mesh = SimMesh::new(16);
+        let node = mesh.virtual_node(0xc001, "SimPothos");
+        let moved = mesh.node_ip(0x0200);
+        let config = crate::BrokerConfig {
+            node_timeout: Duration::from_secs(2),
+            ..Default::default()
+        };
+        let mut broker = SimBroker::start(&mesh, Duration::from_millis(500), config).await;
+
+        mesh.join(node.clone()).await.expect("Unable to join node");
+        next_registration(&mut broker.status).await;
+        next_reading_from(&mut broker.readings, node.ip).await;
+
+        // A node that re-parents is only moved, it keeps its observation
+        mesh.reparent(node.rloc, 0xc401)
+            .expect("Unable to re-parent node");
+        let moved_to = loop {
+            match tokio::time::timeout(Duration::from_secs(20), broker.status.recv())
+                .await
+                .expect("Timed out waiting for move")
+                .expect("Status channel closed")
+            {
+                NodeStatus::Moved(moved_to) => break moved_to,
+                NodeStatus::Lifecycle(_) => {}
+                other => panic!("Unexpected status {other:?}"),
+            }
+        };
+        assert_eq!(
+            moved_to,
+            NodeMove {
+                eui: node.eui,
+                from: (node.rloc, node.ip),
+                to: (0xc401, node.ip),
+            }
+        );
+        next_reading_from(&mut broker.readings, node.ip).await;
+
+        // A node at a new addr is registered there, and its handler moves
+        // with it instead of being left to time out
+        mesh.change_addr(0xc401, moved)
+            .await
+            .expect("Unable to move node");
+        let (mut moved_to, mut registered) = (None, None);
+        while moved_to.is_none() || registered.is_none() {
+            match tokio::time::timeout(Duration::from_secs(20), broker.status.recv())
+                .await
+                .expect("Timed out waiting for move")
+                .expect("Status channel closed")
+            {
+                NodeStatus::Moved(m) => moved_to = Some((m.from, m.to)),
+                NodeStatus::Registration(reg) => registered = Some((reg.eui, reg.addr)),
+                NodeStatus::Lifecycle(_) => {}
+                other => panic!("Unexpected status {other:?}"),
+            }
+        }
+        assert_eq!(moved_to, Some(((0xc401, node.ip), (0xc401, moved))));
+        assert_eq!(registered, Some((node.eui, moved)));
+        next_reading_from(&mut broker.readings, moved).await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        while let Ok(status) = broker.status.try_recv() {
+            assert!(
+                !matches!(status, NodeStatus::Termination(_)),
+                "Unexpected status {status:?}"
+            );
+        }
+        next_reading_from(&mut broker.readings, moved).await;
+    }
+
+    #[actix::test]
+    async fn check_sim_network_change() {
+        let mesh = SimMesh::new(17);
+        let node = mesh.virtual_node(0xc001, "SimMonstera");
+        let config = crate::BrokerConfig {
+            receive_mode: crate::ReceiveMode::Shared(crate::DEFAULT_SHARED_RCV_PORT),
+            node_timeout: Duration::from_secs(2),
+            ..Default::default()
+        };
+        let mut broker = SimBroker::start(&mesh, Duration::from_millis(500), config).await;
+
+        mesh.join(node.clone()).await.expect("Unable to join node");
+        next_registration(&mut broker.status).await;
+        next_reading_from(&mut broker.readings, node.ip).await;
+
+        // The broker moves to the new prefix and registers the node again
+        // from there, the node is not left reporting to the old socket
+        let (from, to) = (
+            mesh.omr_ip(),
+            Ipv4Addr::new(127, 117, 0, 1).to_ipv6_mapped(),
+        );
+        mesh.change_omr_ip(to);
+        let (mut change, mut registered) = (None, None);
+        while change.is_none() || registered.is_none() {
+            match tokio::time::timeout(Duration::from_secs(20), broker.status.recv())
+                .await
+                .expect("Timed out waiting for network change")
+                .expect("Status channel closed")
+            {
+                NodeStatus::NetworkChange(c) => change = Some((c.from, c.to)),
+                NodeStatus::Registration(reg) => registered = Some(reg.eui),
+                NodeStatus::Lifecycle(_) => {}
+                other => panic!("Unexpected status {other:?}"),
+            }
+        }
+        assert_eq!(change, Some((Some(from), to)));
+        assert_eq!(registered, Some(node.eui));
+        next_reading_from(&mut broker.readings, node.ip).await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        while let Ok(status) = broker.status.try_recv() {
+            assert!(
+                !matches!(status, NodeStatus::Termination(_)),
+                "Unexpected status {status:?}"
+            );
+        }
+        next_reading_from(&mut broker.readings, node.ip).await;
+    }
+
+    #[actix::test]
+    async fn check_sim_concurrent_registration() {
+        let mesh = SimMesh::new(23);
+        let nodes: Vec<_> = [
+            (0xc001, "SimFicus"),
+            (0xc002, "SimIvy"),
+            (0xc003, "SimPothos"),
+        ]
+        .into_iter()
+        .map(|(rloc, name)| mesh.virtual_node(rloc, name))
+        .collect();
+        let hung = mesh.virtual_node(0xc004, "SimBonsai");
+        let mut scenario = vec![SimStep::Join(hung.clone()), SimStep::Hang(hung.rloc)];
+        scenario.extend(nodes.iter().cloned().map(SimStep::Join));
+        mesh.run(&scenario).await.expect("Unable to run scenario");
+
+        // Polled only once in the test, the hung node must be retried on
+        // its backoff and must not hold up the others
+        let config = crate::BrokerConfig {
+            max_registrations: 2,
+            registration_timeout: Duration::from_secs(2),
+            backoff: crate::Backoff {
+                initial: Duration::from_secs(1),
+                max: Duration::from_secs(2),
+            },
+            ..Default::default()
+        };
+        let mut broker = SimBroker::start(&mesh, Duration::from_secs(60), config).await;
+
+        for node in &nodes {
+            next_reading_from(&mut broker.readings, node.ip).await;
+        }
+        let mut transition = next_transition(&mut broker.status, hung.ip).await;
+        while transition.to != Lifecycle::Stale {
+            transition = next_transition(&mut broker.status, hung.ip).await;
+        }
+        let transition = next_transition(&mut broker.status, hung.ip).await;
+        assert_eq!(
+            (transition.from, transition.to),
+            (Some(Lifecycle::Stale), Lifecycle::Registering)
+        );
+    }
+
+    #[actix::test]
+    async fn check_sim_node_leaves_queued() {
+        let mesh = SimMesh::new(24);
+        let hung = mesh.virtual_node(0xc001, "SimBonsai");
+        let node = mesh.virtual_node(0xc002, "SimFern");
+        mesh.run(&[SimStep::Join(hung.clone()), SimStep::Hang(hung.rloc)])
+            .await
+            .expect("Unable to run scenario");
+
+        // One registration at a time, the hung node holds up the others
+        // until it times out
+        let config = crate::BrokerConfig {
+            max_registrations: 1,
+            registration_timeout: Duration::from_secs(3),
+            backoff: crate::Backoff {
+                initial: Duration::from_secs(10),
+                max: Duration::from_secs(10),
+            },
+            ..Default::default()
+        };
+        let mut broker = SimBroker::start(&mesh, Duration::from_millis(500), config).await;
+        expect_transitions(
+            &mut broker.status,
+            hung.ip,
+            &[
+                (None, Lifecycle::Discovered),
+                (Some(Lifecycle::Discovered), Lifecycle::Registering),
+            ],
+        )
+        .await;
+
+        mesh.join(node.clone()).await.expect("Unable to join node");
+        expect_transitions(
+            &mut broker.status,
+            node.ip,
+            &[
+                (None, Lifecycle::Discovered),
+                (Some(Lifecycle::Discovered), Lifecycle::Registering),
+            ],
+        )
+        .await;
+
+        // Polls while its registration is queued leave the node to it, the
+        // attempt fails before the node is evicted
+        mesh.leave(node.rloc).expect("Unable to remove node");
+        expect_transitions(
+            &mut broker.status,
+            node.ip,
+            &[
+                (Some(Lifecycle::Registering), Lifecycle::Stale),
+                (Some(Lifecycle::Stale), Lifecycle::Offline),
+            ],
+        )
+        .await;
+    }
+}
@@ -0,0 +1,107 @@
+use pmind_broker::{NodeStatus, SimMesh, SimStep, SIM_NETWORK};
+use tokio::time::Duration;
+
+#[actix::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    log::info!("Initializing simulated mesh & broker");
+
+    let mesh = SimMesh::new(1);
+    let broker_handle =
+        pmind_broker::broker_with_client(Duration::from_secs(2), 500, Box::new(mesh.client()))
+            .await
+            .inspect_err(|e| {
+                log::error!("Error creating broker & handle {e:}");
+            })?;
+
+    let (sensor_stream_tx, mut sensor_stream_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (node_state_tx, mut node_state_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (network_tx, mut network_rx) = tokio::sync::mpsc::unbounded_channel();
+
+    broker_handle
+        .send(pmind_broker::ClientSubscribe {
+            id: 0,
+            sensor_readings: sensor_stream_tx,
+            node_status: node_state_tx,
+            classes: pmind_broker::SensorClass::ALL.to_vec(),
+            network_status: Some(network_tx),
+        })
+        .await
+        .inspect_err(|e| {
+            log::error!("Error sending client subscribe request {e:}");
+        })??;
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(reading) = sensor_stream_rx.recv() => {
+                    log::info!("Reading from {}: {:?}", reading.addr, reading.data);
+                }
+                Some(status) = node_state_rx.recv() => match status {
+                    NodeStatus::Registration(reg) => {
+                        log::info!(
+                            "Registered {} ({}) eui {:02x?} at {} with sensors {:?}",
+                            reg.name,
+                            reg.species,
+                            reg.eui,
+                            reg.addr,
+                            reg.capabilities.sensors
+                        );
+                    }
+                    NodeStatus::Termination((addr, state)) => {
+                        log::info!("Lost node at {addr}: {state:?}");
+                    }
+                    NodeStatus::Lifecycle(transition) => {
+                        log::info!(
+                            "Node {:#06x} at {} is {}",
+                            transition.rloc,
+                            transition.addr,
+                            transition.to.name()
+                        );
+                    }
+                    NodeStatus::Moved(moved) => {
+                        log::info!("Node {:02x?} moved from {:?} to {:?}", moved.eui, moved.from, moved.to);
+                    }
+                    NodeStatus::NetworkChange(change) => {
+                        log::info!("Broker moved from {:?} to {} on {}", change.from, change.to, change.prefix);
+                    }
+                    NodeStatus::LinkMetrics(link) => {
+                        log::info!("Node {:02x?} link {:?}", link.eui, link.metrics);
+                    }
+                },
+                Some(status) = network_rx.recv() => {
+                    log::info!("Network {:?}, mesh up: {}", status.event, status.is_mesh_up());
+                }
+                else => break,
+            }
+        }
+    });
+
+    let jade = mesh.virtual_node(0xc001, "Jade");
+    let fern = mesh.virtual_node(0xc002, "Fern");
+    let pothos = mesh.virtual_node(0xc003, "Pothos");
+
+    mesh.run(&[
+        SimStep::Join(jade.clone()),
+        SimStep::Join(fern.clone()),
+        SimStep::Wait(Duration::from_secs(10)),
+        SimStep::Join(pothos.clone()),
+        SimStep::ChangeAddr(fern.rloc, mesh.node_ip(0x0200)),
+        SimStep::Wait(Duration::from_secs(10)),
+        SimStep::Reparent(fern.rloc, 0xc402),
+        SimStep::Wait(Duration::from_secs(10)),
+        SimStep::Network(None),
+        SimStep::Wait(Duration::from_secs(10)),
+        SimStep::Network(Some(SIM_NETWORK)),
+        SimStep::Wait(Duration::from_secs(10)),
+        SimStep::Silence(jade.rloc),
+        SimStep::Leave(pothos.rloc),
+    ])
+    .await?;
+
+    // Block until SIGINT, silent nodes time out in the background
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
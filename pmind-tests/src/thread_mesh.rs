@@ -21,16 +21,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             id: 0,
             sensor_readings: sensor_stream_tx,
             node_status: node_state_tx,
+            classes: pmind_broker::SensorClass::ALL.to_vec(),
+            network_status: None,
         })
         .await
-        .map_err(|e| {
+        .inspect_err(|e| {
             log::error!("Error sending client subscribe request {e:}");
-            e
         })??;
 
-    loop {
-        // todo block on broker or SIGINT / SIGQUIT / SIGSTP from stdin
-    }
+    // Block until SIGINT, the broker tasks run in the background
+    tokio::signal::ctrl_c().await?;
 
     Ok(())
 }
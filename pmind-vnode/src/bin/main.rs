@@ -0,0 +1,225 @@
+use std::net::Ipv6Addr;
+use tokio::time::Duration;
+
+use pmind_vnode::{
+    Eui, Generator, GrowthStage, NodeConfig, Psk, ReadingSource, TransmissionParams, VirtualNode,
+    WireFormat,
+};
+
+const USAGE: &str = "Usage: pmind-vnode [OPTIONS]
+
+Options:
+    --addr <IPV6>         Addr to bind port 1212 on [default: ::]
+    --count <N>           Number of nodes to run, bound to consecutive
+                          addrs starting at --addr [default: 1]
+    --name <NAME>         Plant name to register with [default: SirPots]
+    --pot <N>             Pot number to register with [default: 666]
+    --species <NAME>      Plant species to register with [default: Jade]
+    --stage <STAGE>       Growth stage to register with, one of sprouting,
+                          seedling, vegetative, reproductive or senescence
+                          [default: vegetative]
+    --eui <HEX>           6 byte EUI, e.g. 6055f9f70778; incremented per
+                          node [default: derived from the process id]
+    --interval <SECS>     Seconds between soil readings [default: 5]
+    --light-interval <SECS>
+                          Seconds between light readings [default: 30]
+    --env-interval <SECS> Seconds between env readings [default: 60]
+    --ack-timeout <MS>    Initial timeout before retransmitting an
+                          unacknowledged notification [default: 2000]
+    --format <FORMAT>     Reading encoding, json or cbor [default: cbor]
+    --psk <HEX>           16 byte key shared with the broker, every node
+                          run uses the same key [default: none, serve in
+                          the clear]
+    --replay <FILE>       Replay SensorReading JSON lines from FILE instead
+                          of generating readings
+    --moisture <MIN:MAX>  Generated soil moisture range [default: 300:900]
+    --temp <MIN:MAX>      Generated soil temp range [default: 18:26]
+    --lux <MIN:MAX>       Generated lux range [default: 50:400]
+    --no-light            Generate readings without a light sensor
+    --seed <N>            Generator seed [default: derived from the process id]
+    -h, --help            Print this message";
+
+struct Args {
+    addr: Ipv6Addr,
+    count: u16,
+    name: String,
+    pot_num: u32,
+    species: String,
+    growth_stage: GrowthStage,
+    eui: Eui,
+    interval: Duration,
+    light_interval: Duration,
+    env_interval: Duration,
+    transmission: TransmissionParams,
+    format: WireFormat,
+    psk: Option<Psk>,
+    replay: Option<String>,
+    generator: Generator,
+    seed: u64,
+}
+
+fn parse_range<T: std::str::FromStr>(val: &str) -> Result<(T, T), String> {
+    let (min, max) = val
+        .split_once(':')
+        .ok_or(format!("Expected MIN:MAX, got {val}"))?;
+    Ok((
+        min.parse().map_err(|_| format!("Invalid min {min}"))?,
+        max.parse().map_err(|_| format!("Invalid max {max}"))?,
+    ))
+}
+
+fn parse_secs(val: &str) -> Result<Duration, String> {
+    let secs: f64 = val.parse().map_err(|_| format!("Invalid interval {val}"))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("Invalid interval {val}"))
+}
+
+fn parse_eui(val: &str) -> Result<Eui, String> {
+    let raw = u64::from_str_radix(val, 16).map_err(|_| format!("Invalid EUI {val}"))?;
+    if val.len() != 12 {
+        return Err(format!("EUI {val} must be 12 hex digits"));
+    }
+    let mut eui = [0u8; 6];
+    eui.copy_from_slice(&raw.to_be_bytes()[2..]);
+    Ok(eui)
+}
+
+fn parse_psk(val: &str) -> Result<Psk, String> {
+    pmind_vnode::parse_psk(val).ok_or(format!("PSK {val} must be 32 hex digits"))
+}
+
+fn parse_args() -> Result<Args, String> {
+    let pid = std::process::id();
+    let mut args = Args {
+        addr: Ipv6Addr::UNSPECIFIED,
+        count: 1,
+        name: "SirPots".to_string(),
+        pot_num: 666,
+        species: "Jade".to_string(),
+        growth_stage: GrowthStage::Vegetative,
+        // Locally administered prefix so it never collides with real nodes
+        eui: [
+            0x02,
+            0x00,
+            0x00,
+            (pid >> 16) as u8,
+            (pid >> 8) as u8,
+            pid as u8,
+        ],
+        interval: Duration::from_secs(5),
+        light_interval: Duration::from_secs(30),
+        env_interval: Duration::from_secs(60),
+        transmission: TransmissionParams::default(),
+        format: WireFormat::Cbor,
+        psk: None,
+        replay: None,
+        generator: Generator::default(),
+        seed: pid as u64,
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(flag) = iter.next() {
+        if flag == "-h" || flag == "--help" {
+            println!("{USAGE}");
+            std::process::exit(0);
+        }
+        if flag == "--no-light" {
+            args.generator.lux = None;
+            continue;
+        }
+
+        let val = iter.next().ok_or(format!("Missing value for {flag}"))?;
+        match flag.as_str() {
+            "--addr" => args.addr = val.parse().map_err(|_| format!("Invalid addr {val}"))?,
+            "--count" => args.count = val.parse().map_err(|_| format!("Invalid count {val}"))?,
+            "--name" => args.name = val,
+            "--pot" => args.pot_num = val.parse().map_err(|_| format!("Invalid pot {val}"))?,
+            "--species" => args.species = val,
+            "--stage" => {
+                args.growth_stage =
+                    GrowthStage::from_name(&val).ok_or(format!("Invalid growth stage {val}"))?
+            }
+            "--eui" => args.eui = parse_eui(&val)?,
+            "--interval" => args.interval = parse_secs(&val)?,
+            "--light-interval" => args.light_interval = parse_secs(&val)?,
+            "--env-interval" => args.env_interval = parse_secs(&val)?,
+            "--ack-timeout" => {
+                args.transmission.ack_timeout_ms = val
+                    .parse()
+                    .map_err(|_| format!("Invalid ack timeout {val}"))?
+            }
+            "--format" => {
+                args.format = match val.as_str() {
+                    "json" => WireFormat::Json,
+                    "cbor" => WireFormat::Cbor,
+                    _ => return Err(format!("Invalid format {val}")),
+                }
+            }
+            "--psk" => args.psk = Some(parse_psk(&val)?),
+            "--replay" => args.replay = Some(val),
+            "--moisture" => args.generator.moisture = parse_range(&val)?,
+            "--temp" => args.generator.temp = parse_range(&val)?,
+            "--lux" => args.generator.lux = Some(parse_range(&val)?),
+            "--seed" => args.seed = val.parse().map_err(|_| format!("Invalid seed {val}"))?,
+            _ => return Err(format!("Unknown option {flag}")),
+        }
+    }
+
+    if args.count > 1 && args.addr.is_unspecified() {
+        return Err("--count > 1 needs a starting --addr, each node binds port 1212".to_string());
+    }
+    Ok(args)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let args = parse_args().map_err(|e| format!("{e}\n\n{USAGE}"))?;
+
+    let mut nodes = tokio::task::JoinSet::new();
+    for i in 0..args.count {
+        let addr = Ipv6Addr::from(u128::from(args.addr) + i as u128);
+        let mut eui = args.eui;
+        eui[5] = eui[5].wrapping_add(i as u8);
+        let name = if args.count > 1 {
+            format!("{}-{i}", args.name)
+        } else {
+            args.name.clone()
+        };
+
+        let source = match &args.replay {
+            Some(path) => ReadingSource::replay_from_file(path)?,
+            None => {
+                // Seed each node differently so they do not report in lockstep
+                let mut generator = args.generator.clone();
+                generator.reseed(args.seed.wrapping_add(i as u64));
+                ReadingSource::Generator(generator)
+            }
+        };
+
+        let config = NodeConfig {
+            addr,
+            eui,
+            name,
+            pot_num: args.pot_num,
+            species: args.species.clone(),
+            growth_stage: args.growth_stage,
+            interval: args.interval,
+            light_interval: args.light_interval,
+            env_interval: args.env_interval,
+            transmission: args.transmission,
+            format: args.format,
+            psk: args.psk,
+        };
+        nodes.spawn(VirtualNode::new(config, source).run());
+    }
+
+    // Like the firmware, a node exits on socket error; keep the rest running
+    while let Some(res) = nodes.join_next().await {
+        if let Ok(Err(e)) = res {
+            log::error!("Virtual node exited: {e:}");
+        }
+    }
+
+    Ok(())
+}
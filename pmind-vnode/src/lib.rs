@@ -0,0 +1,596 @@
+//! Host Linux stand-in for a plant-minder sensor node.
+//!
+//! A [`VirtualNode`] speaks the same node protocol as
+//! `Esp32Platform::coap_server_event_loop` in `pmindp-esp32-thread`:
+//! it binds UDP port [`BOUND_PORT`], lists its resources and sensors on
+//! `/.well-known/core`, answers the CoAP Observe registrations from the
+//! broker with the node EUI followed by the plant name, and then notifies
+//! the observers of each [`SensorClass`] resource with that class' part of
+//! the latest `SensorReading`, in the configured [`WireFormat`], each class
+//! at its own interval. Nodes given a pre-shared key protect every exchange
+//! after discovery, like provisioned firmware.
+//! Readings come from a [`ReadingSource`], either replayed from a file or
+//! produced by a [`Generator`].
+//!
+//! This is meant for load testing the broker and demoing the TUI without
+//! hardware; it does not join a Thread mesh, so the broker must be pointed
+//! at it via an `OtClient` that reports the node addrs
+
+use coap_lite::{ContentFormat, Packet};
+use pmindp_protocol::{
+    oscore::SESSION_NONCE_LEN, requested_version, Link, ObserverRegistry, OscoreServer,
+    RequestOutcome, DEFAULT_MAX_AGE,
+};
+use pmindp_sensor::{
+    Light, NodeIdentity, ProtocolHeader, SensorClass, SensorReading, SensorType, Soil,
+};
+use std::{
+    net::{Ipv6Addr, SocketAddr, SocketAddrV6},
+    path::Path,
+};
+use thiserror::Error;
+use tokio::{net::UdpSocket, time::Duration};
+
+pub use pmindp_protocol::{oscore::parse_psk, Psk, TransmissionParams};
+pub use pmindp_sensor::{GrowthStage, WireFormat};
+
+/// Port the node serves CoAP on and sends notifications from, same as the
+/// firmware
+pub const BOUND_PORT: u16 = pmindp_protocol::NODE_COAP_PORT;
+
+pub type Eui = [u8; 6];
+
+#[derive(Error, Debug)]
+pub enum VirtualNodeError {
+    #[error("I/O Error")]
+    Io(#[from] std::io::Error),
+    #[error("Replay line {0} is not a SensorReading: {1}")]
+    Replay(usize, serde_json::Error),
+    #[error("Replay file has no readings")]
+    EmptyReplay,
+    #[error("Json Error")]
+    Json(#[from] serde_json::Error),
+    #[error("Encoding Error {0}")]
+    Wire(#[from] pmindp_sensor::WireError),
+}
+
+/// Identity and timing of a single virtual node
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// Addr to bind [`BOUND_PORT`] on, `::` to match the firmware
+    pub addr: Ipv6Addr,
+    pub eui: Eui,
+    pub name: String,
+    /// Rest of the plant config registered, like the firmware's `PlantConfig`
+    pub pot_num: u32,
+    pub species: String,
+    pub growth_stage: GrowthStage,
+    /// How often to push a soil reading once the handshake is complete,
+    /// each soil reading also advances the [`ReadingSource`]
+    pub interval: Duration,
+    /// How often to push the light part of the latest reading
+    pub light_interval: Duration,
+    /// How often to push the env part of the latest reading
+    pub env_interval: Duration,
+    /// Retransmission timeouts for the confirmable notifications
+    pub transmission: TransmissionParams,
+    /// Encoding of the readings sent
+    pub format: WireFormat,
+    /// Key shared with the broker, `None` to serve in the clear like
+    /// unprovisioned firmware
+    pub psk: Option<Psk>,
+}
+
+/// Produces readings that random walk within configurable ranges
+#[derive(Debug, Clone)]
+pub struct Generator {
+    pub moisture: (u16, u16),
+    pub temp: (f32, f32),
+    /// Range for lux, `None` to report no light sensor
+    pub lux: Option<(f32, f32)>,
+    state: u64,
+    last: Option<SensorReading>,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new(0x5eed)
+    }
+}
+
+impl Generator {
+    /// Generator with the default ranges, seeded so that many nodes
+    /// started at once do not all report the same values
+    pub fn new(seed: u64) -> Self {
+        Self {
+            moisture: (300, 900),
+            temp: (18.0, 26.0),
+            lux: Some((50.0, 400.0)),
+            // xorshift state must never be zero
+            state: seed | 1,
+            last: None,
+        }
+    }
+
+    /// Restart the generator from `seed`, keeping the configured ranges
+    pub fn reseed(&mut self, seed: u64) {
+        self.state = seed | 1;
+        self.last = None;
+    }
+
+    /// xorshift64, good enough for fake sensor data
+    fn next_unit(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Step `prev` by up to 5% of the range in either direction
+    fn walk(&mut self, prev: Option<f32>, (min, max): (f32, f32)) -> f32 {
+        let span = max - min;
+        let next = match prev {
+            Some(p) => p + (self.next_unit() - 0.5) * span * 0.1,
+            None => min + self.next_unit() * span,
+        };
+        next.clamp(min, max)
+    }
+
+    pub fn next_reading(&mut self) -> SensorReading {
+        let last = self.last;
+        let moisture = self.walk(
+            last.map(|l| l.soil.moisture as f32),
+            (self.moisture.0 as f32, self.moisture.1 as f32),
+        );
+        let temp = self.walk(last.map(|l| l.soil.temp), self.temp);
+        let light = self.lux.map(|range| {
+            let lux = self.walk(last.and_then(|l| l.light).map(|l| l.lux), range);
+            Light {
+                // Full spectrum tracks lux closely on the TSL2591
+                fs: (lux * 42.0) as u16,
+                lux,
+            }
+        });
+
+        let reading = SensorReading {
+            soil: Soil {
+                moisture: moisture as u16,
+                temp,
+            },
+            light,
+            gas: None,
+            ts: 0,
+        };
+        self.last = Some(reading);
+        reading
+    }
+}
+
+/// Where a [`VirtualNode`] gets its readings from
+#[derive(Debug, Clone)]
+pub enum ReadingSource {
+    /// Replay recorded readings in order, looping at the end
+    Replay {
+        readings: Vec<SensorReading>,
+        idx: usize,
+    },
+    Generator(Generator),
+}
+
+impl ReadingSource {
+    pub fn replay(readings: Vec<SensorReading>) -> Result<Self, VirtualNodeError> {
+        if readings.is_empty() {
+            return Err(VirtualNodeError::EmptyReplay);
+        }
+        Ok(Self::Replay { readings, idx: 0 })
+    }
+
+    /// Load a replay file of one `SensorReading` JSON object per line, the
+    /// format subscribers receive readings in. Blank lines are skipped
+    pub fn replay_from_file(path: impl AsRef<Path>) -> Result<Self, VirtualNodeError> {
+        let contents = std::fs::read_to_string(path)?;
+        ReadingSource::replay(ReadingSource::parse_replay(&contents)?)
+    }
+
+    fn parse_replay(contents: &str) -> Result<Vec<SensorReading>, VirtualNodeError> {
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty())
+            .map(|(i, l)| {
+                serde_json::from_str::<SensorReading>(l)
+                    .map_err(|e| VirtualNodeError::Replay(i + 1, e))
+            })
+            .collect()
+    }
+
+    /// Sensors a node with these readings would have been built with, as
+    /// listed in its `/.well-known/core`
+    pub fn sensors(&self) -> Vec<SensorType> {
+        let (light, gas) = match self {
+            Self::Replay { readings, .. } => (
+                readings.iter().any(|r| r.light.is_some()),
+                readings.iter().any(|r| r.gas.is_some()),
+            ),
+            Self::Generator(g) => (g.lux.is_some(), false),
+        };
+        let mut sensors = vec![SensorType::Atsamd10];
+        if light {
+            sensors.push(SensorType::Tsl2591);
+        }
+        if gas {
+            sensors.push(SensorType::Bme680);
+        }
+        sensors
+    }
+
+    pub fn next_reading(&mut self) -> SensorReading {
+        match self {
+            Self::Replay { readings, idx } => {
+                let reading = readings[*idx % readings.len()];
+                *idx = (*idx + 1) % readings.len();
+                reading
+            }
+            Self::Generator(g) => g.next_reading(),
+        }
+    }
+}
+
+fn content_format(format: WireFormat) -> ContentFormat {
+    match format {
+        WireFormat::Json => ContentFormat::ApplicationJSON,
+        WireFormat::Cbor => ContentFormat::ApplicationCBOR,
+    }
+}
+
+/// Milliseconds since the epoch, the clock retransmissions run on
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+pub struct VirtualNode {
+    config: NodeConfig,
+    source: ReadingSource,
+}
+
+impl VirtualNode {
+    pub fn new(config: NodeConfig, source: ReadingSource) -> Self {
+        Self { config, source }
+    }
+
+    /// Bind [`BOUND_PORT`] and serve the node protocol until a socket error
+    pub async fn run(mut self) -> Result<(), VirtualNodeError> {
+        let socket = UdpSocket::bind(SocketAddrV6::new(self.config.addr, BOUND_PORT, 0, 0)).await?;
+        log::info!(
+            "Virtual node {} eui {:02x?} listening on {}",
+            self.config.name,
+            self.config.eui,
+            socket.local_addr()?
+        );
+
+        let mut buffer = [0u8; 512];
+        let eui = self.config.eui;
+        let sensors = self.source.sensors();
+        let classes: Vec<_> = SensorClass::ALL
+            .into_iter()
+            .filter(|c| sensors.iter().any(|s| s.class() == *c))
+            .collect();
+        let links: Vec<_> = classes
+            .iter()
+            .map(|class| {
+                Link::new(class.name())
+                    .resource_types(
+                        sensors
+                            .iter()
+                            .filter(|s| s.class() == *class)
+                            .map(|s| s.name()),
+                    )
+                    .content_format(content_format(self.config.format))
+                    .observable()
+            })
+            .collect();
+        let mut observers = classes
+            .iter()
+            .fold(
+                ObserverRegistry::new(SensorClass::Soil.name(), DEFAULT_MAX_AGE),
+                |registry, class| registry.resource(class.name(), DEFAULT_MAX_AGE),
+            )
+            .confirmable(
+                self.config.transmission,
+                u32::from_be_bytes([eui[2], eui[3], eui[4], eui[5]]),
+            )
+            .discoverable(&links);
+        let mut security = self.config.psk.map(|psk| {
+            let mut nonce = [0u8; SESSION_NONCE_LEN];
+            getrandom::fill(&mut nonce).ok();
+            OscoreServer::new(psk, &eui, nonce, &self.config.transmission)
+        });
+        let mut soil_tick = tokio::time::interval(self.config.interval);
+        let mut light_tick = tokio::time::interval(self.config.light_interval);
+        let mut env_tick = tokio::time::interval(self.config.env_interval);
+        let mut latest = None;
+
+        loop {
+            let retransmit = observers.next_deadline().map(|deadline| {
+                tokio::time::Instant::now()
+                    + Duration::from_millis(deadline.saturating_sub(now_ms()))
+            });
+            tokio::select! {
+                _ = tokio::time::sleep_until(retransmit.unwrap_or_else(tokio::time::Instant::now)),
+                    if retransmit.is_some() => {
+                    for (observer, notification) in observers.poll(now_ms()) {
+                        VirtualNode::send(&socket, &mut security, &notification, observer)
+                            .await?;
+                    }
+                }
+                _ = soil_tick.tick() => {
+                    if !observers.is_observed(SensorClass::Soil.name()) {
+                        continue;
+                    }
+                    let reading = *latest.insert(self.source.next_reading());
+                    self.notify(&socket, &mut observers, &mut security, SensorClass::Soil, &reading)
+                        .await?;
+                }
+                _ = light_tick.tick() => {
+                    if let Some(reading) = latest {
+                        self.notify(&socket, &mut observers, &mut security, SensorClass::Light, &reading)
+                            .await?;
+                    }
+                }
+                _ = env_tick.tick() => {
+                    if let Some(reading) = latest {
+                        self.notify(&socket, &mut observers, &mut security, SensorClass::Env, &reading)
+                            .await?;
+                    }
+                }
+                res = socket.recv_from(&mut buffer) => {
+                    let (len, from) = res?;
+                    if len == 0 {
+                        continue;
+                    }
+                    self.handle_packet(&socket, &mut observers, &mut security, &buffer[..len], from)
+                        .await?;
+                }
+            }
+        }
+    }
+
+    /// Notify the observers of `class` with its part of `reading`, if any
+    async fn notify(
+        &self,
+        socket: &UdpSocket,
+        observers: &mut ObserverRegistry<SocketAddr>,
+        security: &mut Option<OscoreServer<SocketAddr>>,
+        class: SensorClass,
+        reading: &SensorReading,
+    ) -> Result<(), VirtualNodeError> {
+        let format = self.config.format;
+        let Some(sensor_data) = reading.encode_class(class, format).transpose()? else {
+            return Ok(());
+        };
+        for (observer, notification) in
+            observers.notify(class.name(), &sensor_data, content_format(format), now_ms())
+        {
+            VirtualNode::send(socket, security, &notification, observer)
+                .await
+                .inspect_err(|e| {
+                    log::error!("Error sending, resetting due to {e:?}");
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Send a notification, protected if the node has a key. Observers
+    /// from a session that was since replaced get nothing
+    async fn send(
+        socket: &UdpSocket,
+        security: &mut Option<OscoreServer<SocketAddr>>,
+        notification: &Packet,
+        observer: SocketAddr,
+    ) -> Result<(), VirtualNodeError> {
+        let notification = match security.as_mut() {
+            Some(security) => security.protect(notification),
+            None => Some(notification.clone()),
+        };
+        let Some(notification) = notification else {
+            return Ok(());
+        };
+        let Ok(packet) = notification.to_bytes() else {
+            log::error!("Unable to encode notification");
+            return Ok(());
+        };
+        socket.send_to(&packet, observer).await?;
+        Ok(())
+    }
+
+    /// Serve an Observe request, anything that is not CoAP is dropped
+    async fn handle_packet(
+        &self,
+        socket: &UdpSocket,
+        observers: &mut ObserverRegistry<SocketAddr>,
+        security: &mut Option<OscoreServer<SocketAddr>>,
+        buffer: &[u8],
+        from: SocketAddr,
+    ) -> Result<(), VirtualNodeError> {
+        let Ok(packet) = Packet::from_bytes(buffer) else {
+            log::info!("Dropping non-CoAP {:02x?} from {:?}", buffer, from);
+            return Ok(());
+        };
+
+        let mut serve = |packet: &Packet| {
+            let header = ProtocolHeader::negotiate(requested_version(packet));
+            let (record, record_format) = NodeIdentity {
+                header,
+                eui: self.config.eui,
+                name: self.config.name.clone(),
+                pot_num: self.config.pot_num,
+                species: self.config.species.clone(),
+                growth_stage: self.config.growth_stage,
+                firmware: env!("CARGO_PKG_VERSION").to_string(),
+            }
+            .reply();
+            observers.handle_request(
+                packet,
+                from,
+                &record,
+                record_format.map(content_format),
+                now_ms(),
+            )
+        };
+        let (outcome, response) = match security.as_mut() {
+            Some(security) => security.handle(&packet, from, now_ms(), serve),
+            None => {
+                let (outcome, response) = serve(&packet);
+                (Some(outcome), response)
+            }
+        };
+        log::info!(
+            "Received CoAP {:?} from {}: {:?}",
+            packet.header.code,
+            from,
+            outcome
+        );
+
+        if let Some(response) = response {
+            if let Ok(response) = response.to_bytes() {
+                socket.send_to(&response, from).await?;
+            }
+        }
+        if outcome == Some(RequestOutcome::Registered) {
+            log::info!("Handshake complete, observer {from}");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coap_lite::Packet;
+    use pmindp_protocol::{discovery, Notification, ObserveClient};
+    use pmindp_sensor::{NodeIdentity, SensorClass, SensorType, Soil, PROTOCOL_VERSION};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use tokio::{net::UdpSocket, time::Duration};
+
+    use super::{Generator, NodeConfig, ReadingSource, VirtualNode, BOUND_PORT};
+
+    const REPLAY: &str = r#"{"soil":{"moisture":956,"temp":21.5},"light":{"fs":3592,"lux":84.9},"gas":null}
+
+{"soil":{"moisture":941,"temp":21.4},"light":null,"gas":null}
+"#;
+
+    #[tokio::test]
+    async fn check_vnode_handshake_and_replay() {
+        let node_ip = Ipv4Addr::new(127, 20, 0, 2).to_ipv6_mapped();
+        let config = NodeConfig {
+            addr: node_ip,
+            eui: [0x60, 0x55, 0xf9, 0xf7, 0x07, 0x78],
+            name: "Jade".to_string(),
+            pot_num: 7,
+            species: "Crassula ovata".to_string(),
+            growth_stage: super::GrowthStage::Seedling,
+            interval: Duration::from_millis(100),
+            light_interval: Duration::from_millis(150),
+            env_interval: Duration::from_millis(150),
+            transmission: Default::default(),
+            format: super::WireFormat::Json,
+            psk: None,
+        };
+        let source = ReadingSource::replay(
+            ReadingSource::parse_replay(REPLAY).expect("Unable to parse replay"),
+        )
+        .expect("Empty replay");
+        tokio::spawn(VirtualNode::new(config, source).run());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let observer = UdpSocket::bind(SocketAddr::new(
+            Ipv4Addr::new(127, 20, 0, 1).to_ipv6_mapped().into(),
+            1254,
+        ))
+        .await
+        .expect("Unable to bind observer");
+        let mut client =
+            ObserveClient::new([0x5a; 8], 1, SensorClass::Soil.name()).version(PROTOCOL_VERSION);
+        let mut buffer = [0u8; 512];
+
+        // The replay has light readings but no gas readings
+        observer
+            .send_to(
+                &client.discover().to_bytes().expect("Bad request"),
+                SocketAddr::new(node_ip.into(), BOUND_PORT),
+            )
+            .await
+            .expect("Unable to send discovery");
+        let (len, _) = observer
+            .recv_from(&mut buffer)
+            .await
+            .expect("No discovery response");
+        let packet = Packet::from_bytes(&buffer[..len]).expect("Bad response");
+        let links = discovery::links(&packet).expect("Bad link format");
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].path, SensorClass::Soil.name());
+        assert_eq!(links[0].resource_types, [SensorType::Atsamd10.name()]);
+        assert_eq!(links[1].path, SensorClass::Light.name());
+        assert_eq!(links[1].resource_types, [SensorType::Tsl2591.name()]);
+
+        observer
+            .send_to(
+                &client.register().to_bytes().expect("Bad request"),
+                SocketAddr::new(node_ip.into(), BOUND_PORT),
+            )
+            .await
+            .expect("Unable to send registration");
+
+        let (len, _) = observer
+            .recv_from(&mut buffer)
+            .await
+            .expect("No registration response");
+        let packet = Packet::from_bytes(&buffer[..len]).expect("Bad response");
+        let Notification::Fresh(record) = client.handle_response(&packet, 0) else {
+            panic!("Registration was not accepted");
+        };
+        let identity = NodeIdentity::decode(record).expect("Bad identity");
+        assert_eq!(identity.header.version, PROTOCOL_VERSION);
+        assert_eq!(identity.eui, [0x60, 0x55, 0xf9, 0xf7, 0x07, 0x78]);
+        assert_eq!(identity.name, "Jade");
+        assert_eq!(
+            (identity.pot_num, identity.species.as_str()),
+            (7, "Crassula ovata")
+        );
+        assert_eq!(identity.firmware, env!("CARGO_PKG_VERSION"));
+
+        let mut moisture = vec![];
+        for i in 0..3 {
+            let (len, from) = observer.recv_from(&mut buffer).await.expect("No reading");
+            assert_eq!(from.port(), BOUND_PORT);
+            let packet = Packet::from_bytes(&buffer[..len]).expect("Bad notification");
+            let Notification::Fresh(data) = client.handle_response(&packet, i) else {
+                panic!("Notification was not fresh");
+            };
+            // Only soil is observed, so light notifications never arrive
+            let soil: Soil = serde_json::from_slice(data).expect("Bad soil reading");
+            moisture.push(soil.moisture);
+        }
+        assert_eq!(moisture, vec![956, 941, 956]);
+    }
+
+    #[tokio::test]
+    async fn check_generator_ranges() {
+        let mut gen = Generator::new(42);
+        gen.lux = None;
+        for _ in 0..1000 {
+            let r = gen.next_reading();
+            assert!((300..=900).contains(&r.soil.moisture));
+            assert!((18.0..=26.0).contains(&r.soil.temp));
+            assert!(r.light.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn check_replay_errors() {
+        assert!(ReadingSource::replay(vec![]).is_err());
+        assert!(ReadingSource::parse_replay("{\"soil\":{}}\n").is_err());
+    }
+}
@@ -0,0 +1,4 @@
+ALTER TABLE plants DROP COLUMN sensors;
+ALTER TABLE plants DROP COLUMN firmware;
+ALTER TABLE plants DROP COLUMN growth_stage;
+ALTER TABLE plants DROP COLUMN pot_num;
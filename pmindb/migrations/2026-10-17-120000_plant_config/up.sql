@@ -0,0 +1,4 @@
+ALTER TABLE plants ADD COLUMN pot_num INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE plants ADD COLUMN growth_stage VARCHAR NOT NULL DEFAULT 'vegetative';
+ALTER TABLE plants ADD COLUMN firmware VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE plants ADD COLUMN sensors VARCHAR NOT NULL DEFAULT '';
@@ -0,0 +1 @@
+ALTER TABLE plants ADD COLUMN state VARCHAR NOT NULL DEFAULT 'online';
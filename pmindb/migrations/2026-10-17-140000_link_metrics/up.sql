@@ -0,0 +1,13 @@
+CREATE TABLE link_metrics (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  parent_plant_eui BINARY NOT NULL REFERENCES plants(eui),
+  rloc INTEGER NOT NULL,
+  parent_rloc INTEGER NOT NULL,
+  avg_rssi INTEGER,
+  last_rssi INTEGER,
+  link_quality INTEGER,
+  link_margin INTEGER,
+  frame_error_rate FLOAT,
+  message_error_rate FLOAT,
+  ts DATETIME NOT NULL
+);
@@ -3,13 +3,15 @@ use std::net::{Ipv6Addr, SocketAddrV6};
 
 use chrono::{DateTime, NaiveDateTime, Utc};
 use futures::{FutureExt, StreamExt};
-use pmindp_sensor::SensorReading;
+use pmindp_sensor::{SensorClass, SensorReading};
 
 use thiserror::Error;
 
 use actix::prelude::*;
 
-use pmind_broker::{Eui, NodeEvent, NodeSensorReading, NodeStatus};
+use pmind_broker::{
+    Eui, Lifecycle, NodeEvent, NodeLinkMetrics, NodeSensorReading, NodeStatus, Registration,
+};
 use tokio::{
     sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
     task::JoinHandle,
@@ -24,8 +26,11 @@ use diesel::{
 };
 
 use crate::{
-    models::{MoistureData, NewGasData, NewLightData, NewMoistureData, NewPlant, PlantRecord},
-    schema::{gas_data, light_data, moisture_data, plants},
+    models::{
+        MoistureData, NewGasData, NewLightData, NewLinkMetricsData, NewMoistureData, NewPlant,
+        PlantRecord,
+    },
+    schema::{gas_data, light_data, link_metrics, moisture_data, plants},
 };
 
 #[derive(Error, Debug)]
@@ -73,7 +78,9 @@ impl PlantDatabase {
                 e
             })?;
 
-        if let Some(gas) = reading.data.gas {
+        // Readings merge the latest report of every class, only store the
+        // class that just reported so the other classes are not duplicated
+        if let (SensorClass::Env, Some(gas)) = (reading.class, reading.data.gas) {
             let result = insert_into(gas_data::dsl::gas_data)
                 .values(NewGasData {
                     parent_plant_eui: plant.eui(),
@@ -94,7 +101,7 @@ impl PlantDatabase {
             log::trace!("Gas data insert result {:?}", result);
         }
 
-        if let Some(light) = reading.data.light {
+        if let (SensorClass::Light, Some(light)) = (reading.class, reading.data.light) {
             let result = insert_into(light_data::dsl::light_data)
                 .values(NewLightData {
                     parent_plant_eui: plant.eui(),
@@ -113,23 +120,25 @@ impl PlantDatabase {
             log::trace!("Light data insert result {:?}", result);
         }
 
-        let result = insert_into(moisture_data::dsl::moisture_data)
-            .values(NewMoistureData {
-                parent_plant_eui: plant.eui(),
-                moisture: reading.data.soil.moisture as f32,
-                temp: reading.data.soil.temp,
-                ts: DateTime::from_timestamp(reading.data.ts, 0)
-                    .unwrap_or_default()
-                    .naive_utc(),
-            })
-            .returning(crate::models::MoistureData::as_returning())
-            .get_result(&mut self.conn)
-            .map_err(|e| {
-                log::error!("Error inserting moisture data record :( {e:}");
-                e
-            })?;
+        if reading.class == SensorClass::Soil {
+            let result = insert_into(moisture_data::dsl::moisture_data)
+                .values(NewMoistureData {
+                    parent_plant_eui: plant.eui(),
+                    moisture: reading.data.soil.moisture as f32,
+                    temp: reading.data.soil.temp,
+                    ts: DateTime::from_timestamp(reading.data.ts, 0)
+                        .unwrap_or_default()
+                        .naive_utc(),
+                })
+                .returning(crate::models::MoistureData::as_returning())
+                .get_result(&mut self.conn)
+                .map_err(|e| {
+                    log::error!("Error inserting moisture data record :( {e:}");
+                    e
+                })?;
 
-        log::trace!("moisture data insert result {:?}", result);
+            log::trace!("moisture data insert result {:?}", result);
+        }
 
         Ok(())
     }
@@ -140,16 +149,21 @@ impl PlantDatabase {
             .first::<PlantRecord>(&mut self.conn)
         {
             Ok(node) => {
-                let addr = plant.addr.clone();
-                let name = plant.name.clone();
+                let update = plant.clone();
                 let result = insert_into(plants::dsl::plants)
                     .values(plant)
                     .on_conflict(plants::dsl::eui)
                     .do_update()
                     .set((
-                        plants::dsl::name.eq(name),
-                        plants::dsl::addr.eq(addr),
+                        plants::dsl::name.eq(update.name),
+                        plants::dsl::species.eq(update.species),
+                        plants::dsl::addr.eq(update.addr),
                         plants::dsl::update_count.eq(node.update_count().wrapping_add(1)),
+                        plants::dsl::pot_num.eq(update.pot_num),
+                        plants::dsl::growth_stage.eq(update.growth_stage),
+                        plants::dsl::firmware.eq(update.firmware),
+                        plants::dsl::sensors.eq(update.sensors),
+                        plants::dsl::state.eq(update.state),
                     ))
                     .returning(crate::models::PlantRecord::as_returning())
                     .get_result(&mut self.conn)
@@ -183,6 +197,31 @@ impl PlantDatabase {
         };
         Ok(())
     }
+
+    fn update_plant_state(&mut self, eui: Eui, state: Lifecycle) -> Result<(), DatabaseError> {
+        let updated = diesel::update(plants::dsl::plants.find(crate::models::Eui::from(eui)))
+            .set(plants::dsl::state.eq(state.name()))
+            .execute(&mut self.conn)
+            .map_err(|e| {
+                log::error!("Error updating plant state :( {e:}");
+                e
+            })?;
+        log::trace!("Plant state update {eui:?} {state:?}: {updated} records");
+        Ok(())
+    }
+
+    fn insert_link_metrics(&mut self, link: NodeLinkMetrics) -> Result<(), DatabaseError> {
+        let result = insert_into(link_metrics::dsl::link_metrics)
+            .values(NewLinkMetricsData::new(link, Utc::now().naive_utc()))
+            .returning(crate::models::LinkMetricsData::as_returning())
+            .get_result(&mut self.conn)
+            .map_err(|e| {
+                log::error!("Error inserting link metrics record :( {e:}");
+                e
+            })?;
+        log::trace!("Link metrics insert result {:?}", result);
+        Ok(())
+    }
 }
 
 impl Actor for PlantDatabase {
@@ -208,11 +247,7 @@ impl Handler<NodeSensorData> for PlantDatabase {
 
 #[derive(Debug, Message)]
 #[rtype(result = "CreateOrModifyResponse")]
-pub struct CreateOrModify {
-    pub eui: Eui,
-    pub addr: Ipv6Addr,
-    pub name: String,
-}
+pub struct CreateOrModify(pub Registration);
 
 type CreateOrModifyResponse = Result<(), DatabaseError>;
 
@@ -227,6 +262,36 @@ impl Handler<CreateOrModify> for PlantDatabase {
     }
 }
 
+#[derive(Debug, Message)]
+#[rtype(result = "SetPlantStateResponse")]
+pub struct SetPlantState(pub Eui, pub Lifecycle);
+
+type SetPlantStateResponse = Result<(), DatabaseError>;
+
+impl Handler<SetPlantState> for PlantDatabase {
+    type Result = SetPlantStateResponse;
+
+    fn handle(&mut self, msg: SetPlantState, _ctx: &mut Self::Context) -> Self::Result {
+        log::trace!("database actor SetPlantState called, msg: {msg:?}");
+        self.update_plant_state(msg.0, msg.1)
+    }
+}
+
+#[derive(Debug, Message)]
+#[rtype(result = "LinkMetricsResponse")]
+pub struct LinkMetrics(pub NodeLinkMetrics);
+
+type LinkMetricsResponse = Result<(), DatabaseError>;
+
+impl Handler<LinkMetrics> for PlantDatabase {
+    type Result = LinkMetricsResponse;
+
+    fn handle(&mut self, msg: LinkMetrics, _ctx: &mut Self::Context) -> Self::Result {
+        log::trace!("database actor LinkMetrics called, msg: {msg:?}");
+        self.insert_link_metrics(msg.0)
+    }
+}
+
 pub struct SubscriptionHandler {
     db_registry_conn_handle: Option<tokio::task::JoinHandle<Result<(), DatabaseError>>>,
     db_sensor_stream_conn_handle: Option<tokio::task::JoinHandle<Result<(), DatabaseError>>>,
@@ -250,21 +315,36 @@ impl SubscriptionHandler {
             while let Some(event) = status_rcvr.recv().await {
                 match event {
                     NodeStatus::Registration(reg) => {
-                        if let Err(e) = db
-                            .send(CreateOrModify {
-                                eui: reg.0,
-                                addr: reg.1,
-                                name: reg.2,
-                            })
-                            .await
-                        {
+                        if let Err(e) = db.send(CreateOrModify(reg)).await {
                             log::error!("database actor handle error {e:}");
                         }
                     }
-                    NodeStatus::Termination((_addr, _error_state)) => {
+                    NodeStatus::Termination((addr, error_state)) => {
+                        // The plant's state follows the lifecycle transitions
                         // TODO: maybe have timer that starts, if node does not come
                         // back within a week or two, evict?
-                        log::info!("TODO! report this to DB, node terminated");
+                        log::info!("Node at {addr} terminated: {error_state:?}");
+                    }
+                    // The plant record follows the node's registration
+                    NodeStatus::Moved(moved) => {
+                        log::info!("Node {:02x?} moved to {}", moved.eui, moved.to.1);
+                    }
+                    NodeStatus::NetworkChange(change) => {
+                        log::info!("Mesh moved to OMR prefix {}", change.prefix);
+                    }
+                    NodeStatus::LinkMetrics(link) => {
+                        if let Err(e) = db.send(LinkMetrics(link)).await {
+                            log::error!("database actor handle error {e:}");
+                        }
+                    }
+                    // Nodes that never registered have no plant record
+                    NodeStatus::Lifecycle(transition) => {
+                        let Some(eui) = transition.eui else {
+                            continue;
+                        };
+                        if let Err(e) = db.send(SetPlantState(eui, transition.to)).await {
+                            log::error!("database actor handle error {e:}");
+                        }
                     }
                 }
             }
@@ -32,19 +32,19 @@ pub trait PlantMinderDatabase {
 
     /// Get all available sensor data for the [`pmind_broker::Eui`]
     /// in the stored database
-    async fn get_full_history(&self, eui: Eui) -> Result<Vec<NodeSensorReading>, DatabaseError>;
+    async fn get_full_history(&self, _eui: Eui) -> Result<Vec<NodeSensorReading>, DatabaseError>;
 }
 
 #[async_trait::async_trait]
 impl PlantMinderDatabase for PlantDatabaseHandler {
-    async fn get_full_history(&self, eui: Eui) -> Result<Vec<NodeSensorReading>, DatabaseError> {
+    async fn get_full_history(&self, _eui: Eui) -> Result<Vec<NodeSensorReading>, DatabaseError> {
         todo!()
     }
 
     async fn get_full_history_since_ts(
         &self,
-        eui: Eui,
-        timestamp: NaiveDateTime,
+        _eui: Eui,
+        _timestamp: NaiveDateTime,
     ) -> Result<Vec<NodeSensorReading>, DatabaseError> {
         todo!()
     }
@@ -15,7 +15,7 @@ use diesel::{
 
 use crate::db::CreateOrModify;
 
-use pmind_broker::Eui as NodeEui;
+use pmind_broker::{Eui as NodeEui, NodeLinkMetrics};
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug, AsExpression, FromSqlRow)]
 #[diesel(sql_type = diesel::sql_types::Binary)]
@@ -24,6 +24,12 @@ pub struct Eui(
     NodeEui,
 );
 
+impl From<NodeEui> for Eui {
+    fn from(eui: NodeEui) -> Self {
+        Eui(eui)
+    }
+}
+
 impl SqlType for Eui {
     type IsNull = NotNull;
 }
@@ -73,6 +79,14 @@ pub(crate) struct PlantRecord {
 
     pub(crate) addr: String,
     pub(crate) update_count: i32,
+    pot_num: i32,
+    /// [`pmindp_sensor::GrowthStage::name`]
+    growth_stage: String,
+    firmware: String,
+    /// Comma separated [`pmind_broker::SensorType::name`]s
+    sensors: String,
+    /// [`pmind_broker::Lifecycle::name`] of the last transition
+    state: String,
     //created_at: NaiveDateTime,
     //updated_at: NaiveDateTime,
     // Desired min & max moisture,
@@ -81,8 +95,6 @@ pub(crate) struct PlantRecord {
     // Desired min & max lux,
     // based on plant species & growth stage
     //  lux_range: pmindp_sensor::Range<f32>,
-    // Growth stage
-    //  growth_stage: pmindp_sensor::GrowthStage,
 }
 
 impl PlantRecord {
@@ -113,16 +125,33 @@ pub(crate) struct NewPlant {
     pub(crate) species: String,
     pub(crate) addr: String,
     pub(crate) update_count: i32,
+    pub(crate) pot_num: i32,
+    pub(crate) growth_stage: String,
+    pub(crate) firmware: String,
+    pub(crate) sensors: String,
+    pub(crate) state: String,
 }
 
 impl NewPlant {
     pub fn new(msg: CreateOrModify) -> Self {
+        let reg = msg.0;
         Self {
-            eui: Eui(msg.eui),
-            name: msg.name,
-            addr: msg.addr.to_string(),
-            species: "".to_string(),
+            eui: Eui(reg.eui),
+            name: reg.name,
+            addr: reg.addr.to_string(),
+            species: reg.species,
             update_count: 0,
+            pot_num: reg.pot_num as i32,
+            growth_stage: reg.growth_stage.name().to_string(),
+            firmware: reg.firmware,
+            sensors: reg
+                .capabilities
+                .sensors
+                .iter()
+                .map(|s| s.name())
+                .collect::<Vec<_>>()
+                .join(","),
+            state: pmind_broker::Lifecycle::Online.name().to_string(),
         }
     }
 }
@@ -190,3 +219,53 @@ pub(crate) struct NewMoistureData {
     pub(crate) temp: f32,
     pub(crate) ts: NaiveDateTime,
 }
+
+#[derive(Queryable, PartialEq, Debug, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::link_metrics)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub(crate) struct LinkMetricsData {
+    pub(crate) id: i32,
+    pub(crate) parent_plant_eui: Eui,
+    pub(crate) rloc: i32,
+    pub(crate) parent_rloc: i32,
+    pub(crate) avg_rssi: Option<i32>,
+    pub(crate) last_rssi: Option<i32>,
+    pub(crate) link_quality: Option<i32>,
+    pub(crate) link_margin: Option<i32>,
+    pub(crate) frame_error_rate: Option<f32>,
+    pub(crate) message_error_rate: Option<f32>,
+    pub(crate) ts: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::link_metrics)]
+pub(crate) struct NewLinkMetricsData {
+    pub(crate) parent_plant_eui: Eui,
+    pub(crate) rloc: i32,
+    pub(crate) parent_rloc: i32,
+    pub(crate) avg_rssi: Option<i32>,
+    pub(crate) last_rssi: Option<i32>,
+    pub(crate) link_quality: Option<i32>,
+    pub(crate) link_margin: Option<i32>,
+    pub(crate) frame_error_rate: Option<f32>,
+    pub(crate) message_error_rate: Option<f32>,
+    pub(crate) ts: NaiveDateTime,
+}
+
+impl NewLinkMetricsData {
+    pub fn new(link: NodeLinkMetrics, ts: NaiveDateTime) -> Self {
+        let metrics = link.metrics;
+        Self {
+            parent_plant_eui: Eui(link.eui),
+            rloc: metrics.rloc as i32,
+            parent_rloc: metrics.parent as i32,
+            avg_rssi: metrics.avg_rssi.map(i32::from),
+            last_rssi: metrics.last_rssi.map(i32::from),
+            link_quality: metrics.link_quality.map(i32::from),
+            link_margin: metrics.link_margin.map(i32::from),
+            frame_error_rate: metrics.frame_error_rate,
+            message_error_rate: metrics.message_error_rate,
+            ts,
+        }
+    }
+}
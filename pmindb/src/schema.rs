@@ -22,6 +22,22 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    link_metrics (id) {
+        id -> Integer,
+        parent_plant_eui -> Binary,
+        rloc -> Integer,
+        parent_rloc -> Integer,
+        avg_rssi -> Nullable<Integer>,
+        last_rssi -> Nullable<Integer>,
+        link_quality -> Nullable<Integer>,
+        link_margin -> Nullable<Integer>,
+        frame_error_rate -> Nullable<Float>,
+        message_error_rate -> Nullable<Float>,
+        ts -> Timestamp,
+    }
+}
+
 diesel::table! {
     moisture_data (id) {
         id -> Integer,
@@ -39,11 +55,23 @@ diesel::table! {
         species -> Text,
         addr -> Text,
         update_count -> Integer,
+        pot_num -> Integer,
+        growth_stage -> Text,
+        firmware -> Text,
+        sensors -> Text,
+        state -> Text,
     }
 }
 
 diesel::joinable!(gas_data -> plants (parent_plant_eui));
 diesel::joinable!(light_data -> plants (parent_plant_eui));
+diesel::joinable!(link_metrics -> plants (parent_plant_eui));
 diesel::joinable!(moisture_data -> plants (parent_plant_eui));
 
-diesel::allow_tables_to_appear_in_same_query!(gas_data, light_data, moisture_data, plants,);
+diesel::allow_tables_to_appear_in_same_query!(
+    gas_data,
+    light_data,
+    link_metrics,
+    moisture_data,
+    plants,
+);
@@ -1,6 +1,6 @@
 use tokio::sync::mpsc::unbounded_channel;
 
-use pmind_broker::BrokerError;
+use pmind_broker::{BrokerConfig, BrokerError, KeyStore, OtCliClient};
 use pmindd::{
     event::{Event, EventHandler},
     minder::{handle_app_cmd, handle_node_state_change, PlantMinder, PlantMinderResult, Tui},
@@ -14,6 +14,17 @@ use tracing_subscriber::FmtSubscriber;
 
 use tracing_log::LogTracer;
 
+const NODE_KEYS: &str = "./node_keys";
+
+// Flag to run without a key file, accepting any node on the mesh
+const ALLOW_UNAUTHENTICATED: &str = "--allow-unauthenticated";
+
+// Subcommand running a commissioning command instead of the TUI
+const COMMISSION: &str = "commission";
+
+const USAGE: &str =
+    "usage: plant-minder [--allow-unauthenticated] | plant-minder commission <command>";
+
 #[actix::main]
 async fn main() -> PlantMinderResult<()> {
     LogTracer::init().expect("Unable to set up log tracer");
@@ -29,12 +40,41 @@ async fn main() -> PlantMinderResult<()> {
 
     tracing::subscriber::set_global_default(sub).expect("Unable to set up tracing subscriber");
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let allow_unauthenticated = match args.as_slice() {
+        [] => false,
+        [flag] if flag == ALLOW_UNAUTHENTICATED => true,
+        [cmd, rest @ ..] if cmd == COMMISSION => {
+            return pmindd::commission::run(rest, Box::new(OtCliClient)).await;
+        }
+        _ => return Err(PlantMinderError::Usage(USAGE.to_string())),
+    };
+
     let (client_event_tx, client_event_rx) = unbounded_channel();
     let mut app = PlantMinder::new(500, client_event_rx);
 
-    let broker_handle = pmind_broker::broker(
+    // Nodes are only accepted if they authenticate with a key in the key
+    // file, running without one has to be asked for
+    let keys = match (
+        std::path::Path::new(NODE_KEYS).exists(),
+        allow_unauthenticated,
+    ) {
+        (true, _) => Some(KeyStore::load(NODE_KEYS)?),
+        (false, true) => {
+            log::warn!("No {NODE_KEYS} key file, accepting unauthenticated nodes");
+            None
+        }
+        (false, false) => return Err(PlantMinderError::NoKeyFile(NODE_KEYS.to_string())),
+    };
+
+    let broker_handle = pmind_broker::broker_with_config(
         tokio::time::Duration::from_secs(15),
         500, // tick rate for broker event loop is in millis
+        Box::new(OtCliClient),
+        BrokerConfig {
+            keys,
+            ..Default::default()
+        },
     )
     .await
     .map_err(|e| {
@@ -44,8 +84,15 @@ async fn main() -> PlantMinderResult<()> {
 
     let (sensor_stream_tx, sensor_stream_rx) = unbounded_channel();
     let (node_state_tx, node_state_rx) = unbounded_channel();
+    let (network_tx, network_rx) = unbounded_channel();
 
-    let mut events = EventHandler::new(1, sensor_stream_rx, node_state_rx, client_event_tx);
+    let mut events = EventHandler::new(
+        1,
+        sensor_stream_rx,
+        node_state_rx,
+        network_rx,
+        client_event_tx,
+    );
 
     // Subscribe to all node sensor related events
     broker_handle
@@ -53,6 +100,8 @@ async fn main() -> PlantMinderResult<()> {
             id: 0,
             sensor_readings: sensor_stream_tx,
             node_status: node_state_tx,
+            classes: pmind_broker::SensorClass::ALL.to_vec(),
+            network_status: Some(network_tx),
         })
         .await
         .map_err(|e| {
@@ -62,7 +111,7 @@ async fn main() -> PlantMinderResult<()> {
 
     #[cfg(feature = "database")]
     {
-        let (mut db_handle, db_stream_tx, db_state_tx) =
+        let (db_handle, db_stream_tx, db_state_tx) =
             PlantDatabaseHandler::new_with_db_conn_tasks("file:./plantminder.db").await?;
 
         // Set up database subscription to all node sensor related events
@@ -71,6 +120,8 @@ async fn main() -> PlantMinderResult<()> {
                 id: 1,
                 sensor_readings: db_stream_tx,
                 node_status: db_state_tx,
+                classes: pmind_broker::SensorClass::ALL.to_vec(),
+                network_status: None,
             })
             .await
             .map_err(|e| {
@@ -90,6 +141,7 @@ async fn main() -> PlantMinderResult<()> {
             Ok(Event::Tick) => app.tick().await,
             Ok(Event::AppCmd(cmd)) => handle_app_cmd(cmd, &mut app).await,
             Ok(Event::NodeState(status)) => handle_node_state_change(status, &mut app).await,
+            Ok(Event::NetworkState(status)) => app.set_network(status),
             Err(e) => {
                 log::error!("Error in app event loop {e:}, exiting");
                 break;
@@ -0,0 +1,59 @@
+//! Commissioning commands, run instead of the TUI by
+//! `plant-minder commission <command>`:
+//! - `dataset` prints the network's active dataset
+//! - `form` forms a new network, on a border router without one
+//! - `join <eui64> [pskd] [timeout secs]` lets a node join, and waits
+//!   until it does or its window expires. Without a PSKd one is generated,
+//!   to build the node with
+
+use pmind_broker::{Commissioner, Eui64, JoinState, OtClient, Pskd, DEFAULT_JOINER_TIMEOUT};
+use tokio::time::Duration;
+
+use crate::{minder::PlantMinderResult, PlantMinderError};
+
+const USAGE: &str =
+    "usage: plant-minder commission <dataset | form | join <eui64> [pskd] [timeout secs]>";
+
+// Seconds between reads of the commissioner's joiner table
+const JOIN_POLL_PERIOD: u64 = 2;
+
+pub async fn run(args: &[String], ot_client: Box<dyn OtClient>) -> PlantMinderResult<()> {
+    let mut commissioner = Commissioner::new(ot_client);
+    match args {
+        [cmd] if cmd == "dataset" => {
+            println!("{:#?}", commissioner.active_dataset().await?);
+        }
+        [cmd] if cmd == "form" => {
+            println!("{:#?}", commissioner.form_network().await?);
+        }
+        [cmd, eui64, rest @ ..] if cmd == "join" && rest.len() <= 2 => {
+            let eui64: Eui64 = eui64.parse()?;
+            let pskd = match rest.first() {
+                Some(pskd) => Pskd::new(pskd)?,
+                None => Pskd::generate()?,
+            };
+            let timeout = match rest.get(1) {
+                Some(secs) => Duration::from_secs(
+                    secs.parse()
+                        .map_err(|_| PlantMinderError::Usage(USAGE.to_string()))?,
+                ),
+                None => DEFAULT_JOINER_TIMEOUT,
+            };
+            println!("Joiner {eui64} may join with PSKd {}", pskd.as_str());
+            commissioner.add_joiner(eui64, pskd, timeout).await?;
+
+            loop {
+                tokio::time::sleep(Duration::from_secs(JOIN_POLL_PERIOD)).await;
+                if let Some((_, state)) = commissioner.poll().await?.pop() {
+                    println!("Joiner {eui64} {state:?}");
+                    if state == JoinState::Expired {
+                        commissioner.remove_joiner(&eui64).await.ok();
+                    }
+                    break;
+                }
+            }
+        }
+        _ => return Err(PlantMinderError::Usage(USAGE.to_string())),
+    }
+    Ok(())
+}
@@ -5,7 +5,7 @@ use tokio::sync::mpsc;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
-use pmind_broker::{NodeSensorReading, NodeStatus};
+use pmind_broker::{NetworkStatus, NodeSensorReading, NodeStatus};
 
 use crate::{minder::PlantMinderResult, PlantMinderError};
 
@@ -14,6 +14,7 @@ pub enum Event {
     Tick,
     AppCmd(AppCmd),
     NodeState(NodeStatus),
+    NetworkState(NetworkStatus),
 }
 
 /// App command is derived
@@ -42,6 +43,7 @@ impl EventHandler {
         tick_rate: u64,
         node_data_rx: UnboundedReceiver<NodeSensorReading>,
         node_state_rx: UnboundedReceiver<NodeStatus>,
+        network_rx: UnboundedReceiver<NetworkStatus>,
         client_data_tx: UnboundedSender<NodeSensorReading>,
     ) -> Self {
         let tick_rate = Duration::from_secs(tick_rate);
@@ -50,6 +52,7 @@ impl EventHandler {
 
         let mut node_data_stream = UnboundedReceiverStream::new(node_data_rx);
         let mut node_state_stream = UnboundedReceiverStream::new(node_state_rx);
+        let mut network_stream = UnboundedReceiverStream::new(network_rx);
 
         let handler = tokio::spawn(async move {
             let mut reader = crossterm::event::EventStream::new();
@@ -61,6 +64,7 @@ impl EventHandler {
 
                 let node_data_stream = node_data_stream.next().fuse();
                 let node_state_stream = node_state_stream.next().fuse();
+                let network_stream = network_stream.next().fuse();
 
                 tokio::select! {
                   _ = _sender.closed() => {
@@ -105,6 +109,10 @@ impl EventHandler {
                     log::debug!("Node state event {state:?}");
                     _sender.send(Event::NodeState(state)).unwrap();
                   }
+                  Some(status) = network_stream => {
+                    log::debug!("Network event {status:?}");
+                    _sender.send(Event::NetworkState(status)).unwrap();
+                  }
                   Some(data) = node_data_stream => {
                     log::debug!("Node data event {data:?}");
                     client_data_tx.send(data).unwrap();
@@ -3,11 +3,12 @@
 //! need to be watered, as part of the plant-minder
 //! system
 
+pub mod commission;
 pub mod event;
 pub mod minder;
 pub mod ui;
 
-use pmind_broker::BrokerError;
+use pmind_broker::{BrokerError, CommissionError, KeyStoreError};
 use pmindb::DatabaseError;
 
 use thiserror::Error;
@@ -18,8 +19,16 @@ pub enum PlantMinderError {
     Io(#[from] std::io::Error),
     #[error("Broker Error")]
     BrokerError(#[from] BrokerError),
+    #[error("Key File Error")]
+    KeyStoreError(#[from] KeyStoreError),
+    #[error("No key file at {0}, run with --allow-unauthenticated to accept any node")]
+    NoKeyFile(String),
     #[error("Event Handling Error")]
     EventError,
     #[error("Database Error")]
     DatabaseError(#[from] DatabaseError),
+    #[error("Commissioning Error")]
+    CommissionError(#[from] CommissionError),
+    #[error("{0}")]
+    Usage(String),
 }
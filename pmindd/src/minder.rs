@@ -1,4 +1,7 @@
-use pmind_broker::{Eui, NodeSensorReading, NodeState, NodeStatus, Registration};
+use pmind_broker::{
+    Eui, GrowthStage, LinkMetrics, NetworkStatus, NodeSensorReading, NodeState, NodeStatus,
+    Registration, SensorType,
+};
 #[cfg(feature = "database")]
 use pmindb::PlantMinderDatabase;
 
@@ -47,6 +50,8 @@ pub struct PlantMinder {
     pub row: usize,
     pub window_start: usize,
     pub window_end: usize,
+    /// Last status of the Thread network, `None` until the broker reports it
+    pub network: Option<NetworkStatus>,
     #[cfg(feature = "database")]
     pub db: Option<Box<dyn PlantMinderDatabase>>,
 }
@@ -58,8 +63,15 @@ pub struct Node {
     pub addr: Ipv6Addr,
     pub eui: Eui,
     pub name: String,
+    pub pot_num: u32,
+    pub species: String,
+    pub growth_stage: GrowthStage,
+    pub firmware: String,
+    pub sensors: Vec<SensorType>,
     pub history: Vec<NodeSensorReading>,
     pub state: NodeState,
+    /// Last link metrics published for the node
+    pub link: Option<LinkMetrics>,
 }
 
 impl Default for Node {
@@ -68,8 +80,14 @@ impl Default for Node {
             addr: Ipv6Addr::from(0u128),
             eui: [0u8; 6],
             name: String::default(),
+            pot_num: 0,
+            species: String::default(),
+            growth_stage: GrowthStage::default(),
+            firmware: String::default(),
+            sensors: vec![],
             history: Vec::with_capacity(MAX_WINDOW),
             state: NodeState::Unknown,
+            link: None,
         }
     }
 }
@@ -87,6 +105,7 @@ impl PlantMinder {
             row: 0,
             window_start: 0,
             window_end: 0,
+            network: None,
             #[cfg(feature = "database")]
             db: None,
         }
@@ -138,13 +157,16 @@ impl PlantMinder {
 
     pub async fn node_registration(&mut self, reg: Registration) {
         log::info!(
-            "Received plant registrition for eui: {:?}, addr {:?}, plant name {:?}",
-            reg.0,
-            reg.1,
-            reg.2
+            "Received plant registrition for eui: {:?}, addr {:?}, plant name {:?}, \
+            species {:?}, firmware {:?}",
+            reg.eui,
+            reg.addr,
+            reg.name,
+            reg.species,
+            reg.firmware
         );
         let history = {
-            if let Some(previous) = self.node_addrs.get(&reg.0) {
+            if let Some(previous) = self.node_addrs.get(&reg.eui) {
                 if let Some(entry) = self.nodes.get(previous) {
                     entry.history.clone()
                 } else {
@@ -157,28 +179,79 @@ impl PlantMinder {
 
         // Evict the old addr from both hashmaps
         if !history.is_empty() {
-            let previous = self.node_addrs.get(&reg.0);
+            let previous = self.node_addrs.get(&reg.eui);
             if let Some(p) = previous {
                 self.nodes.remove(p);
             }
-            self.node_addrs.remove(&reg.0);
+            self.node_addrs.remove(&reg.eui);
         }
 
         self.node_addrs
-            .entry(reg.0)
-            .and_modify(|a| *a = reg.1)
-            .or_insert(reg.1);
-
+            .entry(reg.eui)
+            .and_modify(|a| *a = reg.addr)
+            .or_insert(reg.addr);
+
+        // Nodes report their plant config on every registration, keep the
+        // latest in case it was reflashed
+        let node = Node {
+            history,
+            addr: reg.addr,
+            eui: reg.eui,
+            name: reg.name,
+            pot_num: reg.pot_num,
+            species: reg.species,
+            growth_stage: reg.growth_stage,
+            firmware: reg.firmware,
+            sensors: reg.capabilities.sensors,
+            state: NodeState::Online,
+            link: None,
+        };
         self.nodes
-            .entry(reg.1)
-            .and_modify(|a| a.history.clone_from(&history))
-            .or_insert(Node {
-                history,
-                addr: reg.1,
-                eui: reg.0,
-                name: reg.2,
-                state: NodeState::Online,
-            });
+            .entry(reg.addr)
+            .and_modify(|a| *a = node.clone())
+            .or_insert(node);
+    }
+
+    /// The node `eui` moved to `addr`, its readings are reported from there
+    /// and it keeps its history
+    fn move_node(&mut self, eui: Eui, addr: Ipv6Addr) {
+        let Some(previous) = self
+            .node_addrs
+            .get_mut(&eui)
+            .map(|a| std::mem::replace(a, addr))
+        else {
+            return;
+        };
+        if let Some(mut node) = self.nodes.remove(&previous) {
+            node.addr = addr;
+            self.nodes.insert(addr, node);
+        }
+    }
+
+    /// Nodes are only shown once registered, other nodes are ignored
+    fn set_node_state(&mut self, addr: Ipv6Addr, state: NodeState) {
+        if let Some(node) = self.nodes.get_mut(&addr) {
+            node.state = state;
+        }
+    }
+
+    pub fn set_network(&mut self, status: NetworkStatus) {
+        log::info!(
+            "Network {:?}, mesh up: {}",
+            status.event,
+            status.is_mesh_up()
+        );
+        self.network = Some(status);
+    }
+
+    fn set_link_metrics(&mut self, eui: Eui, link: LinkMetrics) {
+        if let Some(node) = self
+            .node_addrs
+            .get(&eui)
+            .and_then(|addr| self.nodes.get_mut(addr))
+        {
+            node.link = Some(link);
+        }
     }
 
     fn new_data(&mut self, key: Ipv6Addr, data: Vec<NodeSensorReading>) {
@@ -199,20 +272,34 @@ impl PlantMinder {
     fn render_node_last_table(&self, area: Rect, buf: &mut Buffer) {
         let header_style = Style::default().fg(Color::White).bg(Color::Cyan);
 
-        let header = ["Plant Name", "Ipv6 Addr", "State"]
-            .into_iter()
-            .map(Cell::from)
-            .collect::<Row>()
-            .style(header_style)
-            .height(1);
-
+        let header = [
+            "Plant Name",
+            "Species",
+            "Pot",
+            "Stage",
+            "Firmware",
+            "Ipv6 Addr",
+            "Link",
+            "State",
+        ]
+        .into_iter()
+        .map(Cell::from)
+        .collect::<Row>()
+        .style(header_style)
+        .height(1);
+
+        // Nodes can not report while the mesh is down, rather than showing
+        // each of them going offline
+        let mesh_down = self.network.is_some_and(|n| !n.is_mesh_up());
         let rows = self.nodes.iter().map(|(addr, node)| {
-            // apply this general rule of thumb for now,
-            // it is highly tailored to the seesaw moisture sensor
-            let node_state = {
-                if node.history.is_empty() {
-                    "Waiting".to_string()
-                } else {
+            let node_state = match node.state {
+                _ if mesh_down => "Mesh down".to_string(),
+                NodeState::Offline(e) => format!("Offline ({e:?})"),
+                NodeState::Unknown => "Reconnecting".to_string(),
+                NodeState::Online if node.history.is_empty() => "Waiting".to_string(),
+                NodeState::Online => {
+                    // apply this general rule of thumb for now,
+                    // it is highly tailored to the seesaw moisture sensor
                     let last = node.history.len();
                     match node.history[last - 1].data.soil.moisture {
                         750..=1500 => "Good & moist".to_string(),
@@ -224,16 +311,47 @@ impl PlantMinder {
                 }
             };
 
-            Row::new(vec![node.name.clone(), addr.to_string(), node_state])
-                .style(Style::new().fg(Color::Cyan).bg(Color::Black))
-                .height(3)
+            // Average RSSI and link quality (0-3) as heard by the parent
+            let link = match node.link {
+                Some(LinkMetrics {
+                    avg_rssi: Some(rssi),
+                    link_quality,
+                    ..
+                }) => format!(
+                    "{rssi} dBm LQ{}",
+                    link_quality.map_or("?".to_string(), |lq| lq.to_string())
+                ),
+                Some(LinkMetrics {
+                    link_quality: Some(lq),
+                    ..
+                }) => format!("LQ{lq}"),
+                _ => "-".to_string(),
+            };
+
+            Row::new(vec![
+                node.name.clone(),
+                node.species.clone(),
+                node.pot_num.to_string(),
+                node.growth_stage.name().to_string(),
+                node.firmware.clone(),
+                addr.to_string(),
+                link,
+                node_state,
+            ])
+            .style(Style::new().fg(Color::Cyan).bg(Color::Black))
+            .height(3)
         });
 
         let t = Table::new(
             rows,
             [
                 Constraint::Min(20),
-                Constraint::Length(50),
+                Constraint::Min(12),
+                Constraint::Length(5),
+                Constraint::Length(12),
+                Constraint::Length(10),
+                Constraint::Length(40),
+                Constraint::Length(14),
                 Constraint::Min(10),
             ],
         )
@@ -389,10 +507,23 @@ pub async fn handle_app_cmd(cmd: AppCmd, app: &mut PlantMinder) {
 pub async fn handle_node_state_change(event: NodeStatus, app: &mut PlantMinder) {
     match event {
         NodeStatus::Registration(reg) => app.node_registration(reg).await,
-        NodeStatus::Termination((_addr, _error_state)) => {
-            // TODO
-            log::info!("TODO! report this in rendered display");
+        NodeStatus::Termination((addr, error_state)) => {
+            log::info!("Node at {addr} terminated: {error_state:?}");
+            app.set_node_state(*addr.ip(), NodeState::Offline(error_state));
+        }
+        NodeStatus::Lifecycle(transition) => {
+            log::info!("Node at {} is {}", transition.addr, transition.to.name());
+            app.set_node_state(transition.addr, transition.to.into());
+        }
+        NodeStatus::Moved(moved) => {
+            log::info!("Node at {} moved to {}", moved.from.1, moved.to.1);
+            app.move_node(moved.eui, moved.to.1);
+        }
+        // Nodes report their own lifecycle while they are registered again
+        NodeStatus::NetworkChange(change) => {
+            log::info!("Mesh moved to OMR prefix {}", change.prefix);
         }
+        NodeStatus::LinkMetrics(link) => app.set_link_metrics(link.eui, link.metrics),
     }
 }
 
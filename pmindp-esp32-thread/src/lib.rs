@@ -66,6 +66,11 @@ type SensorTimer = Mutex<RefCell<Option<Timer<Timer0<TIMG0>, Blocking>>>>;
 
 static SENSOR_TIMER: SensorTimer = Mutex::new(RefCell::new(None));
 const DEFAULT_MIN_INTERVAL: u64 = 5000;
+/// How often each sensor class is reported (ms), checked on every sensor
+/// timer tick so these should be multiples of [`DEFAULT_MIN_INTERVAL`]
+const SOIL_REPORT_INTERVAL: u64 = 25000;
+const LIGHT_REPORT_INTERVAL: u64 = 60000;
+const ENV_REPORT_INTERVAL: u64 = 120000;
 static SENSOR_TIMER_INTERVAL: Mutex<RefCell<u64>> = Mutex::new(RefCell::new(DEFAULT_MIN_INTERVAL));
 static SENSOR_TIMER_FIRED: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
 
@@ -85,11 +90,12 @@ pub fn init<'a>(
 where
     Esp32Platform<'a>: SensorPlatform,
 {
-    let openthread = esp_openthread::OpenThread::new(ieee802154, timer, Rng::new(rng));
+    let rng = Rng::new(rng);
+    let openthread = esp_openthread::OpenThread::new(ieee802154, timer, rng);
     let timer = timg0.timer0;
-    setup_sensor_timer(timer, 25000);
+    setup_sensor_timer(timer, DEFAULT_MIN_INTERVAL);
 
-    Esp32Platform::new(openthread, sensors)
+    Esp32Platform::new(openthread, sensors, rng)
 }
 
 #[handler]
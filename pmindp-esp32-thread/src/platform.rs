@@ -1,23 +1,45 @@
-use core::pin::pin;
-use esp_hal::reset::software_reset_cpu;
+use core::{cell::RefCell, ffi::c_void, pin::pin, ptr};
+use critical_section::Mutex;
+use esp_hal::{reset::software_reset_cpu, rng::Rng};
 use esp_openthread::{
-    NetworkInterfaceUnicastAddress, OpenThread, OperationalDataset, ThreadTimestamp,
+    sys::bindings::{
+        otDatasetIsCommissioned, otError, otError_OT_ERROR_NONE, otExtAddress,
+        otInstanceInitSingle, otJoinerStart, otLinkGetFactoryAssignedIeeeEui64,
+    },
+    NetworkInterfaceUnicastAddress, OpenThread,
 };
 
-use coap_lite::{CoapRequest, Packet};
-use pmindp_sensor::{PlatformSensorError, SensorPlatform};
+use coap_lite::{ContentFormat, MessageClass, Packet};
+use pmindp_protocol::{
+    directory,
+    oscore::{parse_psk, SESSION_NONCE_LEN},
+    requested_version, service, Endpoint, Link, ObserverRegistry, OscoreServer, Outbox,
+    RequestOutcome, TransmissionParams, DEFAULT_MAX_AGE, RD_PORT,
+};
+use pmindp_sensor::{
+    wire::from_cbor, NodeIdentity, PlatformSensorError, ProtocolHeader, SensorClass,
+    SensorPlatform, SensorType, WireFormat,
+};
+
+use crate::{
+    SensorVec, ENV_REPORT_INTERVAL, LIGHT_REPORT_INTERVAL, SENSOR_TIMER_FIRED, SOIL_REPORT_INTERVAL,
+};
+
+pub const BOUND_PORT: u16 = pmindp_protocol::NODE_COAP_PORT;
+
+type Observer = (no_std_net::Ipv6Addr, u16);
 
-use crate::{SensorVec, SENSOR_TIMER_FIRED};
+/// Ms between attempts to join, while the commissioner has not added the
+/// node or the attempt failed
+const JOIN_RETRY_INTERVAL: u64 = 10_000;
 
-// TODO put this in pmindp-sensor so other crates in
-// workspace can access this
-// also do that for any other constants that both
-// sender and receiver need to know
-pub const BOUND_PORT: u16 = 1212;
+/// Result of the last attempt to join, set by [`joiner_callback`]
+static JOIN_RESULT: Mutex<RefCell<Option<otError>>> = Mutex::new(RefCell::new(None));
 
 pub struct Esp32Platform<'a> {
     openthread: OpenThread<'a>,
     sensors: SensorVec,
+    rng: Rng,
 }
 
 pub enum Esp32PlatformError {
@@ -31,10 +53,11 @@ impl<'a> Esp32Platform<'a>
 where
     Esp32Platform<'a>: SensorPlatform,
 {
-    pub fn new(openthread: OpenThread<'a>, sensors: SensorVec) -> Self {
+    pub fn new(openthread: OpenThread<'a>, sensors: SensorVec, rng: Rng) -> Self {
         Self {
             openthread,
             sensors,
+            rng,
         }
     }
 
@@ -46,39 +69,136 @@ where
                 promiscuous: false,
                 rx_when_idle: false,
                 txpower: 18, // 18 txpower is legal for North America
-                channel: 25, // match the dataset
+                channel: 25, // the joiner scans, the dataset sets it once joined
                 ..esp_ieee802154::Config::default()
             })
             .unwrap();
 
-        let dataset = OperationalDataset {
-            active_timestamp: Some(ThreadTimestamp {
-                seconds: 1,
-                ticks: 0,
-                authoritative: false,
-            }),
-            network_key: Some([
-                0xfe, 0x04, 0x58, 0xf7, 0xdb, 0x96, 0x35, 0x4e, 0xaa, 0x60, 0x41, 0xb8, 0x80, 0xea,
-                0x9c, 0x0f,
-            ]),
-            network_name: Some("OpenThread-58d1".try_into().unwrap()),
-            extended_pan_id: Some([0x3a, 0x90, 0xe3, 0xa3, 0x19, 0xa9, 0x04, 0x94]),
-            pan_id: Some(0x58d1),
-            channel: Some(25),
-            channel_mask: Some(0x07fff800),
-
-            ..OperationalDataset::default()
-        };
-        log::debug!("Programmed child device with dataset : {:?}", dataset);
-
-        self.openthread.set_active_dataset(dataset).unwrap();
         self.openthread.ipv6_set_enabled(true).unwrap();
+        self.join(pmindp_sensor::PLANT_CONFIG.pskd)?;
         self.openthread.thread_set_enabled(true).unwrap();
 
         let mut buffer = [0u8; 512];
         let mut eui: [u8; 6] = [0u8; 6];
+        self.openthread.get_eui(&mut eui);
+
+        // Advertise the node through the border router's SRP server, so that
+        // a broker discovering nodes by service tells it from other Thread
+        // devices. The SRP client starts once it finds the server
+        let instance = service::instance_name(&eui);
+        let host = alloc::format!("pmind-{instance}");
+        let txt = [
+            (service::TXT_NAME, pmindp_sensor::PLANT_CONFIG.name),
+            (service::TXT_SPECIES, pmindp_sensor::PLANT_CONFIG.species),
+            (service::TXT_FIRMWARE, env!("CARGO_PKG_VERSION")),
+        ];
+        if let Err(e) = self
+            .openthread
+            .setup_srp_client_autostart(None)
+            .and_then(|_| self.openthread.setup_srp_client_host_addr_autoconfig())
+            .and_then(|_| self.openthread.setup_srp_client_set_hostname(&host))
+            .and_then(|_| {
+                self.openthread.add_srp_service(
+                    &instance,
+                    service::SERVICE_TYPE,
+                    &[],
+                    &txt,
+                    BOUND_PORT,
+                    None,
+                    None,
+                    None,
+                )
+            })
+        {
+            log::error!("Unable to advertise the node service: {e:?}");
+        }
+        // Representation returned to registrations is the node identity and
+        // plant config, in the protocol version negotiated with the broker;
+        // notifications after that carry sensor readings
+        let mut identity = NodeIdentity::from_config(
+            ProtocolHeader::default(),
+            eui,
+            &pmindp_sensor::PLANT_CONFIG,
+            env!("CARGO_PKG_VERSION"),
+        );
+
+        // Notifications are confirmable so readings lost on the mesh are
+        // retransmitted; the EUI seeds the backoff jitter. Each sensor class
+        // this build has is its own resource, listed with its sensors on
+        // /.well-known/core for the broker
+        let sensors = sensor_types();
+        let mut links = alloc::vec![];
+        let mut observers: ObserverRegistry<Observer> =
+            ObserverRegistry::new(SensorClass::Soil.name(), DEFAULT_MAX_AGE);
+        // Next time (ms since boot) each class is due to report
+        let mut schedule = alloc::vec![];
+        for class in SensorClass::ALL {
+            if !sensors.iter().any(|s| s.class() == class) {
+                continue;
+            }
+            links.push(
+                Link::new(class.name())
+                    .resource_types(
+                        sensors
+                            .iter()
+                            .filter(|s| s.class() == class)
+                            .map(|s| s.name()),
+                    )
+                    .content_format(ContentFormat::ApplicationCBOR)
+                    .observable(),
+            );
+            observers = observers.resource(class.name(), DEFAULT_MAX_AGE);
+            schedule.push((class, 0u64));
+        }
+        let mut observers = observers
+            .confirmable(
+                TransmissionParams::default(),
+                u32::from_be_bytes([eui[2], eui[3], eui[4], eui[5]]),
+            )
+            .discoverable(&links);
+        // Nodes built with a key only serve discovery in the clear; the
+        // session nonce drawn here keeps a rebooted node from reusing the
+        // nonces of its last session
+        let mut security = match pmindp_sensor::PLANT_CONFIG.psk {
+            "" => None,
+            psk => {
+                let Some(psk) = parse_psk(psk) else {
+                    log::error!("psk in cfg.toml must be 32 hex digits");
+                    return Err(Esp32PlatformError::OtherError);
+                };
+                let mut nonce = [0u8; SESSION_NONCE_LEN];
+                self.rng.read(&mut nonce);
+                Some(OscoreServer::new(
+                    psk,
+                    &eui,
+                    nonce,
+                    &TransmissionParams::default(),
+                ))
+            }
+        };
+        // Nodes built with the broker's addr register with its resource
+        // directory, retried until the broker answers since attaching to the
+        // mesh takes a while. The message id is random so that the broker
+        // does not take the registration after a reboot for a retransmission
+        let rd: Option<Observer> = match pmindp_sensor::PLANT_CONFIG.rd {
+            "" => None,
+            rd => match rd.parse::<no_std_net::Ipv6Addr>() {
+                Ok(ip) => Some((ip, RD_PORT)),
+                Err(_) => {
+                    log::error!("rd in cfg.toml must be an IPv6 addr");
+                    return Err(Esp32PlatformError::OtherError);
+                }
+            },
+        };
+        let mut seed = [0u8; 4];
+        self.rng.read(&mut seed);
+        let rd_registration = Endpoint::new(&eui)
+            .links(&links)
+            .register(u16::from_be_bytes([seed[0], seed[1]]), &seed[2..]);
+        let mut registration: Outbox<Observer> =
+            Outbox::new(TransmissionParams::default(), u32::from_be_bytes(seed));
+        let mut registered = rd.is_none();
 
-        let mut observer_addr: Option<(no_std_net::Ipv6Addr, u16)> = None;
         // This block is needed to constrain how long the immutable borrow of openthread,
         // which happens when the socket object is created, exists
         {
@@ -88,11 +208,32 @@ where
 
             // make this big
             let mut send_data_buf: [u8; 127] = [0u8; 127];
-            loop {
+            'serve: loop {
                 self.openthread.process();
                 self.openthread.run_tasklets();
 
-                if let Some((observer, port)) = observer_addr {
+                for ((observer, port), notification) in observers.poll(now_ms()) {
+                    if let Some(Ok(packet)) = protect(&mut security, notification) {
+                        socket.send(observer, port, &packet).ok();
+                    }
+                }
+
+                if let (Some((rd_ip, rd_port)), false) = (rd, registered) {
+                    // Registrations that ran out of retransmissions are
+                    // dropped, and sent anew
+                    let mut resend = registration.poll(now_ms()).resend;
+                    if registration.is_empty() {
+                        registration.send((rd_ip, rd_port), rd_registration.clone(), now_ms());
+                        resend.push(((rd_ip, rd_port), rd_registration.clone()));
+                    }
+                    for (_, request) in resend {
+                        if let Ok(request) = request.to_bytes() {
+                            socket.send(rd_ip, rd_port, &request).ok();
+                        }
+                    }
+                }
+
+                if !observers.observers().is_empty() {
                     let read_sensor = critical_section::with(|cs| {
                         let res = *SENSOR_TIMER_FIRED.borrow_ref_mut(cs);
                         *SENSOR_TIMER_FIRED.borrow_ref_mut(cs) = false;
@@ -100,87 +241,122 @@ where
                     });
 
                     if read_sensor {
-                        match self.sensor_read(&mut send_data_buf) {
-                            Ok(r) => {
-                                if let Ok(sensor_data) = serde_json::to_vec(&r) {
-                                    let len = sensor_data.len();
-                                    if let Err(e) =
-                                        socket.send(observer, port, &sensor_data[0..len])
-                                    {
-                                        // TODO depending on the error, need to set handshake IPv6 to None
-                                        // until observer can reestablish conn; this will prevent the
-                                        // node from sending data until success is better guaranteed
-                                        log::error!("Error sending, resetting due to {e:?}");
-                                        socket.close().ok();
-                                        break;
-                                    }
-                                } else {
-                                    log::error!("Unable to serialize sensor data");
-                                }
+                        let now = now_ms();
+                        for (class, due) in schedule.iter_mut() {
+                            if now < *due || !observers.is_observed(class.name()) {
+                                continue;
                             }
-                            Err(e) => {
-                                log::error!("Sensor error, resetting due to {e:?}");
-                                break;
+                            *due = now + report_interval(*class);
+                            let r = match self.sensor_read_class(&mut send_data_buf, *class) {
+                                Ok(r) => r,
+                                Err(e) => {
+                                    log::error!("Sensor error, resetting due to {e:?}");
+                                    break 'serve;
+                                }
+                            };
+                            let Some(Ok(sensor_data)) = r.encode_class(*class, WireFormat::Cbor)
+                            else {
+                                log::error!("Unable to serialize {} data", class.name());
+                                continue;
+                            };
+                            for ((observer, port), notification) in observers.notify(
+                                class.name(),
+                                &sensor_data,
+                                ContentFormat::ApplicationCBOR,
+                                now,
+                            ) {
+                                let Some(packet) = protect(&mut security, notification) else {
+                                    continue;
+                                };
+                                let Ok(packet) = packet else {
+                                    log::error!("Unable to encode notification");
+                                    continue;
+                                };
+                                if let Err(e) = socket.send(observer, port, &packet) {
+                                    // TODO depending on the error, need to drop the observer
+                                    // until it can re-register; this will prevent the
+                                    // node from sending data until success is better guaranteed
+                                    log::error!("Error sending, resetting due to {e:?}");
+                                    socket.close().ok();
+                                    break 'serve;
+                                }
                             }
-                        };
+                        }
                     }
                 }
 
                 let (len, from, port) = socket.receive(&mut buffer).unwrap();
                 if len > 0 {
                     if let Ok(packet) = Packet::from_bytes(&buffer[..len]) {
-                        let request = CoapRequest::from_packet(packet, from);
-
-                        let plant_name = pmindp_sensor::PLANT_CONFIG.name;
-
-                        let method = *request.get_method();
-                        let path = request.get_path();
-                        // TODO ! Need better solution
-                        let port_req = request.message.header.message_id;
+                        if rd == Some((from, port))
+                            && matches!(packet.header.code, MessageClass::Response(_))
+                        {
+                            registration.acknowledge(&(from, port), packet.header.message_id);
+                            registered = true;
+                            log::info!(
+                                "Registered with the resource directory as {:?}",
+                                directory::location(&packet)
+                            );
+                            continue;
+                        }
+                        let mut serve = |packet: &Packet| {
+                            identity.header = ProtocolHeader::negotiate(requested_version(packet));
+                            let (record, record_format) = identity.reply();
+                            observers.handle_request(
+                                packet,
+                                (from, port),
+                                &record,
+                                record_format.and_then(|f| {
+                                    ContentFormat::try_from(f.content_format() as usize).ok()
+                                }),
+                                now_ms(),
+                            )
+                        };
+                        let (outcome, response) = match security.as_mut() {
+                            Some(security) => {
+                                security.handle(&packet, (from, port), now_ms(), serve)
+                            }
+                            None => {
+                                let (outcome, response) = serve(&packet);
+                                (Some(outcome), response)
+                            }
+                        };
 
                         log::info!(
-                            "Received CoAP request '{} {:?} {}' from {}",
-                            port_req,
-                            method,
-                            path,
-                            from
+                            "Received CoAP {:?} from {} port {}: {:?}",
+                            packet.header.code,
+                            from,
+                            port,
+                            outcome
                         );
 
-                        let mut response = request.response.unwrap();
-                        self.openthread.get_eui(&mut eui);
-                        let mut record = alloc::vec![];
-                        record.extend_from_slice(&eui.clone());
-                        record.extend_from_slice(plant_name.as_bytes());
-                        response.message.payload = record.to_vec();
-                        let packet = response.message.to_bytes().unwrap();
-                        socket.send(from, port_req, packet.as_slice()).ok();
-
-                        let addrs: heapless::Vec<NetworkInterfaceUnicastAddress, 6> =
-                            self.openthread.ipv6_get_unicast_addresses();
-                        print_all_addresses(addrs);
-
-                        log::info!(
-                            "Eui {:#X?} Plant Name {:?} Port {:?}",
-                            eui,
-                            plant_name,
-                            port_req
-                        );
+                        if let Some(response) = response {
+                            if let Ok(response) = response.to_bytes() {
+                                socket.send(from, port, response.as_slice()).ok();
+                            }
+                        }
 
-                        drop(packet);
+                        if outcome == Some(RequestOutcome::Registered) {
+                            let addrs: heapless::Vec<NetworkInterfaceUnicastAddress, 6> =
+                                self.openthread.ipv6_get_unicast_addresses();
+                            print_all_addresses(addrs);
 
-                        observer_addr = Some((from, port_req));
-                        log::info!("Handshake complete");
+                            log::info!(
+                                "Eui {:#X?} Plant Name {:?} observed by {} port {} (protocol v{})",
+                                eui,
+                                identity.name,
+                                from,
+                                port,
+                                identity.header.version
+                            );
+                        }
                     } else {
                         log::info!(
-                            "received {:02x?} from {:?} port {}",
+                            "Dropping non-CoAP {:02x?} from {:?} port {}",
                             &buffer[..len],
                             from,
                             port
                         );
-
-                        socket
-                            .send(from, BOUND_PORT, b"beefface authenticate!")
-                            .unwrap();
                     }
                 }
             }
@@ -193,6 +369,126 @@ where
     pub fn reset(&mut self) {
         software_reset_cpu();
     }
+
+    /// Get the network's dataset from its commissioner with the Thread
+    /// joiner flow, unless the node kept the dataset from joining before.
+    /// The commissioner must have added the node's factory EUI-64 with the
+    /// PSKd it was built with, until then every attempt is retried. Needs
+    /// `esp-openthread` built with the joiner
+    fn join(&mut self, pskd: &str) -> Result<(), Esp32PlatformError> {
+        // Returns the instance `OpenThread` already initialized
+        let instance = unsafe { otInstanceInitSingle() };
+        if unsafe { otDatasetIsCommissioned(instance) } {
+            log::info!("Attaching with the dataset of the last join");
+            return Ok(());
+        }
+        if pskd.is_empty() {
+            log::error!("pskd in cfg.toml is needed to join the network");
+            return Err(Esp32PlatformError::OtherError);
+        }
+        if !pmindp_sensor::is_valid_pskd(pskd) {
+            log::error!("pskd in cfg.toml must be 6 to 32 of 0-9 and A-Y but I, O and Q");
+            return Err(Esp32PlatformError::OtherError);
+        }
+        // Valid PSKds have no interior NUL
+        let pskd = alloc::ffi::CString::new(pskd).unwrap();
+        let version = alloc::ffi::CString::new(env!("CARGO_PKG_VERSION")).unwrap();
+        let mut eui64 = otExtAddress { m8: [0u8; 8] };
+        unsafe { otLinkGetFactoryAssignedIeeeEui64(instance, &mut eui64) };
+        log::info!("Joining as EUI-64 {:02x?}", eui64.m8);
+
+        loop {
+            critical_section::with(|cs| *JOIN_RESULT.borrow_ref_mut(cs) = None);
+            let error = unsafe {
+                otJoinerStart(
+                    instance,
+                    pskd.as_ptr(),
+                    ptr::null(),
+                    c"plant-minder".as_ptr(),
+                    c"pmindp-esp32-thread".as_ptr(),
+                    version.as_ptr(),
+                    ptr::null(),
+                    Some(joiner_callback),
+                    ptr::null_mut(),
+                )
+            };
+            if error != otError_OT_ERROR_NONE {
+                log::error!("Unable to start joiner: {error}");
+                return Err(Esp32PlatformError::PlatformError);
+            }
+
+            let result = loop {
+                self.openthread.process();
+                self.openthread.run_tasklets();
+                if let Some(result) =
+                    critical_section::with(|cs| JOIN_RESULT.borrow_ref_mut(cs).take())
+                {
+                    break result;
+                }
+            };
+            if result == otError_OT_ERROR_NONE {
+                log::info!("Joined the network");
+                return Ok(());
+            }
+            log::warn!("Unable to join ({result}), retrying");
+
+            let retry = now_ms() + JOIN_RETRY_INTERVAL;
+            while now_ms() < retry {
+                self.openthread.process();
+                self.openthread.run_tasklets();
+            }
+        }
+    }
+}
+
+/// Called by OpenThread when an attempt to join ends
+unsafe extern "C" fn joiner_callback(error: otError, _context: *mut c_void) {
+    critical_section::with(|cs| *JOIN_RESULT.borrow_ref_mut(cs) = Some(error));
+}
+
+/// Encode a notification, protected if the node was built with a key.
+/// `None` for observers of a session that was since replaced
+fn protect(
+    security: &mut Option<OscoreServer<Observer>>,
+    notification: Packet,
+) -> Option<Result<alloc::vec::Vec<u8>, coap_lite::error::MessageError>> {
+    let notification = match security.as_mut() {
+        Some(security) => security.protect(&notification)?,
+        None => notification,
+    };
+    Some(notification.to_bytes())
+}
+
+/// Sensors enabled by the features this firmware was built with
+fn sensor_types() -> alloc::vec::Vec<SensorType> {
+    let enabled = [
+        (cfg!(feature = "atsamd10"), SensorType::Atsamd10),
+        (cfg!(feature = "probe-circuit"), SensorType::ProbeCircuit),
+        (cfg!(feature = "st0160"), SensorType::St0160),
+        (cfg!(feature = "tsl2591"), SensorType::Tsl2591),
+        (cfg!(feature = "bme680"), SensorType::Bme680),
+        (cfg!(feature = "sht40"), SensorType::Sht40),
+    ];
+    enabled
+        .into_iter()
+        .filter_map(|(enabled, sensor)| enabled.then_some(sensor))
+        .collect()
+}
+
+/// How often `class` is reported to its observers (ms)
+fn report_interval(class: SensorClass) -> u64 {
+    match class {
+        SensorClass::Soil => SOIL_REPORT_INTERVAL,
+        SensorClass::Light => LIGHT_REPORT_INTERVAL,
+        SensorClass::Env => ENV_REPORT_INTERVAL,
+    }
+}
+
+/// Milliseconds since boot, the clock CoAP retransmissions run on
+fn now_ms() -> u64 {
+    esp_hal::time::current_time()
+        .duration_since_epoch()
+        .to_millis()
 }
 
 fn print_all_addresses(addrs: heapless::Vec<NetworkInterfaceUnicastAddress, 6>) {
@@ -209,11 +505,33 @@ impl<'a> SensorPlatform for Esp32Platform<'a> {
     fn sensor_read(
         &self,
         buffer: &mut [u8],
+    ) -> Result<pmindp_sensor::SensorReading, PlatformSensorError> {
+        self.read_sensors(buffer, None)
+    }
+
+    fn sensor_read_class(
+        &self,
+        buffer: &mut [u8],
+        class: SensorClass,
+    ) -> Result<pmindp_sensor::SensorReading, PlatformSensorError> {
+        self.read_sensors(buffer, Some(class))
+    }
+}
+
+impl<'a> Esp32Platform<'a> {
+    /// Read the sensor of `class`, or every sensor if `None`
+    fn read_sensors(
+        &self,
+        buffer: &mut [u8],
+        class: Option<SensorClass>,
     ) -> Result<pmindp_sensor::SensorReading, PlatformSensorError> {
         let mut d = pmindp_sensor::SensorReading::default();
 
         let mut start = 0;
         self.sensors.iter().enumerate().for_each(|(idx, s)| {
+            if class.is_some_and(|c| c.sensor_index() != idx) {
+                return;
+            }
             if let Some(s) = s {
                 if let Ok(size) = critical_section::with(|cs| {
                     let mut sensor = s.borrow_ref_mut(cs);
@@ -225,31 +543,26 @@ impl<'a> SensorPlatform for Esp32Platform<'a> {
                 }) {
                     match idx {
                         pmindp_sensor::SOIL_IDX => {
-                            if let Ok(soil_reading) =
-                                serde_json::from_slice(&buffer[start..start + size]).map_err(|e| {
-                                    log::error!("Unable to serialize soil reading {e:}");
-                                    PlatformSensorError::Other
+                            if let Ok(soil_reading) = from_cbor(&buffer[start..start + size])
+                                .map_err(|e| {
+                                    log::error!("Unable to deserialize soil reading {e:?}");
                                 })
                             {
                                 d.soil = soil_reading;
                             }
                         }
                         pmindp_sensor::LIGHT_IDX_1 => {
-                            if let Ok(light_reading) =
-                                serde_json::from_slice(&buffer[start..start + size])
-                            {
+                            if let Ok(light_reading) = from_cbor(&buffer[start..start + size]) {
                                 d.light = Some(light_reading);
                             } else {
-                                log::error!("Unable to serialize light reading");
+                                log::error!("Unable to deserialize light reading");
                             }
                         }
                         pmindp_sensor::HUM_IDX => {
-                            if let Ok(hum_reading) =
-                                serde_json::from_slice(&buffer[start..start + size])
-                            {
+                            if let Ok(hum_reading) = from_cbor(&buffer[start..start + size]) {
                                 d.gas = Some(hum_reading);
                             } else {
-                                log::error!("Unable to serialize humidity/gas reading");
+                                log::error!("Unable to deserialize humidity/gas reading");
                             }
                         }
                         // pmindp_sensor::LIGHT_IDX_2 =>{},
@@ -77,10 +77,7 @@ where
 {
     fn read(&mut self, buffer: &mut [u8], start: usize) -> Result<usize, PlatformSensorError> {
         let reading: Gas = self.read_sensor()?;
-        let reading = serde_json::to_vec(&reading).map_err(|e| {
-            log::error!("Serde failed {e:}");
-            PlatformSensorError::Other
-        })?;
+        let reading = pmindp_sensor::wire::to_cbor(&reading);
 
         let len = reading.len();
         buffer[start..start + len].copy_from_slice(&reading);
@@ -72,8 +72,16 @@ impl<'a> MoistureSensor for ProbeCircuit<'a> {
 
 impl<'a> Sensor for ProbeCircuit<'a> {
     fn read(&mut self, buffer: &mut [u8], start: usize) -> Result<usize, PlatformSensorError> {
-        let size = <Self as MoistureSensor>::moisture(self, buffer, start)
-            .map_err(PlatformSensorError::from)?;
-        Ok(size)
+        let moisture = self.moisture().map_err(PlatformSensorError::from)?;
+        log::info!("moisture {:?}", moisture);
+
+        // The probe has no temperature sensor
+        let reading = pmindp_sensor::wire::to_cbor(&pmindp_sensor::Soil {
+            moisture,
+            temp: 0.0,
+        });
+        let len = reading.len();
+        buffer[start..start + len].copy_from_slice(&reading);
+        Ok(len)
     }
 }
@@ -483,10 +483,7 @@ where
 
         let reading: pmindp_sensor::Light = pmindp_sensor::Light { lux, fs };
 
-        let reading = serde_json::to_vec(&reading).map_err(|e| {
-            log::error!("Serde failed {e:}");
-            PlatformSensorError::Other
-        })?;
+        let reading = pmindp_sensor::wire::to_cbor(&reading);
         let len = reading.len();
 
         buffer[start..start + len].copy_from_slice(&reading);
@@ -0,0 +1,207 @@
+//! RFC 9176 resource directory registration.
+//!
+//! Nodes that know the broker's addr register with its resource directory
+//! as soon as they join, with a `POST /rd?ep=<eui>&et=<type>&lt=<lifetime>`
+//! that carries the same links they serve on `/.well-known/core`. The
+//! broker then observes them right away rather than on its next poll of
+//! the mesh. The directory answers 2.01 Created, with the registration
+//! resource (e.g. `/rd/3`) in Location-Path
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use coap_lite::{
+    CoapOption, ContentFormat, MessageClass, MessageType, Packet, RequestType, ResponseType,
+};
+
+use crate::discovery::{self, Link, LinkFormatError};
+
+/// Path of the registration interface
+pub const RD_PATH: &str = "rd";
+
+/// Port the broker serves its resource directory on, the CoAP default
+pub const RD_PORT: u16 = 5683;
+
+/// Lifetime (in seconds) of a registration that does not set `lt`, as
+/// in RFC 9176
+pub const DEFAULT_LIFETIME: u32 = 90_000;
+
+/// Endpoint type plant-minder nodes register as
+pub const ENDPOINT_TYPE: &str = "pmind.node";
+
+/// Why a request is not a valid registration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectoryError {
+    /// Not a POST to the registration interface
+    NotRegistration,
+    /// No `ep` query parameter
+    MissingEndpoint,
+    /// `lt` is not a number of seconds
+    InvalidLifetime,
+    /// Payload is not in link format
+    Links(LinkFormatError),
+}
+
+/// A node's registration with the resource directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    /// `ep` parameter, the node's EUI in hex
+    pub name: String,
+    /// `et` parameter
+    pub endpoint_type: Option<String>,
+    /// `lt` parameter, seconds the registration is kept without an update
+    pub lifetime: u32,
+    /// Resources the node serves
+    pub links: Vec<Link>,
+}
+
+impl Endpoint {
+    /// Registration of the node `eui` as a plant-minder node
+    pub fn new(eui: &[u8]) -> Self {
+        Self {
+            name: crate::service::instance_name(eui),
+            endpoint_type: Some(ENDPOINT_TYPE.to_string()),
+            lifetime: DEFAULT_LIFETIME,
+            links: Vec::new(),
+        }
+    }
+
+    pub fn links(mut self, links: &[Link]) -> Self {
+        self.links = links.to_vec();
+        self
+    }
+
+    pub fn lifetime(mut self, lifetime: u32) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Confirmable request registering the node, the response carries
+    /// `token`
+    pub fn register(&self, message_id: u16, token: &[u8]) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.set_type(MessageType::Confirmable);
+        packet.header.code = MessageClass::Request(RequestType::Post);
+        packet.header.message_id = message_id;
+        packet.set_token(token.to_vec());
+        packet.add_option(CoapOption::UriPath, RD_PATH.as_bytes().to_vec());
+        packet.add_option(
+            CoapOption::UriQuery,
+            format!("ep={}", self.name).into_bytes(),
+        );
+        if let Some(et) = &self.endpoint_type {
+            packet.add_option(CoapOption::UriQuery, format!("et={et}").into_bytes());
+        }
+        packet.add_option(
+            CoapOption::UriQuery,
+            format!("lt={}", self.lifetime).into_bytes(),
+        );
+        packet.set_content_format(ContentFormat::ApplicationLinkFormat);
+        packet.payload = discovery::encode(&self.links).into_bytes();
+        packet
+    }
+
+    /// The registration carried by `request`. Unknown query parameters are
+    /// ignored, as RFC 9176 allows for endpoint attributes
+    pub fn from_request(request: &Packet) -> Result<Self, DirectoryError> {
+        let path: Vec<_> = request
+            .get_option(CoapOption::UriPath)
+            .into_iter()
+            .flatten()
+            .collect();
+        if request.header.code != MessageClass::Request(RequestType::Post)
+            || path != [RD_PATH.as_bytes()]
+        {
+            return Err(DirectoryError::NotRegistration);
+        }
+
+        let (mut name, mut endpoint_type, mut lifetime) = (None, None, DEFAULT_LIFETIME);
+        for query in request
+            .get_option(CoapOption::UriQuery)
+            .into_iter()
+            .flatten()
+        {
+            let Ok(query) = core::str::from_utf8(query) else {
+                continue;
+            };
+            match query.split_once('=') {
+                Some(("ep", ep)) if !ep.is_empty() => name = Some(ep.to_string()),
+                Some(("et", et)) => endpoint_type = Some(et.to_string()),
+                Some(("lt", lt)) => {
+                    lifetime = lt.parse().map_err(|_| DirectoryError::InvalidLifetime)?
+                }
+                _ => {}
+            }
+        }
+
+        let links = core::str::from_utf8(&request.payload)
+            .map_err(|_| DirectoryError::Links(LinkFormatError::NotUtf8))
+            .and_then(|doc| discovery::parse(doc).map_err(DirectoryError::Links))?;
+        Ok(Self {
+            name: name.ok_or(DirectoryError::MissingEndpoint)?,
+            endpoint_type,
+            lifetime,
+            links,
+        })
+    }
+}
+
+/// Location of the registration the directory created, if `response` is
+/// a 2.01 Created
+pub fn location(response: &Packet) -> Option<String> {
+    if response.header.code != MessageClass::Response(ResponseType::Created) {
+        return None;
+    }
+    let mut location = String::new();
+    for seg in response.get_option(CoapOption::LocationPath)? {
+        location.push('/');
+        location.push_str(core::str::from_utf8(seg).ok()?);
+    }
+    Some(location)
+}
+
+#[cfg(test)]
+mod tests {
+    use coap_lite::{CoapOption, ContentFormat};
+
+    use super::{DirectoryError, Endpoint, DEFAULT_LIFETIME, ENDPOINT_TYPE};
+    use crate::Link;
+
+    #[test]
+    fn check_registration_round_trip() {
+        let endpoint = Endpoint::new(&[0x60, 0x55, 0xf9, 0xf7, 0x07, 0x78])
+            .links(&[Link::new("soil")
+                .resource_types(["atsamd10"])
+                .content_format(ContentFormat::ApplicationCBOR)
+                .observable()])
+            .lifetime(3600);
+        let request = endpoint.register(7, &[1, 2]);
+        assert_eq!(
+            request.get_content_format(),
+            Some(ContentFormat::ApplicationLinkFormat)
+        );
+
+        let parsed = Endpoint::from_request(&request).expect("Not a registration");
+        assert_eq!(parsed.name, "6055f9f70778");
+        assert_eq!(parsed.endpoint_type.as_deref(), Some(ENDPOINT_TYPE));
+        assert_eq!(parsed, endpoint);
+
+        // Only `ep` is required
+        let mut request = request.clone();
+        request.clear_option(CoapOption::UriQuery);
+        request.add_option(CoapOption::UriQuery, b"ep=node".to_vec());
+        let parsed = Endpoint::from_request(&request).expect("Not a registration");
+        assert_eq!(
+            (parsed.lifetime, parsed.endpoint_type),
+            (DEFAULT_LIFETIME, None)
+        );
+
+        request.clear_option(CoapOption::UriQuery);
+        assert_eq!(
+            Endpoint::from_request(&request),
+            Err(DirectoryError::MissingEndpoint)
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! RFC 6690 resource discovery.
+//!
+//! Nodes serve `/.well-known/core` in CoRE link format, one link per
+//! resource, e.g. `</soil>;rt="atsamd10";ct=50;obs,</light>;rt="tsl2591";ct=50;obs`.
+//! The `rt` (resource type) attribute lists the sensors behind a resource so
+//! the broker learns what a node was built with before any reading arrives
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use coap_lite::{ContentFormat, MessageClass, Packet, ResponseType};
+use core::fmt::{self, Write};
+
+/// Path nodes serve their link format on
+pub const WELL_KNOWN_CORE: &str = ".well-known/core";
+
+/// Why a discovery response could not be turned into links
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkFormatError {
+    /// Response code was not 2.05 Content, e.g. the node predates discovery
+    NotContent,
+    NotUtf8,
+    /// A link without a `<target>`
+    MissingTarget,
+}
+
+/// A single link of a CoRE link format document
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Link {
+    /// Target path, without the leading `/`
+    pub path: String,
+    /// `rt` attribute values
+    pub resource_types: Vec<String>,
+    /// `ct` attribute
+    pub content_format: Option<u16>,
+    /// `obs` attribute, the resource can be observed (RFC 7641 §6)
+    pub observable: bool,
+}
+
+impl Link {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.trim_start_matches('/').to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn resource_types<'a>(mut self, types: impl IntoIterator<Item = &'a str>) -> Self {
+        self.resource_types = types.into_iter().map(|t| t.to_string()).collect();
+        self
+    }
+
+    pub fn content_format(mut self, content_format: ContentFormat) -> Self {
+        self.content_format = Some(usize::from(content_format) as u16);
+        self
+    }
+
+    pub fn observable(mut self) -> Self {
+        self.observable = true;
+        self
+    }
+
+    fn parse(link: &str) -> Result<Self, LinkFormatError> {
+        let link = link.trim();
+        let rest = link
+            .strip_prefix('<')
+            .ok_or(LinkFormatError::MissingTarget)?;
+        let (target, params) = rest.split_once('>').ok_or(LinkFormatError::MissingTarget)?;
+        let mut parsed = Link::new(target);
+
+        for param in split_unquoted(params, ';') {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param.trim(), None),
+            };
+            match (name, value) {
+                ("rt", Some(value)) => {
+                    parsed.resource_types = value.split_whitespace().map(String::from).collect()
+                }
+                // `ct` may list several formats, the first is the preferred one
+                ("ct", Some(value)) => {
+                    parsed.content_format = value
+                        .split_whitespace()
+                        .next()
+                        .and_then(|ct| ct.parse().ok())
+                }
+                ("obs", _) => parsed.observable = true,
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "</{}>", self.path)?;
+        if !self.resource_types.is_empty() {
+            f.write_str(";rt=\"")?;
+            for (i, rt) in self.resource_types.iter().enumerate() {
+                if i > 0 {
+                    f.write_char(' ')?;
+                }
+                f.write_str(rt)?;
+            }
+            f.write_char('"')?;
+        }
+        if let Some(ct) = self.content_format {
+            write!(f, ";ct={ct}")?;
+        }
+        if self.observable {
+            f.write_str(";obs")?;
+        }
+        Ok(())
+    }
+}
+
+/// Split on `sep`, except inside quoted strings and `<>` targets
+fn split_unquoted(s: &str, sep: char) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    let mut target = false;
+    let mut start = 0;
+    let mut parts = Vec::new();
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '<' if !quoted => target = true,
+            '>' if !quoted => target = false,
+            c if c == sep && !quoted && !target => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts.into_iter().filter(|p| !p.trim().is_empty())
+}
+
+/// Serialize `links` as a link format document
+pub fn encode(links: &[Link]) -> String {
+    let mut doc = String::new();
+    for (i, link) in links.iter().enumerate() {
+        if i > 0 {
+            doc.push(',');
+        }
+        write!(doc, "{link}").ok();
+    }
+    doc
+}
+
+/// Parse a link format document
+pub fn parse(doc: &str) -> Result<Vec<Link>, LinkFormatError> {
+    split_unquoted(doc, ',').map(Link::parse).collect()
+}
+
+/// Links carried by a response to a `/.well-known/core` request
+pub fn links(response: &Packet) -> Result<Vec<Link>, LinkFormatError> {
+    if response.header.code != MessageClass::Response(ResponseType::Content) {
+        return Err(LinkFormatError::NotContent);
+    }
+    parse(core::str::from_utf8(&response.payload).map_err(|_| LinkFormatError::NotUtf8)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use coap_lite::ContentFormat;
+
+    use super::{encode, parse, Link, LinkFormatError};
+
+    #[test]
+    fn check_link_format_round_trip() {
+        let links = vec![
+            Link::new("/soilmoisture")
+                .resource_types(["atsamd10", "tsl2591"])
+                .content_format(ContentFormat::ApplicationJSON)
+                .observable(),
+            Link::new("info"),
+        ];
+        let doc = encode(&links);
+        assert_eq!(
+            doc,
+            r#"</soilmoisture>;rt="atsamd10 tsl2591";ct=50;obs,</info>"#
+        );
+        assert_eq!(parse(&doc), Ok(links));
+
+        // Quoted commas and unknown attributes do not confuse the parser
+        let parsed = parse(r#"</a>;title="x, y";ct="50 60", </b>;if=sensor"#).expect("Bad doc");
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].content_format, Some(50));
+        assert_eq!(parsed[1].path, "b");
+        assert_eq!(parse("soil"), Err(LinkFormatError::MissingTarget));
+    }
+}
@@ -0,0 +1,45 @@
+//! Protocol shared by the plant-minder sensor nodes and the broker.
+//!
+//! Nodes run a CoAP server and the broker observes the sensor resources
+//! on each node following RFC 7641 (see [`observe`]), after learning what
+//! the node serves from its `/.well-known/core` (see [`discovery`]). There
+//! is one resource per `pmindp_sensor::SensorClass`, named after the class,
+//! so each class can be observed and reported on independently, over
+//! exchanges protected end to end with per-node keys (see [`oscore`]).
+//! Nodes may also announce themselves to the broker's resource directory
+//! as they join (see [`directory`]), or advertise a DNS-SD service through
+//! the border router's SRP server (see [`service`]).
+//! Everything here is `no_std` + `alloc` so that the same logic runs on
+//! the esp32 firmware and can be exercised by host tests.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod directory;
+pub mod discovery;
+pub mod observe;
+pub mod oscore;
+pub mod reliability;
+pub mod service;
+
+pub use directory::{DirectoryError, Endpoint, RD_PORT};
+pub use discovery::{Link, LinkFormatError, WELL_KNOWN_CORE};
+pub use observe::{
+    is_fresh, requested_version, Notification, ObserveClient, Observer, ObserverRegistry,
+    RequestOutcome,
+};
+pub use oscore::{OscoreError, OscoreServer, Psk, SecurityContext};
+pub use reliability::{Deduplicator, Outbox, Retransmissions, TransmissionParams};
+
+/// Port that sensor nodes serve CoAP on
+pub const NODE_COAP_PORT: u16 = 1212;
+
+/// Max-Age (in seconds) the nodes attach to notifications, also the
+/// CoAP default (RFC 7252 §5.10.5)
+pub const DEFAULT_MAX_AGE: u32 = 60;
+
+/// Length of the tokens the broker generates for each observation
+pub const TOKEN_LEN: usize = 8;
+
+pub type Token = [u8; TOKEN_LEN];
@@ -0,0 +1,706 @@
+//! RFC 7641 Observe, client (broker) and server (node) sides.
+//!
+//! The client registers interest in a resource with a GET carrying
+//! `Observe: 0` and a token unique to that observation. The server answers
+//! with the current representation and then sends a notification (a 2.05
+//! response with the same token and an increasing `Observe` sequence
+//! number) whenever the resource changes. The client drops notifications
+//! that are older than one it already saw, re-registers when the Max-Age
+//! of the last notification lapses, and deregisters with `Observe: 1`.
+//!
+//! Neither side keeps a clock: callers pass the current time where it is
+//! needed, in seconds for Observe freshness and Max-Age and in
+//! milliseconds for the message layer ([`crate::reliability`])
+
+use alloc::{format, vec, vec::Vec};
+use coap_lite::{
+    CoapOption, ContentFormat, MessageClass, MessageType, Packet, RequestType, ResponseType,
+};
+
+use crate::{
+    discovery::{self, Link, WELL_KNOWN_CORE},
+    reliability::{Deduplicator, Outbox, TransmissionParams},
+    Token, DEFAULT_MAX_AGE,
+};
+
+/// Observe sequence numbers are 24 bits wide (RFC 7641 §4.4)
+pub const SEQ_MASK: u32 = 0x00ff_ffff;
+
+/// Half of the sequence number space, used by the freshness check
+const SEQ_HALF: u32 = 1 << 23;
+
+/// Notifications further apart than this are always considered fresh,
+/// regardless of sequence number (RFC 7641 §3.4)
+pub const FRESHNESS_WINDOW_SECS: u64 = 128;
+
+/// Slack past the Max-Age of the last notification before re-registering,
+/// to allow for the node's reporting interval drifting
+pub const MAX_AGE_GRACE_SECS: u64 = 5;
+
+const OBSERVE_REGISTER: u32 = 0;
+const OBSERVE_DEREGISTER: u32 = 1;
+
+/// URI-Query a registration asks for a protocol version with, e.g. `v=2`
+const VERSION_QUERY: &str = "v=";
+
+/// RFC 7641 §3.4: is a notification with sequence number `v2`, received
+/// `elapsed_secs` after one with `v1`, newer than it
+pub fn is_fresh(v1: u32, v2: u32, elapsed_secs: u64) -> bool {
+    (v1 < v2 && v2 - v1 < SEQ_HALF)
+        || (v1 > v2 && v1 - v2 > SEQ_HALF)
+        || elapsed_secs > FRESHNESS_WINDOW_SECS
+}
+
+/// Protocol version a registration asked for (see
+/// [`ObserveClient::version`]), `None` if it did not ask
+pub fn requested_version(request: &Packet) -> Option<u8> {
+    request
+        .get_option(CoapOption::UriQuery)?
+        .iter()
+        .filter_map(|q| core::str::from_utf8(q).ok()?.strip_prefix(VERSION_QUERY))
+        .find_map(|v| v.parse().ok())
+}
+
+/// Result of [`ObserveClient::handle_response`]
+#[derive(Debug, PartialEq)]
+pub enum Notification<'a> {
+    /// Fresh notification, hand the payload to the application
+    Fresh(&'a [u8]),
+    /// Reordered notification or stale repeat, drop it
+    Stale,
+    /// Retransmission of a message already handled (same message id), drop
+    /// it but acknowledge it again if confirmable
+    Duplicate,
+    /// Empty ACK or RST, only relevant to the message layer (see
+    /// [`Outbox::acknowledge`])
+    Empty,
+    /// Token is not ours; reject it with [`ObserveClient::reset`] so the
+    /// server drops whatever observation it belongs to
+    Unknown,
+    /// The server answered without `Observe` or with an error code, so
+    /// there is no longer an observation and the client must re-register
+    Ended,
+}
+
+/// Client side of a single observation, one per observed node
+#[derive(Debug, Clone)]
+pub struct ObserveClient {
+    token: Token,
+    path: &'static str,
+    message_id: u16,
+    /// Sequence number and receive time of the freshest notification
+    last: Option<(u32, u64)>,
+    max_age: u32,
+    dedup: Deduplicator<()>,
+    /// Protocol version to ask for when registering
+    version: Option<u8>,
+}
+
+impl ObserveClient {
+    /// `token` must be unique to this observation and should be random so
+    /// it cannot be guessed off the mesh; `message_id` seeds the ids used
+    /// for requests
+    pub fn new(token: Token, message_id: u16, path: &'static str) -> Self {
+        Self {
+            token,
+            path,
+            message_id,
+            last: None,
+            max_age: DEFAULT_MAX_AGE,
+            dedup: Deduplicator::new(&TransmissionParams::default()),
+            version: None,
+        }
+    }
+
+    /// Ask the server for protocol `version` in every registration, the
+    /// server answers with the version it will speak
+    pub fn version(mut self, version: u8) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn request(&mut self, path: &str, observe: Option<u32>) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.set_type(MessageType::Confirmable);
+        packet.header.code = MessageClass::Request(RequestType::Get);
+        packet.header.message_id = self.message_id;
+        self.message_id = self.message_id.wrapping_add(1);
+        packet.set_token(self.token.to_vec());
+        if let Some(observe) = observe {
+            packet.set_observe_value(observe);
+        }
+        for seg in path.split('/').filter(|s| !s.is_empty()) {
+            packet.add_option(CoapOption::UriPath, seg.as_bytes().to_vec());
+        }
+        packet
+    }
+
+    /// Request to start (or refresh) the observation. Sequence state is kept
+    /// so notifications still in flight from before are judged correctly
+    pub fn register(&mut self) -> Packet {
+        let mut packet = self.request(self.path, Some(OBSERVE_REGISTER));
+        if let Some(version) = self.version {
+            packet.add_option(
+                CoapOption::UriQuery,
+                format!("{VERSION_QUERY}{version}").into_bytes(),
+            );
+        }
+        packet
+    }
+
+    /// Request to end the observation
+    pub fn deregister(&mut self) -> Packet {
+        self.request(self.path, Some(OBSERVE_DEREGISTER))
+    }
+
+    /// Request for the server's `/.well-known/core`, carrying this
+    /// observation's token so it is routed like the notifications. The
+    /// response is read with [`discovery::links`], not
+    /// [`ObserveClient::handle_response`]
+    pub fn discover(&mut self) -> Packet {
+        self.request(WELL_KNOWN_CORE, None)
+    }
+
+    /// Process a response or notification received at `now` (seconds)
+    pub fn handle_response<'a>(&mut self, packet: &'a Packet, now: u64) -> Notification<'a> {
+        if packet.header.code == MessageClass::Empty {
+            return Notification::Empty;
+        }
+        if packet.get_token() != self.token {
+            return Notification::Unknown;
+        }
+
+        // Piggybacked responses carry our own message id, only messages the
+        // server originated can be retransmissions
+        if packet.header.get_type() != MessageType::Acknowledgement {
+            let message_id = packet.header.message_id;
+            if self.dedup.duplicate(&(), message_id, now * 1000).is_some() {
+                return Notification::Duplicate;
+            }
+            self.dedup.record((), message_id, now * 1000, None);
+        }
+
+        let ok = matches!(
+            packet.header.code,
+            MessageClass::Response(ResponseType::Content)
+        );
+        let seq = match packet.get_observe_value() {
+            Some(Ok(seq)) if ok => seq & SEQ_MASK,
+            _ => {
+                self.last = None;
+                return Notification::Ended;
+            }
+        };
+
+        if let Some((last_seq, last_time)) = self.last {
+            if !is_fresh(last_seq, seq, now.saturating_sub(last_time)) {
+                return Notification::Stale;
+            }
+        }
+
+        self.max_age = packet
+            .get_first_option(CoapOption::MaxAge)
+            .map(|v| v.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32))
+            .unwrap_or(DEFAULT_MAX_AGE);
+        self.last = Some((seq, now));
+        Notification::Fresh(&packet.payload)
+    }
+
+    /// The last notification is older than its Max-Age (plus
+    /// [`MAX_AGE_GRACE_SECS`]), so the observation may have been lost on
+    /// the server and should be refreshed with [`ObserveClient::register`]
+    pub fn reregistration_due(&self, now: u64) -> bool {
+        match self.last {
+            Some((_, last)) => now > last + self.max_age as u64 + MAX_AGE_GRACE_SECS,
+            None => false,
+        }
+    }
+
+    /// Empty ACK for a confirmable notification
+    pub fn ack(packet: &Packet) -> Option<Packet> {
+        if packet.header.get_type() != MessageType::Confirmable {
+            return None;
+        }
+        Some(ObserveClient::empty(packet, MessageType::Acknowledgement))
+    }
+
+    /// Empty RST rejecting `packet`
+    pub fn reset(packet: &Packet) -> Packet {
+        ObserveClient::empty(packet, MessageType::Reset)
+    }
+
+    fn empty(packet: &Packet, message_type: MessageType) -> Packet {
+        let mut empty = Packet::new();
+        empty.header.set_type(message_type);
+        empty.header.code = MessageClass::Empty;
+        empty.header.message_id = packet.header.message_id;
+        empty
+    }
+}
+
+/// Server side record of a single observer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observer<A> {
+    pub addr: A,
+    pub token: Vec<u8>,
+    /// Resource being observed
+    pub path: &'static str,
+    /// Message id of the last notification, to match resets against
+    last_message_id: u16,
+}
+
+/// Result of [`ObserverRegistry::handle_request`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestOutcome {
+    Registered,
+    Deregistered,
+    /// Plain GET without `Observe`
+    Read,
+    /// GET of `/.well-known/core`
+    Discovery,
+    NotFound,
+    /// Retransmission of a request already handled, the response is the
+    /// same as the first time
+    Duplicate,
+    /// Not a request, e.g. an ACK or RST, or a method other than GET
+    Ignored,
+}
+
+/// Server side of Observe for the resources of a node. `A` is the address
+/// type of the transport the server runs on, e.g. `SocketAddr`
+#[derive(Debug, Clone)]
+pub struct ObserverRegistry<A> {
+    /// Observable resources and the Max-Age of their notifications
+    resources: Vec<(&'static str, u32)>,
+    observers: Vec<Observer<A>>,
+    /// One sequence for all resources, it only has to increase per
+    /// observation
+    seq: u32,
+    message_id: u16,
+    /// Pending confirmable notifications, `None` to notify with NON
+    outbox: Option<Outbox<A>>,
+    dedup: Deduplicator<A>,
+    /// Link format served on `/.well-known/core`, if discoverable
+    links: Option<Vec<u8>>,
+}
+
+impl<A: Clone + PartialEq> ObserverRegistry<A> {
+    /// Registry serving the observable resource `path`, notified every
+    /// `max_age` seconds at most
+    pub fn new(path: &'static str, max_age: u32) -> Self {
+        Self {
+            resources: vec![(path, max_age)],
+            observers: vec![],
+            seq: 0,
+            message_id: 0,
+            outbox: None,
+            dedup: Deduplicator::new(&TransmissionParams::default()),
+            links: None,
+        }
+    }
+
+    /// Also serve the observable resource `path`, each resource is
+    /// observed and notified independently. Serving a path again only
+    /// updates its Max-Age
+    pub fn resource(mut self, path: &'static str, max_age: u32) -> Self {
+        match self.resources.iter_mut().find(|(p, _)| *p == path) {
+            Some(resource) => resource.1 = max_age,
+            None => self.resources.push((path, max_age)),
+        }
+        self
+    }
+
+    /// Also serve `links` on `/.well-known/core` (RFC 6690)
+    pub fn discoverable(mut self, links: &[Link]) -> Self {
+        self.links = Some(discovery::encode(links).into_bytes());
+        self
+    }
+
+    /// Send notifications as CON, retransmitted per `params` until
+    /// acknowledged (see [`ObserverRegistry::poll`]). An observer that never
+    /// acknowledges is dropped (RFC 7641 §4.5). `seed` should differ between
+    /// nodes, e.g. derived from the EUI
+    pub fn confirmable(mut self, params: TransmissionParams, seed: u32) -> Self {
+        self.outbox = Some(Outbox::new(params, seed));
+        self.dedup = Deduplicator::new(&params);
+        // Spread message ids too, so restarts do not reuse recent ones
+        self.message_id = seed as u16;
+        self
+    }
+
+    pub fn observers(&self) -> &[Observer<A>] {
+        &self.observers
+    }
+
+    /// Is anyone observing `path`, i.e. is it worth reading its sensors
+    pub fn is_observed(&self, path: &str) -> bool {
+        self.observers.iter().any(|o| o.path == path)
+    }
+
+    fn next_seq(&mut self) -> u32 {
+        self.seq = (self.seq + 1) & SEQ_MASK;
+        self.seq
+    }
+
+    fn next_message_id(&mut self) -> u16 {
+        self.message_id = self.message_id.wrapping_add(1);
+        self.message_id
+    }
+
+    /// Handle a message from `from` received at `now_ms`, returning the
+    /// outcome and the response to send back. `payload` is the current
+    /// representation of the resource, described by `content_format`
+    pub fn handle_request(
+        &mut self,
+        request: &Packet,
+        from: A,
+        payload: &[u8],
+        content_format: Option<ContentFormat>,
+        now_ms: u64,
+    ) -> (RequestOutcome, Option<Packet>) {
+        let message_type = request.header.get_type();
+        match message_type {
+            MessageType::Reset => {
+                self.handle_reset(request, &from);
+                return (RequestOutcome::Ignored, None);
+            }
+            MessageType::Acknowledgement => {
+                if let Some(outbox) = self.outbox.as_mut() {
+                    outbox.acknowledge(&from, request.header.message_id);
+                }
+                return (RequestOutcome::Ignored, None);
+            }
+            _ => {}
+        }
+        if !matches!(request.header.code, MessageClass::Request(RequestType::Get)) {
+            return (RequestOutcome::Ignored, None);
+        }
+
+        if let Some(response) = self
+            .dedup
+            .duplicate(&from, request.header.message_id, now_ms)
+        {
+            return (RequestOutcome::Duplicate, response.cloned());
+        }
+        let (outcome, response) = self.handle_get(request, from.clone(), payload, content_format);
+        self.dedup
+            .record(from, request.header.message_id, now_ms, response.clone());
+        (outcome, response)
+    }
+
+    fn handle_get(
+        &mut self,
+        request: &Packet,
+        from: A,
+        payload: &[u8],
+        content_format: Option<ContentFormat>,
+    ) -> (RequestOutcome, Option<Packet>) {
+        let message_type = request.header.get_type();
+
+        let mut response = Packet::new();
+        response.header.set_type(match message_type {
+            MessageType::Confirmable => MessageType::Acknowledgement,
+            _ => MessageType::NonConfirmable,
+        });
+        response.header.message_id = match message_type {
+            MessageType::Confirmable => request.header.message_id,
+            _ => self.next_message_id(),
+        };
+        response.set_token(request.get_token().to_vec());
+
+        let path = request
+            .get_option(CoapOption::UriPath)
+            .map(|segs| {
+                segs.iter()
+                    .map(|s| core::str::from_utf8(s).unwrap_or_default())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .unwrap_or_default();
+        if let (WELL_KNOWN_CORE, Some(links)) = (path.as_str(), &self.links) {
+            response.header.code = MessageClass::Response(ResponseType::Content);
+            response.set_content_format(ContentFormat::ApplicationLinkFormat);
+            response.payload = links.clone();
+            return (RequestOutcome::Discovery, Some(response));
+        }
+        let Some(&(path, max_age)) = self.resources.iter().find(|(p, _)| *p == path) else {
+            response.header.code = MessageClass::Response(ResponseType::NotFound);
+            return (RequestOutcome::NotFound, Some(response));
+        };
+
+        let token = request.get_token().to_vec();
+        // Observations are identified by endpoint and token (RFC 7641 §4.1)
+        self.observers
+            .retain(|o| !(o.addr == from && o.token == token));
+
+        response.header.code = MessageClass::Response(ResponseType::Content);
+        if let Some(content_format) = content_format {
+            response.set_content_format(content_format);
+        }
+        response.payload = payload.to_vec();
+
+        let outcome = match request.get_observe_value() {
+            Some(Ok(OBSERVE_REGISTER)) => {
+                let seq = self.next_seq();
+                response.set_observe_value(seq);
+                ObserverRegistry::<A>::set_max_age(&mut response, max_age);
+                self.observers.push(Observer {
+                    addr: from,
+                    token,
+                    path,
+                    last_message_id: response.header.message_id,
+                });
+                RequestOutcome::Registered
+            }
+            Some(Ok(OBSERVE_DEREGISTER)) => RequestOutcome::Deregistered,
+            _ => RequestOutcome::Read,
+        };
+        (outcome, Some(response))
+    }
+
+    /// An observer rejected a notification with RST, stop notifying it
+    pub fn handle_reset(&mut self, reset: &Packet, from: &A) {
+        if let Some(outbox) = self.outbox.as_mut() {
+            outbox.acknowledge(from, reset.header.message_id);
+        }
+        self.observers
+            .retain(|o| !(o.addr == *from && o.last_message_id == reset.header.message_id));
+    }
+
+    /// Build a notification carrying `payload` for every observer of
+    /// `path`, in order. `content_format` describes the payload.
+    /// Confirmable notifications are tracked from `now_ms` for
+    /// retransmission
+    pub fn notify(
+        &mut self,
+        path: &str,
+        payload: &[u8],
+        content_format: ContentFormat,
+        now_ms: u64,
+    ) -> Vec<(A, Packet)> {
+        let message_type = match self.outbox {
+            Some(_) => MessageType::Confirmable,
+            None => MessageType::NonConfirmable,
+        };
+        let Some(&(_, max_age)) = self.resources.iter().find(|(p, _)| *p == path) else {
+            return Vec::new();
+        };
+        let seq = self.next_seq();
+        let mut notifications = Vec::new();
+        for i in 0..self.observers.len() {
+            if self.observers[i].path != path {
+                continue;
+            }
+            let message_id = self.next_message_id();
+            let observer = &mut self.observers[i];
+            observer.last_message_id = message_id;
+
+            let mut packet = Packet::new();
+            packet.header.set_type(message_type);
+            packet.header.code = MessageClass::Response(ResponseType::Content);
+            packet.header.message_id = message_id;
+            packet.set_token(observer.token.clone());
+            packet.set_observe_value(seq);
+            ObserverRegistry::<A>::set_max_age(&mut packet, max_age);
+            packet.set_content_format(content_format);
+            packet.payload = payload.to_vec();
+            if let Some(outbox) = self.outbox.as_mut() {
+                outbox.send(observer.addr.clone(), packet.clone(), now_ms);
+            }
+            notifications.push((observer.addr.clone(), packet));
+        }
+        notifications
+    }
+
+    /// Earliest time a confirmable notification is due for retransmission
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.outbox.as_ref().and_then(|o| o.next_deadline())
+    }
+
+    /// Notifications due for retransmission at `now_ms`. Observers that
+    /// did not acknowledge within the retransmission limit are dropped
+    pub fn poll(&mut self, now_ms: u64) -> Vec<(A, Packet)> {
+        let Some(outbox) = self.outbox.as_mut() else {
+            return Vec::new();
+        };
+        let polled = outbox.poll(now_ms);
+        for (addr, packet) in polled.failed {
+            self.observers
+                .retain(|o| !(o.addr == addr && o.token == packet.get_token()));
+        }
+        polled.resend
+    }
+
+    fn set_max_age(packet: &mut Packet, max_age: u32) {
+        let bytes = max_age.to_be_bytes();
+        let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+        packet.clear_option(CoapOption::MaxAge);
+        packet.add_option(CoapOption::MaxAge, bytes[start..].to_vec());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coap_lite::{ContentFormat, MessageType, Packet};
+
+    use super::{
+        is_fresh, requested_version, Notification, ObserveClient, ObserverRegistry, RequestOutcome,
+    };
+    use crate::{discovery, Link, TransmissionParams};
+
+    const TOKEN: [u8; 8] = [0xde, 0xad, 0xbe, 0xef, 0x01, 0x02, 0x03, 0x04];
+    const SOIL: &str = "soil";
+    const LIGHT: &str = "light";
+
+    /// Encode and decode, as every packet crosses the wire in practice
+    fn wire(packet: &Packet) -> Packet {
+        Packet::from_bytes(&packet.to_bytes().expect("Unable to encode")).expect("Bad packet")
+    }
+
+    #[test]
+    fn check_freshness() {
+        assert!(is_fresh(1, 2, 0));
+        assert!(!is_fresh(2, 2, 0));
+        assert!(!is_fresh(3, 2, 0));
+        // Wrapped around the 24 bit space
+        assert!(is_fresh(0x00ff_fffe, 0x1, 0));
+        assert!(!is_fresh(0x1, 0x00ff_fffe, 0));
+        // Old enough that the sequence number no longer matters
+        assert!(is_fresh(3, 2, 129));
+    }
+
+    #[test]
+    fn check_register_notify_deregister() {
+        let mut client = ObserveClient::new(TOKEN, 100, SOIL).version(2);
+        let links = [Link::new(SOIL).observable(), Link::new(LIGHT).observable()];
+        let mut server = ObserverRegistry::new(SOIL, 30)
+            .resource(LIGHT, 120)
+            .discoverable(&links);
+
+        let (outcome, resp) =
+            server.handle_request(&wire(&client.discover()), "broker", b"", None, 0);
+        assert_eq!(outcome, RequestOutcome::Discovery);
+        let resp = wire(&resp.expect("No discovery response"));
+        assert_eq!(discovery::links(&resp), Ok(links.to_vec()));
+
+        let register = wire(&client.register());
+        assert_eq!(requested_version(&register), Some(2));
+        let (outcome, resp) = server.handle_request(
+            &register,
+            "broker",
+            b"hello",
+            Some(ContentFormat::ApplicationCBOR),
+            0,
+        );
+        assert_eq!(outcome, RequestOutcome::Registered);
+        let resp = wire(&resp.expect("No registration response"));
+        assert_eq!(resp.header.get_type(), MessageType::Acknowledgement);
+        assert_eq!(
+            resp.get_content_format(),
+            Some(ContentFormat::ApplicationCBOR)
+        );
+        assert_eq!(
+            client.handle_response(&resp, 0),
+            Notification::Fresh(b"hello")
+        );
+
+        // Only observers of the notified resource are notified
+        assert!(server.is_observed(SOIL) && !server.is_observed(LIGHT));
+        assert!(server
+            .notify(LIGHT, b"{}", ContentFormat::ApplicationJSON, 0)
+            .is_empty());
+        let notifications = server.notify(SOIL, b"{}", ContentFormat::ApplicationJSON, 0);
+        assert_eq!(notifications.len(), 1);
+        let first = wire(&notifications[0].1);
+        assert_eq!(notifications[0].0, "broker");
+        assert_eq!(
+            client.handle_response(&first, 10),
+            Notification::Fresh(b"{}")
+        );
+
+        // A retransmitted notification is a duplicate, a reordered one stale
+        assert_eq!(client.handle_response(&first, 11), Notification::Duplicate);
+        let mut reordered = wire(&first);
+        reordered.header.message_id = first.header.message_id.wrapping_sub(1);
+        assert_eq!(client.handle_response(&reordered, 11), Notification::Stale);
+        let newer = wire(&server.notify(SOIL, b"[]", ContentFormat::ApplicationJSON, 0)[0].1);
+        assert_eq!(
+            client.handle_response(&newer, 12),
+            Notification::Fresh(b"[]")
+        );
+
+        // Max-Age of 30 plus grace
+        assert!(!client.reregistration_due(40));
+        assert!(client.reregistration_due(48));
+
+        let (outcome, _) =
+            server.handle_request(&wire(&client.deregister()), "broker", b"", None, 0);
+        assert_eq!(outcome, RequestOutcome::Deregistered);
+        assert!(server
+            .notify(SOIL, b"{}", ContentFormat::ApplicationJSON, 0)
+            .is_empty());
+    }
+
+    #[test]
+    fn check_reset_and_unknown_token() {
+        let mut client = ObserveClient::new(TOKEN, 1, SOIL);
+        let mut other = ObserveClient::new([0x1; 8], 1, SOIL);
+        let mut server = ObserverRegistry::new(SOIL, 60);
+        server.handle_request(&wire(&other.register()), 7u16, b"", None, 0);
+
+        // Client rejects a notification that is not for it
+        let stray = wire(&server.notify(SOIL, b"{}", ContentFormat::ApplicationJSON, 0)[0].1);
+        assert_eq!(client.handle_response(&stray, 0), Notification::Unknown);
+        server.handle_request(&wire(&ObserveClient::reset(&stray)), 7u16, b"", None, 0);
+        assert!(server.observers().is_empty());
+
+        // Unknown resources are refused and end the observation
+        let mut lost = ObserveClient::new(TOKEN, 1, "nope");
+        let (outcome, resp) = server.handle_request(&wire(&lost.register()), 8u16, b"", None, 0);
+        assert_eq!(outcome, RequestOutcome::NotFound);
+        assert_eq!(
+            lost.handle_response(&wire(&resp.expect("No response")), 0),
+            Notification::Ended
+        );
+    }
+
+    #[test]
+    fn check_confirmable_notifications() {
+        let mut client = ObserveClient::new(TOKEN, 1, SOIL);
+        let mut server =
+            ObserverRegistry::new(SOIL, 60).confirmable(TransmissionParams::default(), 0x60f7);
+        server.handle_request(&wire(&client.register()), "broker", b"", None, 0);
+
+        // Acknowledged notifications are not retransmitted
+        let (_, notification) = server
+            .notify(SOIL, b"{}", ContentFormat::ApplicationJSON, 0)
+            .remove(0);
+        assert_eq!(notification.header.get_type(), MessageType::Confirmable);
+        let ack = ObserveClient::ack(&notification).expect("No ACK for CON");
+        server.handle_request(&wire(&ack), "broker", b"", None, 10);
+        assert!(server.next_deadline().is_none());
+
+        // Retransmitted until the limit, then the observer is dropped
+        server.notify(SOIL, b"{}", ContentFormat::ApplicationJSON, 100);
+        let mut resent = 0;
+        while let Some(deadline) = server.next_deadline() {
+            resent += server.poll(deadline).len();
+        }
+        assert_eq!(resent, 4);
+        assert!(server.observers().is_empty());
+
+        // A retransmitted request gets the same response
+        let request = wire(&client.register());
+        let (_, first) = server.handle_request(&request, "broker", b"", None, 0);
+        let (outcome, again) = server.handle_request(&request, "broker", b"", None, 1);
+        assert_eq!(outcome, RequestOutcome::Duplicate);
+        assert_eq!(
+            first.map(|p| p.to_bytes().expect("Bad packet")),
+            again.map(|p| p.to_bytes().expect("Bad packet"))
+        );
+    }
+}
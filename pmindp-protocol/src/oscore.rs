@@ -0,0 +1,1093 @@
+//! Object security for the CoAP exchanges between broker and nodes with
+//! OSCORE (RFC 8613), checked against the test vectors of its Appendix C.
+//!
+//! Every node is provisioned with a pre-shared key ([`Psk`]) that the
+//! broker keeps by the node's EUI, which is also the node's Sender ID. The
+//! PSK is the Master Secret, with no Master Salt. Requests, responses and
+//! notifications are encrypted and authenticated with AES-CCM-16-64-128
+//! under the keys and Common IV derived from it (§3.2), with the nonce and
+//! AAD of §5. Responses always carry their own Partial IV, which §8.3
+//! allows and notifications need anyway. Replays are detected as §7.4
+//! describes. Only empty ACKs and RSTs are sent in the clear.
+//!
+//! RFC 8613 leaves how a context is established to the application, and
+//! here plant-minder has its own convention. Nodes restart their sequence
+//! numbers when they reboot, so the keys of a session must never outlive
+//! it: the ID Context of a session joins a nonce of the broker to one the
+//! node draws at boot, as in Appendix B.2, but the node hands out its nonce
+//! rather than negotiating it with a 4.01 exchange. The node advertises its
+//! Sender ID and nonce in an OSCORE option on its response to the
+//! (unprotected) discovery of `/.well-known/core`, and the broker opens the
+//! session with its first protected request (see [`SecurityContext`] and,
+//! for nodes, [`OscoreServer`])
+
+use aes::Aes128;
+use alloc::{string::String, vec, vec::Vec};
+use ccm::{
+    aead::{generic_array::GenericArray, AeadInPlace, KeyInit},
+    consts::{U13, U8},
+    Ccm,
+};
+use coap_lite::{CoapOption, MessageClass, MessageType, Packet, RequestType, ResponseType};
+use core::fmt;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::{
+    discovery::WELL_KNOWN_CORE,
+    reliability::{Deduplicator, TransmissionParams},
+};
+
+/// Length of the pre-shared keys and of the keys derived from them
+pub const KEY_LEN: usize = 16;
+
+pub type Psk = [u8; KEY_LEN];
+
+/// Sender ID of the broker, nodes use their EUI
+pub const BROKER_ID: &[u8] = b"";
+
+/// Length of the nonce each side contributes to the ID Context of a
+/// session
+pub const SESSION_NONCE_LEN: usize = 8;
+
+/// AES-CCM-16-64-128 (RFC 8152 §10.2)
+const ALG_AES_CCM_16_64_128: u8 = 10;
+const NONCE_LEN: usize = 13;
+const TAG_LEN: usize = 8;
+/// Longest Sender ID that fits in the AEAD nonce
+const MAX_ID_LEN: usize = NONCE_LEN - 6;
+/// Partial IVs are at most 5 bytes long
+const MAX_PARTIAL_IV: u64 = (1 << 40) - 1;
+/// Partial IVs older than the newest received by this much are replays
+const REPLAY_WINDOW: u64 = 32;
+/// Requests remembered per context for the responses to bind to, one
+/// per observation is all the broker needs
+const MAX_BOUND_REQUESTS: usize = 16;
+
+// Class U options (RFC 8613 §4.1.3) are left outside the encryption, for
+// proxies to read. Everything else but the OSCORE option is Class E
+const CLASS_U: [CoapOption; 4] = [
+    CoapOption::UriHost,
+    CoapOption::UriPort,
+    CoapOption::ProxyUri,
+    CoapOption::ProxyScheme,
+];
+
+// Flags of the OSCORE option (RFC 8613 §6.1)
+const FLAG_PARTIAL_IV_LEN: u8 = 0x07;
+const FLAG_KID: u8 = 0x08;
+const FLAG_KID_CONTEXT: u8 = 0x10;
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const CBOR_NULL: u8 = 0xf6;
+
+type AesCcm = Ccm<Aes128, U8, U13>;
+
+/// Why a message could not be protected or verified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OscoreError {
+    /// No OSCORE option, the message was sent in the clear
+    NotProtected,
+    /// OSCORE option or protected message could not be parsed
+    Malformed,
+    /// Request for another Sender ID or ID Context than this session's,
+    /// e.g. from before the node rebooted
+    UnknownContext,
+    /// Response with a token that matches no protected request
+    UnknownRequest,
+    /// Partial IV already received, or too old to tell
+    Replay,
+    /// Authentication tag did not verify: wrong key or tampered with
+    Unauthenticated,
+    /// Sequence numbers are used up, a new session is needed
+    SequenceExhausted,
+}
+
+/// Value of the OSCORE option (RFC 8613 §6.1)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OscoreOption {
+    pub partial_iv: Option<u64>,
+    pub kid: Option<Vec<u8>>,
+    pub kid_context: Option<Vec<u8>>,
+}
+
+impl OscoreOption {
+    /// OSCORE option of `packet`, `None` if it has none
+    pub fn of(packet: &Packet) -> Result<Option<Self>, OscoreError> {
+        packet
+            .get_first_option(CoapOption::Oscore)
+            .map(|value| OscoreOption::parse(value))
+            .transpose()
+    }
+
+    fn parse(value: &[u8]) -> Result<Self, OscoreError> {
+        let Some((&flags, mut rest)) = value.split_first() else {
+            return Ok(OscoreOption::default());
+        };
+        let mut option = OscoreOption::default();
+        let piv_len = (flags & FLAG_PARTIAL_IV_LEN) as usize;
+        if piv_len > 5 || rest.len() < piv_len {
+            return Err(OscoreError::Malformed);
+        }
+        if piv_len > 0 {
+            let (piv, tail) = rest.split_at(piv_len);
+            option.partial_iv = Some(piv.iter().fold(0, |acc, b| (acc << 8) | *b as u64));
+            rest = tail;
+        }
+        if flags & FLAG_KID_CONTEXT != 0 {
+            let (&len, tail) = rest.split_first().ok_or(OscoreError::Malformed)?;
+            if tail.len() < len as usize {
+                return Err(OscoreError::Malformed);
+            }
+            let (kid_context, tail) = tail.split_at(len as usize);
+            option.kid_context = Some(kid_context.to_vec());
+            rest = tail;
+        }
+        if flags & FLAG_KID != 0 {
+            option.kid = Some(rest.to_vec());
+        } else if !rest.is_empty() {
+            return Err(OscoreError::Malformed);
+        }
+        Ok(option)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let piv = self.partial_iv.map(partial_iv_bytes).unwrap_or_default();
+        let mut flags = piv.len() as u8;
+        if self.kid.is_some() {
+            flags |= FLAG_KID;
+        }
+        if self.kid_context.is_some() {
+            flags |= FLAG_KID_CONTEXT;
+        }
+        if flags == 0 {
+            return Vec::new();
+        }
+        let mut value = vec![flags];
+        value.extend_from_slice(&piv);
+        if let Some(kid_context) = &self.kid_context {
+            value.push(kid_context.len() as u8);
+            value.extend_from_slice(kid_context);
+        }
+        if let Some(kid) = &self.kid {
+            value.extend_from_slice(kid);
+        }
+        value
+    }
+}
+
+/// Advertise the node's Sender ID `kid` and session `nonce` on the
+/// response to an unprotected discovery, see [`advertised`]
+pub fn advertise(response: &mut Packet, kid: &[u8], nonce: &[u8]) {
+    let option = OscoreOption {
+        partial_iv: None,
+        kid: Some(kid.to_vec()),
+        kid_context: Some(nonce.to_vec()),
+    };
+    response.clear_option(CoapOption::Oscore);
+    response.add_option(CoapOption::Oscore, option.encode());
+}
+
+/// Sender ID and session nonce a node advertised on `response`. This is
+/// unauthenticated, a node that lies about it fails the first protected
+/// exchange
+pub fn advertised(response: &Packet) -> Option<(Vec<u8>, Vec<u8>)> {
+    match OscoreOption::of(response) {
+        Ok(Some(OscoreOption {
+            partial_iv: None,
+            kid: Some(kid),
+            kid_context: Some(nonce),
+        })) => Some((kid, nonce)),
+        _ => None,
+    }
+}
+
+/// Parse a key written as 32 hex digits, e.g. from a node's build config
+pub fn parse_psk(hex: &str) -> Option<Psk> {
+    let mut psk = [0u8; KEY_LEN];
+    if hex.len() != 2 * KEY_LEN {
+        return None;
+    }
+    for (i, byte) in psk.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(psk)
+}
+
+/// Partial IVs of the recipient seen so far, RFC 8613 §7.4
+#[derive(Debug, Clone, Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    /// Bit `n` is set if `highest - n` was received
+    seen: u32,
+}
+
+impl ReplayWindow {
+    fn is_replay(&self, piv: u64) -> bool {
+        match self.highest {
+            Some(highest) if piv <= highest => {
+                let age = highest - piv;
+                age >= REPLAY_WINDOW || self.seen & (1 << age) != 0
+            }
+            _ => false,
+        }
+    }
+
+    fn record(&mut self, piv: u64) {
+        match self.highest {
+            Some(highest) if piv <= highest => self.seen |= 1 << (highest - piv),
+            Some(highest) => {
+                let shift = piv - highest;
+                self.seen = if shift >= REPLAY_WINDOW {
+                    1
+                } else {
+                    (self.seen << shift) | 1
+                };
+                self.highest = Some(piv);
+            }
+            None => {
+                self.highest = Some(piv);
+                self.seen = 1;
+            }
+        }
+    }
+}
+
+/// Keys, sequence number and replay window of one side of a session
+/// (RFC 8613 §3)
+#[derive(Clone)]
+pub struct SecurityContext {
+    id_context: Option<Vec<u8>>,
+    sender_id: Vec<u8>,
+    recipient_id: Vec<u8>,
+    sender_key: [u8; KEY_LEN],
+    recipient_key: [u8; KEY_LEN],
+    common_iv: [u8; NONCE_LEN],
+    /// Sender sequence number, the Partial IV of the next message sent
+    seq: u64,
+    replay: ReplayWindow,
+    /// Last request sent or received with each token, that responses to
+    /// it are bound to
+    requests: Vec<BoundRequest>,
+}
+
+/// A request responses are bound to (RFC 8613 §5.4), by its token
+#[derive(Debug, Clone)]
+struct BoundRequest {
+    token: Vec<u8>,
+    kid: Vec<u8>,
+    piv: u64,
+    /// Partial IV of the newest notification received for the request,
+    /// its Notification Number (RFC 8613 §7.4.1)
+    notification: Option<u64>,
+}
+
+impl fmt::Debug for SecurityContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecurityContext")
+            .field("id_context", &self.id_context)
+            .field("sender_id", &self.sender_id)
+            .field("recipient_id", &self.recipient_id)
+            .field("seq", &self.seq)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SecurityContext {
+    /// Derive the context of the session `id_context` from `psk`, used as
+    /// the Master Secret with no Master Salt. Sender IDs longer than 7
+    /// bytes do not fit the nonce
+    pub fn new(
+        psk: &Psk,
+        id_context: &[u8],
+        sender_id: &[u8],
+        recipient_id: &[u8],
+    ) -> Result<Self, OscoreError> {
+        SecurityContext::derive(psk, &[], Some(id_context), sender_id, recipient_id)
+    }
+
+    /// Derive the context as RFC 8613 §3.2 does, from any Master Secret
+    /// and Master Salt and with the ID Context left out if `None`
+    fn derive(
+        secret: &[u8],
+        salt: &[u8],
+        id_context: Option<&[u8]>,
+        sender_id: &[u8],
+        recipient_id: &[u8],
+    ) -> Result<Self, OscoreError> {
+        if sender_id.len() > MAX_ID_LEN || recipient_id.len() > MAX_ID_LEN {
+            return Err(OscoreError::Malformed);
+        }
+        let mut context = SecurityContext {
+            id_context: id_context.map(<[u8]>::to_vec),
+            sender_id: sender_id.to_vec(),
+            recipient_id: recipient_id.to_vec(),
+            sender_key: [0; KEY_LEN],
+            recipient_key: [0; KEY_LEN],
+            common_iv: [0; NONCE_LEN],
+            seq: 0,
+            replay: ReplayWindow::default(),
+            requests: Vec::new(),
+        };
+        let hkdf = Hkdf::<Sha256>::new(Some(salt), secret);
+        expand(&hkdf, id_context, sender_id, "Key", &mut context.sender_key);
+        expand(
+            &hkdf,
+            id_context,
+            recipient_id,
+            "Key",
+            &mut context.recipient_key,
+        );
+        expand(&hkdf, id_context, &[], "IV", &mut context.common_iv);
+        Ok(context)
+    }
+
+    pub fn id_context(&self) -> &[u8] {
+        self.id_context.as_deref().unwrap_or_default()
+    }
+
+    pub fn recipient_id(&self) -> &[u8] {
+        &self.recipient_id
+    }
+
+    /// Protected form of `packet`: the code, options and payload are
+    /// encrypted, leaving the message type, id, token and `Observe` outside.
+    /// Requests open (or refresh) the binding of their token, responses
+    /// must answer a request bound to theirs
+    pub fn protect(&mut self, packet: &Packet) -> Result<Packet, OscoreError> {
+        let piv = self.seq;
+        if piv > MAX_PARTIAL_IV {
+            return Err(OscoreError::SequenceExhausted);
+        }
+        let token = packet.get_token();
+        let is_request = matches!(packet.header.code, MessageClass::Request(_));
+        let (request_kid, request_piv) = match is_request {
+            true => (self.sender_id.clone(), piv),
+            false => self
+                .bound(token)
+                .map(|r| (r.kid.clone(), r.piv))
+                .ok_or(OscoreError::UnknownRequest)?,
+        };
+
+        let mut ciphertext = plaintext(packet)?;
+        let tag = AesCcm::new(GenericArray::from_slice(&self.sender_key))
+            .encrypt_in_place_detached(
+                GenericArray::from_slice(&self.nonce(&self.sender_id, piv)),
+                &aad(&request_kid, request_piv),
+                &mut ciphertext,
+            )
+            .map_err(|_| OscoreError::Malformed)?;
+        ciphertext.extend_from_slice(&tag);
+        self.seq += 1;
+        if is_request {
+            self.bind(token, request_kid, request_piv);
+        }
+
+        let observe = match packet.get_observe_value() {
+            Some(Ok(observe)) => Some(observe),
+            _ => None,
+        };
+        let mut protected = Packet::new();
+        protected.header.set_type(packet.header.get_type());
+        protected.header.message_id = packet.header.message_id;
+        protected.header.code = match (is_request, observe.is_some()) {
+            (true, true) => MessageClass::Request(RequestType::Fetch),
+            (true, false) => MessageClass::Request(RequestType::Post),
+            (false, true) => MessageClass::Response(ResponseType::Content),
+            (false, false) => MessageClass::Response(ResponseType::Changed),
+        };
+        protected.set_token(token.to_vec());
+        if let Some(observe) = observe {
+            protected.set_observe_value(observe);
+        }
+        for option in CLASS_U {
+            if let Some(values) = packet.get_option(option) {
+                protected.set_option(option, values.clone());
+            }
+        }
+        let option = OscoreOption {
+            partial_iv: Some(piv),
+            kid: is_request.then(|| self.sender_id.clone()),
+            kid_context: self.id_context.clone().filter(|_| is_request),
+        };
+        protected.add_option(CoapOption::Oscore, option.encode());
+        protected.payload = ciphertext;
+        Ok(protected)
+    }
+
+    /// Verify and decrypt a protected `packet`, back to the message as it
+    /// was sent. Replays are detected as RFC 8613 §7.4 does: requests by a
+    /// sliding window of their Partial IVs, notifications by the
+    /// Notification Number of their observation, and other responses as
+    /// only one is taken per request
+    pub fn unprotect(&mut self, packet: &Packet) -> Result<Packet, OscoreError> {
+        let option = OscoreOption::of(packet)?.ok_or(OscoreError::NotProtected)?;
+        let piv = option.partial_iv.ok_or(OscoreError::Malformed)?;
+        let token = packet.get_token();
+        let is_request = matches!(packet.header.code, MessageClass::Request(_));
+        let is_notification = !is_request && packet.get_observe_value().is_some();
+        let (request_kid, request_piv) = match is_request {
+            true => {
+                if option.kid.as_deref() != Some(self.recipient_id.as_slice())
+                    || option.kid_context != self.id_context
+                {
+                    return Err(OscoreError::UnknownContext);
+                }
+                if self.replay.is_replay(piv) {
+                    return Err(OscoreError::Replay);
+                }
+                (self.recipient_id.clone(), piv)
+            }
+            false => {
+                let request = self.bound(token).ok_or(OscoreError::UnknownRequest)?;
+                if is_notification && request.notification.is_some_and(|n| piv <= n) {
+                    return Err(OscoreError::Replay);
+                }
+                (request.kid.clone(), request.piv)
+            }
+        };
+        if packet.payload.len() <= TAG_LEN {
+            return Err(OscoreError::Malformed);
+        }
+
+        let (ciphertext, tag) = packet.payload.split_at(packet.payload.len() - TAG_LEN);
+        let mut plaintext = ciphertext.to_vec();
+        AesCcm::new(GenericArray::from_slice(&self.recipient_key))
+            .decrypt_in_place_detached(
+                GenericArray::from_slice(&self.nonce(&self.recipient_id, piv)),
+                &aad(&request_kid, request_piv),
+                &mut plaintext,
+                GenericArray::from_slice(tag),
+            )
+            .map_err(|_| OscoreError::Unauthenticated)?;
+        match (is_request, is_notification) {
+            (true, _) => {
+                self.replay.record(piv);
+                self.bind(token, request_kid, request_piv);
+            }
+            (false, true) => {
+                if let Some(request) = self.requests.iter_mut().find(|r| r.token == token) {
+                    request.notification = Some(piv);
+                }
+            }
+            (false, false) => self.requests.retain(|r| r.token != token),
+        }
+
+        // The plaintext is the code, options and payload of the message,
+        // give it back a header to parse it with
+        let mut bytes = vec![0x40, plaintext[0], 0, 0];
+        bytes.extend_from_slice(&plaintext[1..]);
+        let mut unprotected = Packet::from_bytes(&bytes).map_err(|_| OscoreError::Malformed)?;
+        unprotected.header.set_type(packet.header.get_type());
+        unprotected.header.message_id = packet.header.message_id;
+        unprotected.set_token(token.to_vec());
+        for option in CLASS_U {
+            if let Some(values) = packet.get_option(option) {
+                unprotected.set_option(option, values.clone());
+            }
+        }
+        Ok(unprotected)
+    }
+
+    fn bind(&mut self, token: &[u8], kid: Vec<u8>, piv: u64) {
+        self.requests.retain(|r| r.token != token);
+        if self.requests.len() >= MAX_BOUND_REQUESTS {
+            self.requests.remove(0);
+        }
+        self.requests.push(BoundRequest {
+            token: token.to_vec(),
+            kid,
+            piv,
+            notification: None,
+        });
+    }
+
+    fn bound(&self, token: &[u8]) -> Option<&BoundRequest> {
+        self.requests.iter().find(|r| r.token == token)
+    }
+
+    /// AEAD nonce of the message `id` sent with Partial IV `piv`
+    fn nonce(&self, id: &[u8], piv: u64) -> [u8; NONCE_LEN] {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[0] = id.len() as u8;
+        nonce[1 + MAX_ID_LEN - id.len()..1 + MAX_ID_LEN].copy_from_slice(id);
+        nonce[1 + MAX_ID_LEN..].copy_from_slice(&piv.to_be_bytes()[3..]);
+        for (n, iv) in nonce.iter_mut().zip(self.common_iv) {
+            *n ^= iv;
+        }
+        nonce
+    }
+}
+
+/// HKDF-SHA256 expansion of the `kind` key or IV for `id` into `out`,
+/// RFC 8613 §3.2.1
+fn expand(hkdf: &Hkdf<Sha256>, id_context: Option<&[u8]>, id: &[u8], kind: &str, out: &mut [u8]) {
+    let mut info = Vec::new();
+    cbor_head(&mut info, MAJOR_ARRAY, 5);
+    cbor_bytes(&mut info, id);
+    match id_context {
+        Some(id_context) => cbor_bytes(&mut info, id_context),
+        None => info.push(CBOR_NULL),
+    }
+    cbor_head(&mut info, MAJOR_UNSIGNED, ALG_AES_CCM_16_64_128 as u64);
+    cbor_head(&mut info, MAJOR_TEXT, kind.len() as u64);
+    info.extend_from_slice(kind.as_bytes());
+    cbor_head(&mut info, MAJOR_UNSIGNED, out.len() as u64);
+    // Only fails for outputs longer than 255 hashes
+    hkdf.expand(&info, out).ok();
+}
+
+/// COSE Enc_structure the AEAD authenticates, RFC 8613 §5.4
+fn aad(request_kid: &[u8], request_piv: u64) -> Vec<u8> {
+    let mut external = Vec::new();
+    cbor_head(&mut external, MAJOR_ARRAY, 5);
+    cbor_head(&mut external, MAJOR_UNSIGNED, 1);
+    cbor_head(&mut external, MAJOR_ARRAY, 1);
+    cbor_head(&mut external, MAJOR_UNSIGNED, ALG_AES_CCM_16_64_128 as u64);
+    cbor_bytes(&mut external, request_kid);
+    cbor_bytes(&mut external, &partial_iv_bytes(request_piv));
+    // No class I options
+    cbor_bytes(&mut external, &[]);
+
+    let mut aad = Vec::new();
+    cbor_head(&mut aad, MAJOR_ARRAY, 3);
+    cbor_head(&mut aad, MAJOR_TEXT, 8);
+    aad.extend_from_slice(b"Encrypt0");
+    cbor_bytes(&mut aad, &[]);
+    cbor_bytes(&mut aad, &external);
+    aad
+}
+
+/// Code, Class E options and payload of `packet`, as they follow the
+/// header on the wire
+fn plaintext(packet: &Packet) -> Result<Vec<u8>, OscoreError> {
+    let mut inner = Packet::new();
+    inner.header.code = packet.header.code;
+    for (number, values) in packet.options() {
+        let option = CoapOption::from(*number);
+        if option != CoapOption::Oscore && !CLASS_U.contains(&option) {
+            inner.set_option(option, values.clone());
+        }
+    }
+    inner.payload = packet.payload.clone();
+    let bytes = inner.to_bytes().map_err(|_| OscoreError::Malformed)?;
+    let mut plaintext = vec![bytes[1]];
+    plaintext.extend_from_slice(&bytes[4..]);
+    Ok(plaintext)
+}
+
+/// Shortest big endian encoding, zero is a single byte
+fn partial_iv_bytes(piv: u64) -> Vec<u8> {
+    let bytes = piv.to_be_bytes();
+    let start = bytes
+        .iter()
+        .position(|b| *b != 0)
+        .unwrap_or(bytes.len() - 1);
+    bytes[start..].to_vec()
+}
+
+fn cbor_head(out: &mut Vec<u8>, major: u8, value: u64) {
+    match value {
+        0..=23 => out.push((major << 5) | value as u8),
+        24..=0xff => out.extend_from_slice(&[(major << 5) | 24, value as u8]),
+        _ => {
+            out.push((major << 5) | 25);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+    }
+}
+
+fn cbor_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    cbor_head(out, MAJOR_BYTES, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Node side of OSCORE: answers the unprotected discovery with the node's
+/// Sender ID and session nonce, verifies every other request, and
+/// protects whatever is sent back. The node keeps the context of the
+/// broker session that last opened one
+#[derive(Clone)]
+pub struct OscoreServer<A> {
+    psk: Psk,
+    sender_id: Vec<u8>,
+    nonce: [u8; SESSION_NONCE_LEN],
+    context: Option<SecurityContext>,
+    /// Protected responses sent, to answer retransmitted requests with
+    /// (their Partial IV would otherwise be a replay)
+    dedup: Deduplicator<A>,
+}
+
+impl<A: Clone + PartialEq> OscoreServer<A> {
+    /// Server for the node `sender_id` (its EUI) provisioned with `psk`.
+    /// `nonce` must be random and drawn anew at every boot
+    pub fn new(
+        psk: Psk,
+        sender_id: &[u8],
+        nonce: [u8; SESSION_NONCE_LEN],
+        params: &TransmissionParams,
+    ) -> Self {
+        Self {
+            psk,
+            sender_id: sender_id.to_vec(),
+            nonce,
+            context: None,
+            dedup: Deduplicator::new(params),
+        }
+    }
+
+    /// Is a broker session open, i.e. can notifications be protected
+    pub fn is_open(&self) -> bool {
+        self.context.is_some()
+    }
+
+    /// Handle `request` from `from` received at `now_ms`: `serve` is
+    /// handed the request as the broker sent it (e.g. to
+    /// [`crate::ObserverRegistry::handle_request`]) and its response is
+    /// protected. Returns the outcome of `serve`, `None` if the request
+    /// never reached it, and the response to send. Unprotected requests for
+    /// anything but `/.well-known/core` and requests that do not verify
+    /// get 4.01 Unauthorized
+    pub fn handle<R>(
+        &mut self,
+        request: &Packet,
+        from: A,
+        now_ms: u64,
+        serve: impl FnOnce(&Packet) -> (R, Option<Packet>),
+    ) -> (Option<R>, Option<Packet>) {
+        // ACKs and RSTs are never protected
+        if !matches!(request.header.code, MessageClass::Request(_)) {
+            let (outcome, response) = serve(request);
+            return (Some(outcome), response);
+        }
+        let message_id = request.header.message_id;
+        if let Some(response) = self.dedup.duplicate(&from, message_id, now_ms) {
+            return (None, response.cloned());
+        }
+
+        let (outcome, response) = match OscoreOption::of(request) {
+            Ok(None) if path(request) == WELL_KNOWN_CORE => {
+                let (outcome, mut response) = serve(request);
+                if let Some(response) = response.as_mut() {
+                    advertise(response, &self.sender_id, &self.nonce);
+                }
+                (Some(outcome), response)
+            }
+            Ok(Some(option)) => match self.unprotect(request, &option) {
+                Ok(request) => {
+                    let (outcome, response) = serve(&request);
+                    let response = response.and_then(|r| self.protect(&r));
+                    (Some(outcome), response)
+                }
+                // Retransmissions were answered above, so this is an attack
+                Err(OscoreError::Replay) => return (None, None),
+                Err(_) => (None, Some(unauthorized(request))),
+            },
+            _ => (None, Some(unauthorized(request))),
+        };
+        self.dedup
+            .record(from, message_id, now_ms, response.clone());
+        (outcome, response)
+    }
+
+    /// Protect a notification (or any response) for the open session
+    pub fn protect(&mut self, packet: &Packet) -> Option<Packet> {
+        self.context.as_mut()?.protect(packet).ok()
+    }
+
+    /// Verify `request` with the open session's context, or open a new one
+    /// if the request verifies under the ID Context it names
+    fn unprotect(
+        &mut self,
+        request: &Packet,
+        option: &OscoreOption,
+    ) -> Result<Packet, OscoreError> {
+        let kid_context = option.kid_context.as_deref().unwrap_or_default();
+        if let Some(context) = self
+            .context
+            .as_mut()
+            .filter(|c| c.id_context() == kid_context)
+        {
+            return context.unprotect(request);
+        }
+        // Sessions are only opened for this boot's nonce
+        if kid_context.len() != 2 * SESSION_NONCE_LEN
+            || kid_context[SESSION_NONCE_LEN..] != self.nonce
+        {
+            return Err(OscoreError::UnknownContext);
+        }
+        let kid = option.kid.as_deref().unwrap_or_default();
+        let mut context = SecurityContext::new(&self.psk, kid_context, &self.sender_id, kid)?;
+        let request = context.unprotect(request)?;
+        self.context = Some(context);
+        Ok(request)
+    }
+}
+
+fn path(packet: &Packet) -> String {
+    packet
+        .get_option(CoapOption::UriPath)
+        .map(|segs| {
+            segs.iter()
+                .map(|s| core::str::from_utf8(s).unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .unwrap_or_default()
+}
+
+fn unauthorized(request: &Packet) -> Packet {
+    let mut response = Packet::new();
+    response.header.set_type(match request.header.get_type() {
+        MessageType::Confirmable => MessageType::Acknowledgement,
+        _ => MessageType::NonConfirmable,
+    });
+    response.header.message_id = request.header.message_id;
+    response.header.code = MessageClass::Response(ResponseType::Unauthorized);
+    response.set_token(request.get_token().to_vec());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use coap_lite::{CoapOption, ContentFormat, MessageClass, Packet, ResponseType};
+
+    use super::{
+        advertised, OscoreError, OscoreServer, SecurityContext, BROKER_ID, SESSION_NONCE_LEN,
+        TAG_LEN,
+    };
+    use crate::{
+        discovery, Link, Notification, ObserveClient, ObserverRegistry, TransmissionParams,
+    };
+
+    const EUI: [u8; 6] = [0x60, 0x55, 0xf9, 0x01, 0x02, 0x03];
+    const PSK: [u8; 16] = [0x42; 16];
+    const NODE_NONCE: [u8; SESSION_NONCE_LEN] = [0x0d; SESSION_NONCE_LEN];
+    const SOIL: &str = "soil";
+
+    fn wire(packet: &Packet) -> Packet {
+        Packet::from_bytes(&packet.to_bytes().expect("Unable to encode")).expect("Bad packet")
+    }
+
+    #[test]
+    fn check_protected_observation() {
+        let mut node = OscoreServer::new(PSK, &EUI, NODE_NONCE, &TransmissionParams::default());
+        let mut registry = ObserverRegistry::new(SOIL, 60).discoverable(&[Link::new(SOIL)]);
+        let mut client = ObserveClient::new([0x5a; 8], 1, SOIL);
+
+        // Discovery is in the clear and tells the broker which key to use
+        let (_, response) = node.handle(&wire(&client.discover()), "broker", 0, |r| {
+            registry.handle_request(r, "broker", b"", None, 0)
+        });
+        let response = wire(&response.expect("No discovery response"));
+        assert!(discovery::links(&response).is_ok());
+        let (kid, nonce) = advertised(&response).expect("Nothing advertised");
+        assert_eq!(
+            (kid.as_slice(), nonce.as_slice()),
+            (&EUI[..], &NODE_NONCE[..])
+        );
+        let id_context = [[0xb0; SESSION_NONCE_LEN], NODE_NONCE].concat();
+        let mut broker =
+            SecurityContext::new(&PSK, &id_context, BROKER_ID, &kid).expect("Bad context");
+
+        // Unprotected registrations are refused
+        let (outcome, response) = node.handle(&wire(&client.register()), "broker", 0, |r| {
+            registry.handle_request(r, "broker", b"", None, 0)
+        });
+        assert!(outcome.is_none() && registry.observers().is_empty());
+        assert_eq!(
+            response.map(|r| r.header.code),
+            Some(MessageClass::Response(ResponseType::Unauthorized))
+        );
+
+        let register = wire(
+            &broker
+                .protect(&client.register())
+                .expect("Unable to protect"),
+        );
+        assert!(register
+            .get_option(coap_lite::CoapOption::UriPath)
+            .is_none());
+        let (_, response) = node.handle(&register, "broker", 0, |r| {
+            registry.handle_request(r, "broker", b"Jade", None, 0)
+        });
+        let response = broker
+            .unprotect(&wire(&response.expect("No registration response")))
+            .expect("Registration response does not verify");
+        assert_eq!(
+            client.handle_response(&response, 0),
+            Notification::Fresh(b"Jade")
+        );
+
+        // A retransmitted registration gets the same response, a replayed
+        // one after it is dropped
+        let (outcome, again) = node.handle(
+            &register,
+            "broker",
+            1,
+            |_: &Packet| -> ((), Option<Packet>) { unreachable!() },
+        );
+        assert!(outcome.is_none() && again.is_some());
+        let (_, replayed) = node.handle(
+            &register,
+            "stranger",
+            1,
+            |_: &Packet| -> ((), Option<Packet>) { unreachable!() },
+        );
+        assert!(replayed.is_none());
+
+        let (_, notification) = registry
+            .notify(SOIL, b"{}", ContentFormat::ApplicationJSON, 0)
+            .remove(0);
+        let notification = wire(&node.protect(&notification).expect("No session"));
+        assert_ne!(notification.payload, b"{}");
+        let plain = broker
+            .unprotect(&notification)
+            .expect("Notification does not verify");
+        assert_eq!(
+            client.handle_response(&plain, 1),
+            Notification::Fresh(b"{}")
+        );
+        assert_eq!(
+            broker.unprotect(&notification).err(),
+            Some(OscoreError::Replay)
+        );
+
+        // Anyone else on the mesh can neither tamper nor forge, and failed
+        // attempts do not use up the genuine Partial IV
+        let (_, next) = registry
+            .notify(SOIL, b"[]", ContentFormat::ApplicationJSON, 0)
+            .remove(0);
+        let next = wire(&node.protect(&next).expect("No session"));
+        let mut tampered = next.clone();
+        tampered.payload[0] ^= 1;
+        assert_eq!(
+            broker.unprotect(&tampered).err(),
+            Some(OscoreError::Unauthenticated)
+        );
+        assert!(broker.unprotect(&next).is_ok());
+        let mut stranger =
+            SecurityContext::new(&[0x13; 16], &id_context, &EUI, BROKER_ID).expect("Bad context");
+        stranger.bind(&[0x5a; 8], BROKER_ID.to_vec(), 0);
+        stranger.seq = 100;
+        let (_, mut spoofed) = registry
+            .notify(SOIL, b"{\"moisture\":1}", ContentFormat::ApplicationJSON, 0)
+            .remove(0);
+        spoofed = stranger.protect(&spoofed).expect("Unable to protect");
+        assert_eq!(
+            broker.unprotect(&spoofed).err(),
+            Some(OscoreError::Unauthenticated)
+        );
+        assert_eq!(
+            broker.unprotect(&client.register()).err(),
+            Some(OscoreError::NotProtected)
+        );
+
+        // A session from before the node rebooted is not accepted
+        let mut rebooted = OscoreServer::new(PSK, &EUI, [0x0e; 8], &Default::default());
+        let (_, response) = rebooted.handle(
+            &wire(
+                &broker
+                    .protect(&client.register())
+                    .expect("Unable to protect"),
+            ),
+            "broker",
+            0,
+            |_: &Packet| -> ((), Option<Packet>) { unreachable!() },
+        );
+        assert_eq!(
+            response.map(|r| r.header.code),
+            Some(MessageClass::Response(ResponseType::Unauthorized))
+        );
+    }
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("Bad hex"))
+            .collect()
+    }
+
+    /// Contexts of the client and the server of RFC 8613 Appendix C.1
+    fn rfc_contexts() -> (SecurityContext, SecurityContext) {
+        let secret = hex("0102030405060708090a0b0c0d0e0f10");
+        let salt = hex("9e7ca92223786340");
+        (
+            SecurityContext::derive(&secret, &salt, None, &[], &[0x01]).expect("Bad context"),
+            SecurityContext::derive(&secret, &salt, None, &[0x01], &[]).expect("Bad context"),
+        )
+    }
+
+    #[test]
+    fn check_rfc8613_test_vectors() {
+        // C.1.1, key derivation
+        let (mut client, mut server) = rfc_contexts();
+        assert_eq!(
+            client.sender_key.to_vec(),
+            hex("f0910ed7295e6ad4b54fc793154302ff")
+        );
+        assert_eq!(
+            client.recipient_key.to_vec(),
+            hex("ffb14e093c94c9cac9471648b4f98710")
+        );
+        assert_eq!(client.common_iv.to_vec(), hex("4622d4dd6d944168eefb54987c"));
+        assert_eq!(
+            (server.sender_key, server.recipient_key),
+            (client.recipient_key, client.sender_key)
+        );
+
+        // C.4, a request with Uri-Host left outside
+        client.seq = 20;
+        let request = Packet::from_bytes(&hex("44015d1f00003974396c6f63616c686f737483747631"))
+            .expect("Bad request");
+        let protected = client.protect(&request).expect("Unable to protect");
+        assert_eq!(
+            protected.to_bytes().expect("Unable to encode"),
+            hex("44025d1f00003974396c6f63616c686f7374620914ff612f1092f1776f1c1668b3825e")
+        );
+        let unprotected = server
+            .unprotect(&protected)
+            .expect("Request does not verify");
+        assert_eq!(unprotected.to_bytes().ok(), request.to_bytes().ok());
+
+        // C.8, a response with its own Partial IV
+        let response = Packet::from_bytes(&hex("64455d1f00003974ff48656c6c6f20576f726c6421"))
+            .expect("Bad response");
+        let protected = server.protect(&response).expect("Unable to protect");
+        assert_eq!(
+            protected.to_bytes().expect("Unable to encode"),
+            hex("64445d1f00003974920100ff4d4c13669384b67354b2b6175ff4b8658c666a6cf88e")
+        );
+        let unprotected = client
+            .unprotect(&protected)
+            .expect("Response does not verify");
+        assert_eq!(unprotected.payload, b"Hello World!");
+    }
+
+    fn notification(token: &[u8], observe: u32) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.code = MessageClass::Response(ResponseType::Content);
+        packet.set_token(token.to_vec());
+        packet.set_observe_value(observe);
+        packet.payload = b"{}".to_vec();
+        packet
+    }
+
+    #[test]
+    fn check_rejected_messages() {
+        let id_context = [[0xb0; SESSION_NONCE_LEN], NODE_NONCE].concat();
+        let context = |psk: &[u8; 16], sender: &[u8], recipient: &[u8]| {
+            SecurityContext::new(psk, &id_context, sender, recipient).expect("Bad context")
+        };
+        let mut broker = context(&PSK, BROKER_ID, &EUI);
+        let mut node = context(&PSK, &EUI, BROKER_ID);
+        let mut client = ObserveClient::new([0x5a; 8], 1, SOIL);
+
+        // Replayed requests, and requests older than the replay window
+        let request = wire(
+            &broker
+                .protect(&client.register())
+                .expect("Unable to protect"),
+        );
+        assert!(node.unprotect(&request).is_ok());
+        assert_eq!(node.unprotect(&request).err(), Some(OscoreError::Replay));
+        broker.seq = 100;
+        assert!(node
+            .unprotect(
+                &broker
+                    .protect(&client.register())
+                    .expect("Unable to protect")
+            )
+            .is_ok());
+        broker.seq = 10;
+        let stale = broker
+            .protect(&client.register())
+            .expect("Unable to protect");
+        assert_eq!(node.unprotect(&stale).err(), Some(OscoreError::Replay));
+
+        // Tampered ciphertext or tag, and a payload too short for the tag,
+        // do not use up the genuine Partial IV
+        broker.seq = 101;
+        let request = broker
+            .protect(&client.register())
+            .expect("Unable to protect");
+        let last = request.payload.len() - 1;
+        for byte in [0, last] {
+            let mut tampered = request.clone();
+            tampered.payload[byte] ^= 0x80;
+            assert_eq!(
+                node.unprotect(&tampered).err(),
+                Some(OscoreError::Unauthenticated)
+            );
+        }
+        let mut short = request.clone();
+        short.payload.truncate(TAG_LEN);
+        assert_eq!(node.unprotect(&short).err(), Some(OscoreError::Malformed));
+        assert!(node.unprotect(&request).is_ok());
+
+        // Another key, Sender ID or ID Context
+        let request = broker
+            .protect(&client.register())
+            .expect("Unable to protect");
+        let mut wrong_key = context(&[0x13; 16], &EUI, BROKER_ID);
+        assert_eq!(
+            wrong_key.unprotect(&request).err(),
+            Some(OscoreError::Unauthenticated)
+        );
+        let mut wrong_kid = context(&PSK, &EUI, b"x");
+        assert_eq!(
+            wrong_kid.unprotect(&request).err(),
+            Some(OscoreError::UnknownContext)
+        );
+        let mut wrong_context =
+            SecurityContext::new(&PSK, &[0xb1; 16], &EUI, BROKER_ID).expect("Bad context");
+        assert_eq!(
+            wrong_context.unprotect(&request).err(),
+            Some(OscoreError::UnknownContext)
+        );
+
+        // Truncated options: a Partial IV or kid context cut short
+        for option in [&[0x03, 0x01][..], &[0x19, 0x00, 0x08, 0x01]] {
+            let mut truncated = request.clone();
+            truncated.clear_option(CoapOption::Oscore);
+            truncated.add_option(CoapOption::Oscore, option.to_vec());
+            assert_eq!(
+                node.unprotect(&truncated).err(),
+                Some(OscoreError::Malformed)
+            );
+        }
+
+        // Notifications must be newer than the last of their observation,
+        // other responses are taken once, and only for a request
+        assert!(node.unprotect(&request).is_ok());
+        let token = client.register().get_token().to_vec();
+        let older = node
+            .protect(&notification(&token, 2))
+            .expect("Unable to protect");
+        let newer = node
+            .protect(&notification(&token, 3))
+            .expect("Unable to protect");
+        assert!(broker.unprotect(&newer).is_ok());
+        assert_eq!(broker.unprotect(&older).err(), Some(OscoreError::Replay));
+        let mut response = notification(&token, 0);
+        response.clear_option(CoapOption::Observe);
+        let response = node.protect(&response).expect("Unable to protect");
+        assert!(broker.unprotect(&response).is_ok());
+        assert_eq!(
+            broker.unprotect(&response).err(),
+            Some(OscoreError::UnknownRequest)
+        );
+        assert_eq!(
+            node.protect(&notification(&[0x01], 4)).err(),
+            Some(OscoreError::UnknownRequest)
+        );
+        let mut unknown = newer.clone();
+        unknown.set_token(vec![0x01]);
+        assert_eq!(
+            broker.unprotect(&unknown).err(),
+            Some(OscoreError::UnknownRequest)
+        );
+    }
+}
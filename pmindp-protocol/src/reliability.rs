@@ -0,0 +1,325 @@
+//! RFC 7252 §4 message layer reliability: retransmission of confirmable
+//! messages with exponential backoff, and deduplication of received
+//! messages by message id.
+//!
+//! Like [`crate::observe`], nothing here keeps a clock. Callers pass the
+//! current time in milliseconds and poll the [`Outbox`] for due
+//! retransmissions, e.g. when [`Outbox::next_deadline`] passes
+
+use alloc::{collections::VecDeque, vec::Vec};
+use coap_lite::{MessageType, Packet};
+
+/// RFC 7252 §4.8 defaults
+pub const ACK_TIMEOUT_MS: u64 = 2_000;
+pub const ACK_RANDOM_FACTOR: f32 = 1.5;
+pub const MAX_RETRANSMIT: u8 = 4;
+
+/// Upper bound on remembered exchanges per [`Deduplicator`], so memory
+/// stays bounded on the nodes
+const DEDUP_CAPACITY: usize = 16;
+
+/// Transmission parameters (RFC 7252 §4.8), configurable so that slow or
+/// sleepy links can be given more time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransmissionParams {
+    pub ack_timeout_ms: u64,
+    /// Initial timeouts are picked between `ack_timeout_ms` and
+    /// `ack_timeout_ms * ack_random_factor` so peers do not retransmit in
+    /// lockstep
+    pub ack_random_factor: f32,
+    pub max_retransmit: u8,
+}
+
+impl Default for TransmissionParams {
+    fn default() -> Self {
+        Self {
+            ack_timeout_ms: ACK_TIMEOUT_MS,
+            ack_random_factor: ACK_RANDOM_FACTOR,
+            max_retransmit: MAX_RETRANSMIT,
+        }
+    }
+}
+
+impl TransmissionParams {
+    /// MAX_TRANSMIT_SPAN: time from the first transmission to the last
+    /// retransmission of a confirmable message
+    pub fn max_transmit_span_ms(&self) -> u64 {
+        let backoff = (1u64 << self.max_retransmit) - 1;
+        (self.ack_timeout_ms as f32 * backoff as f32 * self.ack_random_factor) as u64
+    }
+
+    /// MAX_TRANSMIT_WAIT: time from the first transmission until the sender
+    /// gives up on an acknowledgement
+    pub fn max_transmit_wait_ms(&self) -> u64 {
+        let backoff = (1u64 << (self.max_retransmit + 1)) - 1;
+        (self.ack_timeout_ms as f32 * backoff as f32 * self.ack_random_factor) as u64
+    }
+
+    /// EXCHANGE_LIFETIME: how long a message id must be remembered for
+    /// deduplication (with the RFC default MAX_LATENCY and PROCESSING_DELAY)
+    pub fn exchange_lifetime_ms(&self) -> u64 {
+        self.max_transmit_span_ms() + 2 * 100_000 + self.ack_timeout_ms
+    }
+}
+
+/// Confirmable message awaiting an ACK (or RST)
+#[derive(Debug, Clone)]
+struct Pending<A> {
+    to: A,
+    packet: Packet,
+    retransmits: u8,
+    timeout_ms: u64,
+    deadline_ms: u64,
+}
+
+/// Result of [`Outbox::poll`]
+#[derive(Debug, Default)]
+pub struct Retransmissions<A> {
+    /// Messages to send again
+    pub resend: Vec<(A, Packet)>,
+    /// Messages that were never acknowledged after `max_retransmit`
+    /// retransmissions, the peer should be considered unreachable
+    pub failed: Vec<(A, Packet)>,
+}
+
+/// Confirmable messages sent to one or more peers, retransmitted with
+/// exponential backoff until acknowledged
+#[derive(Debug, Clone)]
+pub struct Outbox<A> {
+    params: TransmissionParams,
+    pending: Vec<Pending<A>>,
+    /// xorshift state for picking the initial timeout
+    state: u32,
+}
+
+impl<A: Clone + PartialEq> Outbox<A> {
+    /// `seed` should differ between peers sharing a link, e.g. derived
+    /// from an EUI or a random source
+    pub fn new(params: TransmissionParams, seed: u32) -> Self {
+        Self {
+            params,
+            pending: Vec::new(),
+            // xorshift state must never be zero
+            state: seed | 1,
+        }
+    }
+
+    pub fn params(&self) -> &TransmissionParams {
+        &self.params
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    fn initial_timeout(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        let unit = (self.state >> 8) as f32 / (1u32 << 24) as f32;
+        let factor = 1.0 + unit * (self.params.ack_random_factor - 1.0);
+        (self.params.ack_timeout_ms as f32 * factor) as u64
+    }
+
+    /// Track `packet`, which the caller sends now. Non-confirmable packets
+    /// are ignored. A pending message to the same peer with the same token
+    /// is superseded, keeping its backoff state (RFC 7641 §4.5.2), so only
+    /// the newest notification is retransmitted
+    pub fn send(&mut self, to: A, packet: Packet, now_ms: u64) {
+        if packet.header.get_type() != MessageType::Confirmable {
+            return;
+        }
+        if let Some(p) = self
+            .pending
+            .iter_mut()
+            .find(|p| p.to == to && p.packet.get_token() == packet.get_token())
+        {
+            p.packet = packet;
+            return;
+        }
+
+        let timeout_ms = self.initial_timeout();
+        self.pending.push(Pending {
+            to,
+            packet,
+            retransmits: 0,
+            timeout_ms,
+            deadline_ms: now_ms + timeout_ms,
+        });
+    }
+
+    /// An ACK or RST for `message_id` arrived from `from`, stop
+    /// retransmitting and return the acknowledged message
+    pub fn acknowledge(&mut self, from: &A, message_id: u16) -> Option<Packet> {
+        let idx = self
+            .pending
+            .iter()
+            .position(|p| p.to == *from && p.packet.header.message_id == message_id)?;
+        Some(self.pending.swap_remove(idx).packet)
+    }
+
+    /// Is a message with `token` to `to` still awaiting acknowledgement
+    pub fn is_pending(&self, to: &A, token: &[u8]) -> bool {
+        self.pending
+            .iter()
+            .any(|p| p.to == *to && p.packet.get_token() == token)
+    }
+
+    /// Drop every pending message to `to`, e.g. when the peer goes away
+    pub fn cancel(&mut self, to: &A) {
+        self.pending.retain(|p| p.to != *to);
+    }
+
+    /// Earliest time a retransmission is due
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.pending.iter().map(|p| p.deadline_ms).min()
+    }
+
+    /// Collect due retransmissions, doubling each timeout, and messages
+    /// that ran out of retransmissions
+    pub fn poll(&mut self, now_ms: u64) -> Retransmissions<A> {
+        let mut out = Retransmissions {
+            resend: Vec::new(),
+            failed: Vec::new(),
+        };
+        let max_retransmit = self.params.max_retransmit;
+        self.pending.retain_mut(|p| {
+            if now_ms < p.deadline_ms {
+                return true;
+            }
+            if p.retransmits >= max_retransmit {
+                out.failed.push((p.to.clone(), p.packet.clone()));
+                return false;
+            }
+            p.retransmits += 1;
+            p.timeout_ms *= 2;
+            p.deadline_ms = now_ms + p.timeout_ms;
+            out.resend.push((p.to.clone(), p.packet.clone()));
+            true
+        });
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Exchange<A> {
+    from: A,
+    message_id: u16,
+    expires_ms: u64,
+    response: Option<Packet>,
+}
+
+/// Recently received message ids per peer, so that a retransmitted
+/// message is not processed twice (RFC 7252 §4.5). The response sent for
+/// a message can be kept, to answer duplicates the same way
+#[derive(Debug, Clone)]
+pub struct Deduplicator<A> {
+    lifetime_ms: u64,
+    exchanges: VecDeque<Exchange<A>>,
+}
+
+impl<A: PartialEq> Deduplicator<A> {
+    pub fn new(params: &TransmissionParams) -> Self {
+        Self {
+            lifetime_ms: params.exchange_lifetime_ms(),
+            exchanges: VecDeque::new(),
+        }
+    }
+
+    /// `Some` (with the response recorded for it, if any) when a message
+    /// with `message_id` from `from` was already received
+    pub fn duplicate(&mut self, from: &A, message_id: u16, now_ms: u64) -> Option<Option<&Packet>> {
+        self.exchanges.retain(|e| e.expires_ms > now_ms);
+        self.exchanges
+            .iter()
+            .find(|e| e.from == *from && e.message_id == message_id)
+            .map(|e| e.response.as_ref())
+    }
+
+    /// Remember a received message and the response sent for it
+    pub fn record(&mut self, from: A, message_id: u16, now_ms: u64, response: Option<Packet>) {
+        if self.exchanges.len() >= DEDUP_CAPACITY {
+            self.exchanges.pop_front();
+        }
+        self.exchanges.push_back(Exchange {
+            from,
+            message_id,
+            expires_ms: now_ms + self.lifetime_ms,
+            response,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coap_lite::{MessageType, Packet};
+
+    use super::{Deduplicator, Outbox, TransmissionParams};
+
+    fn con(message_id: u16, token: u8) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.set_type(MessageType::Confirmable);
+        packet.header.message_id = message_id;
+        packet.set_token(vec![token]);
+        packet
+    }
+
+    #[test]
+    fn check_backoff_and_give_up() {
+        let params = TransmissionParams::default();
+        let mut outbox = Outbox::new(params, 7);
+        outbox.send("node", con(1, 1), 0);
+
+        // Initial timeout is within [ACK_TIMEOUT, ACK_TIMEOUT * ACK_RANDOM_FACTOR]
+        let first = outbox.next_deadline().expect("Nothing pending");
+        assert!((2_000..=3_000).contains(&first));
+        assert!(outbox.poll(first - 1).resend.is_empty());
+
+        let mut now = first;
+        let mut timeouts = vec![];
+        for _ in 0..params.max_retransmit {
+            let polled = outbox.poll(now);
+            assert_eq!(polled.resend.len(), 1);
+            let next = outbox.next_deadline().expect("Nothing pending");
+            timeouts.push(next - now);
+            now = next;
+        }
+        assert_eq!(timeouts[1], timeouts[0] * 2);
+        assert_eq!(timeouts[3], timeouts[0] * 8);
+
+        let polled = outbox.poll(now);
+        assert_eq!(polled.failed.len(), 1);
+        assert!(outbox.is_empty());
+        assert!(now <= params.max_transmit_wait_ms());
+    }
+
+    #[test]
+    fn check_ack_and_supersede() {
+        let mut outbox = Outbox::new(TransmissionParams::default(), 7);
+        outbox.send("node", con(1, 1), 0);
+        outbox.send("node", con(2, 1), 10);
+        outbox.send("other", con(3, 1), 10);
+
+        // The newer message with the same token replaced the first
+        assert!(outbox.acknowledge(&"node", 1).is_none());
+        assert_eq!(
+            outbox.acknowledge(&"node", 2).map(|p| p.header.message_id),
+            Some(2)
+        );
+        assert!(outbox.acknowledge(&"node", 3).is_none());
+        outbox.cancel(&"other");
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn check_dedup() {
+        let mut dedup = Deduplicator::new(&TransmissionParams::default());
+        assert!(dedup.duplicate(&"node", 5, 0).is_none());
+        dedup.record("node", 5, 0, Some(con(5, 9)));
+        assert!(dedup.duplicate(&"other", 5, 1).is_none());
+        let response = dedup.duplicate(&"node", 5, 1).expect("Not a duplicate");
+        assert_eq!(response.map(|p| p.header.message_id), Some(5));
+        // Forgotten once the exchange lifetime passes
+        assert!(dedup.duplicate(&"node", 5, 1_000_000).is_none());
+    }
+}
@@ -0,0 +1,28 @@
+//! DNS-SD service plant-minder nodes advertise through the border router's
+//! SRP server.
+//!
+//! Nodes register a `_plantminder._udp` service instance named after their
+//! EUI, on the port they serve CoAP on, with TXT records for the plant
+//! they were configured for. The broker browses the SRP server for the
+//! service so that other Thread devices on the mesh are never taken for
+//! sensor nodes
+
+use alloc::{format, string::String};
+
+/// Service type nodes register
+pub const SERVICE_TYPE: &str = "_plantminder._udp";
+
+/// TXT key of the plant's name
+pub const TXT_NAME: &str = "name";
+
+/// TXT key of the plant's species
+pub const TXT_SPECIES: &str = "species";
+
+/// TXT key of the firmware version
+pub const TXT_FIRMWARE: &str = "fw";
+
+/// Service instance name of the node `eui`, its EUI in hex as in
+/// [`crate::Endpoint::new`]
+pub fn instance_name(eui: &[u8]) -> String {
+    eui.iter().map(|b| format!("{b:02x}")).collect()
+}
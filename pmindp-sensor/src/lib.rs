@@ -13,8 +13,17 @@
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+use alloc::vec::Vec;
 use serde::{Deserialize, Serialize};
 
+pub mod protocol;
+pub mod wire;
+
+pub use protocol::{NodeIdentity, ProtocolHeader, PROTOCOL_VERSION};
+pub use wire::{WireError, WireFormat};
+
 /// [`PlantConfig`] struct is used at compile time by
 /// esp32 nodes, to report to the RPI what plants they
 /// are currently associated with
@@ -28,6 +37,28 @@ pub struct PlantConfig {
     species: &'static str,
     #[default(GrowthStage::Vegetative)]
     growth_stage: GrowthStage,
+    /// Key shared with the broker as 32 hex digits, empty to serve in the
+    /// clear
+    #[default("")]
+    psk: &'static str,
+    /// IPv6 addr of the broker's resource directory to register with on
+    /// joining, empty to wait for the broker to poll the mesh
+    #[default("")]
+    rd: &'static str,
+    /// PSKd the node joins the Thread network with, the commissioner must
+    /// have it for the node's EUI-64
+    #[default("")]
+    pskd: &'static str,
+}
+
+/// Characters a PSKd is made of, the digits and uppercase letters but
+/// I, O, Q and Z
+pub const PSKD_ALPHABET: &[u8] = b"0123456789ABCDEFGHJKLMNPRSTUVWXY";
+
+/// Check `pskd` is 6 to 32 characters of [`PSKD_ALPHABET`], as Thread
+/// requires of a joining device credential
+pub fn is_valid_pskd(pskd: &str) -> bool {
+    (6..=32).contains(&pskd.len()) && pskd.bytes().all(|c| PSKD_ALPHABET.contains(&c))
 }
 
 #[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
@@ -49,6 +80,117 @@ pub enum GrowthStage {
     Senescence,
 }
 
+impl GrowthStage {
+    pub const ALL: [GrowthStage; 5] = [
+        GrowthStage::Sprouting,
+        GrowthStage::Seedling,
+        GrowthStage::Vegetative,
+        GrowthStage::Reproductive,
+        GrowthStage::Senescence,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GrowthStage::Sprouting => "sprouting",
+            GrowthStage::Seedling => "seedling",
+            GrowthStage::Vegetative => "vegetative",
+            GrowthStage::Reproductive => "reproductive",
+            GrowthStage::Senescence => "senescence",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        GrowthStage::ALL.into_iter().find(|s| s.name() == name)
+    }
+}
+
+/// Sensor parts a node can be built with, named after the
+/// `pmindp-esp32-thread` features that enable them. Nodes advertise these
+/// as resource types in their `/.well-known/core`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SensorType {
+    Atsamd10,
+    ProbeCircuit,
+    St0160,
+    Tsl2591,
+    Bme680,
+    Sht40,
+}
+
+impl SensorType {
+    pub const ALL: [SensorType; 6] = [
+        SensorType::Atsamd10,
+        SensorType::ProbeCircuit,
+        SensorType::St0160,
+        SensorType::Tsl2591,
+        SensorType::Bme680,
+        SensorType::Sht40,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SensorType::Atsamd10 => "atsamd10",
+            SensorType::ProbeCircuit => "probe-circuit",
+            SensorType::St0160 => "st0160",
+            SensorType::Tsl2591 => "tsl2591",
+            SensorType::Bme680 => "bme680",
+            SensorType::Sht40 => "sht40",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        SensorType::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    pub fn class(&self) -> SensorClass {
+        match self {
+            SensorType::Atsamd10 | SensorType::ProbeCircuit | SensorType::St0160 => {
+                SensorClass::Soil
+            }
+            SensorType::Tsl2591 => SensorClass::Light,
+            SensorType::Bme680 | SensorType::Sht40 => SensorClass::Env,
+        }
+    }
+}
+
+/// Groups of sensors that nodes report separately, each on its own
+/// observable resource (named after the class) at its own interval
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SensorClass {
+    /// [`Soil`] readings, every node has a soil sensor
+    Soil,
+    /// [`Light`] readings
+    Light,
+    /// [`Gas`] (temperature, pressure, humidity) readings
+    Env,
+}
+
+impl SensorClass {
+    pub const ALL: [SensorClass; 3] = [SensorClass::Soil, SensorClass::Light, SensorClass::Env];
+
+    /// Also the path of the resource the class is served on
+    pub fn name(&self) -> &'static str {
+        match self {
+            SensorClass::Soil => "soil",
+            SensorClass::Light => "light",
+            SensorClass::Env => "env",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        SensorClass::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    /// Slot of the class' sensor in a platform's sensor vec
+    pub fn sensor_index(&self) -> usize {
+        match self {
+            SensorClass::Soil => SOIL_IDX,
+            SensorClass::Light => LIGHT_IDX_1,
+            SensorClass::Env => HUM_IDX,
+        }
+    }
+}
+
 /// System must have at a bare minimum soil sensor, all other
 /// sensors are optional
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
@@ -60,6 +202,44 @@ pub struct SensorReading {
     pub ts: i64,
 }
 
+impl SensorReading {
+    /// Encode the part of the reading for `class` in `format`, as served
+    /// on the class' resource. `None` if the reading has no data for it
+    pub fn encode_class(
+        &self,
+        class: SensorClass,
+        format: WireFormat,
+    ) -> Option<Result<Vec<u8>, WireError>> {
+        match class {
+            SensorClass::Soil => Some(format.encode(&self.soil)),
+            SensorClass::Light => self.light.as_ref().map(|l| format.encode(l)),
+            SensorClass::Env => self.gas.as_ref().map(|g| format.encode(g)),
+        }
+    }
+
+    /// Update the part of the reading for `class` from a payload of the
+    /// class' resource, sent by a node speaking the version in `header`,
+    /// leaving the other parts as they were
+    pub fn merge_class(
+        &mut self,
+        class: SensorClass,
+        header: ProtocolHeader,
+        format: WireFormat,
+        payload: &[u8],
+    ) -> Result<(), WireError> {
+        // Every version so far lays readings out the same
+        if !header.is_supported() {
+            return Err(WireError::UnsupportedVersion(header.version));
+        }
+        match class {
+            SensorClass::Soil => self.soil = format.decode(payload)?,
+            SensorClass::Light => self.light = Some(format.decode(payload)?),
+            SensorClass::Env => self.gas = Some(format.decode(payload)?),
+        }
+        Ok(())
+    }
+}
+
 pub const MAX_SENSORS: usize = 5;
 pub const SOIL_IDX: usize = 0;
 pub const LIGHT_IDX_1: usize = 1;
@@ -101,6 +281,17 @@ pub struct Gas {
 /// allow support for different sensor types
 pub trait SensorPlatform {
     fn sensor_read(&self, buff: &mut [u8]) -> Result<SensorReading, PlatformSensorError>;
+
+    /// Read only the sensors of `class`, the rest of the reading is left
+    /// at its defaults. Platforms that cannot read sensors selectively
+    /// read them all
+    fn sensor_read_class(
+        &self,
+        buff: &mut [u8],
+        _class: SensorClass,
+    ) -> Result<SensorReading, PlatformSensorError> {
+        self.sensor_read(buff)
+    }
 }
 
 /// [`Sensor`] trait defines the base sensor read operation, to allow support for
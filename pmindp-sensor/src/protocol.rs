@@ -0,0 +1,271 @@
+//! Versions of the protocol nodes speak to the broker.
+//!
+//! The broker asks for the newest version it speaks when it registers as
+//! an observer, and the node answers with the version both will use in the
+//! [`ProtocolHeader`] of its [`NodeIdentity`]. Everything the node sends
+//! afterwards follows that version, so changing the layout of a reading
+//! (e.g. adding a field to [`crate::Gas`]) is a new version, and the broker
+//! keeps reading nodes that were not updated yet.
+//!
+//! 1. No header. The registration reply is the 6 byte EUI followed by the
+//!    UTF-8 name, without a content-format. Nodes that are not asked for a
+//!    version answer this way
+//! 2. The registration reply is a CBOR [`NodeIdentity`], tagged with the
+//!    CBOR content-format. Readings are laid out as in version 1
+//! 3. The [`NodeIdentity`] also carries the rest of the node's
+//!    [`PlantConfig`] and its firmware version
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    wire::{self, narrow, Cbor, CborError, Decoder, Encoder, WireError, WireFormat},
+    GrowthStage, PlantConfig,
+};
+
+/// Newest version, the one nodes built from this tree ask for and answer with
+pub const PROTOCOL_VERSION: u8 = 3;
+
+/// Oldest version still understood
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+/// Version of nodes that answer without a [`ProtocolHeader`]
+pub const LEGACY_PROTOCOL_VERSION: u8 = 1;
+
+/// Leads every versioned message. It is encoded as the version number,
+/// the first item of the message's CBOR array, and never changes layout
+/// so that messages of any version can be told apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolHeader {
+    pub version: u8,
+}
+
+impl Default for ProtocolHeader {
+    fn default() -> Self {
+        ProtocolHeader {
+            version: PROTOCOL_VERSION,
+        }
+    }
+}
+
+impl ProtocolHeader {
+    pub fn is_supported(&self) -> bool {
+        (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.version)
+    }
+
+    /// Version to answer a peer that asked for `requested`: the closest one
+    /// this side speaks. Peers that do not ask predate versioning
+    pub fn negotiate(requested: Option<u8>) -> Self {
+        ProtocolHeader {
+            version: requested.map_or(LEGACY_PROTOCOL_VERSION, |v| {
+                v.clamp(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)
+            }),
+        }
+    }
+
+    /// Header of a versioned CBOR message, without decoding the rest
+    pub fn peek(payload: &[u8]) -> Result<Self, CborError> {
+        let mut dec = Decoder::new(payload);
+        match dec.array_len()? {
+            0 => Err(CborError::WrongLength(0)),
+            _ => ProtocolHeader::decode_cbor(&mut dec),
+        }
+    }
+}
+
+impl Cbor for ProtocolHeader {
+    fn encode_cbor(&self, enc: &mut Encoder) {
+        enc.unsigned(self.version as u64);
+    }
+
+    fn decode_cbor(dec: &mut Decoder<'_>) -> Result<Self, CborError> {
+        Ok(ProtocolHeader {
+            version: narrow(dec.unsigned()?)?,
+        })
+    }
+}
+
+/// Who a node is and what plant it minds, its reply to a registration.
+/// Fields a node's version does not send are left at their defaults
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NodeIdentity {
+    pub header: ProtocolHeader,
+    pub eui: [u8; 6],
+    pub name: String,
+    pub pot_num: u32,
+    pub species: String,
+    pub growth_stage: GrowthStage,
+    /// Version of the firmware the node runs
+    pub firmware: String,
+}
+
+impl NodeIdentity {
+    pub fn new(header: ProtocolHeader, eui: [u8; 6], name: &str) -> Self {
+        NodeIdentity {
+            header,
+            eui,
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Identity of a node built with `config`, running `firmware`
+    pub fn from_config(
+        header: ProtocolHeader,
+        eui: [u8; 6],
+        config: &PlantConfig,
+        firmware: &str,
+    ) -> Self {
+        NodeIdentity {
+            header,
+            eui,
+            name: config.name.to_string(),
+            pot_num: config.pot_num,
+            species: config.species.to_string(),
+            growth_stage: config.growth_stage,
+            firmware: firmware.to_string(),
+        }
+    }
+
+    /// Encode as the registration reply of the header's version, with the
+    /// format to tag it with (`None` for no content-format)
+    pub fn reply(&self) -> (Vec<u8>, Option<WireFormat>) {
+        match self.header.version {
+            LEGACY_PROTOCOL_VERSION => {
+                let mut reply = self.eui.to_vec();
+                reply.extend_from_slice(self.name.as_bytes());
+                (reply, None)
+            }
+            _ => (wire::to_cbor(self), Some(WireFormat::Cbor)),
+        }
+    }
+
+    /// Decode a version 2 or later registration reply
+    pub fn decode(payload: &[u8]) -> Result<Self, WireError> {
+        let header = ProtocolHeader::peek(payload)?;
+        if !header.is_supported() || header.version == LEGACY_PROTOCOL_VERSION {
+            return Err(WireError::UnsupportedVersion(header.version));
+        }
+        Ok(wire::from_cbor(payload)?)
+    }
+
+    /// Decode an untagged, version 1 registration reply
+    pub fn from_legacy(payload: &[u8]) -> Self {
+        let mut eui = [0u8; 6];
+        let mut name = String::new();
+        if payload.len() >= 6 {
+            eui.copy_from_slice(&payload[..6]);
+            name = String::from_utf8_lossy(&payload[6..]).into_owned();
+        }
+        NodeIdentity {
+            header: ProtocolHeader {
+                version: LEGACY_PROTOCOL_VERSION,
+            },
+            eui,
+            name,
+            ..Default::default()
+        }
+    }
+}
+
+impl NodeIdentity {
+    /// Number of items a version's identity is encoded as
+    fn items(version: u8) -> u64 {
+        match version {
+            ..=2 => 3,
+            _ => 7,
+        }
+    }
+}
+
+impl Cbor for NodeIdentity {
+    fn encode_cbor(&self, enc: &mut Encoder) {
+        enc.array(NodeIdentity::items(self.header.version));
+        self.header.encode_cbor(enc);
+        enc.bytes(&self.eui);
+        enc.text(&self.name);
+        if self.header.version >= 3 {
+            enc.unsigned(self.pot_num as u64);
+            enc.text(&self.species);
+            self.growth_stage.encode_cbor(enc);
+            enc.text(&self.firmware);
+        }
+    }
+
+    fn decode_cbor(dec: &mut Decoder<'_>) -> Result<Self, CborError> {
+        let len = dec.array_len()?;
+        let header = ProtocolHeader::decode_cbor(dec)?;
+        if len != NodeIdentity::items(header.version) {
+            return Err(CborError::WrongLength(len));
+        }
+        let eui = dec.bytes()?;
+        let mut identity = NodeIdentity {
+            header,
+            eui: eui
+                .try_into()
+                .map_err(|_| CborError::WrongLength(eui.len() as u64))?,
+            name: dec.text()?.to_string(),
+            ..Default::default()
+        };
+        if header.version >= 3 {
+            identity.pot_num = narrow(dec.unsigned()?)?;
+            identity.species = dec.text()?.to_string();
+            identity.growth_stage = GrowthStage::decode_cbor(dec)?;
+            identity.firmware = dec.text()?.to_string();
+        }
+        Ok(identity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NodeIdentity, ProtocolHeader, PROTOCOL_VERSION};
+    use crate::{wire::to_cbor, WireError, WireFormat, PLANT_CONFIG};
+
+    #[test]
+    fn check_version_negotiation() {
+        let eui = [0x60, 0x55, 0xf9, 0x01, 0x02, 0x03];
+
+        // Brokers that do not ask get the legacy reply
+        let legacy = NodeIdentity::new(ProtocolHeader::negotiate(None), eui, "Jade");
+        let (reply, format) = legacy.reply();
+        assert_eq!(format, None);
+        assert_eq!(&reply[..6], &eui);
+        assert_eq!(NodeIdentity::from_legacy(&reply), legacy);
+
+        // A newer broker gets the newest version the node speaks
+        let header = ProtocolHeader::negotiate(Some(PROTOCOL_VERSION + 1));
+        assert_eq!(header.version, PROTOCOL_VERSION);
+        let identity = NodeIdentity::from_config(header, eui, &PLANT_CONFIG, "0.1.0");
+        let (reply, format) = identity.reply();
+        assert_eq!(format, Some(WireFormat::Cbor));
+        assert_eq!(
+            NodeIdentity::decode(&reply).expect("Bad identity"),
+            identity
+        );
+
+        // An older broker only gets the fields its version has
+        let older = NodeIdentity {
+            header: ProtocolHeader::negotiate(Some(2)),
+            ..identity.clone()
+        };
+        let decoded = NodeIdentity::decode(&older.reply().0).expect("Bad identity");
+        assert_eq!((decoded.eui, decoded.name.as_str()), (eui, "SirPots"));
+        assert!(decoded.species.is_empty() && decoded.firmware.is_empty());
+
+        // Newer nodes can be told apart even if the rest changed
+        let future = NodeIdentity::new(
+            ProtocolHeader {
+                version: PROTOCOL_VERSION + 1,
+            },
+            eui,
+            "Jade",
+        );
+        assert!(matches!(
+            NodeIdentity::decode(&to_cbor(&future)),
+            Err(WireError::UnsupportedVersion(v)) if v == PROTOCOL_VERSION + 1
+        ));
+    }
+}
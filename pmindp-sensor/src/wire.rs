@@ -0,0 +1,452 @@
+//! Wire formats sensor readings are sent in.
+//!
+//! Nodes used to send readings as JSON, which spends most of a 127 byte
+//! 802.15.4 frame on field names. [`WireFormat::Cbor`] is a compact
+//! binary alternative: a minimal CBOR (RFC 8949) encoding of the reading
+//! structs as arrays, in field order, with floats as single precision.
+//! E.g. a [`Soil`] reading `{"moisture":956,"temp":21.5}` (28 bytes as
+//! JSON) is `82 19 03bc fa 41ac0000` (9 bytes). Absent optional parts are
+//! CBOR `null`. Each format is marked with its CoAP content-format so
+//! receivers can tell them apart
+
+use alloc::vec::Vec;
+
+use crate::{Gas, GrowthStage, Light, SensorReading, Soil};
+
+/// Encoding of a reading payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    /// `application/json`
+    Json,
+    /// `application/cbor`
+    #[default]
+    Cbor,
+}
+
+impl WireFormat {
+    /// CoAP content-format (RFC 7252 §12.3)
+    pub fn content_format(&self) -> u16 {
+        match self {
+            WireFormat::Json => 50,
+            WireFormat::Cbor => 60,
+        }
+    }
+
+    pub fn from_content_format(content_format: u16) -> Option<Self> {
+        match content_format {
+            50 => Some(WireFormat::Json),
+            60 => Some(WireFormat::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Cbor + serde::Serialize>(&self, value: &T) -> Result<Vec<u8>, WireError> {
+        match self {
+            WireFormat::Json => Ok(serde_json::to_vec(value)?),
+            WireFormat::Cbor => Ok(to_cbor(value)),
+        }
+    }
+
+    pub fn decode<T: Cbor + serde::de::DeserializeOwned>(
+        &self,
+        payload: &[u8],
+    ) -> Result<T, WireError> {
+        match self {
+            WireFormat::Json => Ok(serde_json::from_slice(payload)?),
+            WireFormat::Cbor => Ok(from_cbor(payload)?),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    Json(serde_json::Error),
+    Cbor(CborError),
+    /// Payload of a [`crate::protocol`] version this side does not speak
+    UnsupportedVersion(u8),
+}
+
+impl From<serde_json::Error> for WireError {
+    fn from(e: serde_json::Error) -> Self {
+        WireError::Json(e)
+    }
+}
+
+impl From<CborError> for WireError {
+    fn from(e: CborError) -> Self {
+        WireError::Cbor(e)
+    }
+}
+
+impl core::fmt::Display for WireError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WireError::Json(e) => write!(f, "Json error {e}"),
+            WireError::Cbor(e) => write!(f, "Cbor error {e:?}"),
+            WireError::UnsupportedVersion(v) => write!(f, "Unsupported protocol version {v}"),
+        }
+    }
+}
+
+impl core::error::Error for WireError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CborError {
+    /// Payload ended mid item
+    Eof,
+    /// Item of a different type than the field at this position
+    UnexpectedType(u8),
+    /// Array with a different number of fields than the struct
+    WrongLength(u64),
+    /// Integer does not fit the field
+    OutOfRange,
+    /// Bytes left over after the value
+    TrailingBytes,
+    /// Text string that is not UTF-8
+    InvalidUtf8,
+}
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const NULL: u8 = 0xf6;
+const FLOAT32: u8 = 0xfa;
+const FLOAT64: u8 = 0xfb;
+
+/// Types with a CBOR encoding
+pub trait Cbor: Sized {
+    fn encode_cbor(&self, enc: &mut Encoder);
+    fn decode_cbor(dec: &mut Decoder<'_>) -> Result<Self, CborError>;
+}
+
+pub fn to_cbor<T: Cbor>(value: &T) -> Vec<u8> {
+    let mut enc = Encoder(Vec::new());
+    value.encode_cbor(&mut enc);
+    enc.0
+}
+
+pub fn from_cbor<T: Cbor>(payload: &[u8]) -> Result<T, CborError> {
+    let mut dec = Decoder(payload);
+    let value = T::decode_cbor(&mut dec)?;
+    if !dec.0.is_empty() {
+        return Err(CborError::TrailingBytes);
+    }
+    Ok(value)
+}
+
+pub struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn head(&mut self, major: u8, val: u64) {
+        let major = major << 5;
+        match val {
+            0..=23 => self.0.push(major | val as u8),
+            24..=0xff => self.0.extend_from_slice(&[major | 24, val as u8]),
+            0x100..=0xffff => {
+                self.0.push(major | 25);
+                self.0.extend_from_slice(&(val as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                self.0.push(major | 26);
+                self.0.extend_from_slice(&(val as u32).to_be_bytes());
+            }
+            _ => {
+                self.0.push(major | 27);
+                self.0.extend_from_slice(&val.to_be_bytes());
+            }
+        }
+    }
+
+    pub fn array(&mut self, len: u64) {
+        self.head(MAJOR_ARRAY, len);
+    }
+
+    pub fn unsigned(&mut self, val: u64) {
+        self.head(MAJOR_UNSIGNED, val);
+    }
+
+    pub fn signed(&mut self, val: i64) {
+        match val {
+            0.. => self.head(MAJOR_UNSIGNED, val as u64),
+            _ => self.head(MAJOR_NEGATIVE, !val as u64),
+        }
+    }
+
+    pub fn bytes(&mut self, val: &[u8]) {
+        self.head(MAJOR_BYTES, val.len() as u64);
+        self.0.extend_from_slice(val);
+    }
+
+    pub fn text(&mut self, val: &str) {
+        self.head(MAJOR_TEXT, val.len() as u64);
+        self.0.extend_from_slice(val.as_bytes());
+    }
+
+    pub fn f32(&mut self, val: f32) {
+        self.0.push(FLOAT32);
+        self.0.extend_from_slice(&val.to_be_bytes());
+    }
+
+    pub fn option<T: Cbor>(&mut self, val: &Option<T>) {
+        match val {
+            Some(v) => v.encode_cbor(self),
+            None => self.0.push(NULL),
+        }
+    }
+}
+
+pub struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    pub fn new(payload: &'a [u8]) -> Self {
+        Decoder(payload)
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], CborError> {
+        if self.0.len() < N {
+            return Err(CborError::Eof);
+        }
+        let (bytes, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(bytes.try_into().unwrap_or([0; N]))
+    }
+
+    fn peek(&self) -> Result<u8, CborError> {
+        self.0.first().copied().ok_or(CborError::Eof)
+    }
+
+    /// Major type and argument of the next item
+    fn head(&mut self) -> Result<(u8, u64), CborError> {
+        let [initial] = self.take::<1>()?;
+        let val = match initial & 0x1f {
+            val @ 0..=23 => val as u64,
+            24 => u8::from_be_bytes(self.take()?) as u64,
+            25 => u16::from_be_bytes(self.take()?) as u64,
+            26 => u32::from_be_bytes(self.take()?) as u64,
+            27 => u64::from_be_bytes(self.take()?),
+            _ => return Err(CborError::UnexpectedType(initial)),
+        };
+        Ok((initial >> 5, val))
+    }
+
+    /// Expect an array of exactly `len` items
+    pub fn array(&mut self, len: u64) -> Result<(), CborError> {
+        match self.array_len()? {
+            n if n == len => Ok(()),
+            n => Err(CborError::WrongLength(n)),
+        }
+    }
+
+    /// Expect an array, of any number of items
+    pub fn array_len(&mut self) -> Result<u64, CborError> {
+        match self.head()? {
+            (MAJOR_ARRAY, n) => Ok(n),
+            (major, _) => Err(CborError::UnexpectedType(major << 5)),
+        }
+    }
+
+    fn string(&mut self, major: u8) -> Result<&'a [u8], CborError> {
+        match self.head()? {
+            (m, len) if m == major => {
+                let len = usize::try_from(len).map_err(|_| CborError::OutOfRange)?;
+                if self.0.len() < len {
+                    return Err(CborError::Eof);
+                }
+                let (bytes, rest) = self.0.split_at(len);
+                self.0 = rest;
+                Ok(bytes)
+            }
+            (m, _) => Err(CborError::UnexpectedType(m << 5)),
+        }
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], CborError> {
+        self.string(MAJOR_BYTES)
+    }
+
+    pub fn text(&mut self) -> Result<&'a str, CborError> {
+        core::str::from_utf8(self.string(MAJOR_TEXT)?).map_err(|_| CborError::InvalidUtf8)
+    }
+
+    pub fn unsigned(&mut self) -> Result<u64, CborError> {
+        match self.head()? {
+            (MAJOR_UNSIGNED, val) => Ok(val),
+            (major, _) => Err(CborError::UnexpectedType(major << 5)),
+        }
+    }
+
+    pub fn signed(&mut self) -> Result<i64, CborError> {
+        match self.head()? {
+            (MAJOR_UNSIGNED, val) => i64::try_from(val).map_err(|_| CborError::OutOfRange),
+            (MAJOR_NEGATIVE, val) => i64::try_from(val)
+                .map(|v| !v)
+                .map_err(|_| CborError::OutOfRange),
+            (major, _) => Err(CborError::UnexpectedType(major << 5)),
+        }
+    }
+
+    /// Single or double precision float
+    pub fn f32(&mut self) -> Result<f32, CborError> {
+        match self.take::<1>()? {
+            [FLOAT32] => Ok(f32::from_be_bytes(self.take()?)),
+            [FLOAT64] => Ok(f64::from_be_bytes(self.take()?) as f32),
+            [initial] => Err(CborError::UnexpectedType(initial)),
+        }
+    }
+
+    pub fn option<T: Cbor>(&mut self) -> Result<Option<T>, CborError> {
+        if self.peek()? == NULL {
+            self.take::<1>()?;
+            return Ok(None);
+        }
+        T::decode_cbor(self).map(Some)
+    }
+}
+
+pub(crate) fn narrow<T: TryFrom<u64>>(val: u64) -> Result<T, CborError> {
+    T::try_from(val).map_err(|_| CborError::OutOfRange)
+}
+
+impl Cbor for Soil {
+    fn encode_cbor(&self, enc: &mut Encoder) {
+        enc.array(2);
+        enc.unsigned(self.moisture as u64);
+        enc.f32(self.temp);
+    }
+
+    fn decode_cbor(dec: &mut Decoder<'_>) -> Result<Self, CborError> {
+        dec.array(2)?;
+        Ok(Soil {
+            moisture: narrow(dec.unsigned()?)?,
+            temp: dec.f32()?,
+        })
+    }
+}
+
+impl Cbor for Light {
+    fn encode_cbor(&self, enc: &mut Encoder) {
+        enc.array(2);
+        enc.unsigned(self.fs as u64);
+        enc.f32(self.lux);
+    }
+
+    fn decode_cbor(dec: &mut Decoder<'_>) -> Result<Self, CborError> {
+        dec.array(2)?;
+        Ok(Light {
+            fs: narrow(dec.unsigned()?)?,
+            lux: dec.f32()?,
+        })
+    }
+}
+
+impl Cbor for Gas {
+    fn encode_cbor(&self, enc: &mut Encoder) {
+        enc.array(4);
+        enc.f32(self.temp);
+        enc.f32(self.p);
+        enc.f32(self.h);
+        enc.unsigned(self.gas as u64);
+    }
+
+    fn decode_cbor(dec: &mut Decoder<'_>) -> Result<Self, CborError> {
+        dec.array(4)?;
+        Ok(Gas {
+            temp: dec.f32()?,
+            p: dec.f32()?,
+            h: dec.f32()?,
+            gas: narrow(dec.unsigned()?)?,
+        })
+    }
+}
+
+/// Encoded as its index in [`GrowthStage::ALL`]
+impl Cbor for GrowthStage {
+    fn encode_cbor(&self, enc: &mut Encoder) {
+        let idx = GrowthStage::ALL.iter().position(|s| s == self);
+        enc.unsigned(idx.unwrap_or_default() as u64);
+    }
+
+    fn decode_cbor(dec: &mut Decoder<'_>) -> Result<Self, CborError> {
+        let idx: usize = narrow(dec.unsigned()?)?;
+        GrowthStage::ALL
+            .get(idx)
+            .copied()
+            .ok_or(CborError::OutOfRange)
+    }
+}
+
+impl Cbor for SensorReading {
+    fn encode_cbor(&self, enc: &mut Encoder) {
+        enc.array(4);
+        self.soil.encode_cbor(enc);
+        enc.option(&self.light);
+        enc.option(&self.gas);
+        enc.signed(self.ts);
+    }
+
+    fn decode_cbor(dec: &mut Decoder<'_>) -> Result<Self, CborError> {
+        dec.array(4)?;
+        Ok(SensorReading {
+            soil: Soil::decode_cbor(dec)?,
+            light: dec.option()?,
+            gas: dec.option()?,
+            ts: dec.signed()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_cbor, to_cbor, CborError, WireFormat};
+    use crate::{Gas, Light, SensorReading, Soil};
+
+    #[test]
+    fn check_cbor_encoding() {
+        let soil = Soil {
+            moisture: 956,
+            temp: 21.5,
+        };
+        let encoded = to_cbor(&soil);
+        assert_eq!(
+            encoded,
+            [0x82, 0x19, 0x03, 0xbc, 0xfa, 0x41, 0xac, 0x00, 0x00]
+        );
+        assert_eq!(serde_json::to_vec(&soil).unwrap().len(), 28);
+
+        let reading = SensorReading {
+            soil,
+            light: None,
+            gas: Some(Gas {
+                temp: 22.0,
+                p: 1013.25,
+                h: 40.5,
+                gas: 120_000,
+            }),
+            ts: -1,
+        };
+        let decoded: SensorReading = from_cbor(&to_cbor(&reading)).expect("Bad reading");
+        assert_eq!(decoded.soil.moisture, 956);
+        assert!(decoded.light.is_none());
+        assert_eq!(decoded.gas.map(|g| g.gas), Some(120_000));
+        assert_eq!(decoded.ts, -1);
+
+        // Both formats decode through the same entry point
+        let light = Light {
+            fs: 3592,
+            lux: 84.9,
+        };
+        for format in [WireFormat::Json, WireFormat::Cbor] {
+            let payload = format.encode(&light).expect("Unable to encode");
+            let decoded: Light = format.decode(&payload).expect("Unable to decode");
+            assert_eq!(decoded.fs, 3592);
+        }
+
+        assert_eq!(from_cbor::<Soil>(&encoded[..5]).err(), Some(CborError::Eof));
+        assert_eq!(
+            from_cbor::<Light>(&[0x82, 0x1a, 0x00, 0x01, 0x00, 0x00, 0xf6]).err(),
+            Some(CborError::OutOfRange)
+        );
+    }
+}